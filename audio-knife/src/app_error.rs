@@ -5,7 +5,6 @@ use axum::{
     response::{IntoResponse, Response},
 };
 
-#[allow(unused)]
 pub struct AppError(anyhow::Error);
 
 // Tell Axum how to convert `AppError` into a response.
@@ -0,0 +1,143 @@
+//! Output formats of the billing records endpoint.
+use std::borrow::Cow;
+
+use axum::body::Bytes;
+use serde::Deserialize;
+
+use context_switch::{BillingRecordValue, billing_collector::BillingRecords};
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillingFormat {
+    /// The billing records grouped by service and scope as one JSON array.
+    #[default]
+    Json,
+    /// The billing records grouped by service and scope, one JSON object per line.
+    Ndjson,
+    /// One row per billing record with the columns `service`, `scope`, `name`, and `value`.
+    Csv,
+    /// The billing records rolled up by name.
+    Summary,
+}
+
+/// Renders the billing records as newline-delimited JSON. Each line is serialized only when the
+/// response body pulls it.
+pub fn ndjson(
+    records: Vec<BillingRecords>,
+) -> impl Iterator<Item = Result<Bytes, serde_json::Error>> {
+    records.into_iter().map(|records| {
+        let mut line = serde_json::to_vec(&records)?;
+        line.push(b'\n');
+        Ok(line.into())
+    })
+}
+
+/// Renders the billing records as CSV. Durations are rendered in seconds. Rows are sorted to make
+/// the output deterministic.
+pub fn csv(records: &[BillingRecords]) -> String {
+    let mut rows: Vec<[String; 4]> = records
+        .iter()
+        .flat_map(|records| {
+            records.records().iter().map(|record| {
+                [
+                    records.service().to_string(),
+                    records.scope().unwrap_or_default().to_string(),
+                    record.name.clone(),
+                    csv_value(&record.value),
+                ]
+            })
+        })
+        .collect();
+    rows.sort();
+
+    let mut csv = String::from("service,scope,name,value\n");
+    for row in rows {
+        let fields: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_value(value: &BillingRecordValue) -> String {
+    match value {
+        BillingRecordValue::Duration { duration } => duration.as_secs_f64().to_string(),
+        BillingRecordValue::Count { count } => count.to_string(),
+    }
+}
+
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use context_switch::{BillingId, BillingRecord, billing_collector::BillingCollector};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn csv_has_one_row_per_record() {
+        let (id, mut collector) = collector();
+        assert_eq!(
+            csv(&collector.collect(&id)),
+            "service,scope,name,value\n\
+             azure-synthesize,,audio,2.5\n\
+             openai-dialog,\"agent, de\",tokens:input:audio,100\n\
+             openai-dialog,\"agent, de\",tokens:output:audio,42\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_lines_parse_back_to_the_records() {
+        let (id, mut collector) = collector();
+        let records = collector.collect(&id);
+        let expected = serde_json::to_value(&records).unwrap();
+
+        let parsed: Vec<Value> = ndjson(records)
+            .map(|line| {
+                let line = line.unwrap();
+                assert_eq!(line.last(), Some(&b'\n'));
+                serde_json::from_slice(&line).unwrap()
+            })
+            .collect();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(Value::Array(parsed), expected);
+    }
+
+    fn collector() -> (BillingId, BillingCollector) {
+        let id = BillingId::from("call".to_string());
+        let mut collector = BillingCollector::default();
+        collector
+            .record(
+                &id,
+                "openai-dialog",
+                Some("agent, de".into()),
+                vec![
+                    BillingRecord::count("tokens:input:audio", 100),
+                    BillingRecord::count("tokens:output:audio", 42),
+                ],
+            )
+            .unwrap();
+        collector
+            .record(
+                &id,
+                "azure-synthesize",
+                None,
+                vec![BillingRecord::duration(
+                    "audio",
+                    Duration::from_millis(2500),
+                )],
+            )
+            .unwrap();
+        (id, collector)
+    }
+}
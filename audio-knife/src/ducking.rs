@@ -0,0 +1,155 @@
+//! Ducking of outbound audio while the caller speaks.
+//!
+//! Instead of clearing the playback as soon as the caller barges in, outbound audio can be
+//! attenuated while speech is detected in the input. The gain follows with a short attack and a
+//! longer release, so that the attenuation is applied without clicks.
+//!
+//! Only audio that was not sent to the client yet can be attenuated. Audio the event scheduler
+//! already forwarded ahead of playback is played back unchanged.
+use std::time::Duration;
+
+use context_switch::{AudioFrame, TurnDetector, TurnSignal};
+
+/// The normalized RMS level of the input above which the caller is considered speaking.
+const SPEECH_THRESHOLD: f32 = 0.02;
+/// How long the input must be silent before the outbound audio is restored.
+const SPEECH_HANGOVER: Duration = Duration::from_millis(300);
+/// The time constant the gain follows when it is reduced.
+const ATTACK: Duration = Duration::from_millis(20);
+/// The time constant the gain follows when it is restored.
+const RELEASE: Duration = Duration::from_millis(250);
+
+/// Detects speech in the input audio and decides the gain of the outbound audio.
+#[derive(Debug)]
+pub struct DuckingDetector {
+    detector: TurnDetector,
+    /// The gain of the outbound audio while the caller speaks.
+    ducked_gain: f32,
+}
+
+impl DuckingDetector {
+    /// Creates a detector that attenuates the outbound audio by `attenuation_db` while the caller
+    /// speaks.
+    pub fn new(attenuation_db: f32) -> Self {
+        Self {
+            detector: TurnDetector::new(SPEECH_THRESHOLD, SPEECH_HANGOVER),
+            ducked_gain: 10f32.powf(-attenuation_db / 20.0),
+        }
+    }
+
+    /// Returns the new target gain of the outbound audio when the caller starts or stops
+    /// speaking.
+    pub fn process(&mut self, frame: &AudioFrame) -> Option<f32> {
+        match self.detector.process(frame)? {
+            TurnSignal::Started => Some(self.ducked_gain),
+            TurnSignal::Ended => Some(1.0),
+        }
+    }
+}
+
+/// Applies a smoothed gain to outbound audio.
+#[derive(Debug)]
+pub struct Ducker {
+    gain: f32,
+    target: f32,
+}
+
+impl Default for Ducker {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            target: 1.0,
+        }
+    }
+}
+
+impl Ducker {
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+    }
+
+    pub fn process(&mut self, frame: &mut AudioFrame) {
+        if self.gain == 1.0 && self.target == 1.0 {
+            return;
+        }
+        let time_constant = if self.target < self.gain {
+            ATTACK
+        } else {
+            RELEASE
+        };
+        let sample_rate = frame.format.sample_rate as f32;
+        let coefficient = 1.0 - (-1.0 / (time_constant.as_secs_f32() * sample_rate)).exp();
+        let channels = frame.format.channels as usize;
+        for samples in frame.samples.chunks_mut(channels) {
+            self.gain += coefficient * (self.target - self.gain);
+            for sample in samples {
+                *sample = (*sample as f32 * self.gain).round() as i16;
+            }
+        }
+        // Snap to the target to stop processing when the gain is restored.
+        if (self.gain - self.target).abs() < 1e-3 {
+            self.gain = self.target;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use context_switch::AudioFormat;
+
+    use super::*;
+
+    const FORMAT: AudioFormat = AudioFormat {
+        channels: 1,
+        sample_rate: 16000,
+    };
+
+    /// 20ms of a constant level.
+    fn frame(level: i16) -> AudioFrame {
+        AudioFrame {
+            format: FORMAT,
+            samples: vec![level; 320],
+        }
+    }
+
+    /// Plays back `frames` of output while feeding input of `input_level` and returns the level of
+    /// the last output frame.
+    fn duck(
+        detector: &mut DuckingDetector,
+        ducker: &mut Ducker,
+        input_level: i16,
+        frames: usize,
+    ) -> i16 {
+        let mut output = frame(0);
+        for _ in 0..frames {
+            if let Some(target) = detector.process(&frame(input_level)) {
+                ducker.set_target(target);
+            }
+            output = frame(10000);
+            ducker.process(&mut output);
+        }
+        *output.samples.last().unwrap()
+    }
+
+    #[test]
+    fn output_is_attenuated_while_the_caller_speaks() {
+        let mut detector = DuckingDetector::new(12.0);
+        let mut ducker = Ducker::default();
+
+        assert_eq!(duck(&mut detector, &mut ducker, 0, 10), 10000);
+        // 12dB are a factor of about 0.25.
+        let ducked = duck(&mut detector, &mut ducker, 8000, 10);
+        assert!((2480..=2530).contains(&ducked), "{ducked}");
+    }
+
+    #[test]
+    fn output_is_restored_after_the_caller_stopped_speaking() {
+        let mut detector = DuckingDetector::new(12.0);
+        let mut ducker = Ducker::default();
+
+        duck(&mut detector, &mut ducker, 8000, 10);
+        // Within the hangover, the output stays attenuated.
+        assert!(duck(&mut detector, &mut ducker, 0, 10) < 2600);
+        assert_eq!(duck(&mut detector, &mut ducker, 0, 100), 10000);
+    }
+}
@@ -5,6 +5,15 @@
 //!
 //! The audio requests are immediately forwarded as long there are not 5 seconds of audio playback
 //! pending. The other events are delayed until audio is _assumed_ to be played back by FreeSWITCH.
+//!
+//! Because playback drifts in practice, clients may report how much audio they have not played
+//! back yet. These reports correct the simulated timing, smoothed exponentially. Without them,
+//! playback is assumed to happen in real time.
+//!
+//! Optionally, consecutive audio events are coalesced into larger ones before they are sent, which
+//! reduces the number of events for clients that receive audio as JSON.
+//!
+//! While the caller speaks, outbound audio may be attenuated, see [`crate::ducking`].
 use std::{
     cmp::max,
     collections::VecDeque,
@@ -19,17 +28,51 @@ use tokio::{
 };
 use tracing::{debug, warn};
 
-use context_switch::{AudioFormat, OutputModality, OutputPath, ServerEvent};
+use context_switch::{
+    AudioFormat, AudioFrame, OutputModality, OutputPath, ServerEvent, make_loudness_normalizer,
+};
+
+use crate::ducking::Ducker;
+
+/// Feedback from the client side of the session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feedback {
+    /// The duration of audio the client reported as not played back yet.
+    PlaybackPending(Duration),
+    /// The gain outbound audio should be ducked to.
+    DuckingGain(f32),
+}
 
 /// Runs an event scheduler that manages the timing of events sent to FreeSWITCH.
 ///
 /// This delays audio packets if more than 5 seconds are pending, and control packets if currently
 /// audio is being assumed to be played back.
+///
+/// If `loudness_target` is set, outbound audio is normalized toward this loudness in LUFS.
+///
+/// `feedback` receives the durations of audio the client reported as pending, which are blended
+/// into the simulated playback with the `playback_smoothing` factor, and the gains outbound audio
+/// is ducked to.
+///
+/// If `audio_coalescing` is set, consecutive audio events are merged up to this duration.
+///
+/// When playback starts, audio is held back until `pre_buffer` is queued, see
+/// [`MediaEventScheduler::with_pre_buffer`].
 pub async fn event_scheduler(
     mut receiver: UnboundedReceiver<ServerEvent>,
+    mut feedback: UnboundedReceiver<Feedback>,
     sender: UnboundedSender<ServerEvent>,
+    loudness_target: Option<f32>,
+    playback_smoothing: f64,
+    audio_coalescing: Option<Duration>,
+    pre_buffer: Duration,
 ) -> Result<()> {
-    let mut media_scheduler = MediaEventScheduler::new();
+    let mut media_scheduler = MediaEventScheduler::new(Instant::now())
+        .with_playback_smoothing(playback_smoothing)
+        .with_audio_coalescing(audio_coalescing)
+        .with_pre_buffer(pre_buffer);
+    let mut loudness_normalizer = loudness_target.map(make_loudness_normalizer);
+    let mut ducker = Ducker::default();
 
     let mut wakeup_delay = Duration::MAX;
     loop {
@@ -44,6 +87,15 @@ pub async fn event_scheduler(
                     }
                 }
             },
+            Some(feedback) = feedback.recv() => {
+                match feedback {
+                    Feedback::PlaybackPending(pending) => {
+                        media_scheduler.notify_playback_pending(Instant::now(), pending);
+                    }
+                    Feedback::DuckingGain(gain) => ducker.set_target(gain),
+                }
+                None
+            }
             _ = sleep(wakeup_delay) => {
                 None
             }
@@ -64,6 +116,16 @@ pub async fn event_scheduler(
                     // media scheduler.
                 }
                 OutputPath::Media => {
+                    let event = match (&mut loudness_normalizer, media_scheduler.audio_format) {
+                        (Some(normalizer), Some(format)) => {
+                            normalize_loudness(normalizer, format, event)
+                        }
+                        _ => event,
+                    };
+                    let event = match media_scheduler.audio_format {
+                        Some(format) => duck(&mut ducker, format, event),
+                        None => event,
+                    };
                     media_scheduler.schedule_event(now, event);
                 }
             }
@@ -77,6 +139,43 @@ pub async fn event_scheduler(
     }
 }
 
+fn normalize_loudness(
+    normalizer: &mut impl FnMut(&AudioFrame) -> AudioFrame,
+    format: AudioFormat,
+    event: ServerEvent,
+) -> ServerEvent {
+    match event {
+        ServerEvent::Audio { id, samples } => {
+            let frame = normalizer(&AudioFrame {
+                format,
+                samples: samples.into(),
+            });
+            ServerEvent::Audio {
+                id,
+                samples: frame.samples.into(),
+            }
+        }
+        event => event,
+    }
+}
+
+fn duck(ducker: &mut Ducker, format: AudioFormat, event: ServerEvent) -> ServerEvent {
+    match event {
+        ServerEvent::Audio { id, samples } => {
+            let mut frame = AudioFrame {
+                format,
+                samples: samples.into(),
+            };
+            ducker.process(&mut frame);
+            ServerEvent::Audio {
+                id,
+                samples: frame.samples.into(),
+            }
+        }
+        event => event,
+    }
+}
+
 #[derive(Debug)]
 pub struct MediaEventScheduler {
     /// The Timestamp audio playback is finished.
@@ -87,21 +186,64 @@ pub struct MediaEventScheduler {
     timed_events: VecDeque<(Instant, ServerEvent)>,
     /// Latest audio format seen.
     audio_format: Option<AudioFormat>,
+    /// The weight of a playback report relative to the simulated playback. `1.0` takes reports
+    /// as they are.
+    playback_smoothing: f64,
+    /// The duration consecutive audio events are coalesced up to.
+    audio_coalescing: Option<Duration>,
+    /// The time the oldest audio event that is waiting to be coalesced was scheduled.
+    audio_coalescing_since: Option<Instant>,
+    /// The duration of audio that is queued before playback starts.
+    pre_buffer: Duration,
+    /// The time audio started to be held back for pre-buffering.
+    pre_buffering_since: Option<Instant>,
 }
 
 const MAX_BUFFERED_AUDIO: Duration = Duration::from_secs(5);
 const WAKEUP_DELAY_WHEN_BUFFERS_ARE_FULL: Duration = Duration::from_secs(1);
+pub const DEFAULT_PLAYBACK_SMOOTHING: f64 = 0.5;
 
 impl MediaEventScheduler {
-    pub fn new() -> Self {
+    /// Creates a scheduler. `now` is the scheduler's notion of the current time and must come from
+    /// the same clock as the `now` arguments of the other methods.
+    pub fn new(now: Instant) -> Self {
         Self {
-            audio_finished: Instant::now(),
+            audio_finished: now,
             input_media_events: VecDeque::new(),
             timed_events: VecDeque::new(),
             audio_format: None,
+            playback_smoothing: DEFAULT_PLAYBACK_SMOOTHING,
+            audio_coalescing: None,
+            audio_coalescing_since: None,
+            pre_buffer: Duration::ZERO,
+            pre_buffering_since: None,
         }
     }
 
+    pub fn with_playback_smoothing(mut self, playback_smoothing: f64) -> Self {
+        self.playback_smoothing = playback_smoothing;
+        self
+    }
+
+    /// Coalesces consecutive audio events until they reach `audio_coalescing`.
+    ///
+    /// Audio is held back at most for this duration, and is sent earlier when a non-audio media
+    /// event follows it.
+    pub fn with_audio_coalescing(mut self, audio_coalescing: Option<Duration>) -> Self {
+        self.audio_coalescing = audio_coalescing;
+        self
+    }
+
+    /// Holds back audio when playback starts until `pre_buffer` is queued, which smooths the start
+    /// of bursty audio sources at the cost of latency.
+    ///
+    /// Audio is held back at most for this duration, and is released earlier when a non-audio
+    /// media event follows it.
+    pub fn with_pre_buffer(mut self, pre_buffer: Duration) -> Self {
+        self.pre_buffer = pre_buffer;
+        self
+    }
+
     /// TODO: There could be situation in which … when there is a conversation crossover … the
     /// started event was not sent yet when we received audio here. In this case, we have to ignore
     /// the audio and warn about it.
@@ -116,24 +258,59 @@ impl MediaEventScheduler {
     pub fn schedule_event(&mut self, now: Instant, event: ServerEvent) {
         // Don't give me anything other than media path events!
         debug_assert!(event.output_path() == OutputPath::Media);
-        if let ServerEvent::ClearAudio { .. } = event {
-            self.input_media_events
-                .retain(|e| !matches!(e, ServerEvent::Audio { .. }));
-            // All the non-audio event before `ClearAudio` must be sent as soon as possible, too.
-            self.audio_finished = now;
-            self.timed_events.iter_mut().for_each(|(t, _)| *t = now);
+        match event {
+            ServerEvent::Audio { .. } => {
+                self.audio_coalescing_since.get_or_insert(now);
+            }
+            ServerEvent::ClearAudio { .. } => {
+                self.input_media_events
+                    .retain(|e| !matches!(e, ServerEvent::Audio { .. }));
+                self.audio_coalescing_since = None;
+                self.pre_buffering_since = None;
+                // All the non-audio event before `ClearAudio` must be sent as soon as possible, too.
+                self.audio_finished = now;
+                self.timed_events.iter_mut().for_each(|(t, _)| *t = now);
+            }
+            _ => {}
         }
         self.input_media_events.push_back(event);
     }
 
+    /// Corrects the simulated playback with the duration of audio the client reported as not
+    /// played back yet. Timed events are moved along, because they wait for the same audio.
+    pub fn notify_playback_pending(&mut self, now: Instant, pending: Duration) {
+        let estimated = self
+            .audio_finished
+            .saturating_duration_since(now)
+            .as_secs_f64();
+        let corrected = estimated + self.playback_smoothing * (pending.as_secs_f64() - estimated);
+        let audio_finished = now + Duration::from_secs_f64(corrected.max(0.0));
+        debug!(
+            "Playback correction: estimated {estimated:.3}s, reported {pending:?}, corrected {corrected:.3}s"
+        );
+
+        let shift = |t: &mut Instant| {
+            *t = if audio_finished >= self.audio_finished {
+                *t + (audio_finished - self.audio_finished)
+            } else {
+                max(now, *t - (self.audio_finished - audio_finished))
+            }
+        };
+        self.timed_events.iter_mut().for_each(|(t, _)| shift(t));
+        self.audio_finished = audio_finished;
+    }
+
     /// Process all the events.
     pub fn process(
         &mut self,
         now: Instant,
         sender: &UnboundedSender<ServerEvent>,
     ) -> Result<Option<Duration>> {
-        let d1 = self.process_timed_events(now, sender)?;
+        self.process_timed_events(now, sender)?;
         let d2 = self.process_media_path_events(now, sender)?;
+        // Media path processing may have added timed events, so their wakeup is determined
+        // afterwards.
+        let d1 = self.next_timed_event_delay(now);
         match (d1, d2) {
             (Some(d1), Some(d2)) => Ok(Some(d1.min(d2))),
             (Some(d), None) | (None, Some(d)) => Ok(Some(d)),
@@ -146,15 +323,18 @@ impl MediaEventScheduler {
         &mut self,
         now: Instant,
         sender: &UnboundedSender<ServerEvent>,
-    ) -> Result<Option<Duration>> {
+    ) -> Result<()> {
         while let Some((time_to_send, _)) = self.timed_events.front()
             && *time_to_send <= now
         {
             let (_, event) = self.timed_events.pop_front().unwrap();
             sender.send(event).context("Sending timed event")?;
         }
+        Ok(())
+    }
 
-        Ok(self.timed_events.front().map(|(t, _)| *t - now))
+    fn next_timed_event_delay(&self, now: Instant) -> Option<Duration> {
+        self.timed_events.front().map(|(t, _)| *t - now)
     }
 
     pub fn process_media_path_events(
@@ -170,7 +350,7 @@ impl MediaEventScheduler {
                 return Ok(None);
             };
             match next_event {
-                ServerEvent::Audio { samples, .. } => {
+                ServerEvent::Audio { .. } => {
                     let Some(audio_format) = self.audio_format else {
                         warn!(
                             "Received Audio but without a prior Started event or no audio output, audio is ignored"
@@ -178,16 +358,33 @@ impl MediaEventScheduler {
                         self.input_media_events.pop_front();
                         continue;
                     };
-                    let duration = audio_format.duration(samples.len());
+                    if self.audio_finished == now
+                        && let Some(delay) = self.pre_buffering_delay(now, audio_format)
+                    {
+                        return Ok(Some(delay));
+                    }
+                    self.pre_buffering_since = None;
+                    let (count, duration) = self.coalescable_audio(audio_format);
+                    if let Some(audio_coalescing) = self.audio_coalescing
+                        && duration < audio_coalescing
+                        && count == self.input_media_events.len()
+                    {
+                        // Wait for more audio, but not longer than the coalescing duration.
+                        let since = *self.audio_coalescing_since.get_or_insert(now);
+                        let deadline = since + audio_coalescing;
+                        if deadline > now {
+                            return Ok(Some(deadline - now));
+                        }
+                    }
                     if self.audio_finished >= (now + MAX_BUFFERED_AUDIO) {
                         // Audio buffers are full, process again later.
                         return Ok(Some(WAKEUP_DELAY_WHEN_BUFFERS_ARE_FULL));
                     }
                     self.audio_finished += duration;
+                    self.audio_coalescing_since = None;
 
-                    sender
-                        .send(self.input_media_events.pop_front().unwrap())
-                        .context("Sending audio event")?;
+                    let event = self.pop_coalesced_audio(count);
+                    sender.send(event).context("Sending audio event")?;
                 }
                 _ => {
                     if self.audio_finished > now {
@@ -206,6 +403,63 @@ impl MediaEventScheduler {
             }
         }
     }
+
+    /// Returns how long playback is delayed to pre-buffer the audio at the front of the input
+    /// queue, or `None` if it can start now.
+    fn pre_buffering_delay(&mut self, now: Instant, audio_format: AudioFormat) -> Option<Duration> {
+        let mut queued = Duration::ZERO;
+        for event in &self.input_media_events {
+            let ServerEvent::Audio { samples, .. } = event else {
+                // The audio is complete.
+                return None;
+            };
+            queued += audio_format.duration(samples.len());
+        }
+        if queued >= self.pre_buffer {
+            return None;
+        }
+        let deadline = *self.pre_buffering_since.get_or_insert(now) + self.pre_buffer;
+        (deadline > now).then(|| deadline - now)
+    }
+
+    /// Returns the number of audio events at the front of the input queue that are sent as one
+    /// and their total duration.
+    fn coalescable_audio(&self, audio_format: AudioFormat) -> (usize, Duration) {
+        let mut count = 0;
+        let mut duration = Duration::ZERO;
+        for event in &self.input_media_events {
+            let ServerEvent::Audio { samples, .. } = event else {
+                break;
+            };
+            count += 1;
+            duration += audio_format.duration(samples.len());
+            if self
+                .audio_coalescing
+                .is_none_or(|coalescing| duration >= coalescing)
+            {
+                break;
+            }
+        }
+        (count, duration)
+    }
+
+    /// Removes `count` audio events from the front of the input queue and merges them into one.
+    fn pop_coalesced_audio(&mut self, count: usize) -> ServerEvent {
+        let mut audio = self.input_media_events.drain(..count);
+        let Some(ServerEvent::Audio { id, samples }) = audio.next() else {
+            unreachable!("Coalesced events are audio events");
+        };
+        let mut samples: Vec<i16> = samples.into();
+        for event in audio {
+            if let ServerEvent::Audio { samples: more, .. } = event {
+                samples.extend_from_slice(&more);
+            }
+        }
+        ServerEvent::Audio {
+            id,
+            samples: samples.into(),
+        }
+    }
 }
 
 /// Extract the audio format from output modalities. Returns None or the format. Bails if more than one audio format was found.
@@ -228,3 +482,447 @@ fn audio_format_from_output_modalities(
 
     Ok(Some(single_format))
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+    use super::*;
+    use context_switch::BillingRecord;
+
+    const FORMAT: AudioFormat = AudioFormat {
+        channels: 1,
+        sample_rate: 16000,
+    };
+
+    fn started_scheduler(now: Instant) -> MediaEventScheduler {
+        let mut scheduler = MediaEventScheduler::new(now);
+        scheduler
+            .notify_started(&[OutputModality::Audio { format: FORMAT }])
+            .unwrap();
+        scheduler
+    }
+
+    fn audio(millis: usize) -> ServerEvent {
+        let samples = FORMAT.sample_rate as usize * millis / 1000;
+        ServerEvent::Audio {
+            id: "c".to_string().into(),
+            samples: vec![0i16; samples].into(),
+        }
+    }
+
+    fn text(content: &str) -> ServerEvent {
+        ServerEvent::Text {
+            id: "c".to_string().into(),
+            is_final: true,
+            content: content.into(),
+            language: None,
+            speaker: None,
+        }
+    }
+
+    /// Runs the events through the event scheduler and returns the first `count` events it sends.
+    async fn run_scheduler(events: Vec<ServerEvent>, count: usize) -> Vec<ServerEvent> {
+        let (input_sender, input_receiver) = unbounded_channel();
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let (_feedback_sender, feedback_receiver) = unbounded_channel();
+        let scheduler = tokio::spawn(event_scheduler(
+            input_receiver,
+            feedback_receiver,
+            output_sender,
+            None,
+            DEFAULT_PLAYBACK_SMOOTHING,
+            None,
+            Duration::ZERO,
+        ));
+
+        for event in events {
+            input_sender.send(event).unwrap();
+        }
+        let mut sent = Vec::new();
+        for _ in 0..count {
+            sent.push(output_receiver.recv().await.unwrap());
+        }
+
+        drop(input_sender);
+        scheduler.await.unwrap().unwrap();
+        sent
+    }
+
+    fn started() -> ServerEvent {
+        ServerEvent::Started {
+            id: "c".to_string().into(),
+            modalities: vec![OutputModality::Audio { format: FORMAT }],
+        }
+    }
+
+    fn stopped(drained: bool) -> ServerEvent {
+        ServerEvent::Stopped {
+            id: "c".to_string().into(),
+            drained,
+        }
+    }
+
+    fn drain(receiver: &mut UnboundedReceiver<ServerEvent>) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn audio_is_paced_to_the_buffered_playback_duration() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler = started_scheduler(start);
+
+        for _ in 0..7 {
+            scheduler.schedule_event(start, audio(1000));
+        }
+
+        // Only 5 seconds of audio are sent ahead of the playback.
+        let wakeup = scheduler.process(start, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 5);
+        assert_eq!(wakeup, Some(WAKEUP_DELAY_WHEN_BUFFERS_ARE_FULL));
+
+        // After one second of playback, there is room for one more second.
+        let now = start + Duration::from_secs(1);
+        let wakeup = scheduler.process(now, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 1);
+        assert_eq!(wakeup, Some(WAKEUP_DELAY_WHEN_BUFFERS_ARE_FULL));
+
+        let now = start + Duration::from_millis(1500);
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        assert_eq!(drain(&mut receiver).len(), 1);
+    }
+
+    #[test]
+    fn media_events_are_sent_when_preceding_audio_is_played_back() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler = started_scheduler(start);
+
+        scheduler.schedule_event(start, audio(1200));
+        scheduler.schedule_event(start, audio(800));
+        scheduler.schedule_event(start, text("after audio"));
+
+        let wakeup = scheduler.process(start, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 2);
+        assert_eq!(wakeup, Some(Duration::from_secs(2)));
+
+        let now = start + Duration::from_millis(1999);
+        assert_eq!(
+            scheduler.process(now, &sender).unwrap(),
+            Some(Duration::from_millis(1))
+        );
+        assert!(drain(&mut receiver).is_empty());
+
+        let now = start + Duration::from_secs(2);
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        let events = drain(&mut receiver);
+        assert!(matches!(events[..], [ServerEvent::Text { .. }]));
+    }
+
+    #[test]
+    fn playback_feedback_corrects_the_pacing() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler = started_scheduler(start).with_playback_smoothing(1.0);
+
+        for _ in 0..7 {
+            scheduler.schedule_event(start, audio(1000));
+        }
+        scheduler.process(start, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 5);
+
+        // The client played back faster than real time, 3 instead of 4 seconds are pending, so
+        // there is room for two more seconds.
+        let now = start + Duration::from_secs(1);
+        scheduler.notify_playback_pending(now, Duration::from_secs(3));
+        scheduler.process(now, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 2);
+    }
+
+    #[test]
+    fn playback_feedback_is_smoothed_and_moves_timed_events() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler = started_scheduler(start).with_playback_smoothing(0.5);
+
+        scheduler.schedule_event(start, audio(2000));
+        scheduler.schedule_event(start, text("after audio"));
+        let wakeup = scheduler.process(start, &sender).unwrap();
+        assert_eq!(wakeup, Some(Duration::from_secs(2)));
+        drain(&mut receiver);
+
+        // One second is estimated to be pending, the client reports that playback already
+        // finished: The estimate is corrected halfway.
+        let now = start + Duration::from_secs(1);
+        scheduler.notify_playback_pending(now, Duration::ZERO);
+        assert_eq!(
+            scheduler.process(now, &sender).unwrap(),
+            Some(Duration::from_millis(500))
+        );
+        assert!(drain(&mut receiver).is_empty());
+
+        let now = start + Duration::from_millis(1500);
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        let events = drain(&mut receiver);
+        assert!(matches!(events[..], [ServerEvent::Text { .. }]));
+    }
+
+    #[test]
+    fn small_audio_frames_are_coalesced_within_the_window() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler =
+            started_scheduler(start).with_audio_coalescing(Some(Duration::from_millis(100)));
+
+        for millis in [0, 20, 40] {
+            let now = start + Duration::from_millis(millis);
+            scheduler.schedule_event(now, audio(20));
+            assert_eq!(
+                scheduler.process(now, &sender).unwrap(),
+                Some(Duration::from_millis(100 - millis))
+            );
+            assert!(drain(&mut receiver).is_empty());
+        }
+
+        // The window of the first frame elapsed.
+        let now = start + Duration::from_millis(100);
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        let events = drain(&mut receiver);
+        let [ServerEvent::Audio { samples, .. }] = &events[..] else {
+            panic!("Expected one audio event, got {events:?}");
+        };
+        assert_eq!(FORMAT.duration(samples.len()), Duration::from_millis(60));
+    }
+
+    #[test]
+    fn audio_is_held_until_the_pre_buffer_is_queued() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler = started_scheduler(start).with_pre_buffer(Duration::from_millis(100));
+
+        for millis in [0, 20, 40, 60] {
+            let now = start + Duration::from_millis(millis);
+            scheduler.schedule_event(now, audio(20));
+            assert_eq!(
+                scheduler.process(now, &sender).unwrap(),
+                Some(Duration::from_millis(100 - millis))
+            );
+            assert!(drain(&mut receiver).is_empty());
+        }
+
+        // The target is reached, all queued audio is released.
+        let now = start + Duration::from_millis(80);
+        scheduler.schedule_event(now, audio(20));
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        assert_eq!(drain(&mut receiver).len(), 5);
+
+        // While playing back, audio is not held anymore.
+        let now = start + Duration::from_millis(100);
+        scheduler.schedule_event(now, audio(20));
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        assert_eq!(drain(&mut receiver).len(), 1);
+    }
+
+    #[test]
+    fn pre_buffered_audio_is_released_when_other_events_follow_or_the_target_elapsed() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler = started_scheduler(start).with_pre_buffer(Duration::from_millis(100));
+
+        scheduler.schedule_event(start, audio(20));
+        scheduler.process(start, &sender).unwrap();
+        assert!(drain(&mut receiver).is_empty());
+        scheduler.schedule_event(start, text("after audio"));
+        scheduler.process(start, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 1);
+
+        // After the audio was played back, pre-buffering starts again.
+        let now = start + Duration::from_millis(20);
+        scheduler.process(now, &sender).unwrap();
+        assert_eq!(drain(&mut receiver).len(), 1);
+        scheduler.schedule_event(now, audio(20));
+        scheduler.process(now, &sender).unwrap();
+        assert!(drain(&mut receiver).is_empty());
+        let now = now + Duration::from_millis(100);
+        assert_eq!(scheduler.process(now, &sender).unwrap(), None);
+        assert_eq!(drain(&mut receiver).len(), 1);
+    }
+
+    #[test]
+    fn coalesced_audio_is_sent_when_it_reaches_the_duration_or_other_events_follow() {
+        let (sender, mut receiver) = unbounded_channel();
+        let start = Instant::now();
+        let mut scheduler =
+            started_scheduler(start).with_audio_coalescing(Some(Duration::from_millis(100)));
+
+        for _ in 0..7 {
+            scheduler.schedule_event(start, audio(20));
+        }
+        scheduler.schedule_event(start, text("after audio"));
+        scheduler.process(start, &sender).unwrap();
+
+        let durations: Vec<_> = drain(&mut receiver)
+            .iter()
+            .map(|event| match event {
+                ServerEvent::Audio { samples, .. } => FORMAT.duration(samples.len()),
+                event => panic!("Unexpected event: {event:?}"),
+            })
+            .collect();
+        assert_eq!(
+            durations,
+            [Duration::from_millis(100), Duration::from_millis(40)]
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_overtake_queued_media() {
+        let error = ServerEvent::Error {
+            id: "c".to_string().into(),
+            message: "failed".into(),
+            request_id: None,
+        };
+        let events = vec![started(), audio(50), text("after audio"), error];
+
+        let sent = run_scheduler(events, 4).await;
+        assert!(matches!(
+            sent[..],
+            [
+                ServerEvent::Started { .. },
+                ServerEvent::Audio { .. },
+                ServerEvent::Error { .. },
+                ServerEvent::Text { .. },
+            ]
+        ));
+    }
+
+    fn billing() -> ServerEvent {
+        billing_on(OutputPath::Media)
+    }
+
+    fn billing_on(path: OutputPath) -> ServerEvent {
+        ServerEvent::BillingRecords {
+            id: "c".to_string().into(),
+            path,
+            request_id: None,
+            service: "service".into(),
+            scope: None,
+            records: vec![BillingRecord::duration(
+                "output:audio",
+                Duration::from_secs(1),
+            )],
+        }
+    }
+
+    #[tokio::test]
+    async fn billing_records_follow_the_media_they_bill() {
+        let events = vec![started(), audio(50), billing(), text("after billing")];
+
+        let sent = run_scheduler(events, 4).await;
+        assert!(matches!(
+            sent[..],
+            [
+                ServerEvent::Started { .. },
+                ServerEvent::Audio { .. },
+                ServerEvent::BillingRecords { .. },
+                ServerEvent::Text { .. },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn billing_records_on_the_control_path_overtake_queued_audio() {
+        // The second audio event waits until there is room in the playback buffer.
+        let events = vec![
+            started(),
+            audio(6000),
+            audio(50),
+            billing_on(OutputPath::Control),
+        ];
+
+        let sent = run_scheduler(events, 4).await;
+        assert!(matches!(
+            sent[..],
+            [
+                ServerEvent::Started { .. },
+                ServerEvent::Audio { .. },
+                ServerEvent::BillingRecords { .. },
+                ServerEvent::Audio { .. },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn media_discarded_after_an_undrained_stop_is_not_billed() {
+        let events = vec![started(), audio(1000), billing(), stopped(false)];
+
+        let (input_sender, input_receiver) = unbounded_channel();
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let (_feedback_sender, feedback_receiver) = unbounded_channel();
+        let scheduler = tokio::spawn(event_scheduler(
+            input_receiver,
+            feedback_receiver,
+            output_sender,
+            None,
+            DEFAULT_PLAYBACK_SMOOTHING,
+            None,
+            Duration::ZERO,
+        ));
+        for event in events {
+            input_sender.send(event).unwrap();
+        }
+        let mut sent = Vec::new();
+        for _ in 0..3 {
+            sent.push(output_receiver.recv().await.unwrap());
+        }
+        assert!(matches!(
+            sent[..],
+            [
+                ServerEvent::Started { .. },
+                ServerEvent::Audio { .. },
+                ServerEvent::Stopped { drained: false, .. },
+            ]
+        ));
+
+        // The session ends before the audio is played back.
+        drop(input_sender);
+        scheduler.await.unwrap().unwrap();
+        assert!(drain(&mut output_receiver).is_empty());
+    }
+
+    #[tokio::test]
+    async fn drained_stop_follows_the_last_media_event() {
+        let events = vec![started(), audio(50), text("after audio"), stopped(true)];
+
+        let sent = run_scheduler(events, 4).await;
+        assert!(matches!(
+            sent[..],
+            [
+                ServerEvent::Started { .. },
+                ServerEvent::Audio { .. },
+                ServerEvent::Text { .. },
+                ServerEvent::Stopped { drained: true, .. },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn undrained_stop_overtakes_queued_media() {
+        let events = vec![started(), audio(50), text("after audio"), stopped(false)];
+
+        let sent = run_scheduler(events, 4).await;
+        assert!(matches!(
+            sent[..],
+            [
+                ServerEvent::Started { .. },
+                ServerEvent::Audio { .. },
+                ServerEvent::Stopped { drained: false, .. },
+                ServerEvent::Text { .. },
+            ]
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-conversation sequence numbers for server events.
+//!
+//! Sequence numbers are assigned when events are dispatched to the websocket, after the event
+//! scheduler, so that events intentionally dropped by it (audio cleared by `ClearAudio`) do not
+//! show up as gaps. Audio is sent as binary messages and does not carry sequence numbers, so only
+//! the events dispatched as JSON are numbered.
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use context_switch::{ConversationId, ServerEvent};
+
+#[derive(Debug, Default)]
+pub struct EventSequencer {
+    next: HashMap<ConversationId, u64>,
+}
+
+impl EventSequencer {
+    pub fn sequence(&mut self, event: ServerEvent) -> SequencedEvent {
+        let id = event.conversation_id();
+        let seq = match &event {
+            // The last event of a conversation, so its counter is not needed anymore.
+            ServerEvent::Stopped { .. } | ServerEvent::Error { .. } => {
+                self.next.remove(id).unwrap_or_default()
+            }
+            _ => {
+                let next = self.next.entry(id.clone()).or_default();
+                *next += 1;
+                *next - 1
+            }
+        };
+        SequencedEvent { event, seq }
+    }
+}
+
+/// A server event with its sequence number added as the `seq` field.
+#[derive(Debug, Serialize)]
+pub struct SequencedEvent {
+    #[serde(flatten)]
+    event: ServerEvent,
+    seq: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn text(id: &str, content: &str) -> ServerEvent {
+        ServerEvent::Text {
+            id: id.to_string().into(),
+            is_final: true,
+            content: content.into(),
+            language: None,
+            speaker: None,
+        }
+    }
+
+    fn seq(event: SequencedEvent) -> Value {
+        serde_json::to_value(event).unwrap()["seq"].clone()
+    }
+
+    #[test]
+    fn consecutive_events_carry_increasing_sequence_numbers_per_conversation() {
+        let mut sequencer = EventSequencer::default();
+
+        assert_eq!(seq(sequencer.sequence(text("a", "1"))), json!(0));
+        assert_eq!(seq(sequencer.sequence(text("a", "2"))), json!(1));
+        assert_eq!(seq(sequencer.sequence(text("b", "1"))), json!(0));
+        assert_eq!(seq(sequencer.sequence(text("a", "3"))), json!(2));
+        assert_eq!(seq(sequencer.sequence(text("b", "2"))), json!(1));
+    }
+
+    #[test]
+    fn sequence_numbers_are_forgotten_after_the_conversation_stopped() {
+        let mut sequencer = EventSequencer::default();
+        sequencer.sequence(text("a", "1"));
+        let stopped = ServerEvent::Stopped {
+            id: "a".to_string().into(),
+            drained: true,
+        };
+
+        assert_eq!(seq(sequencer.sequence(stopped)), json!(1));
+        assert!(sequencer.next.is_empty());
+    }
+
+    #[test]
+    fn sequence_number_is_added_to_the_event_json() {
+        let mut sequencer = EventSequencer::default();
+        let event = ServerEvent::Stopped {
+            id: "a".to_string().into(),
+            drained: false,
+        };
+
+        let value = serde_json::to_value(sequencer.sequence(event)).unwrap();
+        assert_eq!(value, json!({ "type": "stopped", "id": "a", "seq": 0 }));
+
+        // Clients that don't know about `seq` still decode the event.
+        let event: ServerEvent = serde_json::from_value(value).unwrap();
+        assert!(matches!(event, ServerEvent::Stopped { .. }));
+    }
+}
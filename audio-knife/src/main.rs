@@ -1,45 +1,62 @@
 //! A context switch websocket server that supports the protocol of mod_audio_fork
 
 mod app_error;
+mod billing_format;
+mod ducking;
 mod event_scheduler;
+mod event_sequencer;
 mod mod_audio_fork;
 mod server_event_router;
 
 use std::env;
+use std::error::Error as _;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
-use axum::body::Bytes;
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{self, Path, WebSocketUpgrade};
-use axum::response::{IntoResponse, Json};
-use axum::routing::get;
+use app_error::AppError;
+use axum::body::{Body, Bytes};
+use axum::extract::ws::{CloseFrame, Message, WebSocket, close_code};
+use axum::extract::{self, Path, Query, WebSocketUpgrade};
+use axum::http::{HeaderValue, header};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
 use axum::serve::ListenerExt;
 use base64::Engine as _;
 use base64::engine::general_purpose;
-use futures_util::stream::SplitSink;
+use billing_format::BillingFormat;
+use ducking::DuckingDetector;
+use event_scheduler::Feedback;
+use event_sequencer::EventSequencer;
+use futures_util::stream::{self, SplitSink, Stream};
 use futures_util::{SinkExt, StreamExt};
 use reqwest::StatusCode;
 use serde::Deserialize;
 use serde_json::Value;
 use server_event_router::ServerEventRouter;
 use tokio::net::TcpListener;
-use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver, channel, unbounded_channel};
+use tokio::sync::mpsc::{
+    Receiver, Sender, UnboundedReceiver, UnboundedSender, channel, unbounded_channel,
+};
 use tokio::{pin, select};
+use tokio_tungstenite::tungstenite::{self, error::CapacityError};
 use tracing::{Instrument, Span, debug, error, info, info_span, warn};
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::format::FmtSpan;
 use uuid::Uuid;
 
-use context_switch::billing_collector::BillingCollector;
+use context_switch::audio_ring_buffer::AudioRingBuffer;
+use context_switch::billing_collector::{BillingCollector, PeekToken};
 use context_switch::{
-    AudioFormat, AudioFrame, BillingId, ClientEvent, ContextSwitch, ConversationId, InputModality,
-    ServerEvent, audio,
+    AudioFormat, AudioFrame, AudioTracer, BillingId, ClientEvent, ContextSwitch, ConversationId,
+    InputModality, ServerEvent, TraceCompression,
 };
 
 const DEFAULT_PORT: u16 = 8123;
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -76,12 +93,116 @@ async fn main() -> Result<()> {
         .map(|path| PathBuf::from(&path))
         .ok();
 
+    // Comma separated, for example `wav,mp3`. If not set, all supported audio types are allowed.
+    let local_file_extensions = env::var("AUDIO_KNIFE_LOCAL_FILE_EXTENSIONS").ok();
+
+    // Outbound audio is normalized toward this loudness in LUFS, for example `-16`.
+    let loudness_target: Option<f32> = env::var("AUDIO_KNIFE_LOUDNESS_TARGET")
+        .ok()
+        .map(|target| target.parse())
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_LOUDNESS_TARGET")?;
+
     let trace_dir = env::var("AUDIO_KNIFE_TRACES")
         .map(|path| PathBuf::from(&path))
         .ok();
 
+    // Compression of the audio traces, `none` or `gzip`.
+    let trace_compression: TraceCompression = env::var("AUDIO_KNIFE_TRACE_COMPRESSION")
+        .ok()
+        .map(|compression| compression.parse())
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_TRACE_COMPRESSION")?
+        .unwrap_or_default();
+
+    // If set, the last milliseconds of input audio are retained per conversation and written to
+    // the audio traces directory when the session fails.
+    let pre_roll_ms: Option<u64> = env::var("AUDIO_KNIFE_PRE_ROLL_MS")
+        .ok()
+        .map(|ms| ms.parse())
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_PRE_ROLL_MS")?;
+
+    let pre_roll = match (pre_roll_ms, &trace_dir) {
+        (Some(ms), Some(dir)) => Some(PreRoll {
+            duration: Duration::from_millis(ms),
+            dir: dir.clone(),
+            compression: trace_compression,
+        }),
+        (Some(_), None) => bail!("AUDIO_KNIFE_PRE_ROLL_MS requires AUDIO_KNIFE_TRACES to be set"),
+        (None, _) => None,
+    };
+
+    // The weight of the playback reports of clients when correcting the simulated playback, in
+    // the range `(0, 1]`.
+    let playback_smoothing: f64 = env::var("AUDIO_KNIFE_PLAYBACK_SMOOTHING")
+        .ok()
+        .map(|factor| factor.parse())
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_PLAYBACK_SMOOTHING")?
+        .unwrap_or(event_scheduler::DEFAULT_PLAYBACK_SMOOTHING);
+    if !(playback_smoothing > 0.0 && playback_smoothing <= 1.0) {
+        bail!("AUDIO_KNIFE_PLAYBACK_SMOOTHING must be in the range (0, 1]");
+    }
+
+    // If set, consecutive outbound audio events are coalesced up to this number of milliseconds
+    // to reduce the number of events sent to clients.
+    let audio_coalescing = env::var("AUDIO_KNIFE_AUDIO_COALESCING_MS")
+        .ok()
+        .map(|ms| ms.parse().map(Duration::from_millis))
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_AUDIO_COALESCING_MS")?;
+
+    // Outbound audio is held back until this number of milliseconds is queued when playback
+    // starts.
+    let pre_buffer = env::var("AUDIO_KNIFE_PRE_BUFFER_MS")
+        .ok()
+        .map(|ms| ms.parse().map(Duration::from_millis))
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_PRE_BUFFER_MS")?
+        .unwrap_or_default();
+
+    // If set, conversations that receive no input for this number of milliseconds are stopped.
+    let idle_timeout = env::var("AUDIO_KNIFE_IDLE_TIMEOUT_MS")
+        .ok()
+        .map(|ms| ms.parse().map(Duration::from_millis))
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_IDLE_TIMEOUT_MS")?;
+
+    // If set, outbound audio is attenuated by this number of decibels while the caller speaks.
+    let ducking_db: Option<f32> = env::var("AUDIO_KNIFE_DUCKING_DB")
+        .ok()
+        .map(|db| db.parse())
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_DUCKING_DB")?;
+    if ducking_db.is_some_and(|db| db <= 0.0) {
+        bail!("AUDIO_KNIFE_DUCKING_DB must be positive");
+    }
+
+    // The maximum size of a websocket message in bytes. Larger inbound messages are rejected and
+    // outbound audio is split into messages of at most this size.
+    let max_message_size: usize = env::var("AUDIO_KNIFE_MAX_MESSAGE_SIZE")
+        .ok()
+        .map(|size| size.parse())
+        .transpose()
+        .context("Failed to parse AUDIO_KNIFE_MAX_MESSAGE_SIZE")?
+        .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+    if max_message_size < 2 {
+        bail!("AUDIO_KNIFE_MAX_MESSAGE_SIZE must be at least 2 bytes");
+    }
+
     info!("Local files path: {local_files:?}");
+    info!("Local file extensions: {local_file_extensions:?}");
+    info!("Loudness target: {loudness_target:?}");
     info!("Audio traces: {trace_dir:?}");
+    info!("Trace compression: {trace_compression:?}");
+    info!("Pre-roll: {pre_roll:?}");
+    info!("Playback smoothing: {playback_smoothing}");
+    info!("Audio coalescing: {audio_coalescing:?}");
+    info!("Pre-buffer: {pre_buffer:?}");
+    info!("Ducking: {ducking_db:?}");
+    info!("Idle timeout: {idle_timeout:?}");
+    info!("Max message size: {max_message_size}");
 
     {
         let args = env::args();
@@ -105,28 +226,37 @@ async fn main() -> Result<()> {
     let registry = {
         let registry = context_switch::registry();
 
-        let playback_service = playback::Playback { local_files };
+        let mut playback_service = playback::Playback::new(local_files)?;
+        if let Some(extensions) = local_file_extensions {
+            playback_service = playback_service.with_local_file_extensions(extensions.split(','));
+        }
         registry.add_service("playback", playback_service)
     };
 
     let billing_collector = Arc::new(Mutex::new(BillingCollector::default()));
 
+    let context_switch = ContextSwitch::new(registry.into(), cs_sender, trace_dir)
+        .with_trace_compression(trace_compression)
+        .with_billing_collector(billing_collector.clone());
+    let context_switch = match idle_timeout {
+        Some(timeout) => context_switch.with_idle_timeout(timeout),
+        None => context_switch,
+    };
+
     let state = State {
-        billing_collector: billing_collector.clone(),
-        context_switch: Arc::new(Mutex::new(
-            ContextSwitch::new(registry.into(), cs_sender, trace_dir)
-                .with_billing_collector(billing_collector),
-        )),
+        billing_collector,
+        context_switch: Arc::new(Mutex::new(context_switch)),
         server_event_router: server_event_distributor.clone(),
+        loudness_target,
+        playback_smoothing,
+        audio_coalescing,
+        pre_buffer,
+        pre_roll,
+        ducking_db,
+        max_message_size,
     };
 
-    let app = axum::Router::new()
-        .route("/", get(ws_get))
-        .route(
-            "/billing-records/{billing_id}/take",
-            get(take_billing_records),
-        )
-        .with_state(state);
+    let app = router(state);
 
     // IMPORTANT: attempt to set `TCP_NODELAY` on every incoming connection.
     // We need to disable the Nagle algorithm to properly support low latency
@@ -152,6 +282,22 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+fn router(state: State) -> axum::Router {
+    axum::Router::new()
+        .route("/", get(ws_get))
+        .route("/validate", post(validate_start))
+        .route("/conversations/{id}/transcript", get(transcript))
+        .route(
+            "/billing-records/{billing_id}/take",
+            get(take_billing_records),
+        )
+        .route(
+            "/billing-records/{billing_id}/confirm/{token}",
+            post(confirm_billing_records),
+        )
+        .with_state(state)
+}
+
 async fn server_event_dispatcher(
     mut receiver: UnboundedReceiver<ServerEvent>,
     distributor: Arc<Mutex<ServerEventRouter>>,
@@ -179,13 +325,31 @@ struct State {
     billing_collector: Arc<Mutex<BillingCollector>>,
     context_switch: Arc<Mutex<ContextSwitch>>,
     server_event_router: Arc<Mutex<ServerEventRouter>>,
+    loudness_target: Option<f32>,
+    playback_smoothing: f64,
+    audio_coalescing: Option<Duration>,
+    pre_buffer: Duration,
+    pre_roll: Option<PreRoll>,
+    ducking_db: Option<f32>,
+    max_message_size: usize,
+}
+
+/// Configuration of the input audio that is retained to be dumped when a session fails.
+#[derive(Debug, Clone)]
+struct PreRoll {
+    duration: Duration,
+    /// The directory the pre-roll is written to.
+    dir: PathBuf,
+    compression: TraceCompression,
 }
 
 async fn ws_get(
     axum::extract::State(state): axum::extract::State<State>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| ws_driver(state.clone(), socket))
+    ws.max_message_size(state.max_message_size)
+        .max_frame_size(state.max_message_size)
+        .on_upgrade(move |socket| ws_driver(state.clone(), socket))
 }
 
 async fn ws_driver(state: State, websocket: WebSocket) {
@@ -203,21 +367,34 @@ async fn ws_driver(state: State, websocket: WebSocket) {
     info!("Client disconnected");
 }
 
+/// Messages sent to the client in response to messages received from it.
 #[derive(Debug)]
-struct Pong(Bytes);
+enum Reply {
+    Pong(Bytes),
+    /// Closes the websocket and ends the dispatcher.
+    Close(CloseFrame),
+}
 
 async fn ws(state: State, mut websocket: WebSocket) -> Result<()> {
     // Wait for the first message event, assuming this is the start event for the conversation.
 
     match websocket.recv().await {
-        Some(msg) => {
-            let msg = msg?;
-            let (session_state, conversation_span, cs_receiver) =
+        Some(Err(e)) => {
+            let close_frame = oversized_message_close_frame(e, state.max_message_size)?;
+            websocket.send(Message::Close(Some(close_frame))).await?;
+            Ok(())
+        }
+        Some(Ok(msg)) => {
+            let (mut session_state, conversation_span, cs_receiver) =
                 SessionState::start_session(state, msg)?;
 
-            ws_session(session_state, cs_receiver, websocket)
-                .instrument(conversation_span)
-                .await
+            let result = ws_session(&mut session_state, cs_receiver, websocket)
+                .instrument(conversation_span.clone())
+                .await;
+            if result.is_err() {
+                conversation_span.in_scope(|| session_state.dump_pre_roll());
+            }
+            result
         }
         None => {
             info!("WebSocket closed before first message was received");
@@ -227,7 +404,7 @@ async fn ws(state: State, mut websocket: WebSocket) -> Result<()> {
 }
 
 async fn ws_session(
-    mut session_state: SessionState,
+    session_state: &mut SessionState,
     cs_receiver: UnboundedReceiver<ServerEvent>,
     websocket: WebSocket,
 ) -> Result<()> {
@@ -238,18 +415,30 @@ async fn ws_session(
     // clear in what granularity audio frames and billing records are sent through.
     let (scheduler_sender, scheduler_receiver) = unbounded_channel();
 
-    let (pong_sender, pong_receiver) = channel(4);
+    let (reply_sender, reply_receiver) = channel(4);
+
+    // Playback reports and ducking of the client for the event scheduler.
+    let (feedback_sender, feedback_receiver) = unbounded_channel();
 
     // The event scheduler
-    let scheduler = event_scheduler::event_scheduler(cs_receiver, scheduler_sender);
+    let scheduler = event_scheduler::event_scheduler(
+        cs_receiver,
+        feedback_receiver,
+        scheduler_sender,
+        session_state.state.loudness_target,
+        session_state.state.playback_smoothing,
+        session_state.state.audio_coalescing,
+        session_state.state.pre_buffer,
+    );
     pin!(scheduler);
 
     let dispatcher = dispatch_channel_messages(
         &billing_collector,
         session_state.billing_id.clone(),
-        pong_receiver,
+        reply_receiver,
         scheduler_receiver,
         ws_sender,
+        session_state.state.max_message_size,
     );
     pin!(dispatcher);
 
@@ -264,10 +453,16 @@ async fn ws_session(
                             peer_close_received = true;
                         }
 
-                        session_state.process_request(&pong_sender, msg)?;
+                        session_state.process_request(&reply_sender, &feedback_sender, msg)?;
                     }
-                    Some(Err(r)) => {
-                        bail!(r);
+                    Some(Err(e)) => {
+                        let close_frame =
+                            oversized_message_close_frame(e, session_state.state.max_message_size)?;
+                        reply_sender
+                            .send(Reply::Close(close_frame))
+                            .await
+                            .context("Sending close frame")?;
+                        return dispatcher.await.context("Dispatcher");
                     }
                     None => {
                         if peer_close_received {
@@ -300,6 +495,10 @@ struct SessionState {
     /// The format of the binary messages sent via the websocket from mod_audio_fork.
     input_audio_format: Option<AudioFormat>,
     billing_id: Option<BillingId>,
+    /// The most recent input audio, if pre-roll is configured.
+    pre_roll: Option<AudioRingBuffer>,
+    /// Detects the caller's speech, if ducking is configured.
+    ducking: Option<DuckingDetector>,
 }
 
 impl Drop for SessionState {
@@ -325,6 +524,7 @@ impl Drop for SessionState {
             .expect("Poison error")
             .process(ClientEvent::Stop {
                 id: self.conversation.clone(),
+                drain: false,
             })
         {
             error!("Internal error: Failed to send the final stop event: {e:?}");
@@ -405,6 +605,17 @@ impl SessionState {
 
         let billing_id = billing_id.clone();
 
+        let pre_roll = state
+            .pre_roll
+            .as_ref()
+            .filter(|_| input_audio_format.is_some())
+            .map(|pre_roll| AudioRingBuffer::new(pre_roll.duration));
+
+        let ducking = state
+            .ducking_db
+            .filter(|_| input_audio_format.is_some())
+            .map(DuckingDetector::new);
+
         state
             .context_switch
             .lock()
@@ -419,16 +630,37 @@ impl SessionState {
                 conversation,
                 input_audio_format,
                 billing_id,
+                pre_roll,
+                ducking,
             },
             conversation_span,
             se_receiver,
         ))
     }
 
-    fn process_request(&mut self, pong_sender: &Sender<Pong>, msg: Message) -> Result<()> {
+    fn process_request(
+        &mut self,
+        reply_sender: &Sender<Reply>,
+        feedback_sender: &UnboundedSender<Feedback>,
+        msg: Message,
+    ) -> Result<()> {
         match msg {
             Message::Text(msg) => {
-                let client_event = Self::decode_client_event(msg.as_str())?;
+                let json_value = Self::decode_json_value(msg.as_str())?;
+
+                // Playback reports are handled here and not forwarded to ContextSwitch.
+                if let Ok(PlaybackEvent::PlaybackStatus { pending }) =
+                    serde_json::from_value(json_value.clone())
+                {
+                    debug!("Received playback status, pending: {pending}");
+                    feedback_sender
+                        .send(Feedback::PlaybackPending(pending.into()))
+                        .context("Sending playback status to the event scheduler")?;
+                    return Ok(());
+                }
+
+                let client_event: ClientEvent =
+                    serde_json::from_value(json_value).context("Deserializing client event")?;
                 debug!("Received client event: `{client_event:?}`");
 
                 // Be sure we don't process events for other than the one we got with the first start event.
@@ -458,10 +690,24 @@ impl SessionState {
             }
             Message::Binary(samples) => {
                 if let Some(audio_format) = self.input_audio_format {
-                    let frame = AudioFrame {
-                        format: audio_format,
-                        samples: audio::from_le_bytes(samples),
+                    let frame = match AudioFrame::from_le_bytes_checked(audio_format, &samples) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            // Dropping the frame keeps the sample alignment of the stream intact.
+                            warn!("Ignored malformed binary audio frame: {e}");
+                            return Ok(());
+                        }
                     };
+                    if let Some(pre_roll) = &mut self.pre_roll {
+                        pre_roll.push(frame.clone());
+                    }
+                    if let Some(ducking) = &mut self.ducking
+                        && let Some(gain) = ducking.process(&frame)
+                    {
+                        feedback_sender
+                            .send(Feedback::DuckingGain(gain))
+                            .context("Sending ducking gain to the event scheduler")?;
+                    }
                     self.state
                         .context_switch
                         .lock()
@@ -476,8 +722,8 @@ impl SessionState {
             }
             Message::Ping(payload) => {
                 info!("Received ping message: {payload:02X?}");
-                pong_sender
-                    .try_send(Pong(payload))
+                reply_sender
+                    .try_send(Reply::Pong(payload))
                     .context("Sending pong event")?;
                 Ok(())
             }
@@ -500,9 +746,20 @@ impl SessionState {
         }
     }
 
-    fn decode_client_event(msg: &str) -> Result<ClientEvent> {
-        let json_value = Self::decode_json_value(msg)?;
-        serde_json::from_value(json_value).context("Deserializing client event")
+    /// Writes the retained input audio to the pre-roll directory.
+    fn dump_pre_roll(&mut self) {
+        let (Some(pre_roll), Some(config)) = (&mut self.pre_roll, &self.state.pre_roll) else {
+            return;
+        };
+        let frames = pre_roll.dump();
+        info!("Dumping {} pre-roll frames", frames.len());
+        let filename = format!("{}-pre-roll.wav", self.conversation);
+        // The file is written when the tracer is dropped.
+        let mut tracer =
+            AudioTracer::new(config.dir.join(filename)).with_compression(config.compression);
+        for frame in frames {
+            tracer.capture_frame(frame);
+        }
     }
 
     /// Because the argument parser of mod_audio_fork may ignore JSON with spaces in it, two formats
@@ -520,6 +777,16 @@ impl SessionState {
     }
 }
 
+/// Events a client may send in addition to the client events, which are handled by audio-knife
+/// itself.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum PlaybackEvent {
+    /// The duration of output audio the client has not played back yet, in seconds. Used to
+    /// correct the pacing of the media events.
+    PlaybackStatus { pending: context_switch::Duration },
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct StartEventAuxiliary {
@@ -527,27 +794,58 @@ struct StartEventAuxiliary {
     pub redirect_output_to: Option<ConversationId>,
 }
 
-/// Dispatches outgoing server events and pongs to the socket's sink.
+/// Returns the close frame that rejects an inbound message exceeding the maximum message size.
+///
+/// Other receive errors are returned as is.
+fn oversized_message_close_frame(
+    error: axum::Error,
+    max_message_size: usize,
+) -> Result<CloseFrame> {
+    let too_long = error
+        .source()
+        .and_then(|source| source.downcast_ref::<tungstenite::Error>())
+        .and_then(|error| match error {
+            tungstenite::Error::Capacity(CapacityError::MessageTooLong { size, .. }) => Some(*size),
+            _ => None,
+        });
+    let Some(size) = too_long else {
+        bail!(error);
+    };
+    warn!("Rejected an inbound message of {size} bytes, the maximum is {max_message_size} bytes");
+    Ok(CloseFrame {
+        code: close_code::SIZE,
+        reason: format!("Message exceeds the maximum size of {max_message_size} bytes").into(),
+    })
+}
+
+/// Dispatches outgoing server events and replies to the socket's sink.
 async fn dispatch_channel_messages(
     billing_collector: &Arc<Mutex<BillingCollector>>,
     billing_id: Option<BillingId>,
-    mut pong_receiver: Receiver<Pong>,
+    mut reply_receiver: Receiver<Reply>,
     mut server_event_receiver: UnboundedReceiver<ServerEvent>,
     mut socket: SplitSink<WebSocket, Message>,
+    max_message_size: usize,
 ) -> Result<()> {
+    let mut sequencer = EventSequencer::default();
     loop {
         select! {
-            pong = pong_receiver.recv() => {
-                if let Some(Pong(payload)) = pong {
-                    debug!("Sending pong: {payload:02X?}");
-                    socket.send(Message::Pong(payload)).await?;
-                } else {
-                    bail!("Pong sender vanished");
+            reply = reply_receiver.recv() => {
+                match reply {
+                    Some(Reply::Pong(payload)) => {
+                        debug!("Sending pong: {payload:02X?}");
+                        socket.send(Message::Pong(payload)).await?;
+                    }
+                    Some(Reply::Close(close_frame)) => {
+                        socket.send(Message::Close(Some(close_frame))).await?;
+                        return Ok(());
+                    }
+                    None => bail!("Reply sender vanished"),
                 }
             }
             event = server_event_receiver.recv() => {
                 if let Some(event) = event {
-                    dispatch_server_event(billing_collector, billing_id.as_ref(), &mut socket, event).await?;
+                    dispatch_server_event(billing_collector, billing_id.as_ref(), &mut sequencer, &mut socket, max_message_size, event).await?;
                 } else {
                     bail!("Context switch event sender vanished");
                 }
@@ -559,13 +857,15 @@ async fn dispatch_channel_messages(
 async fn dispatch_server_event(
     billing_collector: &Arc<Mutex<BillingCollector>>,
     billing_id: Option<&BillingId>,
+    sequencer: &mut EventSequencer,
     socket: &mut SplitSink<WebSocket, Message>,
+    max_message_size: usize,
     event: ServerEvent,
 ) -> Result<()> {
     // Everything besides Audio and ClearAudio gets pushed to FreeSWITCH via the json type.
     match event {
         ServerEvent::Audio { samples, .. } => {
-            mod_audio_fork::dispatch_audio(socket, samples.into()).await
+            mod_audio_fork::dispatch_audio(socket, samples.into(), max_message_size).await
         }
         ServerEvent::ClearAudio { .. } => mod_audio_fork::dispatch_kill_audio(socket).await,
         ServerEvent::BillingRecords {
@@ -586,7 +886,7 @@ async fn dispatch_server_event(
                 );
             }
         }
-        event => mod_audio_fork::dispatch_json(socket, event).await,
+        event => mod_audio_fork::dispatch_json(socket, sequencer.sequence(event)).await,
     }
 }
 
@@ -604,26 +904,344 @@ async fn check_health(address: SocketAddr) -> Result<()> {
     Ok(())
 }
 
+/// Response header of a peek that contains the token to confirm the removal of the records.
+const PEEK_TOKEN_HEADER: &str = "billing-peek-token";
+
+#[derive(Deserialize)]
+struct TakeBillingRecordsQuery {
+    #[serde(default)]
+    format: BillingFormat,
+    /// If `false`, the records are only peeked at. Defaults to `true`.
+    consume: Option<bool>,
+}
+
+/// Validates a start event without starting a conversation.
+async fn validate_start(
+    extract::State(state): extract::State<State>,
+    Json(event): Json<ClientEvent>,
+) -> Result<StatusCode, AppError> {
+    state
+        .context_switch
+        .lock()
+        .expect("poisoned lock")
+        .validate_start(&event)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Streams the `Text` events of an active conversation as Server-Sent Events.
+async fn transcript(
+    extract::State(state): extract::State<State>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let receiver = state
+        .server_event_router
+        .lock()
+        .expect("poisoned lock")
+        .subscribe_transcript(&id.into())?;
+
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Event::default().json_data(event), receiver))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 /// Takes billing records by ID
 async fn take_billing_records(
     extract::State(state): extract::State<State>,
     Path(billing_id): Path<String>,
-) -> impl IntoResponse {
+    Query(query): Query<TakeBillingRecordsQuery>,
+) -> Result<Response, AppError> {
     let billing_id = BillingId::from(billing_id);
 
     // Get billing records from the context_switch instance
-    let records = state
+    let (records, peek_token) = {
+        let mut billing_collector = state.billing_collector.lock().expect("poisoned lock");
+        if query.consume.unwrap_or(true) {
+            (billing_collector.collect(&billing_id), None)
+        } else {
+            let (token, records) = billing_collector.peek(&billing_id);
+            (records, Some(token))
+        }
+    };
+
+    match peek_token {
+        None => info!(
+            "Took {} billing records for ID: {}",
+            records.len(),
+            billing_id
+        ),
+        Some(token) => info!(
+            "Peeked at {} billing records for ID: {}, token: {token}",
+            records.len(),
+            billing_id
+        ),
+    }
+
+    // If the billing_id doesn't exist, there are no records to render.
+    let mut response = match query.format {
+        BillingFormat::Json => Json(records).into_response(),
+        BillingFormat::Ndjson => (
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            Body::from_stream(stream::iter(billing_format::ndjson(records))),
+        )
+            .into_response(),
+        BillingFormat::Csv => (
+            [(header::CONTENT_TYPE, "text/csv")],
+            billing_format::csv(&records),
+        )
+            .into_response(),
+        BillingFormat::Summary => Json(BillingCollector::summary(&records)?).into_response(),
+    };
+    if let Some(token) = peek_token {
+        response
+            .headers_mut()
+            .insert(PEEK_TOKEN_HEADER, HeaderValue::from(token));
+    }
+    Ok(response)
+}
+
+/// Confirms the removal of peeked billing records.
+async fn confirm_billing_records(
+    extract::State(state): extract::State<State>,
+    Path((billing_id, token)): Path<(String, PeekToken)>,
+) -> Result<StatusCode, AppError> {
+    let billing_id = BillingId::from(billing_id);
+
+    state
         .billing_collector
         .lock()
         .expect("poisoned lock")
-        .collect(&billing_id);
+        .confirm(&billing_id, token)?;
 
-    info!(
-        "Took {} billing records for ID: {}",
-        records.len(),
-        billing_id
-    );
+    info!("Confirmed peeked billing records for ID: {billing_id}, token: {token}");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use context_switch::BillingRecord;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    use super::*;
+
+    fn state(max_message_size: usize) -> State {
+        let (cs_sender, _cs_receiver) = unbounded_channel();
+        State {
+            billing_collector: Default::default(),
+            context_switch: Arc::new(Mutex::new(ContextSwitch::new(
+                context_switch::registry().into(),
+                cs_sender,
+                None,
+            ))),
+            server_event_router: Default::default(),
+            loudness_target: None,
+            playback_smoothing: event_scheduler::DEFAULT_PLAYBACK_SMOOTHING,
+            audio_coalescing: None,
+            pre_buffer: Duration::ZERO,
+            pre_roll: None,
+            ducking_db: None,
+            max_message_size,
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_rejected_with_a_close_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(state(1024))).into_future());
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+            .await
+            .unwrap();
+        socket
+            .send(tungstenite::Message::Text("x".repeat(2048).into()))
+            .await
+            .unwrap();
+
+        let Some(Ok(tungstenite::Message::Close(Some(close_frame)))) = socket.next().await else {
+            panic!("Expected a close frame");
+        };
+        assert_eq!(close_frame.code, CloseCode::Size);
+        assert_eq!(
+            close_frame.reason,
+            "Message exceeds the maximum size of 1024 bytes"
+        );
+    }
+
+    #[tokio::test]
+    async fn ndjson_billing_records_are_streamed_line_by_line() {
+        let state = state(1024);
+        let billing_id = BillingId::from("call".to_string());
+        {
+            let mut billing_collector = state.billing_collector.lock().unwrap();
+            billing_collector
+                .record(
+                    &billing_id,
+                    "openai-dialog",
+                    Some("agent".into()),
+                    vec![BillingRecord::count("tokens:input:audio", 100)],
+                )
+                .unwrap();
+            billing_collector
+                .record(
+                    &billing_id,
+                    "azure-synthesize",
+                    None,
+                    vec![BillingRecord::duration("audio", Duration::from_secs(2))],
+                )
+                .unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(state)).into_future());
 
-    // Return the records as JSON - if the billing_id doesn't exist, this will be an empty array
-    Json(records).into_response()
+        let response = reqwest::get(format!(
+            "http://{addr}/billing-records/call/take?format=ndjson"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/x-ndjson"
+        );
+        // A streamed body has no precomputed length.
+        assert_eq!(response.content_length(), None);
+
+        let body = response.text().await.unwrap();
+        let mut lines: Vec<(String, serde_json::Value)> = body
+            .lines()
+            .map(|line| {
+                let records: serde_json::Value = serde_json::from_str(line).unwrap();
+                (records["service"].as_str().unwrap().to_owned(), records)
+            })
+            .collect();
+        lines.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            lines,
+            [
+                (
+                    "azure-synthesize".into(),
+                    serde_json::json!({
+                        "service": "azure-synthesize",
+                        "scope": null,
+                        "records": [{ "name": "audio", "duration": 2.0 }],
+                    })
+                ),
+                (
+                    "openai-dialog".into(),
+                    serde_json::json!({
+                        "service": "openai-dialog",
+                        "scope": "agent",
+                        "records": [{ "name": "tokens:input:audio", "count": 100 }],
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn peeked_billing_records_are_removed_by_a_confirming_post() {
+        let state = state(1024);
+        let billing_id = BillingId::from("call".to_string());
+        state
+            .billing_collector
+            .lock()
+            .unwrap()
+            .record(
+                &billing_id,
+                "azure-synthesize",
+                None,
+                vec![BillingRecord::duration("audio", Duration::from_secs(2))],
+            )
+            .unwrap();
+        let billing_collector = state.billing_collector.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(state)).into_future());
+
+        let response = reqwest::get(format!(
+            "http://{addr}/billing-records/call/take?consume=false"
+        ))
+        .await
+        .unwrap();
+        let token = response.headers()[PEEK_TOKEN_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let confirm = format!("http://{addr}/billing-records/call/confirm/{token}");
+
+        // A GET does not confirm, so the token is still pending afterwards.
+        let response = reqwest::get(&confirm).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = reqwest::Client::new().post(&confirm).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(
+            billing_collector
+                .lock()
+                .unwrap()
+                .collect(&billing_id)
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn transcript_streams_text_events_until_the_conversation_stops() {
+        let state = state(1024);
+        let conversation: ConversationId = "c".to_string().into();
+        let (target, _target_receiver) = unbounded_channel();
+        let server_event_router = state.server_event_router.clone();
+        server_event_router
+            .lock()
+            .unwrap()
+            .add_conversation_target(conversation.clone(), target, None)
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router(state)).into_future());
+
+        // The transcript is subscribed to when the response starts.
+        let mut response = reqwest::get(format!("http://{addr}/conversations/c/transcript"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        let text = ServerEvent::Text {
+            id: conversation.clone(),
+            is_final: true,
+            content: "Hello".into(),
+            language: None,
+            speaker: None,
+        };
+        server_event_router
+            .lock()
+            .unwrap()
+            .dispatch(text.clone())
+            .unwrap();
+
+        let chunk = response.chunk().await.unwrap().unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+        let data = chunk
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(data).unwrap(),
+            serde_json::to_value(text).unwrap()
+        );
+
+        server_event_router
+            .lock()
+            .unwrap()
+            .remove_conversation_target(&conversation)
+            .unwrap();
+        assert_eq!(response.chunk().await.unwrap(), None);
+    }
 }
@@ -33,19 +33,28 @@ impl AudioForkEvent {
     }
 }
 
+/// Sends the samples as binary messages of at most `max_message_size` bytes.
 pub async fn dispatch_audio(
     socket: &mut SplitSink<WebSocket, Message>,
     samples: Vec<i16>,
+    max_message_size: usize,
 ) -> Result<()> {
-    // Use the helper function to convert samples to little-endian bytes
-    let audio_data = to_le_bytes(samples);
+    for chunk in audio_chunks(&samples, max_message_size) {
+        // Use the helper function to convert samples to little-endian bytes
+        let audio_data = to_le_bytes(chunk);
 
-    // Send the binary audio data over the WebSocket
-    socket.send(Message::Binary(audio_data.into())).await?;
+        // Send the binary audio data over the WebSocket
+        socket.send(Message::Binary(audio_data.into())).await?;
+    }
 
     Ok(())
 }
 
+/// Splits the samples so that each chunk fits into a message of `max_message_size` bytes.
+fn audio_chunks(samples: &[i16], max_message_size: usize) -> impl Iterator<Item = &[i16]> {
+    samples.chunks((max_message_size / size_of::<i16>()).max(1))
+}
+
 pub async fn dispatch_json(
     socket: &mut SplitSink<WebSocket, Message>,
     value: impl Serialize,
@@ -65,3 +74,19 @@ async fn dispatch_event(
     debug!("Sending json event: {json}");
     Ok(socket.send(Message::Text(json.into())).await?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audio_is_split_into_messages_under_the_limit() {
+        let samples: Vec<i16> = (0..1000).collect();
+        let chunks: Vec<&[i16]> = audio_chunks(&samples, 801).collect();
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+            [400, 400, 200]
+        );
+        assert_eq!(chunks.concat(), samples);
+    }
+}
@@ -2,27 +2,53 @@
 //! conversation targets.
 //!
 //! Conceptually this is similar to a reverse proxy.
-use std::collections::{HashMap, hash_map::Entry};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result, bail};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
 use context_switch::{ConversationId, OutputPath, ServerEvent};
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ServerEventRouter {
     conversation_targets: HashMap<ConversationId, ConversationTarget>,
+    /// Targets of recently removed conversations and when they were removed. They are retained
+    /// for the grace period to absorb events that are in flight while conversations are
+    /// transitioning.
+    removed_targets: HashMap<ConversationId, (Instant, ConversationTarget)>,
+    grace_period: Duration,
+    /// Read-only subscribers to the `Text` events of active conversations.
+    transcript_subscribers: HashMap<ConversationId, Vec<UnboundedSender<ServerEvent>>>,
+}
+
+impl Default for ServerEventRouter {
+    fn default() -> Self {
+        Self {
+            conversation_targets: Default::default(),
+            removed_targets: Default::default(),
+            grace_period: Self::DEFAULT_GRACE_PERIOD,
+            transcript_subscribers: Default::default(),
+        }
+    }
 }
 
 impl ServerEventRouter {
+    pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
     pub fn dispatch(&mut self, mut event: ServerEvent) -> Result<()> {
+        self.expire_removed_targets(Instant::now());
+
         let conversation = event.conversation_id();
+        self.forward_to_transcript_subscribers(&event);
 
-        match self.conversation_targets.get(conversation) {
+        match self.target(conversation) {
             Some(target) => match &target.redirect_output_to {
                 // May redirect if this is an output event.
                 Some(redirect_output) if event.output_path() == OutputPath::Media => {
-                    if let Some(redir_target) = self.conversation_targets.get(redirect_output) {
+                    if let Some(redir_target) = self.target(redirect_output) {
                         event.set_conversation_id(redirect_output.clone());
                         redir_target
                             .target
@@ -42,6 +68,27 @@ impl ServerEventRouter {
         Ok(())
     }
 
+    fn forward_to_transcript_subscribers(&mut self, event: &ServerEvent) {
+        if !matches!(event, ServerEvent::Text { .. }) {
+            return;
+        }
+        if let Some(subscribers) = self.transcript_subscribers.get_mut(event.conversation_id()) {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+
+    fn expire_removed_targets(&mut self, now: Instant) {
+        let grace_period = self.grace_period;
+        self.removed_targets
+            .retain(|_, (removed, _)| now.duration_since(*removed) < grace_period);
+    }
+
+    fn target(&self, conversation: &ConversationId) -> Option<&ConversationTarget> {
+        self.conversation_targets
+            .get(conversation)
+            .or_else(|| self.removed_targets.get(conversation).map(|(_, t)| t))
+    }
+
     pub fn add_conversation_target(
         &mut self,
         conversation: impl Into<ConversationId>,
@@ -53,6 +100,7 @@ impl ServerEventRouter {
                 bail!("Conversation already exists")
             }
             Entry::Vacant(vacant) => {
+                self.removed_targets.remove(vacant.key());
                 vacant.insert(ConversationTarget {
                     target,
                     redirect_output_to,
@@ -64,11 +112,34 @@ impl ServerEventRouter {
     }
 
     pub fn remove_conversation_target(&mut self, conversation: &ConversationId) -> Result<()> {
-        if self.conversation_targets.remove(conversation).is_none() {
+        let Some(target) = self.conversation_targets.remove(conversation) else {
             bail!("Conversation did not exist");
-        }
+        };
+        // Dropping the senders ends the subscribers' streams.
+        self.transcript_subscribers.remove(conversation);
+        let now = Instant::now();
+        self.expire_removed_targets(now);
+        self.removed_targets
+            .insert(conversation.clone(), (now, target));
         Ok(())
     }
+
+    /// Subscribes to the `Text` events of an active conversation. The returned receiver ends when
+    /// the conversation is removed.
+    pub fn subscribe_transcript(
+        &mut self,
+        conversation: &ConversationId,
+    ) -> Result<UnboundedReceiver<ServerEvent>> {
+        if !self.conversation_targets.contains_key(conversation) {
+            bail!("Conversation does not exist: {conversation}");
+        }
+        let (sender, receiver) = unbounded_channel();
+        self.transcript_subscribers
+            .entry(conversation.clone())
+            .or_default()
+            .push(sender);
+        Ok(receiver)
+    }
 }
 
 #[derive(Debug)]
@@ -76,3 +147,96 @@ struct ConversationTarget {
     target: UnboundedSender<ServerEvent>,
     redirect_output_to: Option<ConversationId>,
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::error::TryRecvError;
+
+    use super::*;
+
+    fn text(id: &ConversationId) -> ServerEvent {
+        ServerEvent::Text {
+            id: id.clone(),
+            is_final: true,
+            content: "in flight".into(),
+            language: None,
+            speaker: None,
+        }
+    }
+
+    #[test]
+    fn events_are_delivered_within_the_grace_period_after_removal() {
+        let mut router = ServerEventRouter::default();
+        let conversation: ConversationId = "c".to_string().into();
+        let (sender, mut receiver) = unbounded_channel();
+        router
+            .add_conversation_target(conversation.clone(), sender, None)
+            .unwrap();
+
+        router.remove_conversation_target(&conversation).unwrap();
+        router.dispatch(text(&conversation)).unwrap();
+
+        assert!(matches!(receiver.try_recv(), Ok(ServerEvent::Text { .. })));
+    }
+
+    #[test]
+    fn events_are_not_delivered_after_the_grace_period() {
+        let mut router = ServerEventRouter {
+            grace_period: Duration::ZERO,
+            ..Default::default()
+        };
+        let conversation: ConversationId = "c".to_string().into();
+        let (sender, mut receiver) = unbounded_channel();
+        router
+            .add_conversation_target(conversation.clone(), sender, None)
+            .unwrap();
+
+        router.remove_conversation_target(&conversation).unwrap();
+        assert!(router.dispatch(text(&conversation)).is_err());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn transcript_subscribers_receive_text_events_only() {
+        let mut router = ServerEventRouter::default();
+        let conversation: ConversationId = "c".to_string().into();
+        let (sender, mut receiver) = unbounded_channel();
+        router
+            .add_conversation_target(conversation.clone(), sender, None)
+            .unwrap();
+        let mut transcript = router.subscribe_transcript(&conversation).unwrap();
+
+        router
+            .dispatch(ServerEvent::Started {
+                id: conversation.clone(),
+                modalities: Vec::new(),
+            })
+            .unwrap();
+        router.dispatch(text(&conversation)).unwrap();
+
+        assert!(matches!(
+            transcript.try_recv(),
+            Ok(ServerEvent::Text { .. })
+        ));
+        assert!(transcript.try_recv().is_err());
+        // The conversation target still receives all events.
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ServerEvent::Started { .. })
+        ));
+        assert!(matches!(receiver.try_recv(), Ok(ServerEvent::Text { .. })));
+
+        router.remove_conversation_target(&conversation).unwrap();
+        assert!(matches!(
+            transcript.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn subscribing_to_an_unknown_conversation_fails() {
+        let mut router = ServerEventRouter::default();
+        let conversation: ConversationId = "c".to_string().into();
+        assert!(router.subscribe_transcript(&conversation).is_err());
+    }
+}
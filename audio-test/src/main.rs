@@ -1,14 +1,21 @@
 //! Initial version done by Claude 3.7
 use std::{
+    borrow::Cow,
     collections::HashSet,
     fs::File,
-    io::BufReader,
+    io::{self, BufReader, Write},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
-    time::Instant,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, bail};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use context_switch_core::{AudioFormat, AudioFrame};
 use indicatif::{ProgressBar, ProgressStyle};
 
@@ -28,6 +35,14 @@ struct Args {
     #[arg(short, long, default_value = "16000")]
     sample_rate: u32,
 
+    /// Process each file at all of these sample rates instead (8000,16000,...)
+    #[arg(long, num_args=1.., value_delimiter = ',')]
+    rates: Option<Vec<u32>>,
+
+    /// Write a per-file and per-rate report to stdout
+    #[arg(long)]
+    report: Option<Report>,
+
     /// Only check files with these extensions (.mp3, .wav, etc.)
     #[arg(short, long, num_args=1.., value_delimiter = ',')]
     extensions: Option<Vec<String>>,
@@ -36,39 +51,72 @@ struct Args {
     #[arg(short, long)]
     list_only: bool,
 
+    /// Number of files to process in parallel
+    #[arg(short, long, default_value = "1")]
+    jobs: NonZeroUsize,
+
     /// Verbose output with detailed debug information
     #[arg(short, long)]
     verbose: bool,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Report {
+    /// Columns: `file`, `sample_rate`, `frames`, `audio_duration_ms`, `processing_time_ms`,
+    /// `result`
+    Csv,
+}
+
+/// The result of processing one file at one sample rate.
+#[derive(Debug)]
+struct FileResult {
+    /// The path relative to the processed directory.
+    file: PathBuf,
+    sample_rate: u32,
+    frames: usize,
+    audio_duration: Duration,
+    processing_time: Duration,
+    passed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Summary {
+    total_files: usize,
+    successful_files: usize,
+    failed_files: usize,
+}
+
 fn main() -> Result<()> {
-    // Initialize logging with more verbose output
+    // Initialize logging with more verbose output. Logs go to stderr, so that the report can be
+    // written to stdout.
     tracing_subscriber::fmt()
         .with_max_level(if std::env::var("RUST_LOG").is_ok() {
             tracing::Level::TRACE
         } else {
             tracing::Level::INFO
         })
+        .with_writer(io::stderr)
         .init();
 
     // Parse command-line arguments
     let args = Args::parse();
 
+    run(args, &mut io::stdout())?;
+    Ok(())
+}
+
+fn run(args: Args, report_output: &mut impl Write) -> Result<Summary> {
     // Check if path exists
     if !args.path.exists() {
         bail!("Path does not exist: {}", args.path.display());
     }
 
-    // Create output format
-    let output_format = AudioFormat {
-        sample_rate: args.sample_rate,
-        channels: 1,
-    };
+    let sample_rates = args.rates.unwrap_or_else(|| vec![args.sample_rate]);
 
     // Common audio extensions if none provided
     let default_extensions = [
-        "wav", "mp3",
-        // "flac", "ogg", "m4a",
+        "wav", "mp3", "ogg",
+        // "flac", "m4a",
         // "aac", "aiff", "wma", "opus", "amr",
     ];
 
@@ -110,15 +158,20 @@ fn main() -> Result<()> {
         }
     }
 
+    // Sort to make the processing order and the report deterministic.
+    audio_files.sort();
+
     // Walk directory
     let total_files = audio_files.len();
-    let mut successful_files = 0;
-    let mut failed_files = 0;
 
     info!("Walking directory: {}", args.path.display());
     info!(
-        "Output format: sample rate {}Hz, {} channel(s)",
-        output_format.sample_rate, output_format.channels
+        "Output format: sample rate(s) {}Hz, 1 channel(s)",
+        sample_rates
+            .iter()
+            .map(|rate| rate.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
     );
     info!(
         "Found {} audio files with extensions: {}",
@@ -135,11 +188,15 @@ fn main() -> Result<()> {
             println!("{}", path.display());
         }
         info!("Listed {} files", total_files);
-        return Ok(());
+        return Ok(Summary {
+            total_files,
+            successful_files: 0,
+            failed_files: 0,
+        });
     }
 
     // Create progress bar
-    let progress = ProgressBar::new(total_files as u64);
+    let progress = ProgressBar::new((total_files * sample_rates.len()) as u64);
     progress.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
@@ -147,52 +204,36 @@ fn main() -> Result<()> {
             .progress_chars("#>-")
     );
 
-    // Process each file
-    for path in audio_files {
+    // Process each file at each rate
+    let work: Vec<(&Path, u32)> = audio_files
+        .iter()
+        .flat_map(|path| sample_rates.iter().map(move |&rate| (path.as_path(), rate)))
+        .collect();
+    let results = process_in_parallel(&work, args.jobs, |(path, sample_rate)| {
         let file_name = path
             .file_name()
             .map(|name| name.to_string_lossy().to_string())
             .unwrap_or_else(|| "unknown".to_string());
+        progress.set_message(format!("Processing {file_name} at {sample_rate}Hz"));
+        let result = process_file_at_rate(&args.path, path, sample_rate, args.verbose);
+        progress.inc(1);
+        result
+    });
 
-        progress.set_message(format!("Processing {file_name}"));
-
-        let start_time = Instant::now();
-        match process_audio_file(&path, output_format) {
-            Ok(frames) => {
-                let duration_ms = start_time.elapsed().as_millis();
-                let frame_count = frames.len();
-                let audio_duration_ms = if !frames.is_empty() {
-                    let samples = frames.iter().map(|f| f.samples.len()).sum::<usize>();
-                    (samples as f64 / output_format.sample_rate as f64) * 1000.0
-                } else {
-                    0.0
-                };
-
-                successful_files += 1;
-
-                if args.verbose {
-                    info!(
-                        "✅ {} - {} frames ({:.2}ms audio) processed in {:.2}ms",
-                        path.display(),
-                        frame_count,
-                        audio_duration_ms,
-                        duration_ms
-                    );
-                }
-            }
-            Err(e) => {
-                failed_files += 1;
+    progress.finish_with_message("Processing complete");
 
-                // Always show errors regardless of verbose mode
-                error!("❌ {} - Error: {}", path.display(), e);
-            }
-        }
+    // Results are in work order, so all rates of a file are adjacent.
+    let successful_files = results
+        .chunks(sample_rates.len())
+        .filter(|file_results| file_results.iter().all(|result| result.passed))
+        .count();
+    let failed_files = total_files - successful_files;
 
-        progress.inc(1);
+    match args.report {
+        Some(Report::Csv) => report_output.write_all(csv_report(&results).as_bytes())?,
+        None => {}
     }
 
-    progress.finish_with_message("Processing complete");
-
     // Print summary
     info!("Summary:");
     info!("  Total files: {}", total_files);
@@ -206,7 +247,117 @@ fn main() -> Result<()> {
         );
     }
 
-    Ok(())
+    Ok(Summary {
+        total_files,
+        successful_files,
+        failed_files,
+    })
+}
+
+/// Process the work items on up to `jobs` threads. The results are returned in the order of the
+/// work items.
+fn process_in_parallel<T: Copy + Sync, R: Send>(
+    work: &[T],
+    jobs: NonZeroUsize,
+    process: impl Fn(T) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(work.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.get().min(work.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&item) = work.get(index) else {
+                        break;
+                    };
+                    let result = process(item);
+                    results.lock().unwrap().push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn process_file_at_rate(root: &Path, path: &Path, sample_rate: u32, verbose: bool) -> FileResult {
+    let output_format = AudioFormat {
+        sample_rate,
+        channels: 1,
+    };
+
+    let start_time = Instant::now();
+    let frames = process_audio_file(path, output_format);
+    let processing_time = start_time.elapsed();
+    let file = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+
+    match frames {
+        Ok(frames) => {
+            let audio_duration: Duration = frames.iter().map(|frame| frame.duration()).sum();
+
+            if verbose {
+                info!(
+                    "✅ {} - {} frames ({:.2}ms audio at {}Hz) processed in {:.2}ms",
+                    path.display(),
+                    frames.len(),
+                    audio_duration.as_secs_f64() * 1000.0,
+                    sample_rate,
+                    processing_time.as_secs_f64() * 1000.0
+                );
+            }
+
+            FileResult {
+                file,
+                sample_rate,
+                frames: frames.len(),
+                audio_duration,
+                processing_time,
+                passed: true,
+            }
+        }
+        Err(e) => {
+            // Always show errors regardless of verbose mode
+            error!("❌ {} at {}Hz - Error: {}", path.display(), sample_rate, e);
+
+            FileResult {
+                file,
+                sample_rate,
+                frames: 0,
+                audio_duration: Duration::ZERO,
+                processing_time,
+                passed: false,
+            }
+        }
+    }
+}
+
+fn csv_report(results: &[FileResult]) -> String {
+    let mut csv =
+        String::from("file,sample_rate,frames,audio_duration_ms,processing_time_ms,result\n");
+    for result in results {
+        csv.push_str(&format!(
+            "{},{},{},{:.2},{:.2},{}\n",
+            csv_field(&result.file.to_string_lossy()),
+            result.sample_rate,
+            result.frames,
+            result.audio_duration.as_secs_f64() * 1000.0,
+            result.processing_time.as_secs_f64() * 1000.0,
+            if result.passed { "pass" } else { "fail" }
+        ));
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
 }
 
 /// Process a single audio file and return frames
@@ -217,6 +368,160 @@ fn process_audio_file(path: &Path, format: AudioFormat) -> Result<Vec<AudioFrame
 
     check_supported_audio_type(&path.to_string_lossy(), None)?;
 
-    read_to_frames(reader, format)
+    read_to_frames(reader, format, false)
         .with_context(|| format!("Failed to process audio: {}", path.display()))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn csv_report_has_one_row_per_file_and_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        // 200ms of audio at 16kHz.
+        fs::write(dir.path().join("speech.wav"), pcm_wav(16000, &[0; 3200])).unwrap();
+        fs::write(dir.path().join("broken.wav"), b"not a wav file").unwrap();
+        fs::write(dir.path().join("notes.txt"), b"ignored").unwrap();
+
+        let args = Args::parse_from([
+            "audio-test",
+            "--path",
+            &dir.path().to_string_lossy(),
+            "--rates",
+            "8000,16000",
+            "--report",
+            "csv",
+        ]);
+        let mut report = Vec::new();
+        let summary = run(args, &mut report).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                total_files: 2,
+                successful_files: 1,
+                failed_files: 1,
+            }
+        );
+        let report = String::from_utf8(report).unwrap();
+
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert_eq!(
+            rows[0],
+            [
+                "file",
+                "sample_rate",
+                "frames",
+                "audio_duration_ms",
+                "processing_time_ms",
+                "result"
+            ]
+        );
+        // Processing time is not deterministic and resampling may not preserve the exact sample
+        // count, so only file, rate, and result are compared for all rows.
+        let results: Vec<[&str; 3]> = rows[1..]
+            .iter()
+            .map(|row| [row[0], row[1], row[5]])
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ["broken.wav", "8000", "fail"],
+                ["broken.wav", "16000", "fail"],
+                ["speech.wav", "8000", "pass"],
+                ["speech.wav", "16000", "pass"],
+            ]
+        );
+        assert_eq!(rows[1][2..4], ["0", "0.00"]);
+        assert_eq!(rows[4][2..4], ["2", "200.00"]);
+    }
+
+    #[test]
+    fn parallel_processing_produces_the_same_summary_and_report() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..6 {
+            fs::write(
+                dir.path().join(format!("speech-{i}.wav")),
+                pcm_wav(16000, &vec![0; 1600 * (i + 1)]),
+            )
+            .unwrap();
+        }
+        for i in 0..3 {
+            fs::write(dir.path().join(format!("broken-{i}.wav")), b"not a wav").unwrap();
+        }
+
+        let run_with_jobs = |jobs: &str| {
+            let args = Args::parse_from([
+                "audio-test",
+                "--path",
+                &dir.path().to_string_lossy(),
+                "--rates",
+                "8000,16000",
+                "--report",
+                "csv",
+                "--jobs",
+                jobs,
+            ]);
+            let mut report = Vec::new();
+            let summary = run(args, &mut report).unwrap();
+            // Drop the non-deterministic processing time.
+            let rows: Vec<String> = String::from_utf8(report)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let columns: Vec<&str> = line.split(',').collect();
+                    [&columns[..4], &columns[5..]].concat().join(",")
+                })
+                .collect();
+            (summary, rows)
+        };
+
+        let (sequential_summary, sequential_rows) = run_with_jobs("1");
+        let (parallel_summary, parallel_rows) = run_with_jobs("4");
+
+        assert_eq!(
+            sequential_summary,
+            Summary {
+                total_files: 9,
+                successful_files: 6,
+                failed_files: 3,
+            }
+        );
+        assert_eq!(parallel_summary, sequential_summary);
+        assert_eq!(parallel_rows, sequential_rows);
+    }
+
+    fn pcm_wav(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+        let channel_count = 1u16;
+        let bits_per_sample = 16u16;
+        let bytes_per_sample = bits_per_sample / 8;
+        let data_len = samples.len() as u32 * bytes_per_sample as u32;
+        let chunk_len = 36 + data_len;
+        let byte_rate = sample_rate * channel_count as u32 * bytes_per_sample as u32;
+        let block_align = channel_count * bytes_per_sample;
+
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&chunk_len.to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&channel_count.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&byte_rate.to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&bits_per_sample.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+}
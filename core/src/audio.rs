@@ -1,3 +1,5 @@
+use std::f64::consts::PI;
+
 pub fn into_i16(audio: impl AsRef<[f32]>) -> Vec<i16> {
     audio
         .as_ref()
@@ -6,6 +8,38 @@ pub fn into_i16(audio: impl AsRef<[f32]>) -> Vec<i16> {
         .collect()
 }
 
+/// Converts normalized samples to `i16` with dither of a triangular probability density.
+///
+/// Without dither, the quantization error of low-level signals follows the signal and is heard as
+/// distortion. Adding noise of up to one least significant bit before rounding turns it into a
+/// constant noise floor. Use this when downconverting sources with more than 16 bits.
+pub fn to_i16_dithered(audio: impl AsRef<[f32]>) -> Vec<i16> {
+    let mut noise = DitherNoise(0x2545_f491);
+    audio
+        .as_ref()
+        .iter()
+        .map(|sample| {
+            let dither = noise.next_unit() - noise.next_unit();
+            (sample * i16::MAX as f32 + dither)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        })
+        .collect()
+}
+
+/// A xorshift generator, which is random enough for dither.
+struct DitherNoise(u32);
+
+impl DitherNoise {
+    /// Returns a value in `0.0..1.0`.
+    fn next_unit(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
 pub fn from_i16(audio: impl AsRef<[i16]>) -> Vec<f32> {
     const I16_MAX: f32 = i16::MAX as f32;
     audio
@@ -15,6 +49,146 @@ pub fn from_i16(audio: impl AsRef<[i16]>) -> Vec<f32> {
         .collect()
 }
 
+/// Samples below this magnitude pass the soft limiter unchanged.
+const SOFT_LIMIT_THRESHOLD: f32 = 0.8;
+
+/// Limits a normalized sample to below full scale without hard clipping.
+///
+/// Magnitudes above the threshold are compressed smoothly into the remaining headroom, so that
+/// gain or mixing stages that exceed full scale do not produce flat-topped waveforms.
+pub fn soft_limit(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_LIMIT_THRESHOLD {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_LIMIT_THRESHOLD;
+    let limited =
+        SOFT_LIMIT_THRESHOLD + headroom * ((magnitude - SOFT_LIMIT_THRESHOLD) / headroom).tanh();
+    limited.copysign(sample)
+}
+
+/// The number of zero crossings of the sinc on each side of the resampling filter.
+const RESAMPLE_ZERO_CROSSINGS: f64 = 16.0;
+/// The filter cutoff relative to the lower Nyquist frequency of both rates, leaving room for the
+/// transition band.
+const RESAMPLE_CUTOFF: f64 = 0.95;
+
+/// Resamples mono audio with a Blackman windowed-sinc filter.
+///
+/// When downsampling, frequencies above the target's Nyquist frequency are removed instead of
+/// being aliased into the audible range.
+pub fn resample(samples: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let filter = ResampleFilter::new(from, to);
+    let len = (samples.len() as f64 * filter.ratio).round() as usize;
+    (0..len)
+        .map(|i| filter.sample(samples, 0, i as f64 / filter.ratio))
+        .collect()
+}
+
+/// Resamples mono audio that arrives in chunks with the filter of [`resample`].
+///
+/// Each output sample is computed as soon as the input on both sides of it is available, so the
+/// output is delayed by half the filter's width, about 2ms. Unlike resampling each chunk on its
+/// own, there are no artifacts at the chunk boundaries.
+#[derive(Debug)]
+pub struct StreamResampler {
+    filter: ResampleFilter,
+    /// The input samples still needed for the following output.
+    input: Vec<i16>,
+    /// The index of the first sample of `input` in the whole input.
+    input_offset: usize,
+    /// The index of the next output sample.
+    output_index: usize,
+}
+
+impl StreamResampler {
+    pub fn new(from: u32, to: u32) -> Self {
+        Self {
+            filter: ResampleFilter::new(from, to),
+            input: Vec::new(),
+            input_offset: 0,
+            output_index: 0,
+        }
+    }
+
+    /// Feeds the next chunk and returns the output samples that can be computed so far.
+    pub fn process(&mut self, samples: &[i16]) -> Vec<i16> {
+        self.input.extend_from_slice(samples);
+        let input_end = (self.input_offset + self.input.len()) as f64;
+        let mut output = Vec::new();
+        loop {
+            let position = self.output_index as f64 / self.filter.ratio;
+            if position + self.filter.half_width >= input_end {
+                break;
+            }
+            output.push(self.filter.sample(&self.input, self.input_offset, position));
+            self.output_index += 1;
+        }
+
+        let position = self.output_index as f64 / self.filter.ratio;
+        let needed = (position - self.filter.half_width).ceil().max(0.0) as usize;
+        let consumed = needed
+            .saturating_sub(self.input_offset)
+            .min(self.input.len());
+        self.input.drain(..consumed);
+        self.input_offset += consumed;
+        output
+    }
+}
+
+#[derive(Debug)]
+struct ResampleFilter {
+    ratio: f64,
+    /// Normalized to the Nyquist frequency of the input.
+    cutoff: f64,
+    /// In input samples.
+    half_width: f64,
+}
+
+impl ResampleFilter {
+    fn new(from: u32, to: u32) -> Self {
+        let ratio = to as f64 / from as f64;
+        let cutoff = ratio.min(1.0) * RESAMPLE_CUTOFF;
+        Self {
+            ratio,
+            cutoff,
+            half_width: RESAMPLE_ZERO_CROSSINGS / cutoff,
+        }
+    }
+
+    /// The output sample at `position` of the input. `samples` start at `offset` of the input.
+    fn sample(&self, samples: &[i16], offset: usize, position: f64) -> i16 {
+        let first = (position - self.half_width).ceil().max(offset as f64) as usize;
+        let last = ((position + self.half_width).floor() as usize).min(offset + samples.len() - 1);
+        let sum: f64 = (first..=last)
+            .map(|j| {
+                let distance = position - j as f64;
+                let weight = self.cutoff
+                    * sinc(self.cutoff * distance)
+                    * blackman(distance / self.half_width);
+                samples[j - offset] as f64 * weight
+            })
+            .sum();
+        sum.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let x = PI * x;
+    x.sin() / x
+}
+
+/// The Blackman window over `-1.0..=1.0`.
+fn blackman(t: f64) -> f64 {
+    0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos()
+}
+
 pub fn to_le_bytes(audio: impl AsRef<[i16]>) -> Vec<u8> {
     let audio = audio.as_ref();
     let mut result = Vec::with_capacity(audio.len() * 2);
@@ -43,3 +217,184 @@ pub fn chunk_8192(audio: Vec<u8>) -> Vec<Vec<u8>> {
         .map(|chunk| chunk.to_vec())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+    use crate::{AudioFormat, AudioFrame};
+
+    /// A linear sweep from 100 Hz to 20 kHz over one second at 48 kHz.
+    fn sweep() -> Vec<i16> {
+        const SAMPLE_RATE: f64 = 48000.0;
+        (0..SAMPLE_RATE as usize)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE;
+                let phase = 2.0 * PI * (100.0 * t + (20000.0 - 100.0) * t * t / 2.0);
+                (0.5 * phase.sin() * i16::MAX as f64) as i16
+            })
+            .collect()
+    }
+
+    /// The mean square of the samples output while the sweep passes `from_hz..to_hz`, relative to
+    /// full scale.
+    fn energy(samples: &[i16], from_hz: f64, to_hz: f64) -> f64 {
+        let time = |hz: f64| (hz - 100.0) / (20000.0 - 100.0);
+        let range = (time(from_hz) * samples.len() as f64) as usize
+            ..(time(to_hz) * samples.len() as f64) as usize;
+        let sum: f64 = samples[range.clone()]
+            .iter()
+            .map(|&sample| (sample as f64 / i16::MAX as f64).powi(2))
+            .sum();
+        sum / range.len() as f64
+    }
+
+    #[test]
+    fn downsampling_a_sweep_removes_instead_of_aliasing_high_frequencies() {
+        let sweep = sweep();
+        let resampled = resample(&sweep, 48000, 8000);
+        let linear = AudioFrame {
+            format: AudioFormat::new(1, 48000),
+            samples: sweep,
+        }
+        .convert_to(AudioFormat::new(1, 8000))
+        .unwrap()
+        .samples;
+        assert_eq!(resampled.len(), 8000);
+        assert_eq!(linear.len(), 8000);
+
+        // A sine with an amplitude of 0.5.
+        const SINE_ENERGY: f64 = 0.125;
+        // The pass band keeps its energy in both.
+        for samples in [&resampled, &linear] {
+            let pass_band = energy(samples, 500.0, 3000.0);
+            assert!((pass_band / SINE_ENERGY - 1.0).abs() < 0.05, "{pass_band}");
+        }
+        // Above the Nyquist frequency, the linear path folds the sweep back, the filter rolls it
+        // off.
+        let stop_band = energy(&resampled, 5000.0, 19000.0);
+        assert!(stop_band < SINE_ENERGY * 1e-4, "{stop_band}");
+        let aliased = energy(&linear, 5000.0, 19000.0);
+        assert!(aliased > SINE_ENERGY * 0.5, "{aliased}");
+    }
+
+    #[test]
+    fn upsampling_keeps_a_sine() {
+        let sine: Vec<i16> = (0..8000)
+            .map(|i| ((TAU * 440.0 * i as f32 / 8000.0).sin() * 16000.0) as i16)
+            .collect();
+        let resampled = resample(&sine, 8000, 16000);
+        assert_eq!(resampled.len(), 16000);
+        // Away from the edges, the upsampled sine matches the ideal one.
+        for (i, &sample) in resampled.iter().enumerate().skip(500).take(15000) {
+            let expected = (TAU * 440.0 * i as f32 / 16000.0).sin() * 16000.0;
+            assert!(
+                (sample as f32 - expected).abs() < 50.0,
+                "{i}: {sample} {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn resampling_a_stream_matches_resampling_at_once() {
+        let sweep = sweep();
+        for (from, to) in [(48000, 8000), (8000, 24000)] {
+            let sweep = &sweep[..from as usize / 2];
+            let at_once = resample(sweep, from, to);
+            let mut resampler = StreamResampler::new(from, to);
+            let streamed: Vec<i16> = sweep
+                .chunks(from as usize / 50)
+                .flat_map(|chunk| resampler.process(chunk))
+                .collect();
+            // Only the output within half the filter's width of the end is still missing.
+            let missing = at_once.len() - streamed.len();
+            assert!(missing <= to as usize / 400, "{missing}");
+            assert_eq!(streamed, at_once[..streamed.len()]);
+        }
+    }
+
+    /// The power of the `hz` component relative to a full scale sine.
+    fn tone_power(samples: &[i16], hz: f64, sample_rate: f64) -> f64 {
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (i, &sample)| {
+                let phase = 2.0 * PI * hz * i as f64 / sample_rate;
+                let sample = sample as f64 / i16::MAX as f64;
+                (re + sample * phase.cos(), im - sample * phase.sin())
+            });
+        let amplitude = 2.0 * (re * re + im * im).sqrt() / samples.len() as f64;
+        amplitude * amplitude
+    }
+
+    #[test]
+    fn dither_removes_the_distortion_of_a_low_level_sine() {
+        const SAMPLE_RATE: f64 = 48000.0;
+        // A sine of 1.5 least significant bits, as decoded from a 24 bit source.
+        let sine: Vec<f32> = (0..SAMPLE_RATE as usize)
+            .map(|i| {
+                (1.5 / i16::MAX as f64 * (2.0 * PI * 1000.0 * i as f64 / SAMPLE_RATE).sin()) as f32
+            })
+            .collect();
+
+        let plain = into_i16(&sine);
+        let dithered = to_i16_dithered(&sine);
+
+        // The level of the sine is kept.
+        let level = tone_power(&dithered, 1000.0, SAMPLE_RATE) / (1.5 / i16::MAX as f64).powi(2);
+        assert!((0.9..1.1).contains(&level), "{level}");
+        // Without dither, the quantization error is concentrated in the harmonics. With dither, it
+        // is spread into a noise floor far below them.
+        let harmonics = |samples: &[i16]| -> f64 {
+            (2..10)
+                .map(|harmonic| tone_power(samples, 1000.0 * harmonic as f64, SAMPLE_RATE))
+                .sum()
+        };
+        let plain_harmonics = harmonics(&plain);
+        let dithered_harmonics = harmonics(&dithered);
+        assert!(
+            dithered_harmonics < plain_harmonics / 10.0,
+            "{dithered_harmonics} vs {plain_harmonics}"
+        );
+    }
+
+    #[test]
+    fn soft_limit_keeps_an_over_unity_mix_below_full_scale() {
+        const SAMPLE_RATE: f32 = 16000.0;
+        let mixed: Vec<f32> = (0..1600)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE;
+                0.7 * (TAU * 440.0 * t).sin() + 0.7 * (TAU * 660.0 * t).sin()
+            })
+            .collect();
+        assert!(mixed.iter().any(|sample| sample.abs() > 1.2));
+
+        let limited: Vec<f32> = mixed.iter().copied().map(soft_limit).collect();
+
+        for (&input, &output) in mixed.iter().zip(&limited) {
+            assert!(output.abs() < 1.0, "{input} was limited to {output}");
+            if input.abs() <= SOFT_LIMIT_THRESHOLD {
+                assert_eq!(input, output);
+            }
+        }
+        assert!(
+            into_i16(&limited)
+                .iter()
+                .all(|sample| *sample > -i16::MAX && *sample < i16::MAX)
+        );
+
+        // Unlike clamping, the limiter never steepens the waveform and keeps the peaks rounded
+        // instead of flat.
+        let steps = |samples: &[f32]| -> Vec<f32> {
+            samples
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .collect()
+        };
+        for (input_step, output_step) in steps(&mixed).into_iter().zip(steps(&limited)) {
+            assert!(output_step <= input_step + f32::EPSILON);
+        }
+        assert!(limited.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,102 @@
+//! A rolling buffer of the most recent audio.
+//!
+//! Retains the frames of a fixed duration of audio, for example to capture what the caller said
+//! just before an error occurred. Memory is bounded by the duration: Whenever the buffered audio
+//! exceeds it, the oldest frames are evicted.
+use std::{collections::VecDeque, mem, time};
+
+use crate::AudioFrame;
+
+#[derive(Debug)]
+pub struct AudioRingBuffer {
+    capacity: time::Duration,
+    frames: VecDeque<AudioFrame>,
+    /// The total duration of the buffered frames.
+    duration: time::Duration,
+}
+
+impl AudioRingBuffer {
+    pub fn new(capacity: time::Duration) -> Self {
+        Self {
+            capacity,
+            frames: VecDeque::new(),
+            duration: time::Duration::ZERO,
+        }
+    }
+
+    /// Appends a frame and evicts the oldest frames until the buffered audio fits into the
+    /// capacity. A frame that is longer than the capacity is not retained.
+    pub fn push(&mut self, frame: AudioFrame) {
+        self.duration += frame.duration();
+        self.frames.push_back(frame);
+        while self.duration > self.capacity
+            && let Some(oldest) = self.frames.pop_front()
+        {
+            self.duration -= oldest.duration();
+        }
+    }
+
+    /// The total duration of the buffered audio.
+    pub fn duration(&self) -> time::Duration {
+        self.duration
+    }
+
+    /// Removes and returns all buffered frames, oldest first.
+    pub fn dump(&mut self) -> Vec<AudioFrame> {
+        self.duration = time::Duration::ZERO;
+        mem::take(&mut self.frames).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::AudioFormat;
+
+    /// A 10ms frame with all samples set to `value`.
+    fn frame(value: i16) -> AudioFrame {
+        AudioFrame {
+            format: AudioFormat::new(1, 16000),
+            samples: vec![value; 160],
+        }
+    }
+
+    fn values(frames: &[AudioFrame]) -> Vec<i16> {
+        frames.iter().map(|frame| frame.samples[0]).collect()
+    }
+
+    #[test]
+    fn oldest_frames_are_evicted_beyond_the_capacity() {
+        let mut buffer = AudioRingBuffer::new(Duration::from_millis(30));
+        for value in 0..3 {
+            buffer.push(frame(value));
+        }
+        assert_eq!(buffer.duration(), Duration::from_millis(30));
+
+        buffer.push(frame(3));
+        buffer.push(frame(4));
+        assert_eq!(buffer.duration(), Duration::from_millis(30));
+        assert_eq!(values(&buffer.dump()), [2, 3, 4]);
+    }
+
+    #[test]
+    fn dump_returns_the_frames_in_order_and_empties_the_buffer() {
+        let mut buffer = AudioRingBuffer::new(Duration::from_secs(1));
+        for value in 0..5 {
+            buffer.push(frame(value));
+        }
+        assert_eq!(values(&buffer.dump()), [0, 1, 2, 3, 4]);
+        assert_eq!(buffer.duration(), Duration::ZERO);
+        assert!(buffer.dump().is_empty());
+    }
+
+    #[test]
+    fn frame_longer_than_the_capacity_is_not_retained() {
+        let mut buffer = AudioRingBuffer::new(Duration::from_millis(5));
+        buffer.push(frame(0));
+        assert_eq!(buffer.duration(), Duration::ZERO);
+        assert!(buffer.dump().is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+//! Barge-in detection for services that don't learn about interruptions from their provider.
+//!
+//! While a service outputs audio, the energy of the input audio is followed with the envelope of
+//! the speech gate. When it stays above the threshold long enough, the caller barged in and the
+//! service should clear its audio output and cancel what it is generating.
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AudioFrame, speech_gate::Envelope};
+
+/// How quickly the envelope follows rising input levels.
+const ATTACK_MS: f32 = 5.0;
+/// How quickly the envelope follows falling input levels.
+const RELEASE_MS: f32 = 50.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BargeInParams {
+    /// The normalized RMS level (0.0 to 1.0) above which the input is considered speech.
+    pub threshold: f32,
+    /// How long the input must stay above the threshold while audio is output.
+    pub min_duration_ms: u64,
+}
+
+#[derive(Debug)]
+pub struct BargeInState {
+    threshold: f32,
+    min_duration: Duration,
+    envelope: Envelope,
+    output_active: bool,
+    /// How long the input has been above the threshold while audio was output.
+    speech: Duration,
+}
+
+impl BargeInState {
+    pub fn new(params: &BargeInParams) -> Self {
+        Self {
+            threshold: params.threshold,
+            min_duration: Duration::from_millis(params.min_duration_ms),
+            envelope: Envelope::new(ATTACK_MS, RELEASE_MS),
+            output_active: false,
+            speech: Duration::ZERO,
+        }
+    }
+
+    /// Barge-ins are detected only while the service outputs audio.
+    pub fn set_output_active(&mut self, active: bool) {
+        self.output_active = active;
+        if !active {
+            self.speech = Duration::ZERO;
+        }
+    }
+}
+
+/// Feeds the next input frame and returns `true` if the caller barged in with it.
+///
+/// After a barge-in, the output is considered inactive until it is set active again.
+pub fn detect(frame: &AudioFrame, state: &mut BargeInState) -> bool {
+    let mut energy = 0.0;
+    for &sample in &frame.samples {
+        let sample = sample as f32 / 32768.0;
+        energy = state
+            .envelope
+            .follow(frame.format.sample_rate, sample * sample);
+    }
+    if !state.output_active {
+        return false;
+    }
+    if energy.sqrt() < state.threshold {
+        state.speech = Duration::ZERO;
+        return false;
+    }
+    state.speech += frame.duration();
+    if state.speech < state.min_duration {
+        return false;
+    }
+    state.set_output_active(false);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioFormat;
+
+    /// 20ms of a square wave.
+    fn frame(level: i16) -> AudioFrame {
+        AudioFrame {
+            format: AudioFormat::new(1, 16000),
+            samples: (0..320)
+                .map(|i| if i % 2 == 0 { level } else { -level })
+                .collect(),
+        }
+    }
+
+    /// Returns the indices of the frames the caller barged in with.
+    fn feed(state: &mut BargeInState, levels: &[i16]) -> Vec<usize> {
+        levels
+            .iter()
+            .enumerate()
+            .filter(|(_, level)| detect(&frame(**level), state))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    fn state() -> BargeInState {
+        BargeInState::new(&BargeInParams {
+            threshold: 0.05,
+            min_duration_ms: 300,
+        })
+    }
+
+    #[test]
+    fn speech_barges_in_only_while_audio_is_output() {
+        let mut state = state();
+        assert_eq!(feed(&mut state, &[8000; 10]), [] as [usize; 0]);
+
+        state.set_output_active(true);
+        // The 15th frame of 20ms completes the minimum duration of 300ms.
+        assert_eq!(feed(&mut state, &[8000; 20]), [14]);
+        // The output is inactive after the barge-in.
+        assert_eq!(feed(&mut state, &[8000; 20]), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn short_noises_do_not_barge_in() {
+        let mut state = state();
+        state.set_output_active(true);
+        let noise = [[8000; 3].as_slice(), &[0; 10]].concat();
+        assert_eq!(feed(&mut state, &noise.repeat(3)), [] as [usize; 0]);
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::{HashMap, hash_map::Entry};
+use std::collections::{BTreeMap, HashMap, btree_map, hash_map::Entry};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use serde::Serialize;
 
 use crate::{BillingRecord, BillingRecordValue, conversation::BillingId};
@@ -12,15 +12,43 @@ pub struct BillingRecords {
     records: Vec<BillingRecord>,
 }
 
+impl BillingRecords {
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    pub fn records(&self) -> &[BillingRecord] {
+        &self.records
+    }
+}
+
 /// Type definition for the inner `HashMap` key in `BillingCollector`
 /// Contains `(service, scope, name)`
 type BillingRecordKey = (String, Option<String>, String);
 
+type BillingRecordMap = HashMap<BillingRecordKey, BillingRecordValue>;
+
+/// Token returned by [`BillingCollector::peek`] to confirm the removal of the peeked records.
+pub type PeekToken = u64;
+
 #[derive(Debug, Default)]
 pub struct BillingCollector {
     /// The inner `HashMap` uses `(service, scope, name)` as the key and stores the `BillingRecordValue`.
     /// The scope is optional.
-    records: HashMap<BillingId, HashMap<BillingRecordKey, BillingRecordValue>>,
+    records: HashMap<BillingId, BillingRecordMap>,
+    /// The latest unconfirmed peek per billing id.
+    peeks: HashMap<BillingId, Peek>,
+    next_peek_token: PeekToken,
+}
+
+#[derive(Debug)]
+struct Peek {
+    token: PeekToken,
+    records: BillingRecordMap,
 }
 
 impl BillingCollector {
@@ -60,27 +88,240 @@ impl BillingCollector {
     }
 
     pub fn collect(&mut self, id: &BillingId) -> Vec<BillingRecords> {
-        if let Some(records_map) = self.records.remove(id) {
-            // Group records by service and scope
-            let mut grouped: HashMap<(String, Option<String>), Vec<BillingRecord>> = HashMap::new();
-
-            for ((service, scope, name), value) in records_map {
-                grouped
-                    .entry((service.clone(), scope))
-                    .or_default()
-                    .push(BillingRecord { name, value });
+        self.peeks.remove(id);
+        self.records
+            .remove(id)
+            .map(group_records)
+            .unwrap_or_default()
+    }
+
+    /// Returns the records without removing them. The returned token confirms their removal.
+    ///
+    /// Only the latest peek of a billing id can be confirmed.
+    pub fn peek(&mut self, id: &BillingId) -> (PeekToken, Vec<BillingRecords>) {
+        let records = self.records.get(id).cloned().unwrap_or_default();
+        let token = self.next_peek_token;
+        self.next_peek_token += 1;
+        self.peeks.insert(
+            id.clone(),
+            Peek {
+                token,
+                records: records.clone(),
+            },
+        );
+        (token, group_records(records))
+    }
+
+    /// Removes the records returned by the peek of the token. Records that were added after the
+    /// peek are kept.
+    pub fn confirm(&mut self, id: &BillingId, token: PeekToken) -> Result<()> {
+        let peek = match self.peeks.entry(id.clone()) {
+            Entry::Occupied(peek) if peek.get().token == token => peek.remove(),
+            _ => bail!("No pending peek with token {token} for billing id {id}"),
+        };
+
+        let Some(records_map) = self.records.get_mut(id) else {
+            return Ok(());
+        };
+        for (key, value) in peek.records {
+            if let Entry::Occupied(mut current) = records_map.entry(key) {
+                current.get_mut().deduct(&value);
+                if current.get().is_zero() {
+                    current.remove();
+                }
             }
+        }
+        if records_map.is_empty() {
+            self.records.remove(id);
+        }
 
-            grouped
-                .into_iter()
-                .map(|((service, scope), records)| BillingRecords {
-                    service,
-                    scope,
-                    records,
-                })
-                .collect()
-        } else {
-            Vec::new()
+        Ok(())
+    }
+
+    /// Rolls up billing records by name over all services and scopes. The result is sorted by
+    /// name.
+    pub fn summary(records: &[BillingRecords]) -> Result<Vec<BillingRecord>> {
+        let mut totals: BTreeMap<&str, BillingRecordValue> = BTreeMap::new();
+        for record in records.iter().flat_map(|r| &r.records) {
+            match totals.entry(&record.name) {
+                btree_map::Entry::Occupied(mut occupied) => {
+                    occupied.get_mut().aggregate_with(&record.value)?;
+                }
+                btree_map::Entry::Vacant(vacant) => {
+                    vacant.insert(record.value.clone());
+                }
+            }
         }
+
+        Ok(totals
+            .into_iter()
+            .map(|(name, value)| BillingRecord {
+                name: name.into(),
+                value,
+            })
+            .collect())
+    }
+}
+
+/// Group records by service and scope.
+fn group_records(records_map: BillingRecordMap) -> Vec<BillingRecords> {
+    let mut grouped: HashMap<(String, Option<String>), Vec<BillingRecord>> = HashMap::new();
+
+    for ((service, scope, name), value) in records_map {
+        grouped
+            .entry((service.clone(), scope))
+            .or_default()
+            .push(BillingRecord { name, value });
+    }
+
+    grouped
+        .into_iter()
+        .map(|((service, scope), records)| BillingRecords {
+            service,
+            scope,
+            records,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn summary_rolls_up_records_over_services_and_scopes() {
+        let id = BillingId::from("call".to_string());
+        let mut collector = BillingCollector::default();
+        collector
+            .record(
+                &id,
+                "openai-dialog",
+                Some("agent".into()),
+                vec![
+                    BillingRecord::count("tokens:input:audio", 100),
+                    BillingRecord::duration("audio", Duration::from_secs(2)),
+                ],
+            )
+            .unwrap();
+        collector
+            .record(
+                &id,
+                "azure-synthesize",
+                None,
+                vec![BillingRecord::duration("audio", Duration::from_secs(3))],
+            )
+            .unwrap();
+        collector
+            .record(
+                &id,
+                "openai-dialog",
+                None,
+                vec![BillingRecord::count("tokens:input:audio", 20)],
+            )
+            .unwrap();
+
+        let summary = BillingCollector::summary(&collector.collect(&id)).unwrap();
+        assert_eq!(
+            summary,
+            [
+                BillingRecord::duration("audio", Duration::from_secs(5)),
+                BillingRecord::count("tokens:input:audio", 120),
+            ]
+        );
+    }
+
+    #[test]
+    fn summary_rejects_incompatible_values() {
+        let id = BillingId::from("call".to_string());
+        let mut collector = BillingCollector::default();
+        collector
+            .record(&id, "a", None, vec![BillingRecord::count("audio", 1)])
+            .unwrap();
+        collector
+            .record(
+                &id,
+                "b",
+                None,
+                vec![BillingRecord::duration("audio", Duration::from_secs(1))],
+            )
+            .unwrap();
+
+        assert!(BillingCollector::summary(&collector.collect(&id)).is_err());
+    }
+
+    fn counts(records: &[BillingRecords]) -> Vec<(String, usize)> {
+        let mut counts: Vec<_> = records
+            .iter()
+            .flat_map(|r| r.records())
+            .map(|r| match r.value {
+                BillingRecordValue::Count { count } => (r.name.clone(), count),
+                BillingRecordValue::Duration { .. } => panic!("Unexpected duration"),
+            })
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn peek_then_confirm_removes_only_the_peeked_records() {
+        let id = BillingId::from("call".to_string());
+        let mut collector = BillingCollector::default();
+        collector
+            .record(&id, "s", None, vec![BillingRecord::count("tokens", 10)])
+            .unwrap();
+
+        let (token, peeked) = collector.peek(&id);
+        assert_eq!(counts(&peeked), [("tokens".into(), 10)]);
+
+        // Recorded between peek and confirmation.
+        collector
+            .record(
+                &id,
+                "s",
+                None,
+                vec![
+                    BillingRecord::count("tokens", 5),
+                    BillingRecord::count("requests", 1),
+                ],
+            )
+            .unwrap();
+
+        collector.confirm(&id, token).unwrap();
+        assert_eq!(
+            counts(&collector.collect(&id)),
+            [("requests".into(), 1), ("tokens".into(), 5)]
+        );
+    }
+
+    #[test]
+    fn peek_without_confirm_leaves_records_intact() {
+        let id = BillingId::from("call".to_string());
+        let mut collector = BillingCollector::default();
+        collector
+            .record(&id, "s", None, vec![BillingRecord::count("tokens", 10)])
+            .unwrap();
+
+        let (_, first) = collector.peek(&id);
+        let (_, second) = collector.peek(&id);
+        assert_eq!(counts(&first), counts(&second));
+        assert_eq!(counts(&collector.collect(&id)), [("tokens".into(), 10)]);
+    }
+
+    #[test]
+    fn only_the_latest_peek_can_be_confirmed() {
+        let id = BillingId::from("call".to_string());
+        let mut collector = BillingCollector::default();
+        collector
+            .record(&id, "s", None, vec![BillingRecord::count("tokens", 10)])
+            .unwrap();
+
+        let (stale, _) = collector.peek(&id);
+        let (latest, _) = collector.peek(&id);
+        assert!(collector.confirm(&id, stale).is_err());
+        collector.confirm(&id, latest).unwrap();
+        assert!(collector.confirm(&id, latest).is_err());
+        assert!(collector.collect(&id).is_empty());
     }
 }
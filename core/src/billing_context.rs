@@ -33,6 +33,34 @@ impl BillingContext {
         }
     }
 
+    /// A context with the same billing id and service that records into a separate collector,
+    /// so that the records can be discarded or moved over later.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            collector: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Moves the records of a [`Self::detached`] context into the collector of this one.
+    pub(crate) fn record_detached(&self, detached: &BillingContext) -> Result<()> {
+        let records = detached
+            .collector
+            .lock()
+            .expect("Lock poisoned")
+            .collect(&detached.billing_id);
+        let mut collector = self.collector.lock().expect("Lock poisoned");
+        for records in records {
+            collector.record(
+                &self.billing_id,
+                records.service(),
+                records.scope().map(str::to_owned),
+                records.records().to_vec(),
+            )?;
+        }
+        Ok(())
+    }
+
     pub fn record(
         &self,
         scope: impl Into<Option<String>>,
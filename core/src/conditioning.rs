@@ -0,0 +1,98 @@
+//! Input conditioning processors that can be combined with the speech gate.
+use std::f32::consts::TAU;
+
+use crate::AudioFrame;
+
+/// Returns a processing function that removes the DC offset with a one-pole high-pass filter.
+///
+/// - `cutoff_hz`: Frequencies below are attenuated. 20 Hz removes DC without affecting speech.
+pub fn make_dc_removal_processor(
+    cutoff_hz: f32,
+) -> Box<dyn FnMut(&AudioFrame) -> AudioFrame + Send + Sync> {
+    let mut coeff: Option<f32> = None;
+    let mut previous_input = 0.0f32;
+    let mut previous_output = 0.0f32;
+
+    Box::new(move |frame: &AudioFrame| {
+        let coeff = *coeff
+            .get_or_insert_with(|| (-TAU * cutoff_hz / frame.format.sample_rate as f32).exp());
+
+        let samples = frame
+            .samples
+            .iter()
+            .map(|&s| {
+                let input = s as f32;
+                let output = input - previous_input + coeff * previous_output;
+                previous_input = input;
+                previous_output = output;
+                output.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            })
+            .collect();
+
+        AudioFrame {
+            format: frame.format,
+            samples,
+        }
+    })
+}
+
+/// Returns a processing function that applies automatic gain control.
+///
+/// - `target_rms`: The normalized RMS level (0.0 to 1.0) the output is adjusted to.
+/// - `max_gain`: Limits the amplification of quiet input, so that noise is not boosted
+///   indefinitely.
+///
+/// The gain is reduced quickly to avoid clipping, and raised slowly to avoid pumping in short
+/// pauses.
+pub fn make_agc_processor(
+    target_rms: f32,
+    max_gain: f32,
+) -> Box<dyn FnMut(&AudioFrame) -> AudioFrame + Send + Sync> {
+    const ENVELOPE_MS: f32 = 50.0;
+    const ATTACK_MS: f32 = 5.0;
+    const RELEASE_MS: f32 = 500.0;
+
+    let mut coeffs: Option<(f32, f32, f32)> = None;
+    let mut envelope = 0.0f32;
+    let mut gain = 1.0f32;
+
+    Box::new(move |frame: &AudioFrame| {
+        let (envelope_coeff, attack_coeff, release_coeff) = *coeffs.get_or_insert_with(|| {
+            let sr = frame.format.sample_rate as f32;
+            let coeff = |ms: f32| (-1.0 / (ms * 0.001 * sr)).exp();
+            (coeff(ENVELOPE_MS), coeff(ATTACK_MS), coeff(RELEASE_MS))
+        });
+
+        let samples = frame
+            .samples
+            .iter()
+            .map(|&s| {
+                let sample_f32 = s as f32 / 32768.0;
+
+                // Mean square energy.
+                let energy = sample_f32 * sample_f32;
+                envelope = envelope_coeff * (envelope - energy) + energy;
+
+                let rms = envelope.sqrt();
+                let desired_gain = if rms > 0.0 {
+                    (target_rms / rms).min(max_gain)
+                } else {
+                    max_gain
+                };
+                let coeff = if desired_gain < gain {
+                    attack_coeff
+                } else {
+                    release_coeff
+                };
+                gain = coeff * (gain - desired_gain) + desired_gain;
+
+                (sample_f32 * gain * 32767.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            })
+            .collect();
+
+        AudioFrame {
+            format: frame.format,
+            samples,
+        }
+    })
+}
@@ -1,13 +1,19 @@
-use std::sync::Arc;
+use std::collections::{HashMap, hash_map::Entry};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time;
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result};
 use derive_more::derive::{Display, From, Into};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{Receiver, UnboundedSender, channel};
+use serde_json::Value;
+use tokio::sync::mpsc::{Receiver, UnboundedSender, channel, unbounded_channel};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::{pin, select, time::sleep};
 
 use crate::{
-    AudioFormat, AudioFrame, BillingRecord, InputModality, OutputModality, OutputPath, Registry,
-    billing_context::BillingContext,
+    AudioFormat, AudioFrame, BillingRecord, BillingRecordValue, InputModality, OutputModalities,
+    OutputModality, OutputPath, Registry, billing_context::BillingContext,
 };
 
 pub const AI_ASSISTANT_SPEAKER: &str = "~:ai-assistant";
@@ -16,11 +22,14 @@ pub const AI_ASSISTANT_SPEAKER: &str = "~:ai-assistant";
 pub struct Conversation {
     registry: Arc<Registry>,
     pub input_modality: InputModality,
-    pub output_modalities: Vec<OutputModality>,
+    output_modalities: OutputModalities,
     input: Receiver<Input>,
     output: UnboundedSender<Output>,
     send_started_event: bool,
     billing_context: Option<BillingContext>,
+    metadata: Option<Value>,
+    trace_id: Option<String>,
+    nested_conversations: Option<Arc<Semaphore>>,
 }
 
 impl Conversation {
@@ -34,11 +43,14 @@ impl Conversation {
         Self {
             registry: Registry::empty().into(),
             input_modality,
-            output_modalities: output_modalities.into(),
+            output_modalities: output_modalities.into().into(),
             input,
             output,
             send_started_event: true,
             billing_context: None,
+            metadata: None,
+            trace_id: None,
+            nested_conversations: None,
         }
     }
 
@@ -62,6 +74,29 @@ impl Conversation {
         }
     }
 
+    pub fn with_metadata(self, metadata: Value) -> Self {
+        Self {
+            metadata: Some(metadata),
+            ..self
+        }
+    }
+
+    pub fn with_trace_id(self, trace_id: String) -> Self {
+        Self {
+            trace_id: Some(trace_id),
+            ..self
+        }
+    }
+
+    /// Limits the number of nested conversations that run at the same time. Further nested
+    /// conversations wait until one of them ends.
+    pub fn with_max_nested_conversations(self, max: usize) -> Self {
+        Self {
+            nested_conversations: Some(Semaphore::new(max).into()),
+            ..self
+        }
+    }
+
     pub fn with_no_started_event(self) -> Self {
         Self {
             send_started_event: false,
@@ -70,88 +105,67 @@ impl Conversation {
     }
 
     pub fn require_text_input_only(&self) -> Result<()> {
-        match self.input_modality {
-            InputModality::Audio { .. } => bail!("Audio input is not supported"),
-            InputModality::Text => Ok(()),
-        }
+        self.input_modality.require_text_input_only()
     }
 
     pub fn require_audio_input(&self) -> Result<AudioFormat> {
-        match self.input_modality {
-            InputModality::Audio { format } => Ok(format),
-            InputModality::Text => bail!("Audio input is required"),
-        }
+        self.input_modality.require_audio_input()
+    }
+
+    /// The information about the caller the client passed when starting the conversation.
+    pub fn metadata(&self) -> Option<&Value> {
+        self.metadata.as_ref()
+    }
+
+    /// The id providers receive with each request, so that their logs can be correlated with
+    /// this conversation, see [`crate::trace::TRACE_ID_HEADER`].
+    pub fn trace_id(&self) -> Option<&str> {
+        self.trace_id.as_deref()
+    }
+
+    pub fn output_modalities(&self) -> &OutputModalities {
+        &self.output_modalities
     }
 
-    /// Extract the audio format of a single audio output. If there are more than one audio output
-    /// modalities, this function will fail.
     pub fn require_one_audio_output(&self) -> Result<AudioFormat> {
-        let mut audio_outputs = self
-            .output_modalities
-            .iter()
-            .filter(|m| matches!(m, OutputModality::Audio { .. }));
-        let Some(OutputModality::Audio { format }) = audio_outputs.next() else {
-            bail!("Expecting one audio output");
-        };
-        if audio_outputs.next().is_some() {
-            bail!("Expecting one audio output");
-        }
-        Ok(*format)
+        self.output_modalities.require_one_audio_output()
     }
 
-    /// Returns `true` if there is one single `Text` output. Interim text is not considered. If
-    /// there is none, this function returns `false`, if there is more than one, this function fails.
     pub fn has_one_text_output(&self) -> Result<bool> {
-        let count = self
-            .output_modalities
-            .iter()
-            .filter(|m| matches!(m, OutputModality::Text))
-            .count();
-        match count {
-            0 => Ok(false),
-            1 => Ok(true),
-            _ => bail!("Expecting at most one text output"),
-        }
+        self.output_modalities.has_one_text_output()
     }
 
     pub fn require_single_audio_output(&self) -> Result<AudioFormat> {
-        match self.output_modalities.as_slice() {
-            [OutputModality::Audio { format }] => Ok(*format),
-            _ => bail!("Expect single audio output"),
-        }
+        self.output_modalities.require_single_audio_output()
     }
 
     pub fn require_text_output(&self, supports_interim_text: bool) -> Result<()> {
-        for modality in &self.output_modalities {
-            match modality {
-                OutputModality::Audio { .. } => bail!("No audio output expected"),
-                OutputModality::Text => {}
-                OutputModality::InterimText => {
-                    if !supports_interim_text {
-                        bail!("Interim text is unsupported")
-                    }
-                }
-            }
-        }
-
-        Ok(())
+        self.output_modalities
+            .require_text_output(supports_interim_text)
     }
 
     /// Start the conversation.
+    ///
+    /// The output channel is unbounded, so posting `ServiceStarted` does not depend on how much
+    /// output the receiver has yet to consume. It fails only if the receiver is gone.
     pub fn start(self) -> Result<(ConversationInput, ConversationOutput)> {
         let input = ConversationInput {
             registry: self.registry,
             modality: self.input_modality,
             input: self.input,
+            trace_id: self.trace_id,
+            nested_conversations: self.nested_conversations,
         };
         let output = ConversationOutput {
             modalities: self.output_modalities,
             output: self.output,
-            billing_context: self.billing_context,
+            billing_context: self.billing_context.clone(),
+            deferred_billing: Default::default(),
+            interval_billing: Arc::new(Mutex::new(IntervalBilling::new(self.billing_context))),
         };
         if self.send_started_event {
             output.post(Output::ServiceStarted {
-                modalities: output.modalities.clone(),
+                modalities: output.modalities.to_vec(),
             })?;
         }
         Ok((input, output))
@@ -163,6 +177,8 @@ pub struct ConversationInput {
     registry: Arc<Registry>,
     modality: InputModality,
     input: Receiver<Input>,
+    trace_id: Option<String>,
+    nested_conversations: Option<Arc<Semaphore>>,
 }
 
 impl ConversationInput {
@@ -170,6 +186,38 @@ impl ConversationInput {
         self.input.recv().await
     }
 
+    /// Runs `connect` while receiving up to `lead_in` of input audio, so that audio arriving while
+    /// a backend stream is being set up can be sent in one burst once it's established.
+    ///
+    /// Returns the result of `connect` and all input received in the meantime. When the lead-in is
+    /// full, no more input is received, so that it stays queued.
+    pub async fn buffer_lead_in<T>(
+        &mut self,
+        lead_in: time::Duration,
+        connect: impl Future<Output = Result<T>>,
+    ) -> Result<(T, Vec<Input>)> {
+        pin!(connect);
+        let mut buffered = Vec::new();
+        let mut buffered_audio = time::Duration::ZERO;
+        let mut input_closed = false;
+        loop {
+            select! {
+                connected = &mut connect => return Ok((connected?, buffered)),
+                input = self.input.recv(), if !input_closed && buffered_audio < lead_in => {
+                    match input {
+                        Some(input) => {
+                            if let Input::Audio { frame } = &input {
+                                buffered_audio += frame.duration();
+                            }
+                            buffered.push(input);
+                        }
+                        None => input_closed = true,
+                    }
+                }
+            }
+        }
+    }
+
     /// Run a nested service conversation with one single input request and wait until it's
     /// completed.
     ///
@@ -185,27 +233,289 @@ impl ConversationInput {
         request: Input,
     ) -> Result<()> {
         let service = self.registry.service(service_name)?;
+        let conversation = self.nested_conversation(
+            output,
+            service_name,
+            self.modality,
+            output.modalities.to_vec(),
+            vec![request],
+            output.output.clone(),
+        )?;
+        let _permit = self.nested_permit().await?;
+        service.converse(params, conversation).await
+    }
+
+    /// Like [`Self::converse`], but retries the request if it fails before the service produced
+    /// any audio.
+    ///
+    /// Until the first audio frame, the output of an attempt is held back and discarded if it
+    /// fails, so that retries don't emit output or bill twice. Once audio was output, a failure
+    /// is returned without retrying.
+    ///
+    /// Billing records that are not delivered with the media are recorded when the request
+    /// ended, and only for the attempt whose output was committed.
+    pub async fn converse_with_retry(
+        &self,
+        output: &ConversationOutput,
+        service_name: &str,
+        params: serde_json::Value,
+        request: Input,
+        retry: &RetryParams,
+    ) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            let mut held_output = HeldOutput::new(output);
+            let result = self
+                .converse_holding_output(
+                    &mut held_output,
+                    service_name,
+                    params.clone(),
+                    request.clone(),
+                )
+                .await;
+            match result {
+                Ok(()) => {
+                    held_output.commit()?;
+                    return held_output.record_billing();
+                }
+                Err(_) if !held_output.is_committed() && attempt < retry.attempts => {
+                    sleep(retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(error) => {
+                    if held_output.is_committed() {
+                        held_output.record_billing()?;
+                    }
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    async fn converse_holding_output(
+        &self,
+        output: &mut HeldOutput<'_>,
+        service_name: &str,
+        params: serde_json::Value,
+        request: Input,
+    ) -> Result<()> {
+        let service = self.registry.service(service_name)?;
+        let (nested_output, mut nested_events) = unbounded_channel();
+        let mut conversation = self.nested_conversation(
+            output.output,
+            service_name,
+            self.modality,
+            output.output.modalities.to_vec(),
+            vec![request],
+            nested_output,
+        )?;
+        if let Some(billing_context) = &output.billing_context {
+            conversation = conversation
+                .with_billing_context(billing_context.clone().with_service(service_name));
+        }
 
-        let (input_tx, input_rx) = channel(1);
-        input_tx.try_send(request)?;
+        let _permit = self.nested_permit().await?;
+        let conversation = service.converse(params, conversation);
+        pin!(conversation);
+        loop {
+            select! {
+                result = &mut conversation => {
+                    result?;
+                    break;
+                }
+                Some(event) = nested_events.recv() => output.forward(event)?,
+            }
+        }
+        while let Ok(event) = nested_events.try_recv() {
+            output.forward(event)?;
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::converse`], but also returns the audio frames the nested service produced.
+    pub async fn converse_capturing_audio(
+        &self,
+        output: &ConversationOutput,
+        service_name: &str,
+        params: serde_json::Value,
+        request: Input,
+    ) -> Result<Vec<AudioFrame>> {
+        let service = self.registry.service(service_name)?;
+        let (nested_output, mut nested_events) = unbounded_channel();
+        let conversation = self.nested_conversation(
+            output,
+            service_name,
+            self.modality,
+            output.modalities.to_vec(),
+            vec![request],
+            nested_output,
+        )?;
+
+        let mut frames = Vec::new();
+        let mut forward = |event: Output| {
+            if let Output::Audio { frame } = &event {
+                frames.push(frame.clone());
+            }
+            output.post(event)
+        };
+
+        let _permit = self.nested_permit().await?;
+        let conversation = service.converse(params, conversation);
+        pin!(conversation);
+        loop {
+            select! {
+                result = &mut conversation => {
+                    result?;
+                    break;
+                }
+                Some(event) = nested_events.recv() => forward(event)?,
+            }
+        }
+        while let Ok(event) = nested_events.try_recv() {
+            forward(event)?;
+        }
+
+        Ok(frames)
+    }
+
+    /// Run a nested transcription service conversation over the audio frames and return the final
+    /// texts.
+    ///
+    /// Billing records are sent to the conversation output, all other output is dropped.
+    pub async fn transcribe(
+        &self,
+        output: &ConversationOutput,
+        service_name: &str,
+        params: serde_json::Value,
+        frames: Vec<AudioFrame>,
+    ) -> Result<Vec<String>> {
+        let Some(format) = frames.first().map(|frame| frame.format) else {
+            return Ok(Vec::new());
+        };
+        let service = self.registry.service(service_name)?;
+        let (nested_output, mut nested_events) = unbounded_channel();
+        let conversation = self.nested_conversation(
+            output,
+            service_name,
+            InputModality::Audio { format },
+            vec![OutputModality::Text],
+            frames
+                .into_iter()
+                .map(|frame| Input::Audio { frame })
+                .collect(),
+            nested_output,
+        )?;
+        let permit = self.nested_permit().await?;
+        service.converse(params, conversation).await?;
+        drop(permit);
+
+        let mut texts = Vec::new();
+        while let Ok(event) = nested_events.try_recv() {
+            match event {
+                Output::Text {
+                    is_final: true,
+                    text,
+                    ..
+                } => texts.push(text),
+                Output::BillingRecords { .. } => output.post(event)?,
+                Output::ServiceStarted { .. }
+                | Output::ServiceReady
+                | Output::Audio { .. }
+                | Output::Text { .. }
+                | Output::RequestCompleted { .. }
+                | Output::TurnCompleted { .. }
+                | Output::ClearAudio
+                | Output::ServiceEvent { .. } => {}
+            }
+        }
+
+        Ok(texts)
+    }
+
+    /// Run a nested synthesizer service conversation with one single request and return the audio
+    /// frames it produced in `format`.
+    ///
+    /// Billing records are sent to the conversation output, all other output is dropped.
+    pub async fn synthesize(
+        &self,
+        output: &ConversationOutput,
+        service_name: &str,
+        params: serde_json::Value,
+        request: Input,
+        format: AudioFormat,
+    ) -> Result<Vec<AudioFrame>> {
+        let service = self.registry.service(service_name)?;
+        let (nested_output, mut nested_events) = unbounded_channel();
+        let conversation = self.nested_conversation(
+            output,
+            service_name,
+            self.modality,
+            vec![OutputModality::Audio { format }],
+            vec![request],
+            nested_output,
+        )?;
+        let permit = self.nested_permit().await?;
+        service.converse(params, conversation).await?;
+        drop(permit);
+
+        let mut frames = Vec::new();
+        while let Ok(event) = nested_events.try_recv() {
+            match event {
+                Output::Audio { frame } => frames.push(frame),
+                Output::BillingRecords { .. } => output.post(event)?,
+                Output::ServiceStarted { .. }
+                | Output::ServiceReady
+                | Output::Text { .. }
+                | Output::RequestCompleted { .. }
+                | Output::TurnCompleted { .. }
+                | Output::ClearAudio
+                | Output::ServiceEvent { .. } => {}
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Waits until another nested conversation may run, see
+    /// [`Conversation::with_max_nested_conversations`].
+    async fn nested_permit(&self) -> Result<Option<SemaphorePermit<'_>>> {
+        let Some(semaphore) = &self.nested_conversations else {
+            return Ok(None);
+        };
+        Ok(Some(semaphore.acquire().await?))
+    }
+
+    /// Create a nested conversation that receives all inputs and then ends its input.
+    fn nested_conversation(
+        &self,
+        output: &ConversationOutput,
+        service_name: &str,
+        input_modality: InputModality,
+        output_modalities: Vec<OutputModality>,
+        inputs: Vec<Input>,
+        nested_output: UnboundedSender<Output>,
+    ) -> Result<Conversation> {
+        let (input_tx, input_rx) = channel(inputs.len().max(1));
+        for input in inputs {
+            input_tx.try_send(input)?;
+        }
         drop(input_tx);
 
         // Don't add a registry, so to allow nested only once. Idea: CS should remove this service
         // from the registry passed to this conversation such that we could nest and remove all
         // services that are in use.
-        let mut conversation = Conversation::new_nested(
-            self.modality,
-            output.modalities.clone(),
-            input_rx,
-            output.output.clone(),
-        );
+        let mut conversation =
+            Conversation::new_nested(input_modality, output_modalities, input_rx, nested_output);
 
         if let Some(billing_context) = &output.billing_context {
             conversation = conversation
                 .with_billing_context(billing_context.clone().with_service(service_name));
         }
+        if let Some(trace_id) = &self.trace_id {
+            conversation = conversation.with_trace_id(trace_id.clone());
+        }
 
-        service.converse(params, conversation).await
+        Ok(conversation)
     }
 }
 
@@ -213,19 +523,110 @@ impl ConversationInput {
 // azure-transcribe for example.
 #[derive(Debug, Clone)]
 pub struct ConversationOutput {
-    // Architecture: Define `OutputModalities` and put all the queries in there and make
-    // `&OutputModalities` accessible.
-    modalities: Vec<OutputModality>,
+    modalities: OutputModalities,
     output: UnboundedSender<Output>,
     billing_context: Option<BillingContext>,
+    deferred_billing: Arc<Mutex<DeferredBilling>>,
+    interval_billing: Arc<Mutex<IntervalBilling>>,
+}
+
+/// Billing records per request that are recorded when the request completes.
+type DeferredBilling = HashMap<Option<RequestId>, Vec<(Option<String>, Vec<BillingRecord>)>>;
+
+/// Billing records that are aggregated per scope and name and recorded once per interval, see
+/// [`BillingSchedule::Interval`].
+#[derive(Debug)]
+struct IntervalBilling {
+    billing_context: Option<BillingContext>,
+    last_recorded: time::Instant,
+    pending: HashMap<(Option<String>, String), BillingRecordValue>,
+}
+
+impl IntervalBilling {
+    fn new(billing_context: Option<BillingContext>) -> Self {
+        Self {
+            billing_context,
+            last_recorded: time::Instant::now(),
+            pending: HashMap::new(),
+        }
+    }
+
+    fn add(
+        &mut self,
+        scope: Option<String>,
+        records: Vec<BillingRecord>,
+        interval: time::Duration,
+    ) -> Result<()> {
+        for BillingRecord { name, value } in records {
+            match self.pending.entry((scope.clone(), name)) {
+                Entry::Occupied(mut pending) => pending.get_mut().aggregate_with(&value)?,
+                Entry::Vacant(pending) => {
+                    pending.insert(value);
+                }
+            }
+        }
+        if self.last_recorded.elapsed() >= interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.last_recorded = time::Instant::now();
+        let Some(billing_context) = &self.billing_context else {
+            return Ok(());
+        };
+        let mut scopes: HashMap<Option<String>, Vec<BillingRecord>> = HashMap::new();
+        for ((scope, name), value) in self.pending.drain() {
+            scopes
+                .entry(scope)
+                .or_default()
+                .push(BillingRecord { name, value });
+        }
+        for (scope, records) in scopes {
+            billing_context.record(scope, records)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for IntervalBilling {
+    /// Records what is left when the last clone of the output is gone, so that the remainder of a
+    /// stream is billed, too.
+    fn drop(&mut self) {
+        // Errors can't be propagated from here.
+        let _ = self.flush();
+    }
 }
 
 impl ConversationOutput {
+    pub fn modalities(&self) -> &OutputModalities {
+        &self.modalities
+    }
+
+    /// Signals that the upstream service is connected and configured.
+    ///
+    /// Services that connect to a provider send this once the provider accepted the session,
+    /// which can be considerably later than the `ServiceStarted` output sent on start.
+    pub fn service_ready(&self) -> Result<()> {
+        self.post(Output::ServiceReady)
+    }
+
     pub fn audio_frame(&self, frame: AudioFrame) -> Result<()> {
         self.post(Output::Audio { frame })
     }
 
+    /// Signals that the service finished the response of a dialog turn, after its audio.
+    pub fn turn_completed(&self, response_id: Option<String>) -> Result<()> {
+        self.post(Output::TurnCompleted { response_id })
+    }
+
+    /// Clears the audio output that was not played back yet.
+    ///
+    /// The requests that are pending are cancelled, because their audio is cleared, too, see
+    /// [`Self::request_cancelled`].
     pub fn clear_audio(&self) -> Result<()> {
+        self.deferred_billing.lock().expect("Lock poisoned").clear();
         self.post(Output::ClearAudio)
     }
 
@@ -244,10 +645,32 @@ impl ConversationOutput {
         })
     }
 
+    /// Signals that the request completed and records its deferred billing records.
     pub fn request_completed(&self, request_id: Option<RequestId>) -> Result<()> {
+        let deferred = self
+            .deferred_billing
+            .lock()
+            .expect("Lock poisoned")
+            .remove(&request_id);
+        if let (Some(billing_context), Some(deferred)) = (&self.billing_context, deferred) {
+            for (scope, records) in deferred {
+                billing_context.record(scope, records)?;
+            }
+        }
         self.post(Output::RequestCompleted { request_id })
     }
 
+    /// Discards the deferred billing records of a request that did not complete.
+    ///
+    /// Deferred billing records of requests that are pending when the conversation ends are
+    /// discarded, too.
+    pub fn request_cancelled(&self, request_id: &Option<RequestId>) {
+        self.deferred_billing
+            .lock()
+            .expect("Lock poisoned")
+            .remove(request_id);
+    }
+
     /// Output a service event object.
     pub fn service_event(&self, path: OutputPath, value: impl Serialize) -> Result<()> {
         let value = serde_json::to_value(&value)?;
@@ -282,6 +705,20 @@ impl ConversationOutput {
                     records,
                 })
             }
+            BillingSchedule::OnRequestComplete => {
+                self.deferred_billing
+                    .lock()
+                    .expect("Lock poisoned")
+                    .entry(request_id)
+                    .or_default()
+                    .push((scope.into(), records));
+                Ok(())
+            }
+            BillingSchedule::Interval(interval) => self
+                .interval_billing
+                .lock()
+                .expect("Lock poisoned")
+                .add(scope.into(), records, interval),
         }
     }
 
@@ -290,15 +727,108 @@ impl ConversationOutput {
     }
 }
 
+/// How often a nested service request is attempted, see
+/// [`ConversationInput::converse_with_retry`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryParams {
+    /// The maximum number of attempts, including the first one.
+    pub attempts: u32,
+    /// The delay before the first retry, doubled for each following one.
+    pub backoff_ms: u64,
+}
+
+impl RetryParams {
+    /// The delay after the failed `attempt`, starting at 1.
+    fn backoff(&self, attempt: u32) -> time::Duration {
+        time::Duration::from_millis(self.backoff_ms).saturating_mul(1 << (attempt - 1).min(16))
+    }
+}
+
+/// Holds back the output of a nested service until its first audio frame, so that the output of
+/// a failed attempt can be discarded.
+///
+/// The billing records the nested service records directly are collected separately and only
+/// recorded by [`Self::record_billing`].
+struct HeldOutput<'a> {
+    output: &'a ConversationOutput,
+    /// `None` after the output was committed.
+    held: Option<Vec<Output>>,
+    billing_context: Option<BillingContext>,
+}
+
+impl<'a> HeldOutput<'a> {
+    fn new(output: &'a ConversationOutput) -> Self {
+        Self {
+            output,
+            held: Some(Vec::new()),
+            billing_context: output
+                .billing_context
+                .as_ref()
+                .map(BillingContext::detached),
+        }
+    }
+
+    fn forward(&mut self, event: Output) -> Result<()> {
+        let Some(held) = &mut self.held else {
+            return self.output.post(event);
+        };
+        match event {
+            Output::Audio { .. } => {
+                self.commit()?;
+                self.output.post(event)
+            }
+            Output::ServiceStarted { .. }
+            | Output::ServiceReady
+            | Output::Text { .. }
+            | Output::RequestCompleted { .. }
+            | Output::TurnCompleted { .. }
+            | Output::ClearAudio
+            | Output::ServiceEvent { .. }
+            | Output::BillingRecords { .. } => {
+                held.push(event);
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the billing records the nested service recorded directly.
+    fn record_billing(&self) -> Result<()> {
+        match (&self.output.billing_context, &self.billing_context) {
+            (Some(billing_context), Some(detached)) => billing_context.record_detached(detached),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_committed(&self) -> bool {
+        self.held.is_none()
+    }
+
+    /// Sends all the held output and forwards everything that follows directly.
+    fn commit(&mut self) -> Result<()> {
+        for event in self.held.take().into_iter().flatten() {
+            self.output.post(event)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum BillingSchedule {
     /// Bill immediately, independent of media output.
     Now,
     /// Bill when associated output media arrived (got played back).
     Media,
+    /// Bill when the request completes. Requests that are cancelled, cleared, or don't complete
+    /// before the conversation ends are not billed.
+    OnRequestComplete,
+    /// Aggregate the records per scope and name and bill them once the interval elapsed since
+    /// they were billed last. For services that produce records per audio frame. What's left is
+    /// billed when the output and all its clones are dropped.
+    Interval(time::Duration),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Input {
     Audio {
         frame: AudioFrame,
@@ -317,6 +847,22 @@ pub enum Input {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, From, Into, Display, Serialize, Deserialize)]
 pub struct RequestId(String);
 
+/// Error context that identifies the request a service failed on.
+///
+/// Attached to a service's error, the request id is reported in the conversation's error event,
+/// so that clients can correlate the error with the request.
+#[derive(Debug, Clone)]
+pub struct RequestFailed(pub Option<RequestId>);
+
+impl fmt::Display for RequestFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(request_id) => write!(f, "Request `{request_id}` failed"),
+            None => write!(f, "Request failed"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, From, Into, Display, Serialize, Deserialize)]
 pub struct BillingId(String);
 
@@ -325,6 +871,9 @@ pub enum Output {
     ServiceStarted {
         modalities: Vec<OutputModality>,
     },
+    /// The upstream service is connected and configured, see
+    /// [`ConversationOutput::service_ready`].
+    ServiceReady,
     Audio {
         frame: AudioFrame,
     },
@@ -337,6 +886,9 @@ pub enum Output {
     RequestCompleted {
         request_id: Option<RequestId>,
     },
+    TurnCompleted {
+        response_id: Option<String>,
+    },
     ClearAudio,
     ServiceEvent {
         path: OutputPath,
@@ -349,3 +901,456 @@ pub enum Output {
         records: Vec<BillingRecord>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use anyhow::bail;
+    use async_trait::async_trait;
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::Service;
+    use crate::billing_collector::BillingCollector;
+
+    #[test]
+    fn start_does_not_depend_on_the_output_being_consumed() {
+        let format = AudioFormat::new(1, 16000);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let start = || {
+            let (_input_sender, input_receiver) = channel(1);
+            Conversation::new(
+                InputModality::Text,
+                [OutputModality::Audio { format }],
+                input_receiver,
+                output_sender.clone(),
+            )
+            .start()
+        };
+
+        // A backlog of output nobody consumed yet.
+        let (_input, output) = start().unwrap();
+        for _ in 0..1000 {
+            output.clear_audio().unwrap();
+        }
+        assert!(start().is_ok());
+
+        let outputs: Vec<_> = iter::from_fn(|| output_receiver.try_recv().ok()).collect();
+        assert_eq!(outputs.len(), 1002);
+        assert!(matches!(outputs[1001], Output::ServiceStarted { .. }));
+
+        drop(output_receiver);
+        assert!(start().is_err());
+    }
+
+    #[tokio::test]
+    async fn lead_in_is_buffered_while_connecting_and_flushed_after() {
+        let format = AudioFormat::new(1, 16000);
+        // 100ms frames.
+        let frame = |value: i16| AudioFrame {
+            format,
+            samples: vec![value; 1600],
+        };
+        let (input_sender, input_receiver) = channel(4);
+        let (output_sender, _output_receiver) = unbounded_channel();
+        let (mut input, _output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        for value in 0..3 {
+            input_sender
+                .send(Input::Audio {
+                    frame: frame(value),
+                })
+                .await
+                .unwrap();
+        }
+
+        let connect = async {
+            sleep(Duration::from_millis(10)).await;
+            // The mock backend's stream.
+            Ok(Vec::new())
+        };
+        let (mut backend, lead_in) = input
+            .buffer_lead_in(Duration::from_millis(200), connect)
+            .await
+            .unwrap();
+        for input in lead_in {
+            let Input::Audio { frame } = input else {
+                panic!("Unexpected input");
+            };
+            backend.push(frame.samples[0]);
+        }
+        assert_eq!(backend, [0, 1]);
+
+        // Input beyond the lead-in stays queued.
+        assert!(matches!(
+            input.recv().await,
+            Some(Input::Audio { frame }) if frame.samples[0] == 2
+        ));
+    }
+
+    /// Fails the first request before producing audio, and synthesizes one frame afterwards. Each
+    /// attempt bills the request immediately and the characters with the media.
+    #[derive(Debug, Default)]
+    struct FlakySynthesizer {
+        attempts: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Service for FlakySynthesizer {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), conversation: Conversation) -> Result<()> {
+            let format = conversation.require_single_audio_output()?;
+            let (mut input, output) = conversation.start()?;
+            let Some(Input::Text { request_id, .. }) = input.recv().await else {
+                bail!("Expected a text request");
+            };
+            let billing = BillingRecord::count("output:requests", 1);
+            output.billing_records(None, None, [billing], BillingSchedule::Now)?;
+            let billing = BillingRecord::count("output:characters", 5);
+            output.billing_records(None, None, [billing], BillingSchedule::Media)?;
+            if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                bail!("Transient failure");
+            }
+            output.audio_frame(AudioFrame {
+                format,
+                samples: vec![0; 160],
+            })?;
+            output.request_completed(request_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn nested_request_is_retried_without_duplicate_output() {
+        let format = AudioFormat::new(1, 16000);
+        let registry = Registry::empty().add_service("synthesize", FlakySynthesizer::default());
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let collector = Arc::new(Mutex::new(BillingCollector::default()));
+        let (input, output) = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .with_registry(registry.into())
+        .with_billing_context(BillingContext::new(
+            BillingId::from("call".to_string()),
+            "playback",
+            collector.clone(),
+        ))
+        .start()
+        .unwrap();
+
+        let request = Input::Text {
+            request_id: Some(RequestId::from("r1".to_string())),
+            text: "Hello".into(),
+            text_type: None,
+            billing_scope: None,
+        };
+        let retry = RetryParams {
+            attempts: 2,
+            backoff_ms: 1,
+        };
+        input
+            .converse_with_retry(&output, "synthesize", Value::Null, request, &retry)
+            .await
+            .unwrap();
+
+        let mut outputs = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            outputs.push(output);
+        }
+        assert!(
+            matches!(
+                &outputs[..],
+                [
+                    Output::ServiceStarted { .. },
+                    Output::BillingRecords { records, .. },
+                    Output::Audio { .. },
+                    Output::RequestCompleted { .. },
+                ] if records == &[BillingRecord::count("output:characters", 5)]
+            ),
+            "Unexpected outputs: {outputs:?}"
+        );
+        // The request billed by the failed attempt is discarded.
+        let billed = collector
+            .lock()
+            .unwrap()
+            .collect(&BillingId::from("call".to_string()));
+        assert_eq!(billed.len(), 1);
+        assert_eq!(billed[0].service(), "synthesize");
+        assert_eq!(
+            billed[0].records(),
+            [BillingRecord::count("output:requests", 1)]
+        );
+
+        let retry = RetryParams {
+            attempts: 1,
+            backoff_ms: 1,
+        };
+        let registry = Registry::empty().add_service("synthesize", FlakySynthesizer::default());
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .with_registry(registry.into())
+        .start()
+        .unwrap();
+        let request = Input::Text {
+            request_id: None,
+            text: "Hello".into(),
+            text_type: None,
+            billing_scope: None,
+        };
+        assert!(
+            input
+                .converse_with_retry(&output, "synthesize", Value::Null, request, &retry)
+                .await
+                .is_err()
+        );
+        // The started event of the parent conversation only.
+        assert!(matches!(
+            output_receiver.try_recv(),
+            Ok(Output::ServiceStarted { .. })
+        ));
+        assert!(output_receiver.try_recv().is_err());
+    }
+
+    /// Completes each request after a while and records how many requests ran at once.
+    #[derive(Debug, Default)]
+    struct ConcurrencyProbe {
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Service for ConcurrencyProbe {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), conversation: Conversation) -> Result<()> {
+            let (mut input, output) = conversation.start()?;
+            let Some(Input::Text { request_id, .. }) = input.recv().await else {
+                bail!("Expected a text request");
+            };
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            output.request_completed(request_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn nested_conversations_beyond_the_limit_are_queued() {
+        let probe = ConcurrencyProbe::default();
+        let max_running = probe.max_running.clone();
+        let registry = Registry::empty().add_service("synthesize", probe);
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio {
+                format: AudioFormat::new(1, 16000),
+            }],
+            input_receiver,
+            output_sender,
+        )
+        .with_registry(registry.into())
+        .with_max_nested_conversations(2)
+        .start()
+        .unwrap();
+
+        let input = Arc::new(input);
+        let requests: Vec<_> = (0..6)
+            .map(|i| {
+                let input = input.clone();
+                let output = output.clone();
+                tokio::spawn(async move {
+                    let request = Input::Text {
+                        request_id: Some(RequestId::from(format!("r{i}"))),
+                        text: "Hello".into(),
+                        text_type: None,
+                        billing_scope: None,
+                    };
+                    input
+                        .converse(&output, "synthesize", Value::Null, request)
+                        .await
+                })
+            })
+            .collect();
+        for request in requests {
+            request.await.unwrap().unwrap();
+        }
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        let completed = iter::from_fn(|| output_receiver.try_recv().ok())
+            .filter(|output| matches!(output, Output::RequestCompleted { .. }))
+            .count();
+        assert_eq!(completed, 6);
+    }
+
+    #[test]
+    fn interval_billing_aggregates_the_records_of_frames() {
+        let billing_id = BillingId::from("call".to_string());
+        let collector = Arc::new(Mutex::new(BillingCollector::default()));
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, _output_receiver) = unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio {
+                format: AudioFormat::new(1, 16000),
+            },
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .with_billing_context(BillingContext::new(
+            billing_id.clone(),
+            "transcribe",
+            collector.clone(),
+        ))
+        .start()
+        .unwrap();
+
+        let billing_output = output.clone();
+        for _ in 0..100 {
+            billing_output
+                .billing_records(
+                    None,
+                    None,
+                    [BillingRecord::duration(
+                        "input:audio",
+                        Duration::from_millis(20),
+                    )],
+                    BillingSchedule::Interval(Duration::from_secs(60)),
+                )
+                .unwrap();
+        }
+        assert!(collector.lock().unwrap().collect(&billing_id).is_empty());
+
+        drop(billing_output);
+        drop(output);
+
+        let records = collector.lock().unwrap().collect(&billing_id);
+        let [records] = &records[..] else {
+            panic!("Expected the records of one service, got {records:?}");
+        };
+        assert_eq!(
+            records.records(),
+            [BillingRecord::duration(
+                "input:audio",
+                Duration::from_secs(2)
+            )]
+        );
+    }
+
+    #[test]
+    fn deferred_billing_is_recorded_on_completion_and_discarded_on_cancellation() {
+        let billing_id = BillingId::from("call".to_string());
+        let collector = Arc::new(Mutex::new(BillingCollector::default()));
+        let (output, _output_receiver) = deferred_billing_output(&billing_id, &collector);
+
+        let cancelled = Some(RequestId::from("cancelled".to_string()));
+        let completed = Some(RequestId::from("completed".to_string()));
+        let pending = Some(RequestId::from("pending".to_string()));
+        for (request_id, seconds) in [(&cancelled, 1), (&completed, 2), (&pending, 4)] {
+            bill_on_completion(&output, request_id, seconds);
+        }
+        assert!(collector.lock().unwrap().collect(&billing_id).is_empty());
+
+        output.request_cancelled(&cancelled);
+        output.request_completed(completed).unwrap();
+        output.request_completed(cancelled).unwrap();
+        // The conversation ends before the pending request completes.
+        drop(output);
+
+        let records = collector.lock().unwrap().collect(&billing_id);
+        assert_eq!(
+            BillingCollector::summary(&records).unwrap(),
+            [BillingRecord::duration(
+                "output:audio",
+                Duration::from_secs(2)
+            )]
+        );
+    }
+
+    #[test]
+    fn clearing_audio_discards_the_deferred_billing_of_pending_requests() {
+        let billing_id = BillingId::from("call".to_string());
+        let collector = Arc::new(Mutex::new(BillingCollector::default()));
+        let (output, _output_receiver) = deferred_billing_output(&billing_id, &collector);
+
+        let cleared = Some(RequestId::from("cleared".to_string()));
+        let next = Some(RequestId::from("next".to_string()));
+        bill_on_completion(&output, &cleared, 1);
+        output.clear_audio().unwrap();
+        output.request_completed(cleared).unwrap();
+        bill_on_completion(&output, &next, 2);
+        output.request_completed(next).unwrap();
+
+        let records = collector.lock().unwrap().collect(&billing_id);
+        assert_eq!(
+            BillingCollector::summary(&records).unwrap(),
+            [BillingRecord::duration(
+                "output:audio",
+                Duration::from_secs(2)
+            )]
+        );
+    }
+
+    fn deferred_billing_output(
+        billing_id: &BillingId,
+        collector: &Arc<Mutex<BillingCollector>>,
+    ) -> (ConversationOutput, UnboundedReceiver<Output>) {
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, output_receiver) = unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio {
+                format: AudioFormat::new(1, 16000),
+            }],
+            input_receiver,
+            output_sender,
+        )
+        .with_billing_context(BillingContext::new(
+            billing_id.clone(),
+            "synthesize",
+            collector.clone(),
+        ))
+        .start()
+        .unwrap();
+        (output, output_receiver)
+    }
+
+    fn bill_on_completion(
+        output: &ConversationOutput,
+        request_id: &Option<RequestId>,
+        seconds: u64,
+    ) {
+        output
+            .billing_records(
+                request_id.clone(),
+                None,
+                [BillingRecord::duration(
+                    "output:audio",
+                    Duration::from_secs(seconds),
+                )],
+                BillingSchedule::OnRequestComplete,
+            )
+            .unwrap();
+    }
+}
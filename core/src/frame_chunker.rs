@@ -0,0 +1,110 @@
+//! Re-chunking of audio into frames of a fixed duration.
+//!
+//! Providers stream audio in whatever sizes they like. The chunker buffers the samples and
+//! carries the remainder over to the next push, so that all frames except the final one have the
+//! same duration.
+use std::{mem, time};
+
+use anyhow::{Result, bail};
+
+use crate::{AudioFormat, AudioFrame};
+
+#[derive(Debug)]
+pub struct FrameChunker {
+    format: AudioFormat,
+    /// Number of samples (over all channels) in one frame.
+    frame_samples: usize,
+    pending: Vec<i16>,
+}
+
+impl FrameChunker {
+    pub fn new(format: AudioFormat, frame_duration: time::Duration) -> Result<Self> {
+        let samples_per_channel =
+            (format.sample_rate as f64 * frame_duration.as_secs_f64()).round() as usize;
+        if samples_per_channel == 0 {
+            bail!("Frame duration {frame_duration:?} is too short for {format:?}");
+        }
+        let frame_samples = samples_per_channel * format.channels as usize;
+        Ok(Self {
+            format,
+            frame_samples,
+            pending: Vec::with_capacity(frame_samples),
+        })
+    }
+
+    /// Push samples and return all the complete frames that are available.
+    pub fn push(&mut self, samples: &[i16]) -> Vec<AudioFrame> {
+        self.pending.extend_from_slice(samples);
+        let complete = self.pending.len() / self.frame_samples * self.frame_samples;
+        let remainder = self.pending.split_off(complete);
+        let complete = mem::replace(&mut self.pending, remainder);
+        complete
+            .chunks_exact(self.frame_samples)
+            .map(|chunk| AudioFrame {
+                format: self.format,
+                samples: chunk.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Return the remaining samples as a shorter frame, if there are any.
+    pub fn flush(&mut self) -> Option<AudioFrame> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(AudioFrame {
+            format: self.format,
+            samples: mem::take(&mut self.pending),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn irregular_chunks_are_rechunked_without_sample_loss() {
+        let format = AudioFormat::new(1, 16000);
+        let frame_duration = Duration::from_millis(20);
+        let mut chunker = FrameChunker::new(format, frame_duration).unwrap();
+
+        let input: Vec<i16> = (0..2000).map(|i| i as i16).collect();
+        let mut frames = Vec::new();
+        let mut rest = input.as_slice();
+        for size in [1, 319, 500, 7, 641, 100, 432] {
+            let (chunk, tail) = rest.split_at(size);
+            frames.extend(chunker.push(chunk));
+            rest = tail;
+        }
+        assert!(rest.is_empty());
+        frames.extend(chunker.flush());
+
+        let (last, complete) = frames.split_last().unwrap();
+        assert_eq!(complete.len(), 6);
+        assert!(complete.iter().all(|f| f.duration() == frame_duration));
+        assert!(last.duration() < frame_duration);
+
+        let output: Vec<i16> = frames.into_iter().flat_map(|f| f.samples).collect();
+        assert_eq!(output, input);
+        assert!(chunker.flush().is_none());
+    }
+
+    #[test]
+    fn frames_contain_all_channels() {
+        let format = AudioFormat::new(2, 8000);
+        let mut chunker = FrameChunker::new(format, Duration::from_millis(10)).unwrap();
+        let frames = chunker.push(&[0; 400]);
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|f| f.samples.len() == 160));
+        assert_eq!(chunker.flush().unwrap().samples.len(), 80);
+    }
+
+    #[test]
+    fn zero_duration_is_rejected() {
+        let format = AudioFormat::new(1, 16000);
+        assert!(FrameChunker::new(format, Duration::ZERO).is_err());
+    }
+}
@@ -1,8 +1,12 @@
 use std::fmt;
 
+use anyhow::{Context, Result};
 use derive_more::Deref;
 use isolang::Language;
 use oxilangtag::LanguageTag;
+use serde::Serialize;
+
+use crate::{ConversationOutput, OutputPath};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LanguageCodeError {
@@ -105,6 +109,46 @@ impl Languages {
     }
 }
 
+/// Output as a service event to warn the client that the backend rejected the requested language
+/// and the fallback language is used instead.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "languageFallback", rename_all = "camelCase")]
+struct LanguageFallback<'a> {
+    requested_language: &'a str,
+    fallback_language: &'a str,
+    reason: String,
+}
+
+/// Connects to a backend with `language`.
+///
+/// If the backend rejects the connection and a `fallback_language` is set, a warning event is
+/// output and the connection is retried with the fallback language.
+pub async fn connect_with_fallback_language<T>(
+    output: &ConversationOutput,
+    language: &str,
+    fallback_language: Option<&str>,
+    mut connect: impl AsyncFnMut(&str) -> Result<T>,
+) -> Result<T> {
+    let error = match connect(language).await {
+        Ok(connection) => return Ok(connection),
+        Err(error) => error,
+    };
+    let Some(fallback_language) = fallback_language else {
+        return Err(error);
+    };
+    output.service_event(
+        OutputPath::Control,
+        LanguageFallback {
+            requested_language: language,
+            fallback_language,
+            reason: format!("{error:#}"),
+        },
+    )?;
+    connect(fallback_language)
+        .await
+        .with_context(|| format!("Connecting with fallback language `{fallback_language}`"))
+}
+
 /// Converts a BCP 47 language tag into its ISO 639-3 language code.
 ///
 /// The conversion uses the primary language subtag only and ignores script, region, variant,
@@ -159,7 +203,86 @@ pub fn iso639_3_to_bcp47(code: &str) -> Result<String, LanguageCodeError> {
 
 #[cfg(test)]
 mod tests {
+    use anyhow::bail;
+    use serde_json::json;
+    use tokio::sync::mpsc::{UnboundedReceiver, channel, unbounded_channel};
+
     use super::*;
+    use crate::{Conversation, InputModality, Output, OutputModality};
+
+    /// A backend that only supports `en-US` and records the languages it was connected with.
+    async fn connect_backend(attempts: &mut Vec<String>, language: &str) -> Result<String> {
+        attempts.push(language.to_string());
+        if language != "en-US" {
+            bail!("Unsupported language: {language}");
+        }
+        Ok(format!("connected with {language}"))
+    }
+
+    fn text_conversation() -> (ConversationOutput, UnboundedReceiver<Output>) {
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, output_receiver) = unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        (output, output_receiver)
+    }
+
+    #[tokio::test]
+    async fn rejected_language_is_retried_with_the_fallback_language() {
+        let (output, mut output_receiver) = text_conversation();
+        let mut attempts = Vec::new();
+
+        let connection = connect_with_fallback_language(
+            &output,
+            "xx-XX",
+            Some("en-US"),
+            async |language: &str| connect_backend(&mut attempts, language).await,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(connection, "connected with en-US");
+        assert_eq!(attempts, ["xx-XX", "en-US"]);
+        let mut events = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            if let Output::ServiceEvent { path, value } = output {
+                events.push((path, value));
+            }
+        }
+        assert_eq!(
+            events,
+            [(
+                OutputPath::Control,
+                json!({
+                    "type": "languageFallback",
+                    "requestedLanguage": "xx-XX",
+                    "fallbackLanguage": "en-US",
+                    "reason": "Unsupported language: xx-XX",
+                })
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_language_fails_without_a_fallback_language() {
+        let (output, _output_receiver) = text_conversation();
+        let mut attempts = Vec::new();
+
+        let result =
+            connect_with_fallback_language(&output, "xx-XX", None, async |language: &str| {
+                connect_backend(&mut attempts, language).await
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts, ["xx-XX"]);
+    }
 
     #[test]
     fn bcp47_to_iso639_3_for_primary_language_tags() {
@@ -0,0 +1,140 @@
+//! Provider latency per request, reported on the control path for SLA monitoring.
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{ConversationOutput, OutputPath, RequestId};
+
+/// Measures how long a provider takes to respond to a request.
+#[derive(Debug)]
+pub struct LatencyTimer {
+    sent: Instant,
+    first_result: Option<Duration>,
+}
+
+impl LatencyTimer {
+    /// Starts measuring when the request is sent to the provider.
+    pub fn start() -> Self {
+        Self {
+            sent: Instant::now(),
+            first_result: None,
+        }
+    }
+
+    /// Called for each result the provider sends, only the first one is recorded.
+    pub fn result_received(&mut self) {
+        self.first_result.get_or_insert_with(|| self.sent.elapsed());
+    }
+
+    /// Sends the latencies of the completed request as a `providerLatency` service event.
+    pub fn report(&self, output: &ConversationOutput, request_id: Option<RequestId>) -> Result<()> {
+        let latency = ProviderLatency {
+            request_id,
+            first_result_ms: self.first_result.map(|d| d.as_millis() as u64),
+            total_ms: self.sent.elapsed().as_millis() as u64,
+        };
+        output.service_event(OutputPath::Control, latency)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "providerLatency", rename_all = "camelCase")]
+pub struct ProviderLatency {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<RequestId>,
+    /// The time from sending the request until the first audio or text arrived, `None` if the
+    /// provider did not return any.
+    pub first_result_ms: Option<u64>,
+    /// The time from sending the request until it completed.
+    pub total_ms: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::{
+        AudioFormat, AudioFrame, Conversation, Input, InputModality, Output, OutputModality,
+        Service,
+    };
+
+    /// Responds with audio after 150ms and completes each request 50ms later.
+    #[derive(Debug)]
+    struct DelayedProvider;
+
+    #[async_trait]
+    impl Service for DelayedProvider {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), conversation: Conversation) -> Result<()> {
+            let format = conversation.require_single_audio_output()?;
+            let (mut input, output) = conversation.start()?;
+            while let Some(Input::Text { request_id, .. }) = input.recv().await {
+                let mut latency = LatencyTimer::start();
+                sleep(Duration::from_millis(150)).await;
+                for _ in 0..2 {
+                    latency.result_received();
+                    output.audio_frame(AudioFrame {
+                        format,
+                        samples: vec![0; 160],
+                    })?;
+                    sleep(Duration::from_millis(25)).await;
+                }
+                latency.report(&output, request_id.clone())?;
+                output.request_completed(request_id)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn latency_of_the_provider_is_reported() {
+        let format = AudioFormat::new(1, 16000);
+        let (input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let conversation = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        );
+        input_sender
+            .send(Input::Text {
+                request_id: Some(RequestId::from("r".to_string())),
+                text: "Hello".into(),
+                text_type: None,
+                billing_scope: None,
+            })
+            .await
+            .unwrap();
+        drop(input_sender);
+        DelayedProvider
+            .conversation((), conversation)
+            .await
+            .unwrap();
+
+        let mut latencies = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            if let Output::ServiceEvent { path, value } = output {
+                assert_eq!(path, OutputPath::Control);
+                latencies.push(serde_json::from_value::<ProviderLatency>(value).unwrap());
+            }
+        }
+        let [latency] = &latencies[..] else {
+            panic!("Expected one latency event, got {latencies:?}");
+        };
+        assert_eq!(latency.request_id, Some(RequestId::from("r".to_string())));
+        let first_result_ms = latency.first_result_ms.unwrap();
+        assert!((150..250).contains(&first_result_ms), "{first_result_ms}");
+        assert!(
+            (200..300).contains(&latency.total_ms),
+            "{}",
+            latency.total_ms
+        );
+    }
+}
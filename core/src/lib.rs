@@ -1,13 +1,27 @@
 pub mod audio;
+pub mod audio_ring_buffer;
+pub mod barge_in;
 pub mod billing_collector;
 mod billing_context;
+pub mod conditioning;
 mod conversation;
 mod duration;
+pub mod frame_chunker;
 pub mod language;
+pub mod latency;
+pub mod loudness;
+mod output_modalities;
+mod preprocessing;
 mod protocol;
 mod registry;
 pub mod service;
 pub mod speech_gate;
+pub mod spoken_text;
+pub mod stable_interim;
+pub mod text;
+mod text_encoding;
+pub mod trace;
+pub mod transcript;
 mod turn_detection;
 
 use std::time;
@@ -18,10 +32,13 @@ use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender, unbounded_chan
 pub use billing_context::BillingContext;
 pub use conversation::*;
 pub use duration::Duration;
+pub use output_modalities::OutputModalities;
+pub use preprocessing::{AgcParams, Preprocessing, Preprocessor, SpeechGateParams};
 pub use protocol::*;
 pub use registry::*;
 pub use service::Service;
-pub use turn_detection::{ThresholdLevel, TurnDetection};
+pub use text_encoding::TextEncoding;
+pub use turn_detection::{ThresholdLevel, TurnDetection, TurnDetector, TurnSignal};
 
 /// A unidirectional audio message. Useful for implementing an audio transfer channel.
 #[derive(Debug)]
@@ -64,7 +81,8 @@ impl AudioMsgProducer {
         })
     }
 
-    /// Sends an audio frame.
+    /// Sends an audio frame. Multi-channel samples are expected to be interleaved, see
+    /// [`AudioFrame::samples`].
     pub fn send_frame(&self, frame: AudioFrame) -> Result<()> {
         if frame.format != self.format {
             bail!(
@@ -153,6 +171,8 @@ pub fn audio_channel(format: AudioFormat) -> (AudioProducer, AudioConsumer) {
 #[derive(Debug, Clone)]
 pub struct AudioFrame {
     pub format: AudioFormat,
+    /// The samples of all channels, interleaved: Channel `j` of the `i`th sample is at
+    /// `samples[i * channels + j]`.
     pub samples: Vec<i16>,
 }
 
@@ -162,6 +182,21 @@ impl AudioFrame {
         Self { format, samples }
     }
 
+    /// Like [`Self::from_le_bytes`], but rejects empty buffers and buffers with an odd number of
+    /// bytes, which `from_le_bytes` would silently truncate.
+    pub fn from_le_bytes_checked(format: AudioFormat, bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() {
+            bail!("Empty audio frame");
+        }
+        if !bytes.len().is_multiple_of(2) {
+            bail!(
+                "Audio frame has an odd number of bytes ({}), expected 16 bit samples",
+                bytes.len()
+            );
+        }
+        Ok(Self::from_le_bytes(format, bytes))
+    }
+
     pub fn to_le_bytes(&self) -> Vec<u8> {
         audio::to_le_bytes(&self.samples)
     }
@@ -170,21 +205,18 @@ impl AudioFrame {
         self.format.duration(self.samples.len())
     }
 
+    /// Downmixes the interleaved channels by averaging them.
     pub fn into_mono(self) -> AudioFrame {
         let format = self.format;
         if format.channels == 1 {
             return self;
         }
-        let samples_per_channel = self.samples.len() / format.channels as usize;
-        let mut mono_samples = vec![0; samples_per_channel];
         let channels_i32 = format.channels as i32;
-
-        (0..samples_per_channel).for_each(|i| {
-            mono_samples[i] = ((0..format.channels)
-                .map(|j| self.samples[i + j as usize * samples_per_channel] as i32)
-                .sum::<i32>()
-                / channels_i32) as i16;
-        });
+        let mono_samples = self
+            .samples
+            .chunks_exact(format.channels as usize)
+            .map(|sample| (sample.iter().map(|&s| s as i32).sum::<i32>() / channels_i32) as i16)
+            .collect();
 
         AudioFrame {
             format: AudioFormat {
@@ -194,4 +226,105 @@ impl AudioFrame {
             samples: mono_samples,
         }
     }
+
+    /// Converts the frame to `format`. Multiple channels can only be mixed down to mono, and only
+    /// mono audio is resampled.
+    pub fn convert_to(self, format: AudioFormat) -> Result<AudioFrame> {
+        if self.format == format {
+            return Ok(self);
+        }
+        let frame = match (self.format.channels, format.channels) {
+            (from, to) if from == to => self,
+            (_, 1) => self.into_mono(),
+            (from, to) => bail!("Can't convert audio from {from} to {to} channels"),
+        };
+        if frame.format.sample_rate == format.sample_rate {
+            return Ok(frame);
+        }
+        if frame.format.channels != 1 {
+            bail!("Only mono audio can be resampled");
+        }
+        Ok(frame.resample(format.sample_rate))
+    }
+
+    /// Resamples mono audio by linear interpolation.
+    fn resample(self, sample_rate: u32) -> AudioFrame {
+        let format = AudioFormat::new(1, sample_rate);
+        let Some(last) = self.samples.len().checked_sub(1) else {
+            return AudioFrame {
+                format,
+                samples: Vec::new(),
+            };
+        };
+        let ratio = self.format.sample_rate as f64 / sample_rate as f64;
+        let len = (self.samples.len() as f64 / ratio).round() as usize;
+        let samples = (0..len)
+            .map(|i| {
+                let position = i as f64 * ratio;
+                let index = position as usize;
+                let current = self.samples[index.min(last)] as f64;
+                let next = self.samples[(index + 1).min(last)] as f64;
+                (current + (next - current) * position.fract()).round() as i16
+            })
+            .collect();
+        AudioFrame { format, samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaved_stereo_is_downmixed_to_mono() {
+        let frame = AudioFrame {
+            format: AudioFormat::new(2, 16000),
+            // L = [1, 3, 5], R = [2, 4, 6]
+            samples: vec![1, 2, 3, 4, 5, 6],
+        };
+        let mono = frame.into_mono();
+        assert_eq!(mono.format, AudioFormat::new(1, 16000));
+        // Averages of 1.5, 3.5 and 5.5, truncated.
+        assert_eq!(mono.samples, [1, 3, 5]);
+    }
+
+    #[test]
+    fn mono_audio_is_resampled_by_interpolation() {
+        let frame = AudioFrame {
+            format: AudioFormat::new(1, 8000),
+            samples: vec![0, 100, 200, 300],
+        };
+        let converted = frame.convert_to(AudioFormat::new(1, 16000)).unwrap();
+        assert_eq!(converted.format, AudioFormat::new(1, 16000));
+        assert_eq!(converted.samples, [0, 50, 100, 150, 200, 250, 300, 300]);
+    }
+
+    #[test]
+    fn mono_audio_is_not_converted_to_multiple_channels() {
+        let frame = AudioFrame {
+            format: AudioFormat::new(1, 8000),
+            samples: vec![0; 4],
+        };
+        assert!(frame.convert_to(AudioFormat::new(2, 8000)).is_err());
+    }
+
+    #[test]
+    fn odd_length_audio_frame_is_rejected() {
+        let format = AudioFormat::new(1, 16000);
+        let error = AudioFrame::from_le_bytes_checked(format, &[1, 0, 2]).unwrap_err();
+        assert!(error.to_string().contains("odd number of bytes"));
+    }
+
+    #[test]
+    fn empty_audio_frame_is_rejected() {
+        let format = AudioFormat::new(1, 16000);
+        assert!(AudioFrame::from_le_bytes_checked(format, &[]).is_err());
+    }
+
+    #[test]
+    fn valid_audio_frame_is_decoded() {
+        let format = AudioFormat::new(1, 16000);
+        let frame = AudioFrame::from_le_bytes_checked(format, &[1, 0, 0xff, 0xff]).unwrap();
+        assert_eq!(frame.samples, [1, -1]);
+    }
 }
@@ -0,0 +1,264 @@
+//! Loudness normalization of outbound audio based on the ITU-R BS.1770 loudness measurement.
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::{AudioFrame, audio};
+
+/// Loudness is measured in non-overlapping blocks of this duration.
+const BLOCK_MS: u32 = 100;
+/// The number of blocks the loudness is integrated over (3 seconds, like the short-term loudness
+/// of EBU R128).
+const WINDOW_BLOCKS: usize = 30;
+/// Blocks below this loudness are considered silence and are ignored.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Limits the amplification of quiet audio.
+const MAX_GAIN_DB: f64 = 20.0;
+const ATTACK_MS: f64 = 100.0;
+const RELEASE_MS: f64 = 1000.0;
+
+/// Returns a processing function that normalizes the loudness of mono audio toward `target_lufs`,
+/// for example `-16.0`.
+///
+/// The loudness is integrated over a sliding window of 3 seconds and the resulting gain is
+/// smoothed. The gain is reduced faster than it is raised, and it is kept in silence, so that
+/// pauses are not amplified. Peaks the gain pushes toward full scale are soft limited.
+pub fn make_loudness_normalizer(
+    target_lufs: f32,
+) -> Box<dyn FnMut(&AudioFrame) -> AudioFrame + Send + Sync> {
+    let target_lufs = target_lufs as f64;
+    let mut state: Option<(LoudnessMeter, f64, f64)> = None;
+    let mut gain = 1.0f64;
+
+    Box::new(move |frame: &AudioFrame| {
+        let (meter, attack_coeff, release_coeff) = state.get_or_insert_with(|| {
+            let sr = frame.format.sample_rate as f64;
+            let coeff = |ms: f64| (-1.0 / (ms * 0.001 * sr)).exp();
+            (
+                LoudnessMeter::new(frame.format.sample_rate),
+                coeff(ATTACK_MS),
+                coeff(RELEASE_MS),
+            )
+        });
+
+        let samples = frame
+            .samples
+            .iter()
+            .map(|&s| {
+                let sample = s as f64 / 32768.0;
+                meter.add(sample);
+
+                let desired_gain = match meter.loudness() {
+                    Some(loudness) => db_to_gain((target_lufs - loudness).min(MAX_GAIN_DB)),
+                    None => gain,
+                };
+                let coeff = if desired_gain < gain {
+                    *attack_coeff
+                } else {
+                    *release_coeff
+                };
+                gain = coeff * (gain - desired_gain) + desired_gain;
+
+                (audio::soft_limit((sample * gain) as f32) * 32768.0) as i16
+            })
+            .collect();
+
+        AudioFrame {
+            format: frame.format,
+            samples,
+        }
+    })
+}
+
+fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Measures the loudness of K-weighted audio over a sliding window of blocks.
+struct LoudnessMeter {
+    filters: [Biquad; 2],
+    block_len: usize,
+    block_energy: f64,
+    block_samples: usize,
+    /// The mean square of the most recent blocks.
+    blocks: VecDeque<f64>,
+    loudness: Option<f64>,
+}
+
+impl LoudnessMeter {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            filters: [
+                Biquad::high_shelf(sample_rate),
+                Biquad::high_pass(sample_rate),
+            ],
+            block_len: (sample_rate * BLOCK_MS / 1000).max(1) as usize,
+            block_energy: 0.0,
+            block_samples: 0,
+            blocks: VecDeque::with_capacity(WINDOW_BLOCKS + 1),
+            loudness: None,
+        }
+    }
+
+    fn add(&mut self, sample: f64) {
+        let weighted = self
+            .filters
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample));
+
+        self.block_energy += weighted * weighted;
+        self.block_samples += 1;
+        if self.block_samples < self.block_len {
+            return;
+        }
+
+        self.blocks
+            .push_back(self.block_energy / self.block_samples as f64);
+        if self.blocks.len() > WINDOW_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.block_energy = 0.0;
+        self.block_samples = 0;
+        self.loudness = gated_loudness(self.blocks.iter().copied());
+    }
+
+    /// The loudness of the window in LUFS, `None` if it contains silence only.
+    fn loudness(&self) -> Option<f64> {
+        self.loudness
+    }
+}
+
+fn gated_loudness(blocks: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = blocks
+        .filter(|&mean_square| lufs(mean_square) > ABSOLUTE_GATE_LUFS)
+        .fold((0.0, 0), |(sum, count), mean_square| {
+            (sum + mean_square, count + 1)
+        });
+    if count == 0 {
+        return None;
+    }
+    Some(lufs(sum / count as f64))
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// A biquad filter in transposed direct form II.
+///
+/// The coefficients of the two K-weighting stages are computed for arbitrary sample rates as in
+/// libebur128.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// Stage 1: Models the acoustic effect of the head.
+    fn high_shelf(sample_rate: u32) -> Self {
+        let f0 = 1681.974450955533;
+        let g = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * f0 / sample_rate as f64).tan();
+        let vh = 10f64.powf(g / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// Stage 2: The RLB weighting curve.
+    fn high_pass(sample_rate: u32) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (PI * f0 / sample_rate as f64).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.z[0];
+        self.z[0] = self.b[1] * input - self.a[0] * output + self.z[1];
+        self.z[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+    use crate::AudioFormat;
+
+    const SAMPLE_RATE: u32 = 16000;
+
+    fn sine(amplitude: f64, seconds: u32) -> Vec<i16> {
+        (0..SAMPLE_RATE * seconds)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                (amplitude * (TAU * 1000.0 * t).sin() * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    fn loudness(samples: &[i16]) -> f64 {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        let mut blocks = Vec::new();
+        for &s in samples {
+            meter.add(s as f64 / 32768.0);
+            if meter.block_samples == 0 {
+                blocks.push(*meter.blocks.back().unwrap());
+            }
+        }
+        gated_loudness(blocks.into_iter()).unwrap()
+    }
+
+    #[test]
+    fn segments_of_differing_loudness_converge_to_the_target() {
+        const TARGET_LUFS: f32 = -16.0;
+        const SEGMENT_SECONDS: u32 = 8;
+        const MEASURED_SECONDS: usize = 2;
+
+        let format = AudioFormat::new(1, SAMPLE_RATE);
+        let mut normalize = make_loudness_normalizer(TARGET_LUFS);
+
+        let segments = [sine(0.05, SEGMENT_SECONDS), sine(0.6, SEGMENT_SECONDS)];
+        assert!(loudness(&segments[1]) - loudness(&segments[0]) > 20.0);
+
+        for segment in segments {
+            let output: Vec<i16> = segment
+                .chunks(SAMPLE_RATE as usize / 50)
+                .flat_map(|samples| {
+                    normalize(&AudioFrame {
+                        format,
+                        samples: samples.to_vec(),
+                    })
+                    .samples
+                })
+                .collect();
+
+            let tail = &output[output.len() - MEASURED_SECONDS * SAMPLE_RATE as usize..];
+            let loudness = loudness(tail);
+            assert!(
+                (loudness - TARGET_LUFS as f64).abs() < 1.0,
+                "Loudness: {loudness} LUFS"
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{Result, bail};
+
+use crate::{AudioFormat, OutputModality};
+
+/// The output modalities of a conversation and the queries services use to validate them.
+#[derive(Debug, Clone, Default)]
+pub struct OutputModalities(Vec<OutputModality>);
+
+impl From<Vec<OutputModality>> for OutputModalities {
+    fn from(modalities: Vec<OutputModality>) -> Self {
+        Self(modalities)
+    }
+}
+
+impl OutputModalities {
+    pub fn as_slice(&self) -> &[OutputModality] {
+        &self.0
+    }
+
+    pub fn to_vec(&self) -> Vec<OutputModality> {
+        self.0.clone()
+    }
+
+    /// The format of the first audio output.
+    pub fn audio(&self) -> Option<AudioFormat> {
+        self.0.iter().find_map(|modality| match modality {
+            OutputModality::Audio { format } => Some(*format),
+            OutputModality::Text | OutputModality::InterimText => None,
+        })
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audio().is_some()
+    }
+
+    /// Returns `true` if final text is requested.
+    pub fn text(&self) -> bool {
+        self.0
+            .iter()
+            .any(|modality| matches!(modality, OutputModality::Text))
+    }
+
+    /// Returns `true` if interim text is requested.
+    pub fn interim_text(&self) -> bool {
+        self.0
+            .iter()
+            .any(|modality| matches!(modality, OutputModality::InterimText))
+    }
+
+    /// Extract the audio format of a single audio output. If there are more than one audio output
+    /// modalities, this function will fail.
+    pub fn require_one_audio_output(&self) -> Result<AudioFormat> {
+        let mut audio_outputs = self
+            .0
+            .iter()
+            .filter(|m| matches!(m, OutputModality::Audio { .. }));
+        let Some(OutputModality::Audio { format }) = audio_outputs.next() else {
+            bail!("Expecting one audio output");
+        };
+        if audio_outputs.next().is_some() {
+            bail!("Expecting one audio output");
+        }
+        Ok(*format)
+    }
+
+    /// Returns `true` if there is one single `Text` output. Interim text is not considered. If
+    /// there is none, this function returns `false`, if there is more than one, this function fails.
+    pub fn has_one_text_output(&self) -> Result<bool> {
+        let count = self
+            .0
+            .iter()
+            .filter(|m| matches!(m, OutputModality::Text))
+            .count();
+        match count {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => bail!("Expecting at most one text output"),
+        }
+    }
+
+    pub fn require_single_audio_output(&self) -> Result<AudioFormat> {
+        match self.0.as_slice() {
+            [OutputModality::Audio { format }] => Ok(*format),
+            _ => bail!("Expect single audio output"),
+        }
+    }
+
+    pub fn require_text_output(&self, supports_interim_text: bool) -> Result<()> {
+        for modality in &self.0 {
+            match modality {
+                OutputModality::Audio { .. } => bail!("No audio output expected"),
+                OutputModality::Text => {}
+                OutputModality::InterimText => {
+                    if !supports_interim_text {
+                        bail!("Interim text is unsupported")
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio() -> OutputModality {
+        OutputModality::Audio {
+            format: AudioFormat::new(1, 16000),
+        }
+    }
+
+    fn modalities(modalities: &[OutputModality]) -> OutputModalities {
+        modalities.to_vec().into()
+    }
+
+    #[test]
+    fn audio_queries() {
+        let audio_only = modalities(&[audio()]);
+        assert_eq!(audio_only.audio(), Some(AudioFormat::new(1, 16000)));
+        assert!(audio_only.has_audio());
+        assert!(audio_only.require_one_audio_output().is_ok());
+        assert!(audio_only.require_single_audio_output().is_ok());
+
+        let audio_and_text = modalities(&[OutputModality::Text, audio()]);
+        assert!(audio_and_text.has_audio());
+        assert!(audio_and_text.require_one_audio_output().is_ok());
+        assert!(audio_and_text.require_single_audio_output().is_err());
+
+        let two_audio = modalities(&[audio(), audio()]);
+        assert!(two_audio.require_one_audio_output().is_err());
+
+        let text_only = modalities(&[OutputModality::Text]);
+        assert_eq!(text_only.audio(), None);
+        assert!(!text_only.has_audio());
+        assert!(text_only.require_one_audio_output().is_err());
+    }
+
+    #[test]
+    fn text_queries() {
+        let none = modalities(&[]);
+        assert!(!none.text());
+        assert!(!none.interim_text());
+        assert!(!none.has_one_text_output().unwrap());
+
+        let text = modalities(&[OutputModality::Text]);
+        assert!(text.text());
+        assert!(!text.interim_text());
+        assert!(text.has_one_text_output().unwrap());
+
+        let interim = modalities(&[OutputModality::Text, OutputModality::InterimText]);
+        assert!(interim.text());
+        assert!(interim.interim_text());
+        assert!(interim.has_one_text_output().unwrap());
+
+        let two_texts = modalities(&[OutputModality::Text, OutputModality::Text]);
+        assert!(two_texts.has_one_text_output().is_err());
+    }
+
+    #[test]
+    fn require_text_output() {
+        let interim = modalities(&[OutputModality::Text, OutputModality::InterimText]);
+        assert!(interim.require_text_output(true).is_ok());
+        assert!(interim.require_text_output(false).is_err());
+
+        let audio_and_text = modalities(&[OutputModality::Text, audio()]);
+        assert!(audio_and_text.require_text_output(true).is_err());
+
+        assert!(modalities(&[]).require_text_output(false).is_ok());
+    }
+}
@@ -0,0 +1,78 @@
+//! Preprocessing of a conversation's input audio before it is passed to the service.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AudioFrame, conditioning::make_agc_processor, speech_gate::make_speech_gate_processor,
+};
+
+/// The preprocessing steps a client requests for a conversation's input audio.
+///
+/// The steps are applied in the order of the fields: Automatic gain control first, so that the
+/// speech gate's threshold is relative to a normalized level.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Preprocessing {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agc: Option<AgcParams>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speech_gate: Option<SpeechGateParams>,
+}
+
+/// Parameters of the automatic gain control, see [`make_agc_processor`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgcParams {
+    pub target_rms: f32,
+    pub max_gain: f32,
+}
+
+/// Parameters of the speech gate, see [`make_speech_gate_processor`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeechGateParams {
+    pub threshold: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Preprocessing {
+    pub fn make_preprocessor(&self) -> Preprocessor {
+        let mut processors: Vec<Processor> = Vec::new();
+        if let Some(agc) = &self.agc {
+            processors.push(make_agc_processor(agc.target_rms, agc.max_gain));
+        }
+        if let Some(gate) = &self.speech_gate {
+            processors.push(make_speech_gate_processor(
+                gate.threshold,
+                gate.attack_ms,
+                gate.release_ms,
+            ));
+        }
+        Preprocessor { processors }
+    }
+}
+
+/// Applies the requested preprocessing steps to the frames of one conversation.
+pub struct Preprocessor {
+    processors: Vec<Processor>,
+}
+
+type Processor = Box<dyn FnMut(&AudioFrame) -> AudioFrame + Send + Sync>;
+
+impl fmt::Debug for Preprocessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Preprocessor")
+            .field("processors", &self.processors.len())
+            .finish()
+    }
+}
+
+impl Preprocessor {
+    pub fn process(&mut self, frame: AudioFrame) -> AudioFrame {
+        self.processors
+            .iter_mut()
+            .fold(frame, |frame, process| process(&frame))
+    }
+}
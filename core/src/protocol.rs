@@ -50,6 +50,20 @@ pub enum InputModality {
 }
 
 impl InputModality {
+    pub fn require_text_input_only(&self) -> Result<()> {
+        match self {
+            InputModality::Audio { .. } => bail!("Audio input is not supported"),
+            InputModality::Text => Ok(()),
+        }
+    }
+
+    pub fn require_audio_input(&self) -> Result<AudioFormat> {
+        match self {
+            InputModality::Audio { format } => Ok(*format),
+            InputModality::Text => bail!("Audio input is required"),
+        }
+    }
+
     pub fn can_receive_audio(&self, input_format: AudioFormat) -> bool {
         matches!(self, InputModality::Audio { format } if *format == input_format)
     }
@@ -88,10 +102,7 @@ impl BillingRecord {
     }
 
     pub fn is_zero(&self) -> bool {
-        match &self.value {
-            BillingRecordValue::Duration { duration } => duration.is_zero(),
-            BillingRecordValue::Count { count } => *count == 0,
-        }
+        self.value.is_zero()
     }
 }
 
@@ -123,6 +134,31 @@ impl BillingRecordValue {
             _ => bail!("Internal error: Incompatible billing record values."),
         }
     }
+
+    /// Deducts another value, saturating at zero. Incompatible values are ignored.
+    pub fn deduct(&mut self, other: &Self) {
+        match (self, other) {
+            (BillingRecordValue::Count { count }, BillingRecordValue::Count { count: count_r }) => {
+                *count = count.saturating_sub(*count_r);
+            }
+            (
+                BillingRecordValue::Duration { duration },
+                BillingRecordValue::Duration {
+                    duration: duration_r,
+                },
+            ) => {
+                *duration = duration.saturating_sub(**duration_r).into();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            BillingRecordValue::Duration { duration } => duration.is_zero(),
+            BillingRecordValue::Count { count } => *count == 0,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
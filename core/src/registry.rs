@@ -5,7 +5,7 @@ use async_trait::async_trait;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 
-use crate::{Service, conversation::Conversation};
+use crate::{InputModality, OutputModalities, Service, conversation::Conversation};
 
 #[derive(Debug)]
 pub struct Registry {
@@ -36,12 +36,95 @@ impl Registry {
         self.services.insert(name, service);
         self
     }
+
+    /// Adds a service with default params.
+    ///
+    /// The params of a conversation are merged into the default params before they are
+    /// deserialized. Objects are merged recursively, all other values of the conversation's
+    /// params take precedence.
+    #[must_use]
+    pub fn add_service_with_default_params(
+        self,
+        name: &'static str,
+        service: impl WrappedService + Send + Sync + 'static,
+        default_params: Value,
+    ) -> Self {
+        self.add_service(
+            name,
+            WithDefaultParams {
+                service,
+                default_params,
+            },
+        )
+    }
+
+    /// Shut down all registered services.
+    pub async fn shutdown(&self) {
+        for service in self.services.values() {
+            service.shutdown().await;
+        }
+    }
 }
 
 /// We wrap the service to able to do Parameters deserialization.
 #[async_trait]
 pub trait WrappedService: fmt::Debug {
     async fn converse(&self, params: Value, conversation: Conversation) -> Result<()>;
+    /// Deserialize the params and check the modalities without starting a conversation.
+    fn validate(
+        &self,
+        params: &Value,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()>;
+    async fn shutdown(&self);
+}
+
+#[derive(Debug)]
+struct WithDefaultParams<S> {
+    service: S,
+    default_params: Value,
+}
+
+#[async_trait]
+impl<S: WrappedService + Send + Sync> WrappedService for WithDefaultParams<S> {
+    async fn converse(&self, params: Value, conversation: Conversation) -> Result<()> {
+        let params = merge_params(self.default_params.clone(), params);
+        self.service.converse(params, conversation).await
+    }
+
+    fn validate(
+        &self,
+        params: &Value,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        let params = merge_params(self.default_params.clone(), params.clone());
+        self.service
+            .validate(&params, input_modality, output_modalities)
+    }
+
+    async fn shutdown(&self) {
+        self.service.shutdown().await
+    }
+}
+
+/// Merges `params` into `defaults`. Missing or null params leave the defaults as they are.
+fn merge_params(defaults: Value, params: Value) -> Value {
+    match (defaults, params) {
+        (defaults, Value::Null) => defaults,
+        (Value::Object(mut defaults), Value::Object(params)) => {
+            for (key, value) in params {
+                let merged = match defaults.remove(&key) {
+                    Some(default) => merge_params(default, value),
+                    None => value,
+                };
+                defaults.insert(key, merged);
+            }
+            Value::Object(defaults)
+        }
+        (_, params) => params,
+    }
 }
 
 #[async_trait]
@@ -54,4 +137,96 @@ where
             serde_json::from_value(params).context("Failed to deserialize service params")?;
         T::conversation(self, params, conversation).await
     }
+
+    fn validate(
+        &self,
+        params: &Value,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        P::deserialize(params).context("Failed to deserialize service params")?;
+        T::check_modalities(self, input_modality, output_modalities)
+    }
+
+    async fn shutdown(&self) {
+        T::shutdown(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+    use tokio::sync::mpsc::{UnboundedSender, channel, unbounded_channel};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Params {
+        api_key: String,
+        region: String,
+        model: Model,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Model {
+        name: String,
+        version: u32,
+    }
+
+    /// Reports the params it is started with.
+    #[derive(Debug)]
+    struct ParamsService {
+        params: UnboundedSender<Params>,
+    }
+
+    #[async_trait]
+    impl Service for ParamsService {
+        type Params = Params;
+
+        async fn conversation(&self, params: Params, _conversation: Conversation) -> Result<()> {
+            self.params.send(params)?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn conversation_params_are_merged_into_the_default_params() {
+        let (params_sender, mut params_receiver) = unbounded_channel();
+        let registry = Registry::empty().add_service_with_default_params(
+            "service",
+            ParamsService {
+                params: params_sender,
+            },
+            json!({
+                "region": "westeurope",
+                "model": { "name": "default", "version": 1 }
+            }),
+        );
+        let params = json!({ "apiKey": "key", "model": { "version": 2 } });
+
+        let service = registry.service("service").unwrap();
+        service
+            .validate(&params, InputModality::Text, &OutputModalities::default())
+            .unwrap();
+
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, _output_receiver) = unbounded_channel();
+        let conversation =
+            Conversation::new(InputModality::Text, [], input_receiver, output_sender);
+        service.converse(params, conversation).await.unwrap();
+
+        assert_eq!(
+            params_receiver.recv().await.unwrap(),
+            Params {
+                api_key: "key".into(),
+                region: "westeurope".into(),
+                model: Model {
+                    name: "default".into(),
+                    version: 2,
+                },
+            }
+        );
+    }
 }
@@ -17,7 +17,7 @@ use anyhow::Result;
 use async_trait::async_trait;
 use serde::de::DeserializeOwned;
 
-use crate::conversation::Conversation;
+use crate::{InputModality, OutputModalities, conversation::Conversation};
 
 #[async_trait]
 pub trait Service: fmt::Debug {
@@ -32,4 +32,23 @@ pub trait Service: fmt::Debug {
     ///
     /// If invalid or unexpected input is received, the function **must** terminate with an error.
     async fn conversation(&self, params: Self::Params, conversation: Conversation) -> Result<()>;
+
+    /// Check if the service supports the modalities of a conversation without starting it.
+    ///
+    /// This is used to validate start events up front. Services must still check the modalities
+    /// in [`Self::conversation`]. The default accepts all modalities.
+    fn check_modalities(
+        &self,
+        _input_modality: InputModality,
+        _output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Release resources that are shared between conversations, like pooled connections or
+    /// background tasks.
+    ///
+    /// Called once when the registry the service is part of is torn down. Conversations that are
+    /// still shutting down gracefully may run concurrently. The default does nothing.
+    async fn shutdown(&self) {}
 }
@@ -4,7 +4,7 @@ pub fn make_speech_gate_processor(
     threshold: f32,
     attack_ms: f32,
     release_ms: f32,
-) -> Box<dyn FnMut(&AudioFrame) -> AudioFrame> {
+) -> Box<dyn FnMut(&AudioFrame) -> AudioFrame + Send + Sync> {
     // soft: (knee_width / threshold)
     // 0.01 / 0.0075 (best so far)
     // 0.05 / 0.025
@@ -225,10 +225,7 @@ pub fn make_speech_gate_processor_soft_rms(
     release_ms: f32,
     knee_width: f32,
 ) -> Box<dyn FnMut(&AudioFrame) -> AudioFrame + Send + Sync> {
-    let mut envelope = 0.0f32;
-    let mut sample_rate: Option<f32> = None;
-    let mut attack_coeff = 0.0f32;
-    let mut release_coeff = 0.0f32;
+    let mut follower = Envelope::new(attack_ms, release_ms);
 
     // For RMS calculation
     const RMS_WINDOW_SIZE: usize = 16; // Small window for responsive detection
@@ -236,13 +233,6 @@ pub fn make_speech_gate_processor_soft_rms(
     let mut buffer_pos = 0;
 
     Box::new(move |frame: &AudioFrame| {
-        if sample_rate.is_none() {
-            let sr = frame.format.sample_rate as f32;
-            sample_rate = Some(sr);
-            attack_coeff = (-1.0 / (attack_ms * 0.001 * sr)).exp();
-            release_coeff = (-1.0 / (release_ms * 0.001 * sr)).exp();
-        }
-
         // Pre-calculate threshold boundaries
         let lower_threshold = threshold - knee_width;
         let upper_threshold = threshold + knee_width;
@@ -259,11 +249,7 @@ pub fn make_speech_gate_processor_soft_rms(
             let energy = rms_buffer.iter().sum::<f32>() / RMS_WINDOW_SIZE as f32;
 
             // Apply envelope follower with different attack/release
-            if energy > envelope {
-                envelope = attack_coeff * (envelope - energy) + energy;
-            } else {
-                envelope = release_coeff * (envelope - energy) + energy;
-            }
+            let envelope = follower.follow(frame.format.sample_rate, energy);
 
             // Apply soft knee gate
             let gain = if envelope >= upper_threshold {
@@ -285,3 +271,42 @@ pub fn make_speech_gate_processor_soft_rms(
         }
     })
 }
+
+/// Follows the energy of a signal with separate attack and release times.
+#[derive(Debug)]
+pub(crate) struct Envelope {
+    attack_ms: f32,
+    release_ms: f32,
+    /// The attack and release coefficients, initialized with the sample rate of the first frame.
+    coefficients: Option<(f32, f32)>,
+    level: f32,
+}
+
+impl Envelope {
+    pub(crate) fn new(attack_ms: f32, release_ms: f32) -> Self {
+        Self {
+            attack_ms,
+            release_ms,
+            coefficients: None,
+            level: 0.0,
+        }
+    }
+
+    /// Feeds the energy of the next sample and returns the new level of the envelope.
+    pub(crate) fn follow(&mut self, sample_rate: u32, energy: f32) -> f32 {
+        let (attack_coeff, release_coeff) = *self.coefficients.get_or_insert_with(|| {
+            let sr = sample_rate as f32;
+            (
+                (-1.0 / (self.attack_ms * 0.001 * sr)).exp(),
+                (-1.0 / (self.release_ms * 0.001 * sr)).exp(),
+            )
+        });
+        let coeff = if energy > self.level {
+            attack_coeff
+        } else {
+            release_coeff
+        };
+        self.level = coeff * (self.level - energy) + energy;
+        self.level
+    }
+}
@@ -0,0 +1,505 @@
+//! Expansion of dates and currency amounts into spoken words before speech synthesis.
+//!
+//! Synthesizers tend to read ISO dates like `2024-01-05` digit by digit and are inconsistent with
+//! currency amounts like `$1,234.56`.
+use anyhow::{Result, bail};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpokenLanguage {
+    English,
+    German,
+}
+
+impl SpokenLanguage {
+    /// Resolves the language from a locale like `en-US`, `de_DE`, or `de`.
+    pub fn from_locale(locale: &str) -> Result<Self> {
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        match language.to_lowercase().as_str() {
+            "en" => Ok(Self::English),
+            "de" => Ok(Self::German),
+            _ => bail!("Spoken text normalization is not supported for `{locale}`"),
+        }
+    }
+
+    /// The thousands and the decimal separator.
+    fn separators(self) -> (char, char) {
+        match self {
+            Self::English => (',', '.'),
+            Self::German => ('.', ','),
+        }
+    }
+}
+
+/// Replaces ISO dates (`2024-01-05`) and currency amounts (`$1,234.56`, `1.234,56 €`) with their
+/// spoken form. All other text is kept.
+pub fn normalize_for_speech(text: &str, language: SpokenLanguage) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut after_digit = false;
+    while let Some(c) = rest.chars().next() {
+        if !after_digit
+            && let Some((spoken, len)) =
+                match_date(rest, language).or_else(|| match_currency(rest, language))
+        {
+            result.push_str(&spoken);
+            rest = &rest[len..];
+            after_digit = false;
+            continue;
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+        after_digit = c.is_ascii_digit();
+    }
+    result
+}
+
+/// Matches an ISO date at the start of `text` and returns its spoken form and length.
+fn match_date(text: &str, language: SpokenLanguage) -> Option<(String, usize)> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 10
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes.get(10).is_some_and(u8::is_ascii_digit)
+    {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<u32> {
+        let digits = &text[range];
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let spoken = match language {
+        SpokenLanguage::English => format!(
+            "{} {}, {}",
+            EN_MONTHS[month as usize - 1],
+            en::ordinal(day),
+            en::year(year)
+        ),
+        SpokenLanguage::German => format!(
+            "{} {} {}",
+            de::ordinal(day),
+            DE_MONTHS[month as usize - 1],
+            de::year(year)
+        ),
+    };
+    Some((spoken, 10))
+}
+
+const EN_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const DE_MONTHS: [&str; 12] = [
+    "Januar",
+    "Februar",
+    "März",
+    "April",
+    "Mai",
+    "Juni",
+    "Juli",
+    "August",
+    "September",
+    "Oktober",
+    "November",
+    "Dezember",
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Currency {
+    Dollar,
+    Euro,
+    Pound,
+}
+
+impl Currency {
+    fn from_symbol(c: char) -> Option<Self> {
+        match c {
+            '$' => Some(Self::Dollar),
+            '€' => Some(Self::Euro),
+            '£' => Some(Self::Pound),
+            _ => None,
+        }
+    }
+}
+
+/// Matches a currency amount with a leading (`$5`) or trailing (`5 €`) currency symbol at the
+/// start of `text` and returns its spoken form and length.
+fn match_currency(text: &str, language: SpokenLanguage) -> Option<(String, usize)> {
+    let first = text.chars().next()?;
+    let (currency, (units, cents), len) = match Currency::from_symbol(first) {
+        Some(currency) => {
+            let symbol_len = first.len_utf8();
+            let (amount, amount_len) = parse_amount(&text[symbol_len..], language)?;
+            (currency, amount, symbol_len + amount_len)
+        }
+        None => {
+            let (amount, amount_len) = parse_amount(text, language)?;
+            let after_amount = &text[amount_len..];
+            let spaces = after_amount.len() - after_amount.trim_start_matches(' ').len();
+            let symbol = after_amount[spaces..].chars().next()?;
+            let currency = Currency::from_symbol(symbol)?;
+            (currency, amount, amount_len + spaces + symbol.len_utf8())
+        }
+    };
+    let spoken = match language {
+        SpokenLanguage::English => en::amount(units, cents, currency),
+        SpokenLanguage::German => de::amount(units, cents, currency),
+    };
+    Some((spoken, len))
+}
+
+/// Parses an amount with optional thousands separators and up to two decimals. Returns the units,
+/// the cents, and the length of the amount.
+fn parse_amount(text: &str, language: SpokenLanguage) -> Option<((u64, u32), usize)> {
+    let (group_separator, decimal_separator) = language.separators();
+    let bytes = text.as_bytes();
+    let digits_at = |start: usize| {
+        bytes[start.min(bytes.len())..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+
+    let mut len = digits_at(0);
+    if len == 0 {
+        return None;
+    }
+    let mut units = text[..len].to_string();
+    // Thousands separators are only accepted if they separate groups of exactly three digits.
+    if len <= 3 {
+        while bytes.get(len) == Some(&(group_separator as u8)) && digits_at(len + 1) == 3 {
+            units.push_str(&text[len + 1..len + 4]);
+            len += 4;
+        }
+    }
+
+    let mut cents = 0;
+    if bytes.get(len) == Some(&(decimal_separator as u8)) {
+        let decimals = digits_at(len + 1);
+        if decimals == 1 || decimals == 2 {
+            let value: u32 = text[len + 1..len + 1 + decimals].parse().ok()?;
+            cents = if decimals == 1 { value * 10 } else { value };
+            len += 1 + decimals;
+        }
+    }
+
+    Some(((units.parse().ok()?, cents), len))
+}
+
+mod en {
+    use super::Currency;
+
+    const ONES: [&str; 20] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+
+    const SCALES: [(u64, &str); 3] = [
+        (1_000_000_000, "billion"),
+        (1_000_000, "million"),
+        (1_000, "thousand"),
+    ];
+
+    pub fn cardinal(n: u64) -> String {
+        for (scale, name) in SCALES {
+            if n >= scale {
+                let head = format!("{} {name}", cardinal(n / scale));
+                return match n % scale {
+                    0 => head,
+                    rest => format!("{head} {}", cardinal(rest)),
+                };
+            }
+        }
+        match n {
+            0..20 => ONES[n as usize].to_string(),
+            20..100 => match n % 10 {
+                0 => TENS[n as usize / 10].to_string(),
+                ones => format!("{}-{}", TENS[n as usize / 10], ONES[ones as usize]),
+            },
+            _ => match n % 100 {
+                0 => format!("{} hundred", ONES[n as usize / 100]),
+                rest => format!("{} hundred {}", ONES[n as usize / 100], cardinal(rest)),
+            },
+        }
+    }
+
+    pub fn ordinal(n: u32) -> String {
+        let cardinal = cardinal(n.into());
+        let split = cardinal.rfind(['-', ' ']).map_or(0, |i| i + 1);
+        let (head, last) = cardinal.split_at(split);
+        let last = match last {
+            "one" => "first".to_string(),
+            "two" => "second".to_string(),
+            "three" => "third".to_string(),
+            "five" => "fifth".to_string(),
+            "eight" => "eighth".to_string(),
+            "nine" => "ninth".to_string(),
+            "twelve" => "twelfth".to_string(),
+            last => match last.strip_suffix('y') {
+                Some(stem) => format!("{stem}ieth"),
+                None => format!("{last}th"),
+            },
+        };
+        format!("{head}{last}")
+    }
+
+    /// Years are spoken in pairs of two digits: `1905` is "nineteen oh five".
+    pub fn year(year: u32) -> String {
+        let (century, rest) = (year / 100, year % 100);
+        match year {
+            1000..=9999 if !(2000..2010).contains(&year) => match rest {
+                0 => format!("{} hundred", cardinal(century.into())),
+                1..10 => format!("{} oh {}", cardinal(century.into()), ONES[rest as usize]),
+                _ => format!("{} {}", cardinal(century.into()), cardinal(rest.into())),
+            },
+            _ => cardinal(year.into()),
+        }
+    }
+
+    pub fn amount(units: u64, cents: u32, currency: Currency) -> String {
+        let (unit, units_name, cent, cents_name) = match currency {
+            Currency::Dollar => ("dollar", "dollars", "cent", "cents"),
+            Currency::Euro => ("euro", "euros", "cent", "cents"),
+            Currency::Pound => ("pound", "pounds", "penny", "pence"),
+        };
+        let units = format!(
+            "{} {}",
+            cardinal(units),
+            if units == 1 { unit } else { units_name }
+        );
+        match cents {
+            0 => units,
+            _ => format!(
+                "{units} and {} {}",
+                cardinal(cents.into()),
+                if cents == 1 { cent } else { cents_name }
+            ),
+        }
+    }
+}
+
+mod de {
+    use super::Currency;
+
+    const ONES: [&str; 20] = [
+        "null",
+        "eins",
+        "zwei",
+        "drei",
+        "vier",
+        "fünf",
+        "sechs",
+        "sieben",
+        "acht",
+        "neun",
+        "zehn",
+        "elf",
+        "zwölf",
+        "dreizehn",
+        "vierzehn",
+        "fünfzehn",
+        "sechzehn",
+        "siebzehn",
+        "achtzehn",
+        "neunzehn",
+    ];
+
+    const TENS: [&str; 10] = [
+        "", "", "zwanzig", "dreißig", "vierzig", "fünfzig", "sechzig", "siebzig", "achtzig",
+        "neunzig",
+    ];
+
+    /// The cardinal number. `1` is spoken as "eins" when standing alone.
+    pub fn cardinal(n: u64) -> String {
+        match n {
+            1 => "eins".into(),
+            n => prefix(n),
+        }
+    }
+
+    /// The cardinal number as it is used before nouns and in compounds, `1` is "ein".
+    fn prefix(n: u64) -> String {
+        match n {
+            1_000_000.. => {
+                let millions = n / 1_000_000;
+                let head = match millions {
+                    1 => "eine Million".to_string(),
+                    millions => format!("{} Millionen", prefix(millions)),
+                };
+                match n % 1_000_000 {
+                    0 => head,
+                    rest => format!("{head} {}", cardinal(rest)),
+                }
+            }
+            1_000.. => {
+                let head = format!("{}tausend", prefix(n / 1_000));
+                match n % 1_000 {
+                    0 => head,
+                    rest => format!("{head}{}", cardinal(rest)),
+                }
+            }
+            100.. => {
+                let head = format!("{}hundert", prefix(n / 100));
+                match n % 100 {
+                    0 => head,
+                    rest => format!("{head}{}", cardinal(rest)),
+                }
+            }
+            20.. => match n % 10 {
+                0 => TENS[n as usize / 10].to_string(),
+                ones => format!("{}und{}", prefix(ones), TENS[n as usize / 10]),
+            },
+            1 => "ein".into(),
+            n => ONES[n as usize].into(),
+        }
+    }
+
+    pub fn ordinal(n: u32) -> String {
+        match n {
+            1 => "erster".into(),
+            3 => "dritter".into(),
+            7 => "siebter".into(),
+            8 => "achter".into(),
+            2..20 => format!("{}ter", cardinal(n.into())),
+            n => format!("{}ster", cardinal(n.into())),
+        }
+    }
+
+    /// Years from 1100 to 1999 are spoken in hundreds: `1990` is "neunzehnhundertneunzig".
+    pub fn year(year: u32) -> String {
+        match year {
+            1100..2000 => match year % 100 {
+                0 => format!("{}hundert", cardinal((year / 100).into())),
+                rest => format!(
+                    "{}hundert{}",
+                    cardinal((year / 100).into()),
+                    cardinal(rest.into())
+                ),
+            },
+            year => cardinal(year.into()),
+        }
+    }
+
+    pub fn amount(units: u64, cents: u32, currency: Currency) -> String {
+        let (unit, cent) = match currency {
+            Currency::Dollar => ("Dollar", "Cent"),
+            Currency::Euro => ("Euro", "Cent"),
+            Currency::Pound => ("Pfund", "Pence"),
+        };
+        let units = format!("{} {unit}", prefix(units));
+        match cents {
+            0 => units,
+            _ => format!("{units} und {} {cent}", prefix(cents.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_dates_and_currency_amounts_are_spoken() {
+        let speak = |text: &str| normalize_for_speech(text, SpokenLanguage::English);
+        assert_eq!(
+            speak("Due on 2024-01-05."),
+            "Due on January fifth, twenty twenty-four."
+        );
+        assert_eq!(
+            speak("1905-12-23"),
+            "December twenty-third, nineteen oh five"
+        );
+        assert_eq!(
+            speak("2003-03-31"),
+            "March thirty-first, two thousand three"
+        );
+        assert_eq!(
+            speak("That is $1,234.56 in total."),
+            "That is one thousand two hundred thirty-four dollars and fifty-six cents in total."
+        );
+        assert_eq!(speak("£1.01"), "one pound and one penny");
+        assert_eq!(speak("€5"), "five euros");
+    }
+
+    #[test]
+    fn german_dates_and_currency_amounts_are_spoken() {
+        let speak = |text: &str| normalize_for_speech(text, SpokenLanguage::German);
+        assert_eq!(
+            speak("Fällig am 2024-01-05."),
+            "Fällig am fünfter Januar zweitausendvierundzwanzig."
+        );
+        assert_eq!(speak("1990-03-01"), "erster März neunzehnhundertneunzig");
+        assert_eq!(
+            speak("Das kostet 1.234,56 €."),
+            "Das kostet eintausendzweihundertvierunddreißig Euro und sechsundfünfzig Cent."
+        );
+        assert_eq!(speak("€1"), "ein Euro");
+        assert_eq!(
+            speak("2.500.000€"),
+            "zwei Millionen fünfhunderttausend Euro"
+        );
+    }
+
+    #[test]
+    fn other_numbers_are_kept() {
+        let speak = |text: &str| normalize_for_speech(text, SpokenLanguage::English);
+        assert_eq!(speak("Call 555-1234 at 10.30"), "Call 555-1234 at 10.30");
+        assert_eq!(
+            speak("2024-13-05 and 12024-01-05"),
+            "2024-13-05 and 12024-01-05"
+        );
+        assert_eq!(speak("$ and 5$"), "$ and five dollars");
+    }
+
+    #[test]
+    fn locales_are_resolved() {
+        assert_eq!(
+            SpokenLanguage::from_locale("en-US").unwrap(),
+            SpokenLanguage::English
+        );
+        assert_eq!(
+            SpokenLanguage::from_locale("de_DE").unwrap(),
+            SpokenLanguage::German
+        );
+        assert!(SpokenLanguage::from_locale("fr-FR").is_err());
+    }
+}
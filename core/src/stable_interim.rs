@@ -0,0 +1,85 @@
+//! Reduces the flicker of interim transcripts.
+//!
+//! Recognizers revise the words of interim results until they are final, so captions that show
+//! every interim keep changing. Here, interim text is only output up to the words two successive
+//! interims agree on. The last word of an interim is never considered stable, because it may not
+//! be complete yet.
+//!
+//! Words are separated by whitespace, so languages that are written without spaces only receive
+//! final text.
+
+/// Holds back the words of interim transcripts the recognizer is still revising.
+#[derive(Debug, Default)]
+pub struct InterimStabilizer {
+    previous: String,
+    /// The stable prefix that was returned last.
+    stable_len: usize,
+}
+
+impl InterimStabilizer {
+    /// Returns the stable prefix of `interim` if it grew since the last one returned.
+    pub fn stabilize(&mut self, interim: &str) -> Option<String> {
+        let stable = stable_prefix(&self.previous, interim);
+        self.previous = interim.to_owned();
+        if stable.len() <= self.stable_len {
+            return None;
+        }
+        self.stable_len = stable.len();
+        Some(stable.to_owned())
+    }
+
+    /// Starts over, for example after a final result.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The words of `interim` that match the words of `previous`, excluding the last word of either.
+fn stable_prefix<'a>(previous: &str, interim: &'a str) -> &'a str {
+    let mut previous_words = previous.split_whitespace().peekable();
+    let mut words = interim.split_whitespace().peekable();
+    let mut end = 0;
+    while let (Some(previous_word), Some(word)) = (previous_words.next(), words.next()) {
+        if previous_word != word || previous_words.peek().is_none() || words.peek().is_none() {
+            break;
+        }
+        // `word` is a slice of `interim`.
+        end = word.as_ptr() as usize - interim.as_ptr() as usize + word.len();
+    }
+    &interim[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stabilized_prefixes_of_revised_interims_are_output() {
+        let mut stabilizer = InterimStabilizer::default();
+        let interims = [
+            "the",
+            "the cat",
+            "the cat sat",
+            "the cap sat on",
+            "the cap sat on the",
+            "the cap sat on the mat",
+        ];
+        let output: Vec<_> = interims
+            .into_iter()
+            .filter_map(|interim| stabilizer.stabilize(interim))
+            .collect();
+        assert_eq!(output, ["the", "the cap sat", "the cap sat on"]);
+
+        stabilizer.reset();
+        assert_eq!(stabilizer.stabilize("a new utterance"), None);
+    }
+
+    #[test]
+    fn spacing_of_the_interim_is_preserved() {
+        assert_eq!(
+            stable_prefix("one  two three", "one  two three"),
+            "one  two"
+        );
+        assert_eq!(stable_prefix("", "one two"), "");
+    }
+}
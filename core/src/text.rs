@@ -0,0 +1,133 @@
+//! Text utilities that respect UTF-8 character boundaries.
+use std::{error, fmt, mem};
+
+use anyhow::{Result, bail};
+
+/// The default maximum number of characters of a text to synthesize.
+pub const DEFAULT_MAX_SYNTHESIZE_TEXT_LEN: usize = 5000;
+
+/// Truncates `text` to at most `max_len` bytes without splitting a character.
+pub fn truncate(text: &mut String, max_len: usize) {
+    let end = text.floor_char_boundary(max_len);
+    text.truncate(end);
+}
+
+/// Fails with [`TextTooLong`] if `text` has more than `max_len` characters.
+pub fn require_max_len(text: &str, max_len: usize) -> Result<()> {
+    let len = text.chars().count();
+    if len > max_len {
+        return Err(TextTooLong { len, max_len }.into());
+    }
+    Ok(())
+}
+
+/// The error when a text exceeds its maximum length.
+///
+/// The message starts with a stable code, so that clients can recognize over-long requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextTooLong {
+    pub len: usize,
+    pub max_len: usize,
+}
+
+impl fmt::Display for TextTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "text_too_long: The text has {} characters, the maximum is {}",
+            self.len, self.max_len
+        )
+    }
+}
+
+impl error::Error for TextTooLong {}
+
+/// Reassembles text from byte chunks whose boundaries may split multi-byte characters.
+#[derive(Debug, Default)]
+pub struct Utf8Accumulator {
+    text: String,
+    /// The bytes of an incomplete character at the end of the last chunk.
+    pending: Vec<u8>,
+}
+
+impl Utf8Accumulator {
+    /// Appends a chunk and returns the text accumulated so far. An incomplete character at the end
+    /// of the chunk is held back until the next chunk completes it.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<&str> {
+        self.pending.extend_from_slice(chunk);
+        let valid_len = match str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) => {
+                if e.error_len().is_some() {
+                    bail!("Invalid UTF-8 in text chunk");
+                }
+                e.valid_up_to()
+            }
+        };
+        let rest = self.pending.split_off(valid_len);
+        let complete = mem::replace(&mut self.pending, rest);
+        self.text
+            .push_str(str::from_utf8(&complete).expect("Validated before"));
+        Ok(&self.text)
+    }
+
+    /// Returns the accumulated text. Fails if the last chunk ended with an incomplete character.
+    pub fn finish(self) -> Result<String> {
+        if !self.pending.is_empty() {
+            bail!("Text ended with an incomplete UTF-8 character");
+        }
+        Ok(self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncation_does_not_split_characters() {
+        let mut text = "a😀b".to_string();
+        truncate(&mut text, 3);
+        assert_eq!(text, "a");
+
+        let mut text = "a😀b".to_string();
+        truncate(&mut text, 5);
+        assert_eq!(text, "a😀");
+
+        let mut text = "short".to_string();
+        truncate(&mut text, 100);
+        assert_eq!(text, "short");
+    }
+
+    #[test]
+    fn max_len_is_measured_in_characters() {
+        assert!(require_max_len("😀😀", 2).is_ok());
+        let error = require_max_len("😀😀😀", 2).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TextTooLong>(),
+            Some(&TextTooLong { len: 3, max_len: 2 })
+        );
+    }
+
+    #[test]
+    fn emoji_split_across_chunks_is_reassembled() {
+        let bytes = "Hi 😀!".as_bytes();
+        // The emoji occupies bytes 3..7, split it in the middle.
+        let mut accumulator = Utf8Accumulator::default();
+        assert_eq!(accumulator.push(&bytes[..5]).unwrap(), "Hi ");
+        assert_eq!(accumulator.push(&bytes[5..6]).unwrap(), "Hi ");
+        assert_eq!(accumulator.push(&bytes[6..]).unwrap(), "Hi 😀!");
+        assert_eq!(accumulator.finish().unwrap(), "Hi 😀!");
+    }
+
+    #[test]
+    fn incomplete_and_invalid_text_is_rejected() {
+        let bytes = "😀".as_bytes();
+        let mut accumulator = Utf8Accumulator::default();
+        accumulator.push(&bytes[..2]).unwrap();
+        assert!(accumulator.finish().is_err());
+
+        let mut accumulator = Utf8Accumulator::default();
+        assert!(accumulator.push(&[b'a', 0xff, b'b']).is_err());
+    }
+}
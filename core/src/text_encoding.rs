@@ -0,0 +1,115 @@
+//! Post-processing of text that is output to clients.
+use serde::{Deserialize, Serialize};
+
+/// How text generated by a model is output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TextEncoding {
+    /// The text is output as generated.
+    #[default]
+    Raw,
+    /// Markdown formatting is removed, so that the text can be displayed as spoken captions.
+    Plain,
+}
+
+impl TextEncoding {
+    pub fn encode(self, text: String) -> String {
+        match self {
+            TextEncoding::Raw => text,
+            TextEncoding::Plain => strip_markdown(&text),
+        }
+    }
+}
+
+/// Removes the markdown formatting that models commonly produce: headings, block quotes, list
+/// bullets, emphasis, strike-through, inline code, and links.
+fn strip_markdown(text: &str) -> String {
+    text.split('\n')
+        .map(|line| strip_inline(strip_line_prefix(line)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn strip_line_prefix(line: &str) -> &str {
+    let trimmed = line.trim_start();
+    let without_heading = trimmed.trim_start_matches('#');
+    if without_heading.len() != trimmed.len() && without_heading.starts_with(' ') {
+        return without_heading.trim_start();
+    }
+    for prefix in ["> ", "- ", "* ", "+ "] {
+        if let Some(rest) = trimmed.strip_prefix(prefix) {
+            return rest;
+        }
+    }
+    line
+}
+
+fn strip_inline(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '`' => {}
+            '~' if chars.peek() == Some(&'~') => {
+                chars.next();
+            }
+            '_' if chars.peek() == Some(&'_') => {
+                chars.next();
+            }
+            '!' if chars.peek() == Some(&'[') => {}
+            '[' => {
+                // A link keeps its text only, but only if the target follows.
+                let rest: String = chars.clone().collect();
+                if let Some((text, target)) = rest.split_once("](")
+                    && let Some(end) = target.find(')')
+                {
+                    result.push_str(&strip_inline(text));
+                    let skipped = text.chars().count() + 2 + target[..=end].chars().count();
+                    for _ in 0..skipped {
+                        chars.next();
+                    }
+                } else {
+                    result.push(c);
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextEncoding;
+
+    #[test]
+    fn raw_keeps_the_text() {
+        let text = "**bold** and `code`";
+        assert_eq!(TextEncoding::Raw.encode(text.into()), text);
+    }
+
+    #[test]
+    fn plain_strips_markdown() {
+        let plain = |text: &str| TextEncoding::Plain.encode(text.into());
+        assert_eq!(plain("This is **bold**."), "This is bold.");
+        assert_eq!(
+            plain("*Emphasis*, __strong__ and ~~gone~~"),
+            "Emphasis, strong and gone"
+        );
+        assert_eq!(plain("Run `cargo test`"), "Run cargo test");
+        assert_eq!(
+            plain("## Heading\n- first\n* second"),
+            "Heading\nfirst\nsecond"
+        );
+        assert_eq!(plain("> quoted"), "quoted");
+        assert_eq!(
+            plain("See [the docs](https://example.com) and ![logo](logo.png)"),
+            "See the docs and logo"
+        );
+        assert_eq!(
+            plain("snake_case stays [as is]"),
+            "snake_case stays [as is]"
+        );
+        assert_eq!(plain("#hashtag"), "#hashtag");
+    }
+}
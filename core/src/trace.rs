@@ -0,0 +1,35 @@
+//! Correlation of conversations with the logs of upstream providers.
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{ConversationOutput, OutputPath};
+
+/// The request header or gRPC metadata key the trace id of a conversation is sent to providers
+/// with.
+pub const TRACE_ID_HEADER: &str = "x-context-switch-trace-id";
+
+/// The response header or gRPC metadata key providers commonly return their request id in.
+pub const PROVIDER_REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Output as a service event when a provider reports the id it tracks a request with.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "providerRequest", rename_all = "camelCase")]
+struct ProviderRequest<'a> {
+    provider: &'a str,
+    request_id: &'a str,
+}
+
+/// Outputs the id `provider` returned for the current request on the control path.
+pub fn output_provider_request(
+    output: &ConversationOutput,
+    provider: &str,
+    request_id: &str,
+) -> Result<()> {
+    output.service_event(
+        OutputPath::Control,
+        ProviderRequest {
+            provider,
+            request_id,
+        },
+    )
+}
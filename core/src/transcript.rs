@@ -0,0 +1,102 @@
+//! Transcription results that carry more than one alternative.
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{ConversationOutput, OutputPath};
+
+/// One recognition hypothesis of an utterance.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alternative {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+}
+
+impl Alternative {
+    /// A `confidence` of 0.0 is treated as not provided, which is how the recognition backends
+    /// report a missing confidence.
+    pub fn new(text: impl Into<String>, confidence: f32) -> Self {
+        Self {
+            text: text.into(),
+            confidence: (confidence != 0.0).then_some(confidence),
+        }
+    }
+}
+
+/// Output as a service event instead of text when a client requests more than one alternative.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename = "transcriptAlternatives", rename_all = "camelCase")]
+struct TranscriptAlternatives {
+    is_final: bool,
+    alternatives: Vec<Alternative>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+}
+
+/// Outputs the alternatives of a recognition result, most likely first, on the control path.
+pub fn output_alternatives(
+    output: &ConversationOutput,
+    is_final: bool,
+    alternatives: Vec<Alternative>,
+    language: Option<String>,
+) -> Result<()> {
+    output.service_event(
+        OutputPath::Control,
+        TranscriptAlternatives {
+            is_final,
+            alternatives,
+            language,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    use super::*;
+    use crate::{AudioFormat, Conversation, InputModality, Output, OutputModality};
+
+    #[test]
+    fn alternatives_are_output_as_a_control_event() {
+        let format = AudioFormat::new(1, 16000);
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        output_alternatives(
+            &output,
+            true,
+            vec![Alternative::new("one", 0.5), Alternative::new("won", 0.0)],
+            Some("en-US".into()),
+        )
+        .unwrap();
+
+        let _started = output_receiver.try_recv().unwrap();
+        let Ok(Output::ServiceEvent { path, value }) = output_receiver.try_recv() else {
+            panic!("Expected a service event");
+        };
+        assert_eq!(path, OutputPath::Control);
+        assert_eq!(
+            value,
+            json!({
+                "type": "transcriptAlternatives",
+                "isFinal": true,
+                "alternatives": [
+                    { "text": "one", "confidence": 0.5 },
+                    { "text": "won" },
+                ],
+                "language": "en-US",
+            })
+        );
+    }
+}
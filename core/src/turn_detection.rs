@@ -1,5 +1,9 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
+use crate::AudioFrame;
+
 /// Provider-neutral turn-detection configuration.
 ///
 /// Each backend converter forwards only the fields it understands and ignores the rest.
@@ -38,3 +42,131 @@ pub enum ThresholdLevel {
     Medium,
     High,
 }
+
+/// A signal of the [`TurnDetector`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnSignal {
+    /// The energy of the input rose above the threshold.
+    Started,
+    /// The energy of the input stayed below the threshold for the configured silence duration.
+    Ended,
+}
+
+/// Provider-independent end-of-turn detection based on the energy of the input audio.
+///
+/// Short pauses within a turn are debounced: A turn ends only after the input stays silent for the
+/// configured duration.
+#[derive(Debug)]
+pub struct TurnDetector {
+    /// The normalized RMS level (0.0 to 1.0) above which the input is considered speech.
+    threshold: f32,
+    silence_duration: Duration,
+    in_turn: bool,
+    silence: Duration,
+}
+
+impl TurnDetector {
+    pub fn new(threshold: f32, silence_duration: Duration) -> Self {
+        Self {
+            threshold,
+            silence_duration,
+            in_turn: false,
+            silence: Duration::ZERO,
+        }
+    }
+
+    /// Feeds the next input frame. Returns a signal if a turn started or ended with this frame.
+    pub fn process(&mut self, frame: &AudioFrame) -> Option<TurnSignal> {
+        let speech = rms(&frame.samples) >= self.threshold;
+        match (self.in_turn, speech) {
+            (false, false) => None,
+            (false, true) => {
+                self.in_turn = true;
+                self.silence = Duration::ZERO;
+                Some(TurnSignal::Started)
+            }
+            (true, true) => {
+                self.silence = Duration::ZERO;
+                None
+            }
+            (true, false) => {
+                self.silence += frame.duration();
+                if self.silence < self.silence_duration {
+                    return None;
+                }
+                self.in_turn = false;
+                Some(TurnSignal::Ended)
+            }
+        }
+    }
+
+    pub fn in_turn(&self) -> bool {
+        self.in_turn
+    }
+}
+
+fn rms(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples
+        .iter()
+        .map(|&s| {
+            let s = s as f32 / 32768.0;
+            s * s
+        })
+        .sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioFormat;
+
+    const FRAME_MS: u64 = 20;
+
+    fn frame(level: i16) -> AudioFrame {
+        let format = AudioFormat::new(1, 16000);
+        AudioFrame {
+            format,
+            samples: (0..format.sample_rate as usize * FRAME_MS as usize / 1000)
+                .map(|i| if i % 2 == 0 { level } else { -level })
+                .collect(),
+        }
+    }
+
+    fn feed(detector: &mut TurnDetector, level: i16, frames: usize) -> Vec<(usize, TurnSignal)> {
+        (0..frames)
+            .filter_map(|i| detector.process(&frame(level)).map(|signal| (i, signal)))
+            .collect()
+    }
+
+    #[test]
+    fn turn_ends_after_the_configured_silence_duration() {
+        let mut detector = TurnDetector::new(0.05, Duration::from_millis(500));
+
+        assert_eq!(feed(&mut detector, 0, 10), []);
+        assert_eq!(feed(&mut detector, 8000, 25), [(0, TurnSignal::Started)]);
+        assert!(detector.in_turn());
+
+        // 25 frames of 20ms silence make up 500ms, the turn ends with the last one.
+        let silence_frames = (500 / FRAME_MS) as usize;
+        assert_eq!(
+            feed(&mut detector, 100, silence_frames + 10),
+            [(silence_frames - 1, TurnSignal::Ended)]
+        );
+        assert!(!detector.in_turn());
+    }
+
+    #[test]
+    fn short_pauses_do_not_end_the_turn() {
+        let mut detector = TurnDetector::new(0.05, Duration::from_millis(500));
+
+        assert_eq!(feed(&mut detector, 8000, 10), [(0, TurnSignal::Started)]);
+        assert_eq!(feed(&mut detector, 0, 20), []);
+        assert_eq!(feed(&mut detector, 8000, 10), []);
+        assert_eq!(feed(&mut detector, 0, 25), [(24, TurnSignal::Ended)]);
+        assert_eq!(feed(&mut detector, 8000, 1), [(0, TurnSignal::Started)]);
+    }
+}
@@ -231,5 +231,7 @@ fn get_aristech_params() -> Result<AristechParams> {
         voice: Some(voice_id),
         token,
         secret,
+        max_text_len: None,
+        report_latency: false,
     })
 }
@@ -95,8 +95,13 @@ async fn main() -> Result<()> {
     let params = AristechParams {
         auth_config,
         language: language.into(),
+        fallback_language: None,
         model: None,  // Optional: Specify a model if needed
         prompt: None, // Optional: Specify a prompt if needed
+        compression: Default::default(),
+        min_confidence: None,
+        endpointing_ms: None,
+        max_alternatives: None,
     };
 
     let (output_producer, mut output_consumer) = unbounded_channel();
@@ -43,9 +43,16 @@ async fn main() -> Result<()> {
             .ok()
             .or_else(|| env::var("AZURE_HOST").ok()),
         region: Some(env::var("AZURE_REGION").unwrap()),
+        regions: None,
         subscription_key: env::var("AZURE_SUBSCRIPTION_KEY").unwrap(),
         language: language.to_string(),
         voice: None,
+        frame_duration_ms: None,
+        normalize_text: false,
+        max_text_len: None,
+        lexicon_urls: Vec::new(),
+        report_latency: false,
+        word_boundaries: false,
     };
 
     let params = serde_json::to_value(params)?;
@@ -60,6 +67,11 @@ async fn main() -> Result<()> {
         }]
         .into(),
         billing_id: None,
+        metadata: None,
+        shutdown_timeout_ms: None,
+        preprocessing: None,
+        frame_duration_ms: None,
+        billing_path: None,
     };
 
     context_switch.process(start)?;
@@ -73,6 +85,7 @@ async fn main() -> Result<()> {
 
     context_switch.process(ClientEvent::Text {
         id: conversation_id.clone(),
+        request_id: None,
         content: text.into(),
         content_type: None,
         billing_scope: None,
@@ -75,11 +75,13 @@ async fn main() -> Result<()> {
             .ok()
             .or_else(|| env::var("AZURE_HOST").ok()),
         region: Some(env::var("AZURE_REGION").expect("AZURE_REGION undefined")),
+        regions: None,
         subscription_key: env::var("AZURE_SUBSCRIPTION_KEY")
             .expect("AZURE_SUBSCRIPTION_KEY undefined"),
         recognition_language: recognition_language.into(),
         target_language: target_language.into(),
         target_voice: None,
+        max_reconnects: 0,
     };
 
     let (output_sender, output_receiver) = unbounded_channel();
@@ -167,7 +169,7 @@ async fn setup_audio_playback(
     async move {
         while let Some(output) = output.recv().await {
             match output {
-                Output::ServiceStarted { .. } => {}
+                Output::ServiceStarted { .. } | Output::ServiceReady => {}
                 Output::Audio { frame } => {
                     if cmd_tx.send(AudioCommand::PlayFrame(frame)).is_err() {
                         break;
@@ -181,7 +183,7 @@ async fn setup_audio_playback(
                 } => {
                     println!("Text ({is_final}, {language:?}, speaker: {speaker:?}): {text}");
                 }
-                Output::RequestCompleted { .. } => {}
+                Output::RequestCompleted { .. } | Output::TurnCompleted { .. } => {}
                 Output::ClearAudio => {
                     if cmd_tx.send(AudioCommand::Clear).is_err() {
                         break;
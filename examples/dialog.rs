@@ -277,7 +277,7 @@ async fn setup_audio_playback(
     async move {
         while let Some(output) = output.recv().await {
             match output {
-                Output::ServiceStarted { .. } => {}
+                Output::ServiceStarted { .. } | Output::ServiceReady => {}
                 Output::Audio { frame } => {
                     if cmd_tx.send(AudioCommand::PlayFrame(frame)).is_err() {
                         break;
@@ -286,7 +286,7 @@ async fn setup_audio_playback(
                 output @ Output::Text { .. } => {
                     println!("{output:?}");
                 }
-                Output::RequestCompleted { .. } => {}
+                Output::RequestCompleted { .. } | Output::TurnCompleted { .. } => {}
                 Output::ClearAudio => {
                     if cmd_tx.send(AudioCommand::Clear).is_err() {
                         break;
@@ -273,11 +273,13 @@ async fn start_conversation(
                     .ok()
                     .or_else(|| env::var("AZURE_HOST").ok()),
                 region: env::var("AZURE_REGION").ok(),
+                regions: None,
                 subscription_key: env::var("AZURE_SUBSCRIPTION_KEY")
                     .expect("AZURE_SUBSCRIPTION_KEY undefined"),
                 language: languages.join_csv(),
                 diarization: provider_args.diarization,
                 speech_gate: false,
+                max_reconnects: 0,
             };
             AzureTranscribe.conversation(params, conversation).await
         }
@@ -299,6 +301,7 @@ async fn start_conversation(
                 min_speech_duration_ms: None,
                 min_silence_duration_ms: None,
                 previous_text: None,
+                lead_in_ms: None,
             };
             ElevenLabsTranscribe
                 .conversation(params, conversation)
@@ -331,6 +334,10 @@ async fn start_conversation(
                 language: languages.join_csv(),
                 diarization: provider_args.diarization,
                 region,
+                location: env::var("GOOGLE_TRANSCRIBE_LOCATION").ok(),
+                min_confidence: None,
+                max_alternatives: None,
+                stable_interim_text: false,
             };
             GoogleTranscribe.conversation(params, conversation).await
         }
@@ -357,8 +364,13 @@ async fn start_conversation(
             let params = aristech::transcribe::Params {
                 auth_config,
                 language: language.replace('-', "_"),
+                fallback_language: None,
                 model: None,
                 prompt: None,
+                compression: Default::default(),
+                min_confidence: None,
+                endpointing_ms: None,
+                max_alternatives: None,
             };
             AristechTranscribe.conversation(params, conversation).await
         }
@@ -395,7 +407,9 @@ async fn start_conversation(
             let params = deepgram_service::transcribe::Params {
                 api_key: env::var("DEEPGRAM_API_KEY").expect("DEEPGRAM_API_KEY undefined"),
                 endpoint: env::var("DEEPGRAM_ENDPOINT").expect("DEEPGRAM_ENDPOINT undefined"),
+                model: provider_args.model.map(str::to_owned),
                 language: languages.join_csv(),
+                fallback_language: None,
                 profanity_filter: false,
                 keyterm: vec![],
                 turn_detection: provider_args.turn_detection.clone(),
@@ -424,6 +438,7 @@ impl Provider {
                 capabilities.model = true;
             }
             Provider::Deepgram => {
+                capabilities.model = true;
                 capabilities.turn_detection = true;
             }
             Provider::Elevenlabs => {
@@ -16,7 +16,17 @@ use symphonia::core::{
     probe::Hint,
 };
 
-use context_switch::{AudioFormat, AudioFrame, make_speech_gate_processor};
+use context_switch::{
+    AudioFormat, AudioFrame, audio, make_agc_processor, make_dc_removal_processor,
+    make_speech_gate_processor,
+};
+
+/// Cutoff of the DC-removal high-pass filter.
+const HIGHPASS_CUTOFF_HZ: f32 = 20.0;
+/// The normalized RMS level the AGC adjusts to (about -12 dBFS).
+const AGC_TARGET_RMS: f32 = 0.25;
+/// The maximum AGC amplification (20 dB).
+const AGC_MAX_GAIN: f32 = 10.0;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -40,36 +50,163 @@ struct Args {
     /// Longer release to avoid cutting off speech during brief pauses (telephony standard)
     #[arg(short, long, default_value = "300")]
     release: f32,
+
+    /// Sweep the threshold over these values instead (for example `0.01,0.05,0.1`)
+    /// Writes one output file per threshold and prints a summary of the gated samples
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    sweep_thresholds: Option<Vec<f32>>,
+
+    /// Remove the DC offset with a high-pass filter before the speech gate
+    #[arg(long)]
+    highpass: bool,
+
+    /// Normalize the level with automatic gain control before the speech gate
+    #[arg(long)]
+    agc: bool,
+
+    /// Dither the decoded samples when converting them to 16 bits, recommended for 24 bit or
+    /// float sources
+    #[arg(long)]
+    dither: bool,
+}
+
+/// The processors applied before the speech gate, in this order.
+#[derive(Debug, Default, Clone, Copy)]
+struct Conditioning {
+    /// Applied when the decoded samples are converted to 16 bits.
+    dither: bool,
+    highpass: bool,
+    agc: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GateSettings {
+    threshold: f32,
+    attack_ms: f32,
+    release_ms: f32,
+}
+
+/// Samples that were not silent in the input, separated by whether the gate silenced them.
+#[derive(Debug, Default, Clone, Copy)]
+struct GateStats {
+    gated: usize,
+    passed: usize,
+}
+
+impl GateStats {
+    fn new(input: &[i16], output: &[i16]) -> Self {
+        let mut stats = Self::default();
+        for (&input, &output) in input.iter().zip(output) {
+            match (input, output) {
+                (0, _) => {}
+                (_, 0) => stats.gated += 1,
+                _ => stats.passed += 1,
+            }
+        }
+        stats
+    }
+
+    fn gated_ratio(&self) -> f64 {
+        let total = self.gated + self.passed;
+        if total == 0 {
+            return 0.0;
+        }
+        self.gated as f64 / total as f64
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let conditioning = Conditioning {
+        dither: args.dither,
+        highpass: args.highpass,
+        agc: args.agc,
+    };
 
     // Process each file
     for input in &args.inputs {
-        process_audio_file(input, args.threshold, args.attack, args.release)?;
+        match &args.sweep_thresholds {
+            Some(thresholds) => {
+                let settings: Vec<_> = thresholds
+                    .iter()
+                    .map(|&threshold| GateSettings {
+                        threshold,
+                        attack_ms: args.attack,
+                        release_ms: args.release,
+                    })
+                    .collect();
+                let results = sweep_audio_file(input, &settings, conditioning)?;
+                print_sweep_summary(&results);
+            }
+            None => {
+                let settings = GateSettings {
+                    threshold: args.threshold,
+                    attack_ms: args.attack,
+                    release_ms: args.release,
+                };
+                let output_path = output_path(input, "speech-gate")?;
+                process_audio_file(input, &output_path, settings, conditioning)?;
+            }
+        }
     }
 
     println!("Processing complete!");
     Ok(())
 }
 
-fn process_audio_file(
+/// Process the file once per setting. The output files are named after the settings.
+fn sweep_audio_file(
     input_path: &Path,
-    threshold: f32,
-    attack_ms: f32,
-    release_ms: f32,
-) -> Result<()> {
-    println!("Processing file: {}", input_path.display());
+    settings: &[GateSettings],
+    conditioning: Conditioning,
+) -> Result<Vec<(GateSettings, PathBuf, GateStats)>> {
+    settings
+        .iter()
+        .map(|&settings| {
+            let suffix = format!(
+                "speech-gate-t{}-a{}-r{}",
+                settings.threshold, settings.attack_ms, settings.release_ms
+            );
+            let output_path = output_path(input_path, &suffix)?;
+            let stats = process_audio_file(input_path, &output_path, settings, conditioning)?;
+            Ok((settings, output_path, stats))
+        })
+        .collect()
+}
+
+fn print_sweep_summary(results: &[(GateSettings, PathBuf, GateStats)]) {
+    println!("Summary:");
+    for (settings, output_path, stats) in results {
+        let gated_ratio = stats.gated_ratio();
+        println!(
+            "  threshold {}, attack {}ms, release {}ms: {:.2}% gated, {:.2}% passed ({})",
+            settings.threshold,
+            settings.attack_ms,
+            settings.release_ms,
+            gated_ratio * 100.0,
+            (1.0 - gated_ratio) * 100.0,
+            output_path.display()
+        );
+    }
+}
 
-    // Get absolute path and create output path with suffix
+/// The output path next to the input file with the suffix added to the file stem.
+fn output_path(input_path: &Path, suffix: &str) -> Result<PathBuf> {
     let abs_path = input_path.absolutize()?.to_path_buf();
     let stem = abs_path.file_stem().context("Failed to get file stem")?;
     let parent = abs_path
         .parent()
         .context("Failed to get parent directory")?;
-    let output_path = parent.join(format!("{}-speech-gate.wav", stem.to_string_lossy()));
+    Ok(parent.join(format!("{}-{suffix}.wav", stem.to_string_lossy())))
+}
 
+fn process_audio_file(
+    input_path: &Path,
+    output_path: &Path,
+    settings: GateSettings,
+    conditioning: Conditioning,
+) -> Result<GateStats> {
+    println!("Processing file: {}", input_path.display());
     println!("Output will be saved to: {}", output_path.display());
 
     // Read the input file
@@ -123,8 +260,17 @@ fn process_audio_file(
     // Create a sample buffer to decode into
     let mut sample_buf = None;
 
+    let mut conditioning_processors: Vec<Box<dyn FnMut(&AudioFrame) -> AudioFrame>> = Vec::new();
+    if conditioning.highpass {
+        conditioning_processors.push(make_dc_removal_processor(HIGHPASS_CUTOFF_HZ));
+    }
+    if conditioning.agc {
+        conditioning_processors.push(make_agc_processor(AGC_TARGET_RMS, AGC_MAX_GAIN));
+    }
+
     // Create the speech gate processor with the specified parameters
-    let mut process_speech_gate = make_speech_gate_processor(threshold, attack_ms, release_ms);
+    let mut process_speech_gate =
+        make_speech_gate_processor(settings.threshold, settings.attack_ms, settings.release_ms);
 
     // Create a WAV writer for the output file
     let spec = WavSpec {
@@ -134,7 +280,7 @@ fn process_audio_file(
         sample_format: SampleFormat::Int,
     };
 
-    let mut writer = WavWriter::create(&output_path, spec)?;
+    let mut writer = WavWriter::create(output_path, spec)?;
 
     // Process the audio packets
     let mut raw_samples: Vec<i16> = Vec::new();
@@ -155,11 +301,14 @@ fn process_audio_file(
             buf.copy_interleaved_ref(decoded);
 
             // Convert to i16 samples
-            let mut frame_samples: Vec<i16> = buf
-                .samples()
-                .iter()
-                .map(|&s: &f32| (s.clamp(-1.0, 1.0) * 32767.0) as i16)
-                .collect();
+            let mut frame_samples: Vec<i16> = if conditioning.dither {
+                audio::to_i16_dithered(buf.samples())
+            } else {
+                buf.samples()
+                    .iter()
+                    .map(|&s: &f32| (s.clamp(-1.0, 1.0) * 32767.0) as i16)
+                    .collect()
+            };
 
             raw_samples.append(&mut frame_samples);
         }
@@ -190,15 +339,24 @@ fn process_audio_file(
 
     // Process audio in chunks to avoid excessive memory usage
     const CHUNK_SIZE: usize = 4096;
+    let mut stats = GateStats::default();
     for chunk in mono_samples.chunks(CHUNK_SIZE) {
-        let input_frame = AudioFrame {
+        let mut input_frame = AudioFrame {
             format: audio_format,
             samples: chunk.to_vec(),
         };
 
+        for process in &mut conditioning_processors {
+            input_frame = process(&input_frame);
+        }
+
         // Apply speech gate processing
         let processed_frame = process_speech_gate(&input_frame);
 
+        let chunk_stats = GateStats::new(&input_frame.samples, &processed_frame.samples);
+        stats.gated += chunk_stats.gated;
+        stats.passed += chunk_stats.passed;
+
         // Write to output WAV file
         for sample in processed_frame.samples {
             writer.write_sample(sample)?;
@@ -208,5 +366,115 @@ fn process_audio_file(
     writer.finalize()?;
     println!("Processed audio saved to: {}", output_path.display());
 
-    Ok(())
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+    use std::fs;
+
+    use hound::WavReader;
+
+    use super::*;
+
+    #[test]
+    fn threshold_sweep_writes_one_file_per_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("ramp.wav");
+        write_ramp_wav(&input_path);
+
+        let settings: Vec<_> = [0.02, 0.1, 0.3]
+            .into_iter()
+            .map(|threshold| GateSettings {
+                threshold,
+                attack_ms: 10.0,
+                release_ms: 300.0,
+            })
+            .collect();
+        let results = sweep_audio_file(&input_path, &settings, Conditioning::default()).unwrap();
+
+        let mut output_files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name != "ramp.wav")
+            .collect();
+        output_files.sort();
+        assert_eq!(
+            output_files,
+            [
+                "ramp-speech-gate-t0.02-a10-r300.wav",
+                "ramp-speech-gate-t0.1-a10-r300.wav",
+                "ramp-speech-gate-t0.3-a10-r300.wav",
+            ]
+        );
+
+        // Higher thresholds gate more samples.
+        let gated_ratios: Vec<f64> = results
+            .iter()
+            .map(|(_, _, stats)| stats.gated_ratio())
+            .collect();
+        assert!(gated_ratios[0] > 0.0, "{gated_ratios:?}");
+        assert!(gated_ratios[0] < gated_ratios[1], "{gated_ratios:?}");
+        assert!(gated_ratios[1] < gated_ratios[2], "{gated_ratios:?}");
+        assert!(gated_ratios[2] < 1.0, "{gated_ratios:?}");
+    }
+
+    #[test]
+    fn highpass_and_agc_remove_dc_and_normalize_the_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_path = dir.path().join("offset.wav");
+        // A quiet sine wave with a large DC offset.
+        write_wav(&input_path, 2 * SAMPLE_RATE, |t| {
+            0.3 + 0.05 * (TAU * 440.0 * t).sin()
+        });
+        let output_path = dir.path().join("offset-conditioned.wav");
+
+        let settings = GateSettings {
+            threshold: 0.01,
+            attack_ms: 10.0,
+            release_ms: 300.0,
+        };
+        let conditioning = Conditioning {
+            dither: false,
+            highpass: true,
+            agc: true,
+        };
+        process_audio_file(&input_path, &output_path, settings, conditioning).unwrap();
+
+        // Skip the first second, in which the filters settle.
+        let samples: Vec<f32> = WavReader::open(&output_path)
+            .unwrap()
+            .samples::<i16>()
+            .skip(SAMPLE_RATE as usize)
+            .map(|s| s.unwrap() as f32 / 32768.0)
+            .collect();
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+
+        assert!(mean.abs() < 0.01, "mean: {mean}");
+        assert!((rms - AGC_TARGET_RMS).abs() < 0.05, "rms: {rms}");
+    }
+
+    const SAMPLE_RATE: u32 = 16000;
+
+    /// One second of a 440 Hz sine wave that fades in linearly.
+    fn write_ramp_wav(path: &Path) {
+        write_wav(path, SAMPLE_RATE, |t| t * (TAU * 440.0 * t).sin());
+    }
+
+    fn write_wav(path: &Path, samples: u32, signal: impl Fn(f32) -> f32) {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for i in 0..samples {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            writer.write_sample((signal(t) * 32767.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
 }
@@ -10,7 +10,12 @@ use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
-use context_switch_core::{AudioFormat, AudioFrame, Conversation, Input, Service};
+use context_switch_core::{
+    AudioFormat, AudioFrame, Conversation, Input, InputModality, OutputModalities, RequestFailed,
+    Service,
+    latency::LatencyTimer,
+    text::{DEFAULT_MAX_SYNTHESIZE_TEXT_LEN, require_max_len},
+};
 
 //TODO: Add `language` field as alternative to `voice_id`
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +25,14 @@ pub struct Params {
     pub voice: Option<String>,
     pub token: String,
     pub secret: String,
+    /// The maximum number of characters of a text to synthesize. Longer texts are rejected.
+    /// Defaults to [`DEFAULT_MAX_SYNTHESIZE_TEXT_LEN`].
+    #[serde(default)]
+    pub max_text_len: Option<usize>,
+    /// Send a `providerLatency` event for each request, see
+    /// [`ProviderLatency`](context_switch_core::latency::ProviderLatency).
+    #[serde(default)]
+    pub report_latency: bool,
 }
 
 #[derive(Debug)]
@@ -29,6 +42,16 @@ pub struct AristechSynthesize;
 impl Service for AristechSynthesize {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_text_input_only()?;
+        output_modalities.require_single_audio_output()?;
+        Ok(())
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         conversation.require_text_input_only()?;
         let output_format = conversation.require_single_audio_output()?;
@@ -37,6 +60,9 @@ impl Service for AristechSynthesize {
         // TODO: Add the possibility to determine this from a language parameter and the
         // `get_voices` function if no voice_id is provided.
         let voice = params.voice.unwrap_or_else(|| "anne_de_DE".to_string());
+        let max_text_len = params
+            .max_text_len
+            .unwrap_or(DEFAULT_MAX_SYNTHESIZE_TEXT_LEN);
 
         // The TLS options struct is needed to provide authentication details
         let tls_options = get_tls_options(params.token, params.secret);
@@ -71,6 +97,7 @@ impl Service for AristechSynthesize {
         };
 
         let (mut input, output) = conversation.start()?;
+        output.service_ready()?;
 
         loop {
             let Some(input) = input.recv().await else {
@@ -85,6 +112,14 @@ impl Service for AristechSynthesize {
                 bail!("Unexpected input");
             };
 
+            if text.trim().is_empty() {
+                debug!("Text is empty, nothing to synthesize");
+                output.request_completed(request_id)?;
+                continue;
+            }
+
+            require_max_len(&text, max_text_len).context(RequestFailed(request_id.clone()))?;
+
             // Create the speech request
             let request = SpeechRequest {
                 text,
@@ -93,20 +128,27 @@ impl Service for AristechSynthesize {
             };
 
             // Get speech stream
+            let mut latency = LatencyTimer::start();
             let mut stream = client
                 .get_speech(request)
                 .await
-                .context("Failed to start Aristech speech stream")?
+                .context("Failed to start Aristech speech stream")
+                .context(RequestFailed(request_id.clone()))?
                 .into_inner();
 
             while let Some(response) = stream
                 .message()
                 .await
-                .context("Error receiving speech stream chunk")?
+                .context("Error receiving speech stream chunk")
+                .context(RequestFailed(request_id.clone()))?
             {
+                latency.result_received();
                 let frame = AudioFrame::from_le_bytes(output_format, &response.data);
                 output.audio_frame(frame)?;
             }
+            if params.report_latency {
+                latency.report(&output, request_id.clone())?;
+            }
             output.request_completed(request_id)?;
         }
     }
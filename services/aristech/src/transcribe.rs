@@ -1,18 +1,35 @@
+use std::{iter, time::Duration};
+
 use anyhow::{Result, anyhow};
 use aristech_stt_client::{
     Auth, SttClientBuilder,
     stt_service::{
-        RecognitionConfig, RecognitionSpec, StreamingRecognitionRequest,
+        EndpointSpec, RecognitionConfig, RecognitionSpec, StreamingRecognitionRequest,
+        StreamingRecognitionResponse,
         recognition_spec::AudioEncoding,
         streaming_recognition_request::{self, StreamingRequest},
     },
 };
 use async_stream::stream;
 use async_trait::async_trait;
+use futures::{Stream, StreamExt, future};
 use serde::Deserialize;
-use tonic::codegen::CompressionEncoding;
+use tokio::select;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::{self, Instant};
+use tonic::{Request, Status, codegen::CompressionEncoding, metadata::AsciiMetadataValue};
+use tracing::{debug, warn};
+
+use context_switch_core::{
+    AudioFrame, BillingRecord, BillingSchedule, Conversation, ConversationInput,
+    ConversationOutput, Input, InputModality, OutputModalities, Service, TurnDetector,
+    language::connect_with_fallback_language,
+    trace::{PROVIDER_REQUEST_ID_HEADER, TRACE_ID_HEADER, output_provider_request},
+    transcript::{Alternative, output_alternatives},
+};
 
-use context_switch_core::{Conversation, Input, Service};
+/// The RMS level above which input audio is considered speech by the endpointing.
+const SPEECH_THRESHOLD: f32 = 0.01;
 
 /// Authentication configuration
 #[derive(Debug, Deserialize)]
@@ -49,11 +66,42 @@ pub struct Params {
     /// N.B. This is expected to be in locale format, e.g. "en_GB" or "de_DE".
     /// A BCP 47 language code (e.g. "en-US") is not expected here.
     pub language: String,
+    /// Used instead of `language` if Aristech rejects it. Expected in the same locale format.
+    pub fallback_language: Option<String>,
     // TODO: Determine whether this could really be used in practice, in the future.
     // It seems that the language code used, automatically chooses the appropriate model. TBC.
     pub model: Option<String>,
     // TODO: Determine whether this could really be used in practice, in the future.
     pub prompt: Option<String>,
+    /// gRPC message compression for sending and receiving. Defaults to gzip.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Final results with a lower confidence (0.0 to 1.0) are suppressed. Results without a
+    /// confidence are always output.
+    pub min_confidence: Option<f32>,
+    /// The silence after which Aristech ends an utterance. If the final result does not arrive
+    /// when the caller did not speak for this duration, the last partial result is output as final.
+    pub endpointing_ms: Option<u32>,
+    /// If more than one, up to this many alternatives of each result are output as a
+    /// `transcriptAlternatives` control event instead of text.
+    pub max_alternatives: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    Gzip,
+    None,
+}
+
+impl Compression {
+    fn encoding(self) -> Option<CompressionEncoding> {
+        match self {
+            Compression::Gzip => Some(CompressionEncoding::Gzip),
+            Compression::None => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -63,9 +111,26 @@ pub struct AristechTranscribe;
 impl Service for AristechTranscribe {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_text_output(true)
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
         conversation.require_text_output(true)?;
+        let results = ResultOutput {
+            interim_results: conversation.output_modalities().interim_text(),
+            min_confidence: params.min_confidence,
+            max_alternatives: params.max_alternatives.filter(|max| *max > 1),
+        };
+        let endpointing = params
+            .endpointing_ms
+            .map(|ms| Duration::from_millis(ms.into()));
 
         // Create the client based on the auth_config
         let client = match params.auth_config {
@@ -93,75 +158,364 @@ impl Service for AristechTranscribe {
                 .map_err(|e| anyhow!("Failed to build Aristech STT client with API key: {}", e))?,
         };
 
-        // Now that the client is built with authentication and language, configure the gzip compression
-        let mut client = client
-            .accept_compressed(CompressionEncoding::Gzip)
-            .send_compressed(CompressionEncoding::Gzip);
+        // Now that the client is built with authentication and language, configure the compression
+        let mut client = match params.compression.encoding() {
+            Some(encoding) => client.accept_compressed(encoding).send_compressed(encoding),
+            None => client,
+        };
 
+        let trace_id = conversation.trace_id().map(str::to_owned);
         let (mut input, output) = conversation.start()?;
 
-        // Configure the initial request
-        let initial_request = StreamingRecognitionRequest {
-            streaming_request: Some(streaming_recognition_request::StreamingRequest::Config(
-                RecognitionConfig {
-                    specification: Some(RecognitionSpec {
-                        audio_encoding: AudioEncoding::Unspecified as i32, // Defaults to LINEAR16_PCM encoding
-                        sample_rate_hertz: input_format.sample_rate as i64,
-                        locale: params.language,
-                        partial_results: true,
-                        single_utterance: false,
-                        model: params.model.unwrap_or_default(),
-                        prompt: params.prompt.unwrap_or_default(),
-                        ..RecognitionSpec::default()
-                    }),
-                },
-            )),
-        };
-
-        let audio_stream = stream! {
-            yield initial_request;
-            while let Some(Input::Audio{frame}) = input.recv().await {
-                let pcm_data = frame.to_le_bytes();
-                yield StreamingRecognitionRequest {
+        let (audio_sender, response_stream, locale) = connect_with_fallback_language(
+            &output,
+            &params.language,
+            params.fallback_language.as_deref(),
+            async |locale: &str| -> Result<_> {
+                // Configure the initial request
+                let initial_request = StreamingRecognitionRequest {
                     streaming_request: Some(
-                        StreamingRequest::AudioContent(pcm_data),
+                        streaming_recognition_request::StreamingRequest::Config(
+                            RecognitionConfig {
+                                specification: Some(RecognitionSpec {
+                                    audio_encoding: AudioEncoding::Unspecified as i32, // Defaults to LINEAR16_PCM encoding
+                                    sample_rate_hertz: input_format.sample_rate as i64,
+                                    locale: locale.to_string(),
+                                    // Always requested, because finality is derived from the end
+                                    // of the utterance, which is only reported with partial
+                                    // results.
+                                    partial_results: true,
+                                    single_utterance: false,
+                                    model: params.model.clone().unwrap_or_default(),
+                                    prompt: params.prompt.clone().unwrap_or_default(),
+                                    endpointing: endpointing.map(|endpointing| EndpointSpec {
+                                        speech_complete_timeout: endpointing.as_secs_f32(),
+                                        ..EndpointSpec::default()
+                                    }),
+                                    ..RecognitionSpec::default()
+                                }),
+                            },
+                        ),
                     ),
                 };
-            }
-        };
 
-        let audio_stream = Box::pin(audio_stream);
+                let (audio_sender, mut audio_receiver) = mpsc::unbounded_channel::<Vec<u8>>();
 
-        // Start the streaming recognition
-        let mut response_stream = client.streaming_recognize(audio_stream).await?.into_inner();
+                let audio_stream = stream! {
+                    yield initial_request;
+                    while let Some(pcm_data) = audio_receiver.recv().await {
+                        yield StreamingRecognitionRequest {
+                            streaming_request: Some(
+                                StreamingRequest::AudioContent(pcm_data),
+                            ),
+                        };
+                    }
+                };
 
-        // Process recognition results
-        while let Some(response) = response_stream
-            .message()
-            .await
-            .map_err(|e| anyhow!("Failed to receive message from stream: {}", e))?
-        {
-            for chunk in response.chunks {
-                // Determine if this is a final result
-                // TODO: Find out if this is really the correct way to determine finality
-                // The `r#final` does not appear to be set.
-                let is_final = chunk.end_of_utterance;
-
-                // Instead of processing all alternatives, just take the first one
-                if let Some(alternative) = chunk.alternatives.into_iter().next() {
-                    output.text(is_final, alternative.text, None, None)?;
+                let mut request = Request::new(Box::pin(audio_stream));
+                if let Some(trace_id) = &trace_id {
+                    request
+                        .metadata_mut()
+                        .insert(TRACE_ID_HEADER, AsciiMetadataValue::try_from(trace_id)?);
+                }
+
+                // Start the streaming recognition. An unsupported locale is rejected here.
+                let response = client.streaming_recognize(request).await?;
+                if let Some(request_id) = response
+                    .metadata()
+                    .get(PROVIDER_REQUEST_ID_HEADER)
+                    .and_then(|request_id| request_id.to_str().ok())
+                {
+                    output_provider_request(&output, "aristech", request_id)?;
+                }
+                Ok((audio_sender, response.into_inner(), locale.to_owned()))
+            },
+        )
+        .await?;
+        output.service_ready()?;
+
+        // Billed by the locale that was connected with, which is the fallback if the requested
+        // one was rejected.
+        let billing_scope = billing_scope(params.model.as_deref(), &locale);
+
+        process_recognition(
+            &mut input,
+            audio_sender,
+            response_stream,
+            &output,
+            &results,
+            endpointing,
+            &billing_scope,
+        )
+        .await
+    }
+}
+
+/// The model, or the language if no model is set, because Aristech chooses the model by the
+/// language then.
+fn billing_scope(model: Option<&str>, language: &str) -> String {
+    match model {
+        Some(model) if !model.is_empty() => model.to_string(),
+        _ => language.to_string(),
+    }
+}
+
+/// Forwards the input audio and processes the recognition results in one loop.
+///
+/// When the input ends, the audio sender is dropped, which completes the request stream, and the
+/// remaining results are drained. When the response stream ends first, the audio sender is dropped
+/// on return, so no audio forwarding outlives the conversation.
+///
+/// If `endpointing` is set, the last partial result is output as final when no final result
+/// arrives in time, see [`UtteranceTimeout`].
+///
+/// The duration of the forwarded audio is billed in `billing_scope`.
+async fn process_recognition(
+    input: &mut ConversationInput,
+    audio_sender: UnboundedSender<Vec<u8>>,
+    mut response_stream: impl Stream<Item = Result<StreamingRecognitionResponse, Status>> + Unpin,
+    output: &ConversationOutput,
+    results: &ResultOutput,
+    endpointing: Option<Duration>,
+    billing_scope: &str,
+) -> Result<()> {
+    // `None` means that the input has ended and the request stream is being closed.
+    let mut audio_sender = Some(audio_sender);
+    let mut utterance_timeout = endpointing.map(UtteranceTimeout::new);
+
+    loop {
+        select! {
+            response = response_stream.next() => {
+                let Some(response) = response else {
+                    return Ok(());
+                };
+                let mut response =
+                    response.map_err(|e| anyhow!("Failed to receive message from stream: {}", e))?;
+                if let Some(utterance_timeout) = &mut utterance_timeout {
+                    utterance_timeout.process(&mut response);
+                }
+                results.output_chunks(output, response)?;
+            }
+            () = utterance_due(&utterance_timeout) => {
+                if let Some(text) = utterance_timeout.as_mut().and_then(UtteranceTimeout::force) {
+                    debug!("Utterance timed out, partial result is output as final");
+                    results.output_final(output, text)?;
+                }
+            }
+            input_event = input.recv(), if audio_sender.is_some() => {
+                match input_event {
+                    Some(Input::Audio { frame }) => {
+                        if let Some(sender) = &audio_sender {
+                            if sender.send(frame.to_le_bytes()).is_ok() {
+                                if let Some(utterance_timeout) = &mut utterance_timeout {
+                                    utterance_timeout.audio(&frame);
+                                }
+                                output.billing_records(
+                                    None,
+                                    billing_scope.to_string(),
+                                    [BillingRecord::duration("input:audio", frame.duration())],
+                                    BillingSchedule::Now,
+                                )?;
+                            } else {
+                                warn!("Request stream closed, stopping audio forwarding");
+                                audio_sender = None;
+                            }
+                        }
+                    }
+                    Some(_) | None => {
+                        audio_sender = None;
+                    }
                 }
             }
         }
+    }
+}
+
+/// Ends utterances on the client side when Aristech does not send a final result after the caller
+/// did not speak for the endpointing duration.
+#[derive(Debug)]
+struct UtteranceTimeout {
+    timeout: Duration,
+    /// Classifies each frame on its own, because the endpointing duration bridges the pauses.
+    detector: TurnDetector,
+    /// When the caller spoke last. Silent and missing audio both count as not speaking.
+    last_speech: Instant,
+    /// The text of the last partial result.
+    pending: Option<String>,
+    /// The text Aristech recognized up to the partial result that was output as final. It is
+    /// removed from the results of the same utterance, so that it is not output twice.
+    forced: Option<String>,
+}
+
+impl UtteranceTimeout {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            detector: TurnDetector::new(SPEECH_THRESHOLD, Duration::ZERO),
+            last_speech: Instant::now(),
+            pending: None,
+            forced: None,
+        }
+    }
+
+    fn audio(&mut self, frame: &AudioFrame) {
+        self.detector.process(frame);
+        if self.detector.in_turn() {
+            self.last_speech = Instant::now();
+        }
+    }
+
+    /// Tracks the partial results and removes the text that was already output as final from the
+    /// results of the same utterance. A final result that contains nothing more is suppressed.
+    fn process(&mut self, response: &mut StreamingRecognitionResponse) {
+        response.chunks.retain_mut(|chunk| {
+            let text = chunk.alternatives.first().map(|best| best.text.clone());
+            let mut continued = true;
+            if let Some(forced) = &self.forced {
+                for alternative in &mut chunk.alternatives {
+                    if let Some(rest) = continuation(&alternative.text, forced) {
+                        alternative.text = rest.to_owned();
+                    }
+                }
+                continued = chunk
+                    .alternatives
+                    .first()
+                    .is_none_or(|best| !best.text.is_empty());
+            }
+            if chunk.end_of_utterance {
+                self.pending = None;
+                self.forced = None;
+            } else if text.is_some() {
+                self.pending = text;
+            }
+            continued
+        });
+    }
+
+    /// Returns the text of the pending partial result to be output as final.
+    fn force(&mut self) -> Option<String> {
+        let text = self.pending.take()?;
+        let output = self
+            .forced
+            .as_ref()
+            .and_then(|forced| continuation(&text, forced))
+            .unwrap_or(&text)
+            .to_owned();
+        self.forced = Some(text);
+        (!output.is_empty()).then_some(output)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|_| self.last_speech + self.timeout)
+    }
+}
+
+/// The text that follows `forced` if `text` continues it.
+fn continuation<'a>(text: &'a str, forced: &str) -> Option<&'a str> {
+    let rest = text.strip_prefix(forced)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim_start())
+}
+
+async fn utterance_due(utterance_timeout: &Option<UtteranceTimeout>) {
+    match utterance_timeout
+        .as_ref()
+        .and_then(UtteranceTimeout::deadline)
+    {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// How the recognized chunks are output.
+#[derive(Debug, Default)]
+struct ResultOutput {
+    /// Interim text is only output if set.
+    interim_results: bool,
+    /// Final text is suppressed if its confidence is below.
+    min_confidence: Option<f32>,
+    /// If set, the alternatives are output as a control event instead of text.
+    max_alternatives: Option<usize>,
+}
 
+impl ResultOutput {
+    fn output_chunks(
+        &self,
+        output: &ConversationOutput,
+        response: StreamingRecognitionResponse,
+    ) -> Result<()> {
+        for chunk in response.chunks {
+            // Determine if this is a final result
+            // TODO: Find out if this is really the correct way to determine finality
+            // The `r#final` does not appear to be set.
+            let is_final = chunk.end_of_utterance;
+            if !is_final && !self.interim_results {
+                continue;
+            }
+
+            let mut alternatives = chunk.alternatives.into_iter();
+            let Some(best) = alternatives.next() else {
+                continue;
+            };
+            // A confidence of 0.0 means that none was provided.
+            if is_final
+                && let Some(min_confidence) = self.min_confidence
+                && best.confidence > 0.0
+                && best.confidence < min_confidence
+            {
+                debug!(
+                    confidence = best.confidence,
+                    min_confidence, "Suppressed final text with low confidence"
+                );
+                continue;
+            }
+
+            match self.max_alternatives {
+                Some(max_alternatives) => {
+                    let alternatives = iter::once(best)
+                        .chain(alternatives)
+                        .take(max_alternatives)
+                        .map(|alternative| {
+                            Alternative::new(alternative.text, alternative.confidence)
+                        })
+                        .collect();
+                    output_alternatives(output, is_final, alternatives, None)?;
+                }
+                None => output.text(is_final, best.text, None, None)?,
+            }
+        }
         Ok(())
     }
+
+    /// Outputs a final result that was not recognized as one by Aristech.
+    fn output_final(&self, output: &ConversationOutput, text: String) -> Result<()> {
+        match self.max_alternatives {
+            Some(_) => output_alternatives(output, true, vec![Alternative::new(text, 0.0)], None),
+            None => output.text(true, text, None, None),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{AuthConfig, Params};
-    use serde_json;
+    use std::time::Duration;
+
+    use futures::{StreamExt, stream};
+    use tokio::sync::mpsc::{self, Sender, UnboundedReceiver};
+    use tokio::time;
+    use tokio_stream::wrappers::UnboundedReceiverStream;
+
+    use super::{
+        AuthConfig, Compression, CompressionEncoding, Params, ResultOutput, Status,
+        StreamingRecognitionResponse, billing_scope, process_recognition,
+    };
+    use aristech_stt_client::stt_service::{SpeechRecognitionAlternative, SpeechRecognitionChunk};
+    use context_switch_core::{
+        AudioFormat, AudioFrame, Conversation, ConversationInput, ConversationOutput, Input,
+        InputModality, Output, OutputModality, OutputPath,
+    };
+    use serde_json::{self, json};
 
     #[test]
     fn test_deserialize_api_key_auth() {
@@ -244,6 +598,7 @@ mod tests {
         assert_eq!(params.language, "en_US");
         assert_eq!(params.model, None); // Default value for model
         assert_eq!(params.prompt, None); // Default value for prompt
+        assert_eq!(params.compression, Compression::Gzip); // Default value for compression
     }
 
     #[test]
@@ -365,4 +720,406 @@ mod tests {
         assert_eq!(params.model, Some("".into()));
         assert_eq!(params.prompt, Some("".into()));
     }
+
+    #[test]
+    fn test_deserialize_compression() {
+        let json_str = r#"{"apiKey": "test_key", "language": "en_US", "compression": "none"}"#;
+        let params: Params = serde_json::from_str(json_str).expect("Failed to parse JSON");
+        assert_eq!(params.compression, Compression::None);
+        assert_eq!(params.compression.encoding(), None);
+
+        let json_str = r#"{"apiKey": "test_key", "language": "en_US", "compression": "gzip"}"#;
+        let params: Params = serde_json::from_str(json_str).expect("Failed to parse JSON");
+        assert_eq!(params.compression, Compression::Gzip);
+        assert_eq!(
+            params.compression.encoding(),
+            Some(CompressionEncoding::Gzip)
+        );
+
+        let json_str = r#"{"apiKey": "test_key", "language": "en_US", "compression": "zstd"}"#;
+        let result: Result<Params, _> = serde_json::from_str(json_str);
+        assert!(result.is_err(), "Should fail for unsupported compression");
+    }
+
+    #[test]
+    fn audio_is_billed_per_model_or_language() {
+        assert_eq!(billing_scope(Some("general_de"), "de_DE"), "general_de");
+        assert_eq!(billing_scope(Some(""), "de_DE"), "de_DE");
+        assert_eq!(billing_scope(None, "de_DE"), "de_DE");
+    }
+
+    #[tokio::test]
+    async fn audio_forwarding_ends_when_response_stream_ends() {
+        let (_input_sender, mut input, output, _output_receiver) = start_conversation();
+        let (audio_sender, mut audio_receiver) = mpsc::unbounded_channel();
+
+        process_recognition(
+            &mut input,
+            audio_sender,
+            stream::empty(),
+            &output,
+            &ResultOutput::default(),
+            None,
+            "en_US",
+        )
+        .await
+        .unwrap();
+
+        // The input is still open, but the request stream must be closed.
+        assert!(audio_receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn response_stream_is_drained_when_input_ends() {
+        let (input_sender, mut input, output, _output_receiver) = start_conversation();
+        let (audio_sender, mut audio_receiver) = mpsc::unbounded_channel();
+        let (response_sender, response_receiver) = mpsc::unbounded_channel();
+
+        let format = AudioFormat::new(1, 16000);
+        input_sender
+            .send(Input::Audio {
+                frame: AudioFrame {
+                    format,
+                    samples: vec![1, 2],
+                },
+            })
+            .await
+            .unwrap();
+        drop(input_sender);
+
+        let recognition = process_recognition(
+            &mut input,
+            audio_sender,
+            UnboundedReceiverStream::new(response_receiver),
+            &output,
+            &ResultOutput::default(),
+            None,
+            "en_US",
+        );
+
+        let (result, ()) = tokio::join!(recognition, async move {
+            assert_eq!(audio_receiver.recv().await, Some(vec![1, 0, 2, 0]));
+            // The request stream is closed after the input ended.
+            assert!(audio_receiver.recv().await.is_none());
+            // But responses are still processed until the provider ends the stream.
+            response_sender
+                .send(Ok(StreamingRecognitionResponse::default()))
+                .unwrap();
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn interim_text_is_only_emitted_when_requested() {
+        let response = || StreamingRecognitionResponse {
+            chunks: vec![chunk("hello", false), chunk("hello world", true)],
+            ..Default::default()
+        };
+
+        for (interim_results, expected) in [
+            (true, vec![(false, "hello"), (true, "hello world")]),
+            (false, vec![(true, "hello world")]),
+        ] {
+            let (_input_sender, _input, output, mut output_receiver) = start_conversation();
+            let results = ResultOutput {
+                interim_results,
+                ..Default::default()
+            };
+            results.output_chunks(&output, response()).unwrap();
+            drop(output);
+
+            let mut texts = Vec::new();
+            while let Ok(output) = output_receiver.try_recv() {
+                if let Output::Text { is_final, text, .. } = output {
+                    texts.push((is_final, text));
+                }
+            }
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(is_final, text)| (is_final, text.to_string()))
+                .collect();
+            assert_eq!(texts, expected);
+        }
+    }
+
+    #[test]
+    fn final_text_below_the_minimum_confidence_is_suppressed() {
+        let response = StreamingRecognitionResponse {
+            chunks: vec![
+                chunk_with_confidence("mumble", false, 0.2),
+                chunk_with_confidence("mumble", true, 0.3),
+                chunk_with_confidence("hello world", true, 0.9),
+                chunk_with_confidence("no confidence", true, 0.0),
+            ],
+            ..Default::default()
+        };
+
+        let (_input_sender, _input, output, mut output_receiver) = start_conversation();
+        let results = ResultOutput {
+            interim_results: true,
+            min_confidence: Some(0.5),
+            ..Default::default()
+        };
+        results.output_chunks(&output, response).unwrap();
+        drop(output);
+
+        let mut texts = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            if let Output::Text { is_final, text, .. } = output {
+                texts.push((is_final, text));
+            }
+        }
+        assert_eq!(
+            texts,
+            [
+                (false, "mumble".to_string()),
+                (true, "hello world".to_string()),
+                (true, "no confidence".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn alternatives_are_output_as_a_control_event_when_requested() {
+        let params: Params = serde_json::from_str(
+            r#"{"apiKey": "test_key", "language": "en_US", "maxAlternatives": 2}"#,
+        )
+        .unwrap();
+        let mut chunk = chunk_with_confidence("one", true, 0.75);
+        for (text, confidence) in [("won", 0.125), ("on", 0.0625)] {
+            chunk.alternatives.push(SpeechRecognitionAlternative {
+                text: text.into(),
+                confidence,
+                ..Default::default()
+            });
+        }
+
+        let (_input_sender, _input, output, mut output_receiver) = start_conversation();
+        let results = ResultOutput {
+            max_alternatives: params.max_alternatives,
+            ..Default::default()
+        };
+        results.output_chunks(&output, response(chunk)).unwrap();
+        drop(output);
+
+        let mut events = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            match output {
+                Output::ServiceEvent { path, value } => events.push((path, value)),
+                Output::Text { .. } => panic!("Unexpected text output"),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            events,
+            [(
+                OutputPath::Control,
+                json!({
+                    "type": "transcriptAlternatives",
+                    "isFinal": true,
+                    "alternatives": [
+                        { "text": "one", "confidence": 0.75 },
+                        { "text": "won", "confidence": 0.125 },
+                    ],
+                })
+            )]
+        );
+    }
+
+    /// Runs the recognition over the responses, which arrive one second apart, and returns the
+    /// texts output.
+    async fn recognize(
+        responses: Vec<StreamingRecognitionResponse>,
+        endpointing: Option<Duration>,
+    ) -> Vec<(bool, String)> {
+        let (_input_sender, mut input, output, mut output_receiver) = start_conversation();
+        let (audio_sender, _audio_receiver) = mpsc::unbounded_channel();
+        let responses = stream::iter(responses).then(|response| async move {
+            time::sleep(Duration::from_secs(1)).await;
+            Ok::<_, Status>(response)
+        });
+
+        process_recognition(
+            &mut input,
+            audio_sender,
+            Box::pin(responses),
+            &output,
+            &ResultOutput {
+                interim_results: true,
+                ..Default::default()
+            },
+            endpointing,
+            "en_US",
+        )
+        .await
+        .unwrap();
+        drop(output);
+
+        let mut texts = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            if let Output::Text { is_final, text, .. } = output {
+                texts.push((is_final, text));
+            }
+        }
+        texts
+    }
+
+    fn response(chunk: SpeechRecognitionChunk) -> StreamingRecognitionResponse {
+        StreamingRecognitionResponse {
+            chunks: vec![chunk],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partial_result_is_output_as_final_after_the_endpointing_timeout() {
+        let responses = vec![
+            response(chunk("hello", false)),
+            StreamingRecognitionResponse::default(),
+            // Arrives after the timeout and is suppressed.
+            response(chunk("hello", true)),
+            response(chunk("bye", false)),
+            response(chunk("bye", true)),
+        ];
+        let texts = recognize(responses, Some(Duration::from_millis(1500))).await;
+        let expected = [
+            (false, "hello"),
+            (true, "hello"),
+            (false, "bye"),
+            (true, "bye"),
+        ]
+        .map(|(is_final, text)| (is_final, text.to_string()));
+        assert_eq!(texts, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn speech_continuing_after_a_forced_final_is_output() {
+        let (input_sender, mut input, output, mut output_receiver) = start_conversation();
+        let (audio_sender, _audio_receiver) = mpsc::unbounded_channel();
+        let (response_sender, response_receiver) = mpsc::unbounded_channel();
+        let results = ResultOutput {
+            interim_results: true,
+            ..Default::default()
+        };
+
+        let recognition = process_recognition(
+            &mut input,
+            audio_sender,
+            UnboundedReceiverStream::new(response_receiver),
+            &output,
+            &results,
+            Some(Duration::from_millis(500)),
+            "en_US",
+        );
+
+        let caller = async move {
+            let speech = || Input::Audio {
+                frame: AudioFrame {
+                    format: AudioFormat::new(1, 16000),
+                    samples: vec![8000; 1600],
+                },
+            };
+            let respond = |text: &str, end_of_utterance: bool| {
+                response_sender
+                    .send(Ok(response(chunk(text, end_of_utterance))))
+                    .unwrap();
+            };
+
+            input_sender.send(speech()).await.unwrap();
+            respond("hello", false);
+            // The caller pauses longer than the endpointing duration.
+            time::sleep(Duration::from_secs(1)).await;
+
+            // And continues for longer than the endpointing duration, in the same utterance.
+            for i in 0..10 {
+                input_sender.send(speech()).await.unwrap();
+                if i == 1 {
+                    respond("hello how are", false);
+                }
+                time::sleep(Duration::from_millis(100)).await;
+            }
+            respond("hello how are you", true);
+        };
+
+        let (result, ()) = tokio::join!(recognition, caller);
+        result.unwrap();
+        drop(output);
+
+        let mut texts = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            if let Output::Text { is_final, text, .. } = output {
+                texts.push((is_final, text));
+            }
+        }
+        let expected = [
+            (false, "hello"),
+            (true, "hello"),
+            (false, "how are"),
+            (true, "how are you"),
+        ]
+        .map(|(is_final, text)| (is_final, text.to_string()));
+        assert_eq!(texts, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn without_endpointing_partial_results_are_not_forced_final() {
+        let params: Params =
+            serde_json::from_str(r#"{"apiKey": "test_key", "language": "en_US"}"#).unwrap();
+        assert_eq!(params.endpointing_ms, None);
+
+        let responses = vec![
+            response(chunk("hello", false)),
+            response(chunk("hello world", false)),
+            response(chunk("hello world", true)),
+        ];
+        let texts = recognize(responses, None).await;
+        let expected = [
+            (false, "hello"),
+            (false, "hello world"),
+            (true, "hello world"),
+        ]
+        .map(|(is_final, text)| (is_final, text.to_string()));
+        assert_eq!(texts, expected);
+    }
+
+    fn chunk(text: &str, end_of_utterance: bool) -> SpeechRecognitionChunk {
+        chunk_with_confidence(text, end_of_utterance, 0.0)
+    }
+
+    fn chunk_with_confidence(
+        text: &str,
+        end_of_utterance: bool,
+        confidence: f32,
+    ) -> SpeechRecognitionChunk {
+        SpeechRecognitionChunk {
+            alternatives: vec![SpeechRecognitionAlternative {
+                text: text.into(),
+                confidence,
+                ..Default::default()
+            }],
+            end_of_utterance,
+            ..Default::default()
+        }
+    }
+
+    fn start_conversation() -> (
+        Sender<Input>,
+        ConversationInput,
+        ConversationOutput,
+        UnboundedReceiver<Output>,
+    ) {
+        let (input_sender, input_receiver) = mpsc::channel(4);
+        let (output_sender, output_receiver) = mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio {
+                format: AudioFormat::new(1, 16000),
+            },
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        (input_sender, input, output, output_receiver)
+    }
 }
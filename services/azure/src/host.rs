@@ -1,35 +1,105 @@
 use std::env;
+use std::error;
+use std::time::Duration;
 
 use anyhow::{Result, anyhow};
+use tokio::time::timeout;
 use url::Url;
 
 use azure_speech::Auth;
 
+use crate::region;
+
+/// How long connecting to the speech service may take before the conversation fails.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 pub struct Host {
     pub(crate) auth: Auth,
+    /// The host or region, for error messages.
+    name: String,
+    connect_timeout: Duration,
 }
 
 impl Host {
     pub fn from_env() -> Result<Self> {
-        let auth = Auth::from_subscription(
+        Self::from_subscription(
             env::var("AZURE_REGION").map_err(|_| anyhow!("Region not set on AZURE_REGION env"))?,
             env::var("AZURE_SUBSCRIPTION_KEY")
                 .map_err(|_| anyhow!("Subscription not set on AZURE_SUBSCRIPTION_KEY env"))?,
-        );
-        Ok(Self { auth })
+        )
     }
 
     pub fn from_host(host: impl Into<String>, subscription_key: impl Into<String>) -> Result<Self> {
-        let auth = Auth::from_host(Url::parse(&host.into())?, subscription_key);
-        Ok(Self { auth })
+        let name = host.into();
+        let auth = Auth::from_host(Url::parse(&name)?, subscription_key);
+        Ok(Self::new(auth, name))
     }
 
     pub fn from_subscription(
         region: impl Into<String>,
         subscription_key: impl Into<String>,
     ) -> Result<Self> {
-        let auth = Auth::from_subscription(region, subscription_key);
-        Ok(Self { auth })
+        let name = region.into();
+        let auth = Auth::from_subscription(name.clone(), subscription_key);
+        Ok(Self::new(auth, name))
+    }
+
+    /// Uses the candidate region with the lowest latency. The regions are probed only once per
+    /// process.
+    pub async fn from_regions(
+        regions: &[String],
+        subscription_key: impl Into<String>,
+    ) -> Result<Self> {
+        let region = region::auto_region(regions).await?;
+        Self::from_subscription(region, subscription_key)
+    }
+
+    fn new(auth: Auth, name: String) -> Self {
+        Self {
+            auth,
+            name,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        Self {
+            connect_timeout,
+            ..self
+        }
+    }
+
+    /// Waits for a client to connect and fails if that takes longer than the connect timeout.
+    pub(crate) async fn connect<T, E>(
+        &self,
+        connect: impl Future<Output = Result<T, E>>,
+    ) -> Result<T>
+    where
+        E: error::Error + Send + Sync + 'static,
+    {
+        let client = timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| anyhow!("Connection to {} timed out", self.name))??;
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future, io};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_fails_when_it_takes_longer_than_the_timeout() {
+        let host = Host::from_subscription("westeurope", "key")
+            .unwrap()
+            .with_connect_timeout(Duration::from_millis(10));
+        let error = host
+            .connect(future::pending::<Result<(), io::Error>>())
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Connection to westeurope timed out");
     }
 }
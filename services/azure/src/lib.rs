@@ -1,4 +1,6 @@
 mod host;
+mod region;
+mod session;
 // TODO: Attempt to make the modules non-pub
 pub mod synthesize;
 pub mod transcribe;
@@ -0,0 +1,104 @@
+//! Selection of the lowest-latency region from a set of candidates.
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use futures::future;
+use tokio::net::TcpStream;
+use tokio::time::{Instant, timeout};
+use tracing::{info, warn};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The selected region per set of candidates. Regions are probed only once per process.
+static SELECTED_REGIONS: LazyLock<Mutex<HashMap<Vec<String>, String>>> =
+    LazyLock::new(Default::default);
+
+/// Returns the candidate region with the lowest connection latency.
+pub async fn auto_region(candidates: &[String]) -> Result<String> {
+    if let Some(region) = SELECTED_REGIONS.lock().unwrap().get(candidates) {
+        return Ok(region.clone());
+    }
+
+    let region = select_region(candidates, probe_latency).await?;
+    info!("Selected Azure region `{region}` out of {candidates:?}");
+    SELECTED_REGIONS
+        .lock()
+        .unwrap()
+        .insert(candidates.to_vec(), region.clone());
+    Ok(region)
+}
+
+/// Probes all candidates concurrently and returns the one with the lowest latency. Candidates
+/// that fail to respond are skipped.
+async fn select_region<F>(candidates: &[String], probe: impl Fn(String) -> F) -> Result<String>
+where
+    F: Future<Output = Result<Duration>>,
+{
+    if candidates.is_empty() {
+        bail!("No candidate regions to select from");
+    }
+
+    let latencies = future::join_all(candidates.iter().map(|region| probe(region.clone()))).await;
+
+    candidates
+        .iter()
+        .zip(latencies)
+        .filter_map(|(region, latency)| match latency {
+            Ok(latency) => Some((region, latency)),
+            Err(e) => {
+                warn!("Failed to probe Azure region `{region}`: {e:?}");
+                None
+            }
+        })
+        .min_by_key(|(_, latency)| *latency)
+        .map(|(region, _)| region.clone())
+        .context("None of the candidate regions could be reached")
+}
+
+/// The time it takes to establish a TCP connection to the region's speech endpoint.
+async fn probe_latency(region: String) -> Result<Duration> {
+    let start = Instant::now();
+    timeout(
+        PROBE_TIMEOUT,
+        TcpStream::connect((format!("{region}.stt.speech.microsoft.com"), 443)),
+    )
+    .await
+    .context("Timed out")??;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lowest_latency_region_is_selected() {
+        let candidates: Vec<String> = ["westeurope", "germanywestcentral", "eastus", "northeurope"]
+            .map(String::from)
+            .into();
+
+        let region = select_region(&candidates, |region| async move {
+            match region.as_str() {
+                "westeurope" => Ok(Duration::from_millis(40)),
+                "germanywestcentral" => Ok(Duration::from_millis(12)),
+                "eastus" => Ok(Duration::from_millis(110)),
+                _ => Err(anyhow!("unreachable")),
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(region, "germanywestcentral");
+    }
+
+    #[tokio::test]
+    async fn selection_fails_if_no_region_responds() {
+        let candidates = vec!["westeurope".to_string()];
+        let result = select_region(&candidates, |_| async { Err(anyhow!("unreachable")) }).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Recognizer sessions that are re-established when Azure ends them prematurely.
+use std::pin::Pin;
+
+use anyhow::{Result, bail};
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio::{pin, select};
+use tracing::warn;
+
+use context_switch_core::{AudioFormat, AudioFrame, ConversationInput, Input};
+
+/// The audio a recognizer session receives: a WAV header followed by the samples.
+pub(crate) type AudioStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+/// A connected recognizer session and the sender of its audio.
+pub(crate) struct Session<S> {
+    wav_header: Vec<u8>,
+    audio_sender: UnboundedSender<Vec<u8>>,
+    events: S,
+}
+
+impl<S> Session<S> {
+    /// Connects a session that receives audio in `input_format`.
+    pub(crate) async fn connect<F>(
+        connect: impl FnOnce(AudioStream) -> F,
+        input_format: AudioFormat,
+    ) -> Result<Self>
+    where
+        F: Future<Output = Result<S>>,
+    {
+        let wav_header = hound::WavSpec {
+            sample_rate: input_format.sample_rate,
+            channels: input_format.channels,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        }
+        .into_header_for_infinite_file();
+        Self::connect_with_header(connect, wav_header).await
+    }
+
+    async fn connect_with_header<F>(
+        connect: impl FnOnce(AudioStream) -> F,
+        wav_header: Vec<u8>,
+    ) -> Result<Self>
+    where
+        F: Future<Output = Result<S>>,
+    {
+        let (audio_sender, mut audio_receiver) = unbounded_channel();
+        let header = wav_header.clone();
+        let audio = stream! {
+            yield header;
+            while let Some(samples) = audio_receiver.recv().await {
+                yield samples;
+            }
+        };
+        let events = connect(Box::pin(audio)).await?;
+        Ok(Self {
+            wav_header,
+            audio_sender,
+            events,
+        })
+    }
+}
+
+/// Forwards the audio input to the recognizer session and handles its events until the input
+/// ends.
+///
+/// Azure ends sessions on its side, for example after timeouts on long calls. When a session
+/// ends while the input is still flowing, a new one is connected, up to `max_reconnects` times.
+pub(crate) async fn recognize_with_reconnects<E, S, F>(
+    mut session: Session<S>,
+    mut connect: impl FnMut(AudioStream) -> F,
+    input: &mut ConversationInput,
+    mut process_frame: impl FnMut(AudioFrame) -> Result<AudioFrame>,
+    mut handle_event: impl FnMut(E) -> Result<()>,
+    max_reconnects: u32,
+) -> Result<()>
+where
+    F: Future<Output = Result<S>>,
+    S: Stream<Item = Result<E>>,
+{
+    let mut reconnects = 0;
+    loop {
+        let Session {
+            wav_header,
+            audio_sender,
+            events,
+        } = session;
+        pin!(events);
+        // `None` after the input ended, which ends the audio of the session.
+        let mut audio_sender = Some(audio_sender);
+
+        loop {
+            select! {
+                event = events.next() => match event {
+                    Some(event) => handle_event(event?)?,
+                    None => break,
+                },
+                input_event = input.recv(), if audio_sender.is_some() => match input_event {
+                    Some(Input::Audio { frame }) => {
+                        let frame = process_frame(frame)?;
+                        if let Some(sender) = &audio_sender {
+                            // If the session is gone, its end is handled above.
+                            let _ = sender.send(frame.to_le_bytes());
+                        }
+                    }
+                    _ => audio_sender = None,
+                },
+            }
+        }
+
+        if audio_sender.is_none() {
+            return Ok(());
+        }
+        if reconnects == max_reconnects {
+            bail!("The recognizer session ended while audio was still sent");
+        }
+        reconnects += 1;
+        warn!("Recognizer session ended prematurely, reconnecting ({reconnects}/{max_reconnects})");
+        session = Session::connect_with_header(&mut connect, wav_header).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{future, stream};
+    use tokio::sync::mpsc::channel;
+    use tokio::task;
+
+    use context_switch_core::{Conversation, InputModality, OutputModality};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn premature_session_end_reconnects_the_recognizer() {
+        let format = AudioFormat::new(1, 16000);
+        let (input_sender, input_receiver) = channel(1);
+        let (output_sender, _output_receiver) = unbounded_channel();
+        let (mut input, _output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        let connects = AtomicUsize::new(0);
+        let mut connect = |audio: AudioStream| {
+            let events = if connects.fetch_add(1, Ordering::SeqCst) == 0 {
+                // The provider ends the first session while audio is still sent.
+                stream::iter([Ok("ended".to_owned())]).boxed()
+            } else {
+                // Skip the WAV header.
+                audio
+                    .skip(1)
+                    .map(|samples| Ok(format!("{} bytes", samples.len())))
+                    .boxed()
+            };
+            future::ready(anyhow::Ok(events))
+        };
+        let send_audio = async {
+            // Wait for the reconnect, so that the audio is received by the second session.
+            while connects.load(Ordering::SeqCst) < 2 {
+                task::yield_now().await;
+            }
+            for _ in 0..2 {
+                let frame = AudioFrame {
+                    format,
+                    samples: vec![0; 160],
+                };
+                input_sender.send(Input::Audio { frame }).await.unwrap();
+            }
+            drop(input_sender);
+        };
+
+        let session = Session::connect(&mut connect, format).await.unwrap();
+        let mut events = Vec::new();
+        let recognize = recognize_with_reconnects(
+            session,
+            connect,
+            &mut input,
+            Ok,
+            |event| {
+                events.push(event);
+                Ok(())
+            },
+            1,
+        );
+        let (result, ()) = tokio::join!(recognize, send_audio);
+        result.unwrap();
+
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(events, ["ended", "320 bytes", "320 bytes"]);
+    }
+}
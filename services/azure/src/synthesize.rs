@@ -1,17 +1,25 @@
+use std::time::Duration;
+
 use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
 
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
+use url::Url;
 
 use azure_speech::stream::StreamExt;
 use azure_speech::synthesizer::ssml::ToSSML;
 use azure_speech::synthesizer::ssml::ssml::{self, SerializeOptions};
-use azure_speech::synthesizer::{self, AudioFormat};
+use azure_speech::synthesizer::{self, AudioFormat, message};
 
 use context_switch_core::{
-    AudioFrame, BillingRecord, BillingSchedule, Conversation, Input, Service,
+    AudioFrame, BillingRecord, BillingSchedule, Conversation, Input, InputModality,
+    OutputModalities, OutputPath, RequestFailed, Service,
+    frame_chunker::FrameChunker,
+    latency::LatencyTimer,
+    spoken_text::{SpokenLanguage, normalize_for_speech},
+    text::{DEFAULT_MAX_SYNTHESIZE_TEXT_LEN, require_max_len},
 };
 
 use crate::Host;
@@ -22,9 +30,34 @@ pub struct Params {
     #[serde(alias = "host")]
     pub endpoint: Option<String>,
     pub region: Option<String>,
+    /// Candidate regions to select the lowest-latency one from, if neither `endpoint` nor `region`
+    /// is set.
+    pub regions: Option<Vec<String>>,
     pub subscription_key: String,
     pub language: String,
     pub voice: Option<String>,
+    /// If set, the synthesized audio is re-chunked into frames of this duration.
+    pub frame_duration_ms: Option<u32>,
+    /// If set, dates and currency amounts in plain text are expanded into spoken words in
+    /// `language` before synthesis. Supported for English and German.
+    #[serde(default)]
+    pub normalize_text: bool,
+    /// The maximum number of characters of a text to synthesize. Longer texts are rejected.
+    /// Defaults to [`DEFAULT_MAX_SYNTHESIZE_TEXT_LEN`].
+    #[serde(default)]
+    pub max_text_len: Option<usize>,
+    /// Custom lexicons referenced from every request's SSML, for example to fix the pronunciation
+    /// of brand and product names. Must be `http` or `https` URLs.
+    #[serde(default)]
+    pub lexicon_urls: Vec<String>,
+    /// Send a `providerLatency` event for each request, see
+    /// [`ProviderLatency`](context_switch_core::latency::ProviderLatency).
+    #[serde(default)]
+    pub report_latency: bool,
+    /// Send a `wordBoundary` event for each spoken word, so that clients can highlight the text
+    /// in sync with the audio.
+    #[serde(default)]
+    pub word_boundaries: bool,
 }
 
 #[derive(Debug)]
@@ -34,6 +67,16 @@ pub struct AzureSynthesize;
 impl Service for AzureSynthesize {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_text_input_only()?;
+        output_modalities.require_single_audio_output()?;
+        Ok(())
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         conversation.require_text_input_only()?;
         let output_format = conversation.require_single_audio_output()?;
@@ -46,6 +89,16 @@ impl Service for AzureSynthesize {
         };
 
         let billing_scope = voice_to_billing_scope(&voice)?;
+        let max_text_len = params
+            .max_text_len
+            .unwrap_or(DEFAULT_MAX_SYNTHESIZE_TEXT_LEN);
+
+        let lexicons = parse_lexicon_urls(&params.lexicon_urls)?;
+
+        let spoken_language = params
+            .normalize_text
+            .then(|| SpokenLanguage::from_locale(&params.language))
+            .transpose()?;
 
         // Host / Auth is lightweight, so we can create this every time.
         let host = {
@@ -53,8 +106,10 @@ impl Service for AzureSynthesize {
                 Host::from_host(endpoint, params.subscription_key)?
             } else if let Some(region) = params.region {
                 Host::from_subscription(region, params.subscription_key)?
+            } else if let Some(regions) = params.regions {
+                Host::from_regions(&regions, params.subscription_key).await?
             } else {
-                bail!("Neither endpoint nor region is defined in params");
+                bail!("Neither endpoint, region, nor regions is defined in params");
             }
         };
 
@@ -62,12 +117,26 @@ impl Service for AzureSynthesize {
         let config = synthesizer::Config::default()
             .disable_auto_detect_language()
             .enable_session_end()
+            .enable_bookmark()
             .with_audio_format(azure_audio_format);
+        let config = if params.word_boundaries {
+            config.enable_word_boundary()
+        } else {
+            config
+        };
+
+        let client = host
+            .connect(synthesizer::Client::connect(host.auth.clone(), config))
+            .await?;
 
-        let client = synthesizer::Client::connect(host.auth.clone(), config).await?;
+        let mut chunker = params
+            .frame_duration_ms
+            .map(|ms| FrameChunker::new(output_format, Duration::from_millis(ms.into())))
+            .transpose()?;
 
         let language = params.language;
         let (mut input, output) = conversation.start()?;
+        output.service_ready()?;
 
         loop {
             let Some(input) = input.recv().await else {
@@ -85,12 +154,24 @@ impl Service for AzureSynthesize {
                 bail!("Unexpected input");
             };
 
+            // Azure does not complete a synthesis without any text.
+            if text.trim().is_empty() {
+                debug!("Text is empty, nothing to synthesize");
+                output.request_completed(request_id)?;
+                continue;
+            }
+
+            require_max_len(&text, max_text_len).context(RequestFailed(request_id.clone()))?;
+
             const TYPE_TEXT: &str = "text/plain";
             const TYPE_SSML: &str = "application/ssml+xml";
 
             let text_type = text_type.as_deref().unwrap_or(TYPE_TEXT);
             let text = match text_type {
-                TYPE_TEXT => TextOrSSML::Text(text),
+                TYPE_TEXT => TextOrSSML::Text(match spoken_language {
+                    Some(language) => normalize_for_speech(&text, language),
+                    None => text,
+                }),
                 TYPE_SSML => TextOrSSML::Ssml(text),
                 ty => {
                     bail!(
@@ -102,45 +183,130 @@ impl Service for AzureSynthesize {
             let azure_request = AzureSynthesizeRequest {
                 language: language.clone(),
                 voice: voice.clone(),
+                lexicons: lexicons.clone(),
                 text,
             };
 
-            let mut stream = client.synthesize(azure_request).await?;
+            let mut latency = LatencyTimer::start();
+            let mut stream = client
+                .synthesize(azure_request)
+                .await
+                .context(RequestFailed(request_id.clone()))?;
             while let Some(event) = stream.next().await {
-                let event = event.context("Azure synthesizer event error")?;
+                let event = event
+                    .context("Azure synthesizer event error")
+                    .context(RequestFailed(request_id.clone()))?;
                 match event {
                     synthesizer::Event::Synthesising(_uuid, audio) => {
+                        latency.result_received();
                         let frame = AudioFrame::from_le_bytes(output_format, &audio);
                         let duration = frame.duration();
                         debug!("Received audio: {duration:?}");
 
-                        // Robustness: Output max size of 1seconds frame. Moreover, define the
-                        // granularity of the frames somewhere.
-                        output.audio_frame(frame)?;
+                        match &mut chunker {
+                            Some(chunker) => {
+                                for frame in chunker.push(&frame.samples) {
+                                    output.audio_frame(frame)?;
+                                }
+                            }
+                            // Robustness: Output max size of 1seconds frame. Moreover, define the
+                            // granularity of the frames somewhere.
+                            None => output.audio_frame(frame)?,
+                        }
                         output.billing_records(
                             request_id.clone(),
                             billing_scope.to_string(),
                             [BillingRecord::duration("output:audio", duration)],
-                            BillingSchedule::Now,
+                            BillingSchedule::OnRequestComplete,
                         )?;
                     }
+                    synthesizer::Event::AudioMetadata(_uuid, metadata) => {
+                        for metadata in metadata {
+                            let event = match metadata {
+                                message::Metadata::Bookmark(bookmark) => {
+                                    ServiceEvent::bookmark(bookmark.bookmark, bookmark.offset)
+                                }
+                                message::Metadata::WordBoundary(boundary) => {
+                                    ServiceEvent::word_boundary(
+                                        boundary.text.text,
+                                        boundary.offset,
+                                        boundary.duration,
+                                    )
+                                }
+                                metadata => {
+                                    debug!("Ignored metadata: {metadata:?}");
+                                    continue;
+                                }
+                            };
+                            output.service_event(OutputPath::Control, event)?;
+                        }
+                    }
                     event => {
                         debug!("Received: {event:?}")
                     }
                 };
             }
 
+            if let Some(frame) = chunker.as_mut().and_then(FrameChunker::flush) {
+                output.audio_frame(frame)?;
+            }
+
+            if params.report_latency {
+                latency.report(&output, request_id.clone())?;
+            }
             output.request_completed(request_id)?;
         }
     }
 }
 
+#[derive(Debug, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum ServiceEvent {
+    /// A `<bookmark>` in the SSML was reached. The offset is relative to the start of the
+    /// request's audio.
+    Bookmark { name: String, offset_ms: u64 },
+    /// A word is spoken from `offset_ms` for `duration_ms`, relative to the start of the
+    /// request's audio.
+    WordBoundary {
+        text: String,
+        offset_ms: u64,
+        duration_ms: u64,
+    },
+}
+
+impl ServiceEvent {
+    fn bookmark(name: String, offset_ticks: u64) -> Self {
+        Self::Bookmark {
+            name,
+            offset_ms: ticks_to_ms(offset_ticks),
+        }
+    }
+
+    fn word_boundary(text: String, offset_ticks: u64, duration_ticks: u64) -> Self {
+        Self::WordBoundary {
+            text,
+            offset_ms: ticks_to_ms(offset_ticks),
+            duration_ms: ticks_to_ms(duration_ticks),
+        }
+    }
+}
+
+/// Azure reports offsets and durations in ticks of 100ns.
+fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks / 10_000
+}
+
 /// This is because we won't want to go through voice and language conversion and therefore we are
 /// forced to use SSML directly.
 #[derive(Debug)]
 struct AzureSynthesizeRequest {
     language: String,
     voice: String,
+    lexicons: Vec<Url>,
     text: TextOrSSML,
 }
 
@@ -156,17 +322,46 @@ impl ToSSML for AzureSynthesizeRequest {
         _language: azure_speech::synthesizer::Language,
         _voice: azure_speech::synthesizer::Voice,
     ) -> azure_speech::Result<String> {
-        let content: ssml::Element = match &self.text {
+        self.ssml()
+    }
+}
+
+impl AzureSynthesizeRequest {
+    fn ssml(&self) -> azure_speech::Result<String> {
+        // Azure expects the lexicons as the first children of the voice.
+        let mut content: Vec<ssml::Element> = self
+            .lexicons
+            .iter()
+            .map(|url| ssml::Meta::new(lexicon_element(url)).into())
+            .collect();
+        content.push(match &self.text {
             TextOrSSML::Text(text) => text.into(),
             TextOrSSML::Ssml(ssml) => ssml::Meta::new(ssml).into(),
-        };
+        });
         serialize_to_ssml(&ssml::speak(
             Some(self.language.as_str()),
-            [ssml::voice(self.voice.as_str(), [content])],
+            [ssml::voice(self.voice.as_str(), content)],
         ))
     }
 }
 
+fn parse_lexicon_urls(urls: &[String]) -> Result<Vec<Url>> {
+    urls.iter()
+        .map(|url| {
+            let parsed = Url::parse(url).with_context(|| format!("Invalid lexicon URL `{url}`"))?;
+            match parsed.scheme() {
+                "http" | "https" => Ok(parsed),
+                scheme => bail!("Unsupported scheme `{scheme}` of lexicon URL `{url}`"),
+            }
+        })
+        .collect()
+}
+
+/// Serialized URLs never contain quotes or angle brackets, only the ampersand must be escaped.
+fn lexicon_element(url: &Url) -> String {
+    format!(r#"<lexicon uri="{}"/>"#, url.as_str().replace('&', "&amp;"))
+}
+
 fn serialize_to_ssml(speak: &impl ssml::Serialize) -> azure_speech::Result<String> {
     speak
         .serialize_to_string(
@@ -241,8 +436,94 @@ fn voice_to_billing_scope(voice: &str) -> Result<BillingScope> {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
     use super::*;
 
+    #[test]
+    fn bookmarks_in_ssml_are_reported_at_their_offset() {
+        let ssml = serialize_to_ssml(&ssml::speak(
+            Some("en-US"),
+            [ssml::voice(
+                "en-US-JennyNeural",
+                [ssml::Meta::new(
+                    r#"Look at the <bookmark mark="flower"/>flower"#,
+                )],
+            )],
+        ))
+        .unwrap();
+        assert!(ssml.contains(r#"<bookmark mark="flower"/>"#));
+
+        // Azure reports the bookmark 1.25s into the audio.
+        let event =
+            serde_json::to_value(ServiceEvent::bookmark("flower".into(), 12_500_000)).unwrap();
+        assert_eq!(
+            event,
+            json!({ "type": "bookmark", "name": "flower", "offsetMs": 1250 })
+        );
+    }
+
+    #[test]
+    fn word_boundaries_are_reported_at_their_offset() {
+        // "Hello world" with the second word starting 0.5125s into the audio.
+        let boundaries = [
+            ("Hello", 500_000, 4_250_000),
+            ("world", 5_125_000, 3_750_000),
+        ];
+        let events: Vec<_> = boundaries
+            .into_iter()
+            .map(|(text, offset, duration)| {
+                serde_json::to_value(ServiceEvent::word_boundary(text.into(), offset, duration))
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                json!({ "type": "wordBoundary", "text": "Hello", "offsetMs": 50, "durationMs": 425 }),
+                json!({ "type": "wordBoundary", "text": "world", "offsetMs": 512, "durationMs": 375 }),
+            ]
+        );
+    }
+
+    fn request(lexicon_urls: &[&str]) -> AzureSynthesizeRequest {
+        let lexicon_urls: Vec<String> = lexicon_urls.iter().map(|url| url.to_string()).collect();
+        AzureSynthesizeRequest {
+            language: "en-US".into(),
+            voice: "en-US-JennyNeural".into(),
+            lexicons: parse_lexicon_urls(&lexicon_urls).unwrap(),
+            text: TextOrSSML::Text("Context Switch".into()),
+        }
+    }
+
+    #[test]
+    fn lexicons_are_referenced_in_the_voice() {
+        let ssml = request(&[
+            "https://example.com/brands.xml",
+            "https://example.com/products.xml?a=1&b=2",
+        ])
+        .ssml()
+        .unwrap();
+        assert!(
+            ssml.contains(concat!(
+                r#"<voice name="en-US-JennyNeural">"#,
+                r#"<lexicon uri="https://example.com/brands.xml"/>"#,
+                r#"<lexicon uri="https://example.com/products.xml?a=1&amp;b=2"/>"#,
+                "Context Switch</voice>"
+            )),
+            "{ssml}"
+        );
+
+        let ssml = request(&[]).ssml().unwrap();
+        assert!(!ssml.contains("lexicon"), "{ssml}");
+    }
+
+    #[test]
+    fn invalid_lexicon_urls_are_rejected() {
+        assert!(parse_lexicon_urls(&["brands.xml".into()]).is_err());
+        assert!(parse_lexicon_urls(&["file:///brands.xml".into()]).is_err());
+    }
+
     #[test]
     fn billing_scope_to_string() {
         assert_eq!(
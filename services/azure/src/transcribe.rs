@@ -1,19 +1,21 @@
 use anyhow::{Context, Result, bail};
 use async_stream::stream;
 use async_trait::async_trait;
-use futures::StreamExt;
+use futures::Stream;
 use serde::Deserialize;
-use tracing::{error, info};
+use tracing::info;
 
 use azure_speech::recognizer::{self, Event};
 
 use context_switch_core::language::Languages;
 use context_switch_core::{
-    BillingRecord, BillingSchedule, Conversation, ConversationOutput, Input, Service,
-    speech_gate::make_speech_gate_processor_soft_rms,
+    AudioFrame, BillingRecord, BillingSchedule, Conversation, ConversationOutput, InputModality,
+    OutputModalities, Service, speech_gate::make_speech_gate_processor_soft_rms,
+    trace::output_provider_request,
 };
 
 use crate::Host;
+use crate::session::{AudioStream, Session, recognize_with_reconnects};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -21,12 +23,19 @@ pub struct Params {
     #[serde(alias = "host")]
     pub endpoint: Option<String>,
     pub region: Option<String>,
+    /// Candidate regions to select the lowest-latency one from, if neither `endpoint` nor `region`
+    /// is set.
+    pub regions: Option<Vec<String>>,
     pub subscription_key: String,
     pub language: String,
     #[serde(default)]
     pub diarization: bool,
     #[serde(default)]
     pub speech_gate: bool,
+    /// How often a recognizer session that ended while audio was still sent is re-established
+    /// before the conversation fails.
+    #[serde(default)]
+    pub max_reconnects: u32,
 }
 
 #[derive(Debug)]
@@ -36,9 +45,19 @@ pub struct AzureTranscribe;
 impl Service for AzureTranscribe {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_text_output(true)
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
         conversation.require_text_output(true)?;
+        let interim_results = conversation.output_modalities().interim_text();
 
         // Host / Auth is lightweight, so we can create this every time.
         let host = {
@@ -46,8 +65,10 @@ impl Service for AzureTranscribe {
                 Host::from_host(endpoint, params.subscription_key)?
             } else if let Some(region) = params.region {
                 Host::from_subscription(region, params.subscription_key)?
+            } else if let Some(regions) = params.regions {
+                Host::from_regions(&regions, params.subscription_key).await?
             } else {
-                bail!("Neither endpoint nor region defined in params");
+                bail!("Neither endpoint, region, nor regions defined in params");
             }
         };
 
@@ -55,95 +76,124 @@ impl Service for AzureTranscribe {
             .context("language must contain at least one locale code")?;
         let include_detected_language = languages.len() > 1;
 
-        let config = recognizer::Config::default()
-            // Disable profanity filter.
-            .set_profanity(recognizer::Profanity::Raw);
-
-        let config = if params.diarization {
-            config.enable_recognize_speaker()
-        } else {
-            config
-        };
-
-        let config = if languages.len() == 1 {
-            config.set_language(recognizer::Language::Custom(languages.first().clone()))
-        } else {
-            config.set_detect_languages(
-                languages
-                    .iter()
-                    .cloned()
-                    .map(recognizer::Language::Custom)
-                    .collect(),
-                recognizer::LanguageDetectMode::Continuous,
+        let mut connect = |audio| {
+            connect_session(
+                &host,
+                recognizer_config(&languages, params.diarization),
+                audio,
             )
-        }
-        .set_output_format(recognizer::OutputFormat::Detailed);
-
-        let client = recognizer::Client::connect(host.auth.clone(), config).await?;
+        };
+        let session = Session::connect(&mut connect, input_format).await?;
 
         let (mut input, output) = conversation.start()?;
+        output.service_ready()?;
 
-        let audio_stream = {
-            let billing_output = output.clone();
-            let wav_header = hound::WavSpec {
-                sample_rate: input_format.sample_rate,
-                channels: input_format.channels,
-                bits_per_sample: 16,
-                sample_format: hound::SampleFormat::Int,
+        let mut speech_gate = if params.speech_gate {
+            info!("Enabling speech gate");
+            Some(make_speech_gate_processor_soft_rms(0.0025, 10., 300., 0.01))
+        } else {
+            None
+        };
+        let process_frame = |mut frame: AudioFrame| -> Result<AudioFrame> {
+            if let Some(ref mut speech_gate) = speech_gate {
+                frame = (speech_gate)(&frame);
             }
-            .into_header_for_infinite_file();
-            stream! {
-                yield wav_header;
-                let mut speech_gate =
-                    if params.speech_gate {
-                        info!("Enabling speech gate");
-                        Some(make_speech_gate_processor_soft_rms(0.0025, 10., 300., 0.01))
-                    }
-                    else {
-                        None
-                    };
-                while let Some(Input::Audio{ mut frame }) = input.recv().await {
-                    if let Some(ref mut speech_gate) = speech_gate {
-                        frame = (speech_gate)(&frame);
-                    }
-                    yield frame.to_le_bytes();
-                    // <https://azure.microsoft.com/en-us/pricing/details/cognitive-services/speech-services/>
-                    // Speech to text hours are measured as the hours of audio _sent to the service_, billed in second increments.
-                    // No `Result<>` context, we can't fail here, instead log an error.
-                    if let Err(e) = billing_output.billing_records(None, None, [BillingRecord::duration("input:audio", frame.duration())], BillingSchedule::Now) {
-                        error!("Internal error: Failed to output billing records: {e}");
+            // <https://azure.microsoft.com/en-us/pricing/details/cognitive-services/speech-services/>
+            // Speech to text hours are measured as the hours of audio _sent to the service_, billed in second increments.
+            output.billing_records(
+                None,
+                None,
+                [BillingRecord::duration("input:audio", frame.duration())],
+                BillingSchedule::Now,
+            )?;
+            Ok(frame)
+        };
+
+        let handle_event = |event: Event| -> Result<()> {
+            match event {
+                Event::SessionStarted(request_id) => {
+                    output_provider_request(&output, "azure", &request_id.to_string())
+                }
+                Event::SessionEnded(_) | Event::StartDetected(_, _) | Event::EndDetected(_, _) => {
+                    Ok(())
+                }
+                Event::Recognizing(_, recognized, _, _, _) => {
+                    if interim_results {
+                        output_recognized_text(
+                            &output,
+                            recognized,
+                            false,
+                            include_detected_language,
+                        )?
                     }
+                    Ok(())
                 }
+                Event::Recognized(_, recognized, _, _, _) => {
+                    output_recognized_text(&output, recognized, true, include_detected_language)
+                }
+                Event::UnMatch(_, _, _, _) => Ok(()),
             }
         };
 
-        let audio_stream = Box::pin(audio_stream);
+        recognize_with_reconnects(
+            session,
+            connect,
+            &mut input,
+            process_frame,
+            handle_event,
+            params.max_reconnects,
+        )
+        .await
+    }
+}
 
+/// Connects a recognizer session that recognizes `audio`.
+async fn connect_session(
+    host: &Host,
+    config: recognizer::Config,
+    audio: AudioStream,
+) -> Result<impl Stream<Item = Result<Event>>> {
+    let client = host
+        .connect(recognizer::Client::connect(host.auth.clone(), config))
+        .await?;
+    Ok(stream! {
         // TODO: do they have an effect?
         let device = recognizer::AudioDevice::unknown();
-
-        let mut stream = client
-            .recognize(audio_stream, recognizer::AudioFormat::Wav, device)
-            .await?;
-
-        while let Some(event) = stream.next().await {
-            match event? {
-                Event::SessionStarted(_)
-                | Event::SessionEnded(_)
-                | Event::StartDetected(_, _)
-                | Event::EndDetected(_, _) => {}
-                Event::Recognizing(_, recognized, _, _, _) => {
-                    output_recognized_text(&output, recognized, false, include_detected_language)?
+        match client.recognize(audio, recognizer::AudioFormat::Wav, device).await {
+            Ok(events) => {
+                for await event in events {
+                    yield event.map_err(anyhow::Error::from);
                 }
-                Event::Recognized(_, recognized, _, _, _) => {
-                    output_recognized_text(&output, recognized, true, include_detected_language)?
-                }
-                Event::UnMatch(_, _, _, _) => {}
             }
+            Err(e) => yield Err(e.into()),
         }
+    })
+}
+
+fn recognizer_config(languages: &Languages, diarization: bool) -> recognizer::Config {
+    let config = recognizer::Config::default()
+        // Disable profanity filter.
+        .set_profanity(recognizer::Profanity::Raw);
 
-        Ok(())
+    let config = if diarization {
+        config.enable_recognize_speaker()
+    } else {
+        config
+    };
+
+    if languages.len() == 1 {
+        config.set_language(recognizer::Language::Custom(languages.first().clone()))
+    } else {
+        config.set_detect_languages(
+            languages
+                .iter()
+                .cloned()
+                .map(recognizer::Language::Custom)
+                .collect(),
+            recognizer::LanguageDetectMode::Continuous,
+        )
     }
+    .set_output_format(recognizer::OutputFormat::Detailed)
 }
 
 fn output_recognized_text(
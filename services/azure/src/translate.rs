@@ -1,27 +1,41 @@
+use std::time::Duration;
+
 use anyhow::{Result, bail};
 use async_stream::stream;
 use async_trait::async_trait;
 use azure_speech::translator::{self, Event};
-use futures::StreamExt;
+use futures::Stream;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error};
+use tracing::debug;
 
 use crate::Host;
+use crate::session::{AudioStream, Session, recognize_with_reconnects};
 use context_switch_core::{
-    AudioFormat, AudioFrame, BillingRecord, BillingSchedule, Conversation, Input, OutputModality,
-    OutputPath, Service,
+    AudioFormat, AudioFrame, BillingRecord, BillingSchedule, Conversation, InputModality,
+    OutputModalities, OutputModality, OutputPath, Service, trace::output_provider_request,
 };
 
+/// How often the input audio is billed. It's billed per frame, which would otherwise produce a
+/// billing record every few milliseconds.
+const INPUT_BILLING_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Params {
     #[serde(alias = "host")]
     pub endpoint: Option<String>,
     pub region: Option<String>,
+    /// Candidate regions to select the lowest-latency one from, if neither `endpoint` nor `region`
+    /// is set.
+    pub regions: Option<Vec<String>>,
     pub subscription_key: String,
     pub recognition_language: String,
     pub target_language: String,
     pub target_voice: Option<String>,
+    /// How often a translator session that ended while audio was still sent is re-established
+    /// before the conversation fails.
+    #[serde(default)]
+    pub max_reconnects: u32,
 }
 
 #[derive(Debug)]
@@ -31,9 +45,20 @@ pub struct AzureTranslate;
 impl Service for AzureTranslate {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        TranslationOutputs::from_modalities(output_modalities.as_slice())?;
+        Ok(())
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
-        let output_modalities = OutputModalities::from_modalities(&conversation.output_modalities)?;
+        let output_modalities =
+            TranslationOutputs::from_modalities(conversation.output_modalities().as_slice())?;
 
         // There is no way to change the translator's output audio format to be found, so we
         // need to use 16 kHz.
@@ -56,74 +81,49 @@ impl Service for AzureTranslate {
                 Host::from_host(endpoint, params.subscription_key)?
             } else if let Some(region) = params.region {
                 Host::from_subscription(region, params.subscription_key)?
+            } else if let Some(regions) = params.regions {
+                Host::from_regions(&regions, params.subscription_key).await?
             } else {
-                bail!("Neither endpoint nor region defined in params");
+                bail!("Neither endpoint, region, nor regions defined in params");
             }
         };
 
-        let config = {
-            // TODO: configure interim events
-            translator::Config {
-                recognition_language: params.recognition_language,
-                target_languages: vec![params.target_language],
-                output_format: translator::OutputFormat::Detailed,
-                synthesize: output_modalities.audio.is_some(),
-                synthesize_voice: params.target_voice,
-                profanity: translator::Profanity::Raw,
-                ..Default::default()
-            }
+        let synthesize = output_modalities.audio.is_some();
+        let mut connect = |audio| {
+            let config = translator_config(
+                &params.recognition_language,
+                &params.target_language,
+                params.target_voice.as_deref(),
+                synthesize,
+            );
+            connect_session(&host, config, audio)
         };
-
-        let client = translator::Client::connect(host.auth.clone(), config).await?;
+        let session = Session::connect(&mut connect, input_format).await?;
 
         let (mut input, output) = conversation.start()?;
+        output.service_ready()?;
 
-        let audio_stream = {
-            let wav_header = hound::WavSpec {
-                sample_rate: input_format.sample_rate,
-                channels: input_format.channels,
-                bits_per_sample: 16,
-                sample_format: hound::SampleFormat::Int,
-            }
-            .into_header_for_infinite_file();
-
-            let billing_output = output.clone();
-
-            stream! {
-                yield wav_header;
-                while let Some(Input::Audio{frame}) = input.recv().await {
-                    yield frame.to_le_bytes();
-                    // <https://azure.microsoft.com/en-us/pricing/details/cognitive-services/speech-services/>
-                    // This price includes 1 audio input and output, up to 2 text translation language using standard or custom Speech to Text and standard Translation.
-                    // No `Result<>` context, we can't fail here, instead log an error.
-                    if let Err(e) = billing_output.billing_records(
-                        None,
-                        None,
-                        [BillingRecord::duration("input:audio", frame.duration())],
-                        BillingSchedule::Now) {
-                        error!("Internal error: Failed to output billing records: {e}");
-                    }
-                }
-            }
+        let process_frame = |frame: AudioFrame| -> Result<AudioFrame> {
+            // <https://azure.microsoft.com/en-us/pricing/details/cognitive-services/speech-services/>
+            // This price includes 1 audio input and output, up to 2 text translation language using standard or custom Speech to Text and standard Translation.
+            output.billing_records(
+                None,
+                None,
+                [BillingRecord::duration("input:audio", frame.duration())],
+                BillingSchedule::Interval(INPUT_BILLING_INTERVAL),
+            )?;
+            Ok(frame)
         };
 
-        let audio_stream = Box::pin(audio_stream);
-
-        // TODO: do they have an effect?
-        let device = translator::AudioDevice::unknown();
-
-        let mut stream = client
-            .translate(audio_stream, translator::AudioFormat::Wav, device)
-            .await?;
-
-        while let Some(event) = stream.next().await {
-            let event = event?;
+        let handle_event = |event: Event| -> Result<()> {
             if !matches!(event, Event::TranslationSynthesis(..)) {
                 debug!("Event: {:?}", event);
             }
 
             match event {
-                Event::SessionStarted(_) => {}
+                Event::SessionStarted(request_id) => {
+                    output_provider_request(&output, "azure", &request_id.to_string())?
+                }
                 Event::SessionEnded(_) => {}
                 Event::StartDetected(_, _) => {}
                 Event::EndDetected(_, _) => {}
@@ -158,9 +158,59 @@ impl Service for AzureTranslate {
                 }
                 Event::NoMatch(_, _, _, _) => {}
             }
+            Ok(())
+        };
+
+        recognize_with_reconnects(
+            session,
+            connect,
+            &mut input,
+            process_frame,
+            handle_event,
+            params.max_reconnects,
+        )
+        .await
+    }
+}
+
+/// Connects a translator session that translates `audio`.
+async fn connect_session(
+    host: &Host,
+    config: translator::Config,
+    audio: AudioStream,
+) -> Result<impl Stream<Item = Result<Event>>> {
+    let client = host
+        .connect(translator::Client::connect(host.auth.clone(), config))
+        .await?;
+    Ok(stream! {
+        // TODO: do they have an effect?
+        let device = translator::AudioDevice::unknown();
+        match client.translate(audio, translator::AudioFormat::Wav, device).await {
+            Ok(events) => {
+                for await event in events {
+                    yield event.map_err(anyhow::Error::from);
+                }
+            }
+            Err(e) => yield Err(e.into()),
         }
+    })
+}
 
-        Ok(())
+fn translator_config(
+    recognition_language: &str,
+    target_language: &str,
+    target_voice: Option<&str>,
+    synthesize: bool,
+) -> translator::Config {
+    // TODO: configure interim events
+    translator::Config {
+        recognition_language: recognition_language.to_owned(),
+        target_languages: vec![target_language.to_owned()],
+        output_format: translator::OutputFormat::Detailed,
+        synthesize,
+        synthesize_voice: target_voice.map(str::to_owned),
+        profanity: translator::Profanity::Raw,
+        ..Default::default()
     }
 }
 
@@ -174,15 +224,15 @@ enum ServiceEvent {
 }
 
 #[derive(Debug, Default)]
-struct OutputModalities {
+struct TranslationOutputs {
     text: bool,
     interim_text: bool,
     audio: Option<AudioFormat>,
 }
 
-impl OutputModalities {
+impl TranslationOutputs {
     pub fn from_modalities(output_modalities: &[OutputModality]) -> Result<Self> {
-        let mut modalities = OutputModalities::default();
+        let mut modalities = TranslationOutputs::default();
         for modality in output_modalities {
             match modality {
                 OutputModality::Audio { format } => {
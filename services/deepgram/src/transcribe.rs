@@ -13,9 +13,10 @@ use deepgram::Deepgram;
 use deepgram::common::flux_response::{FluxResponse, TurnEvent};
 use deepgram::common::options::{Encoding, Model, Options};
 
-use context_switch_core::language::{Languages, bcp47_to_iso639_3};
+use context_switch_core::language::{Languages, bcp47_to_iso639_3, connect_with_fallback_language};
 use context_switch_core::{
-    BillingRecord, BillingSchedule, Conversation, Input, OutputPath, Service, TurnDetection,
+    BillingRecord, BillingSchedule, Conversation, Input, InputModality, OutputModalities,
+    OutputPath, Service, TurnDetection,
 };
 
 #[derive(Debug, Deserialize)]
@@ -24,7 +25,12 @@ pub struct Params {
     pub api_key: String,
     #[serde(alias = "host")]
     pub endpoint: String,
+    /// The Flux model, for example `flux-general-en`. Defaults to the model that supports
+    /// `language`.
+    pub model: Option<String>,
     pub language: String,
+    /// Used instead of `language` if Deepgram rejects it.
+    pub fallback_language: Option<String>,
     #[serde(default)]
     pub profanity_filter: bool,
     #[serde(default)]
@@ -53,44 +59,20 @@ enum ServiceOutputEvent {
 impl Service for DeepgramTranscribe {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_text_output(true)
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
         conversation.require_text_output(true)?;
+        let interim_results = conversation.output_modalities().interim_text();
 
-        let languages = Languages::from_csv(&params.language)
-            .context("language must contain at least one locale code")?;
-
-        let (model, language_hints) = select_model_and_language_hints(&languages)?;
-        let mut options_builder = Options::builder().model(model);
-
-        if let Some(turn_detection) = &params.turn_detection {
-            if let Some(eot_threshold) = turn_detection.threshold {
-                options_builder = options_builder.eot_threshold(eot_threshold);
-            }
-            if let Some(eot_timeout_ms) = turn_detection.timeout_ms {
-                options_builder = options_builder.eot_timeout_ms(eot_timeout_ms);
-            }
-            if let Some(eager_eot_threshold) = turn_detection.eager_threshold {
-                options_builder = options_builder.eager_eot_threshold(eager_eot_threshold);
-            }
-        }
-        if params.profanity_filter {
-            options_builder = options_builder.profanity_filter(true);
-        }
-
-        let options_builder = if let Some(language_hints) = language_hints {
-            options_builder.language_hint(language_hints)
-        } else {
-            options_builder
-        };
-
-        let options_builder = if params.keyterm.is_empty() {
-            options_builder
-        } else {
-            options_builder.keyterms(params.keyterm.iter().map(String::as_str))
-        };
-
-        let options = options_builder.build();
         info!(endpoint = %params.endpoint, "Using Deepgram endpoint");
         let endpoint = normalize_endpoint(&params.endpoint)?;
         if endpoint != params.endpoint {
@@ -98,18 +80,29 @@ impl Service for DeepgramTranscribe {
         }
 
         // ADR: endpoint is required for GDPR-safe explicit routing.
-        let deepgram = Deepgram::with_base_url_and_api_key(endpoint.as_str(), params.api_key)?;
+        let deepgram = Deepgram::with_base_url_and_api_key(endpoint.as_str(), &params.api_key)?;
 
         let (mut input, output) = conversation.start()?;
-        let (mut audio_tx, audio_rx) = mpsc::channel::<std::result::Result<Bytes, io::Error>>(8);
 
-        let mut stream = deepgram
-            .transcription()
-            .flux_request_with_options(options)
-            .encoding(Encoding::Linear16)
-            .sample_rate(input_format.sample_rate)
-            .stream(audio_rx)
-            .await?;
+        let (mut audio_tx, mut stream) = connect_with_fallback_language(
+            &output,
+            &params.language,
+            params.fallback_language.as_deref(),
+            async |language: &str| -> Result<_> {
+                let (audio_tx, audio_rx) =
+                    mpsc::channel::<std::result::Result<Bytes, io::Error>>(8);
+                let stream = deepgram
+                    .transcription()
+                    .flux_request_with_options(flux_options(&params, language)?)
+                    .encoding(Encoding::Linear16)
+                    .sample_rate(input_format.sample_rate)
+                    .stream(audio_rx)
+                    .await?;
+                Ok((audio_tx, stream))
+            },
+        )
+        .await?;
+        output.service_ready()?;
 
         // Drive audio forwarding (with billing) and Deepgram response processing in a single loop so
         // termination and billing stay deterministic: any error or end-of-input breaks immediately,
@@ -172,7 +165,7 @@ impl Service for DeepgramTranscribe {
 
                     match event {
                         TurnEvent::Update => {
-                            if !transcript.is_empty() {
+                            if interim_results && !transcript.is_empty() {
                                 output.text(false, transcript, language, None)?;
                             }
                         }
@@ -236,6 +229,48 @@ impl Service for DeepgramTranscribe {
     }
 }
 
+/// Builds the Flux options for the comma separated `language` list.
+fn flux_options(params: &Params, language: &str) -> Result<Options> {
+    let languages =
+        Languages::from_csv(language).context("language must contain at least one locale code")?;
+
+    let (model, language_hints) = select_model_and_language_hints(&languages)?;
+    let model = match &params.model {
+        Some(model) => Model::CustomId(model.clone()),
+        None => model,
+    };
+    let mut options_builder = Options::builder().model(model);
+
+    if let Some(turn_detection) = &params.turn_detection {
+        if let Some(eot_threshold) = turn_detection.threshold {
+            options_builder = options_builder.eot_threshold(eot_threshold);
+        }
+        if let Some(eot_timeout_ms) = turn_detection.timeout_ms {
+            options_builder = options_builder.eot_timeout_ms(eot_timeout_ms);
+        }
+        if let Some(eager_eot_threshold) = turn_detection.eager_threshold {
+            options_builder = options_builder.eager_eot_threshold(eager_eot_threshold);
+        }
+    }
+    if params.profanity_filter {
+        options_builder = options_builder.profanity_filter(true);
+    }
+
+    let options_builder = if let Some(language_hints) = language_hints {
+        options_builder.language_hint(language_hints)
+    } else {
+        options_builder
+    };
+
+    let options_builder = if params.keyterm.is_empty() {
+        options_builder
+    } else {
+        options_builder.keyterms(params.keyterm.iter().map(String::as_str))
+    };
+
+    Ok(options_builder.build())
+}
+
 fn select_model_and_language_hints(languages: &Languages) -> Result<(Model, Option<Vec<String>>)> {
     if languages.len() > 1 {
         return Ok((
@@ -274,3 +309,39 @@ fn normalize_endpoint(endpoint: &str) -> Result<String> {
         "invalid endpoint: expected full listen path (for example wss://api.deepgram.com/v2/listen), got base URL ({endpoint})"
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(model: Option<&str>) -> Params {
+        Params {
+            api_key: "key".into(),
+            endpoint: "wss://api.deepgram.com/v2/listen".into(),
+            model: model.map(str::to_owned),
+            language: "en-US".into(),
+            fallback_language: None,
+            profanity_filter: false,
+            keyterm: Vec::new(),
+            turn_detection: None,
+        }
+    }
+
+    #[test]
+    fn model_is_selected_by_language() {
+        assert_eq!(
+            flux_options(&params(None), "en-US").unwrap(),
+            Options::builder().model(Model::FluxGeneralEn).build()
+        );
+    }
+
+    #[test]
+    fn configured_model_is_used_as_custom_id() {
+        assert_eq!(
+            flux_options(&params(Some("flux-general-en-v2")), "en-US").unwrap(),
+            Options::builder()
+                .model(Model::CustomId("flux-general-en-v2".into()))
+                .build()
+        );
+    }
+}
@@ -17,7 +17,7 @@ use url::Url;
 use context_switch_core::language::{bcp47_to_iso639_3, iso639_to_bcp47};
 use context_switch_core::{
     AudioFormat, AudioFrame, BillingRecord, BillingSchedule, Conversation, ConversationInput,
-    ConversationOutput, Input, Service,
+    ConversationOutput, Input, InputModality, OutputModalities, Service,
 };
 
 // Observed Scribe v2 behavior as of 2026-04-02:
@@ -65,6 +65,9 @@ pub struct Params {
     pub min_silence_duration_ms: Option<u32>,
     /// Optional prior text context sent only with the first `input_audio_chunk`.
     pub previous_text: Option<String>,
+    /// Input audio up to this duration is buffered while the websocket is being connected and
+    /// sent in one chunk once it's established. This reduces the clipping of the first word.
+    pub lead_in_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize)]
@@ -110,6 +113,15 @@ struct ConversationLoopConfig {
 impl Service for ElevenLabsTranscribe {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_text_output(true)
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
         conversation.require_text_output(true)?;
@@ -134,29 +146,48 @@ impl Service for ElevenLabsTranscribe {
             HeaderValue::from_str(&params.api_key).context("Invalid xi-api-key header value")?,
         );
 
+        let (mut input, output) = conversation.start()?;
+
         // Disable Nagle (`TCP_NODELAY`) to reduce latency for realtime audio chunk streaming.
-        let (socket, _) = connect_async_with_config(request, None, true)
-            .await
-            .context("Connecting to ElevenLabs realtime websocket")?;
+        let connect = async {
+            connect_async_with_config(request, None, true)
+                .await
+                .map(|(socket, _)| socket)
+                .context("Connecting to ElevenLabs realtime websocket")
+        };
+        let lead_in = Duration::from_millis(params.lead_in_ms.unwrap_or_default());
+        let (socket, lead_in) = input.buffer_lead_in(lead_in, connect).await?;
+        output.service_ready()?;
 
         let (write, mut read) = socket.split();
-        let (mut input, output) = conversation.start()?;
         let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
         let writer_task = tokio::spawn(run_writer(write, outbound_rx));
         let mut outbound_closed = false;
-
-        let conversation_result = run_conversation_loop(
-            &mut input,
-            &output,
-            &mut read,
-            &outbound_tx,
-            &mut outbound_closed,
-            ConversationLoopConfig {
-                input_format,
-                include_language_detection,
-            },
-            params.previous_text.as_deref(),
-        )
+        let mut previous_text = params.previous_text.as_deref();
+
+        let conversation_result = async {
+            if let Some(frame) = lead_in_frame(lead_in, input_format)? {
+                enqueue_audio_chunk_and_emit_billing(
+                    &outbound_tx,
+                    &output,
+                    frame,
+                    previous_text.take(),
+                )?;
+            }
+            run_conversation_loop(
+                &mut input,
+                &output,
+                &mut read,
+                &outbound_tx,
+                &mut outbound_closed,
+                ConversationLoopConfig {
+                    input_format,
+                    include_language_detection,
+                },
+                previous_text,
+            )
+            .await
+        }
         .await;
 
         if !outbound_closed {
@@ -228,6 +259,24 @@ where
     }
 }
 
+/// Joins the audio of the buffered lead-in into one frame.
+fn lead_in_frame(lead_in: Vec<Input>, input_format: AudioFormat) -> Result<Option<AudioFrame>> {
+    let mut joined: Option<AudioFrame> = None;
+    for input in lead_in {
+        let Input::Audio { frame } = input else {
+            continue;
+        };
+        if frame.format != input_format {
+            bail!("Received mixed input audio formats in conversation");
+        }
+        match &mut joined {
+            Some(joined) => joined.samples.extend(frame.samples),
+            None => joined = Some(frame),
+        }
+    }
+    Ok(joined)
+}
+
 fn enqueue_audio_chunk_and_emit_billing(
     outbound_tx: &mpsc::UnboundedSender<OutboundMessage>,
     output: &ConversationOutput,
@@ -0,0 +1,238 @@
+//! Writes the audio of a synthesizer to WAV files, so that synthesizers can be debugged without a
+//! client that plays back audio.
+//!
+//! Because clients choose the path of the file, this service is not part of the default registry.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+use tokio::task;
+use tracing::debug;
+
+use context_switch_core::{
+    AudioFormat, AudioFrame, BillingRecord, BillingSchedule, Conversation, Input, InputModality,
+    OutputModalities, RequestFailed, Service,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Params {
+    pub synthesizer_service: String,
+    pub synthesizer_params: serde_json::Value,
+    /// The WAV file the synthesized audio is written to. Each request overwrites it.
+    pub path: PathBuf,
+    /// The format the audio is synthesized and written in.
+    pub format: AudioFormat,
+}
+
+#[derive(Debug)]
+pub struct FileSink;
+
+#[async_trait]
+impl Service for FileSink {
+    type Params = Params;
+
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        _output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_text_input_only()
+    }
+
+    async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
+        conversation.require_text_input_only()?;
+        let (mut input, output) = conversation.start()?;
+
+        while let Some(request) = input.recv().await {
+            let Input::Text {
+                request_id,
+                billing_scope,
+                ..
+            } = &request
+            else {
+                bail!("Only text input is supported");
+            };
+            let request_id = request_id.clone();
+            let billing_scope = billing_scope.clone();
+            async {
+                let frames = input
+                    .synthesize(
+                        &output,
+                        &params.synthesizer_service,
+                        params.synthesizer_params.clone(),
+                        request,
+                        params.format,
+                    )
+                    .await?;
+                let duration = write_wav(params.path.clone(), params.format, frames).await?;
+                output.billing_records(
+                    request_id.clone(),
+                    billing_scope,
+                    [BillingRecord::duration("file-sink:audio", duration)],
+                    BillingSchedule::Now,
+                )?;
+                output.request_completed(request_id.clone())
+            }
+            .await
+            .context(RequestFailed(request_id))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the frames to a 16 bit WAV file and returns the duration of the audio.
+async fn write_wav(
+    path: PathBuf,
+    format: AudioFormat,
+    frames: Vec<AudioFrame>,
+) -> Result<Duration> {
+    task::spawn_blocking(move || {
+        let spec = WavSpec {
+            channels: format.channels,
+            sample_rate: format.sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec)
+            .with_context(|| format!("Failed to create `{}`", path.display()))?;
+        let mut duration = Duration::ZERO;
+        for frame in frames {
+            duration += frame.duration();
+            for sample in frame.samples {
+                writer.write_sample(sample)?;
+            }
+        }
+        writer
+            .finalize()
+            .with_context(|| format!("Failed to finalize `{}`", path.display()))?;
+        debug!("Wrote {duration:?} of audio to `{}`", path.display());
+        Ok(duration)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use std::sync::{Arc, Mutex};
+
+    use hound::WavReader;
+    use serde_json::{Value, json};
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    use super::*;
+    use context_switch_core::billing_collector::BillingCollector;
+    use context_switch_core::{BillingContext, BillingId, Output, OutputModality, Registry};
+
+    /// Synthesizes two frames of 100ms per request.
+    #[derive(Debug)]
+    struct MockSynthesizer;
+
+    #[async_trait]
+    impl Service for MockSynthesizer {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), conversation: Conversation) -> Result<()> {
+            let format = conversation.require_single_audio_output()?;
+            let (mut input, output) = conversation.start()?;
+            while let Some(Input::Text { request_id, .. }) = input.recv().await {
+                for value in [1, 2] {
+                    output.audio_frame(AudioFrame {
+                        format,
+                        samples: vec![value; format.sample_rate as usize / 10],
+                    })?;
+                }
+                output.request_completed(request_id)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn synthesized_audio_is_written_to_a_wav_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("synthesized.wav");
+        let format = AudioFormat::new(1, 16000);
+        let params = json!({
+            "synthesizerService": "synthesize",
+            "synthesizerParams": Value::Null,
+            "path": path,
+            "format": format,
+        });
+
+        let registry = Arc::new(
+            Registry::empty()
+                .add_service("synthesize", MockSynthesizer)
+                .add_service("file-sink", FileSink),
+        );
+        let billing_id = BillingId::from("call".to_string());
+        let collector = Arc::new(Mutex::new(BillingCollector::default()));
+        let (input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let conversation = Conversation::new(
+            InputModality::Text,
+            Vec::<OutputModality>::new(),
+            input_receiver,
+            output_sender,
+        )
+        .with_registry(registry.clone())
+        .with_billing_context(BillingContext::new(
+            billing_id.clone(),
+            "file-sink",
+            collector.clone(),
+        ));
+
+        input_sender
+            .send(Input::Text {
+                request_id: None,
+                text: "Hello".into(),
+                text_type: None,
+                billing_scope: None,
+            })
+            .await
+            .unwrap();
+        drop(input_sender);
+        registry
+            .service("file-sink")
+            .unwrap()
+            .converse(params, conversation)
+            .await
+            .unwrap();
+
+        let outputs: Vec<_> = iter::from_fn(|| output_receiver.try_recv().ok()).collect();
+        assert!(
+            matches!(
+                &outputs[..],
+                [
+                    Output::ServiceStarted { .. },
+                    Output::RequestCompleted { .. }
+                ]
+            ),
+            "{outputs:?}"
+        );
+
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 16000);
+        assert_eq!(reader.spec().channels, 1);
+        let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+        assert_eq!(samples.len(), 3200);
+        assert_eq!((samples[0], samples[3199]), (1, 2));
+
+        let records = collector.lock().unwrap().collect(&billing_id);
+        let [records] = &records[..] else {
+            panic!("Expected the records of one service, got {records:?}");
+        };
+        assert_eq!(
+            records.records(),
+            [BillingRecord::duration(
+                "file-sink:audio",
+                Duration::from_millis(200)
+            )]
+        );
+    }
+}
@@ -45,6 +45,7 @@ impl Client {
             Ok(session) => session,
             Err(error) => return Err(connect_error_with_voice_context(&self.params, error)),
         };
+        output.service_ready()?;
 
         loop {
             tokio::select! {
@@ -168,6 +169,7 @@ impl Client {
             ServerEvent::TurnComplete => {
                 self.finalize_output_transcription(text_outputs, output, state)?;
                 output.service_event(OutputPath::Media, ServiceOutputEvent::TurnComplete)?;
+                output.turn_completed(None)?;
             }
             ServerEvent::Interrupted => {
                 // We expect a TurnComplete afterward, so don't finalize the output transcription
@@ -4,7 +4,9 @@ use anyhow::{Result, bail};
 use async_trait::async_trait;
 use tracing::info;
 
-use context_switch_core::{AudioFormat, Conversation, OutputModality, Service};
+use context_switch_core::{
+    AudioFormat, Conversation, InputModality, OutputModalities, OutputModality, Service,
+};
 
 mod client;
 mod conversation_state;
@@ -20,10 +22,22 @@ pub struct GoogleDialog;
 impl Service for GoogleDialog {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_one_audio_output()?;
+        TextOutputs::from_modalities(output_modalities.as_slice())?;
+        Ok(())
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let _input_format = conversation.require_audio_input()?;
         let output_format = conversation.require_one_audio_output()?;
-        let text_outputs = TextOutputs::from_modalities(&conversation.output_modalities)?;
+        let text_outputs =
+            TextOutputs::from_modalities(conversation.output_modalities().as_slice())?;
 
         let expected_output = AudioFormat::new(1, gemini_live::audio::OUTPUT_SAMPLE_RATE);
         if output_format != expected_output {
@@ -2,11 +2,12 @@
 //! <https://github.com/bouzuya/googleapis-tonic/blob/master/examples/googleapis-tonic-google-firestore-v1-1/>
 
 use std::error;
+use std::time::Duration;
 use std::{env, sync::Arc};
 
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use async_stream::{stream, try_stream};
-use context_switch_core::{AudioFormat, audio};
+use context_switch_core::{AudioFormat, audio, trace::TRACE_ID_HEADER};
 use futures::Stream;
 use google_cloud_auth::credentials::AccessTokenCredentials;
 use google_cloud_auth::credentials::service_account;
@@ -21,6 +22,7 @@ use googleapis_tonic_google_cloud_speech_v2::google::cloud::speech::v2::{
 };
 use googleapis_tonic_google_cloud_speech_v2::google::cloud::speech::v2::streaming_recognize_request::StreamingRequest;
 use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::timeout;
 use tonic::transport;
 use tracing::debug;
 
@@ -31,28 +33,35 @@ type Client =
         tonic::service::interceptor::InterceptedService<tonic::transport::Channel, AuthInterceptor>,
     >;
 
-#[derive(Default)]
+/// How long connecting to the endpoint may take before the conversation fails.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub(crate) struct Config {
     endpoint: &'static str,
-    location: &'static str,
+    location: String,
+    connect_timeout: Duration,
 }
 
-impl From<Region> for Config {
-    fn from(value: Region) -> Self {
-        match value {
-            Region::Global => Self {
-                endpoint: "https://speech.googleapis.com",
-                location: "global",
-            },
-            Region::Eu => Self {
-                endpoint: "https://eu-speech.googleapis.com",
-                location: "eu",
-            },
-            Region::Us => Self {
-                endpoint: "https://us-speech.googleapis.com",
-                location: "us",
-            },
+impl Config {
+    /// The configuration of a region's endpoint. `location` overrides the region's default
+    /// recognizer location.
+    pub(crate) fn new(region: Region, location: Option<&str>) -> Result<Self> {
+        let (endpoint, default_location) = match region {
+            Region::Global => ("https://speech.googleapis.com", "global"),
+            Region::Eu => ("https://eu-speech.googleapis.com", "eu"),
+            Region::Us => ("https://us-speech.googleapis.com", "us"),
+        };
+        let location = location.unwrap_or(default_location);
+        if !matches!(region, Region::Global) && location == "global" {
+            bail!(
+                "The `global` location is not available at the regional endpoint {endpoint}, use the region's location instead"
+            );
         }
+        Ok(Self {
+            endpoint,
+            location: location.to_owned(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        })
     }
 }
 
@@ -108,25 +117,34 @@ impl Host {
         let token_source: Arc<dyn google_cloud_token::TokenSource> =
             Arc::new(ServiceAccountTokenSource { credentials });
 
-        let channel = transport::Channel::from_static(params.endpoint)
-            .tls_config(transport::ClientTlsConfig::new().with_webpki_roots())?
-            .connect()
-            .await?;
+        let endpoint = transport::Channel::from_static(params.endpoint)
+            .tls_config(transport::ClientTlsConfig::new().with_webpki_roots())?;
+        let channel = timeout(params.connect_timeout, endpoint.connect())
+            .await
+            .map_err(|_| anyhow!("Connection to {} timed out", params.endpoint))??;
 
         Ok(Self {
             channel,
             token_source,
             project_id,
-            location: params.location.to_owned(),
+            location: params.location,
         })
     }
 
-    pub async fn client(&self) -> Result<TranscribeClient> {
+    /// A client that sends `trace_id` with each request.
+    pub async fn client(&self, trace_id: Option<&str>) -> Result<TranscribeClient> {
         let inner = self.channel.clone();
         let token = self.token_source.token().await.map_err(|e| anyhow!(e))?;
         let mut metadata_value = tonic::metadata::AsciiMetadataValue::try_from(token)?;
         metadata_value.set_sensitive(true);
-        let interceptor = AuthInterceptor { metadata_value };
+        let trace_id = trace_id
+            .map(tonic::metadata::AsciiMetadataValue::try_from)
+            .transpose()
+            .context("Trace id is not a valid metadata value")?;
+        let interceptor = AuthInterceptor {
+            metadata_value,
+            trace_id,
+        };
         let client = SpeechClient::with_interceptor(inner, interceptor);
         Ok(TranscribeClient {
             client,
@@ -139,6 +157,7 @@ impl Host {
 #[derive(Clone)]
 struct AuthInterceptor {
     metadata_value: tonic::metadata::AsciiMetadataValue,
+    trace_id: Option<tonic::metadata::AsciiMetadataValue>,
 }
 
 impl tonic::service::Interceptor for AuthInterceptor {
@@ -149,10 +168,23 @@ impl tonic::service::Interceptor for AuthInterceptor {
         request
             .metadata_mut()
             .insert("authorization", self.metadata_value.clone());
+        if let Some(trace_id) = &self.trace_id {
+            request
+                .metadata_mut()
+                .insert(TRACE_ID_HEADER, trace_id.clone());
+        }
         Ok(request)
     }
 }
 
+/// Optional recognition features.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Features {
+    pub diarization: bool,
+    /// The maximum number of alternatives per result, Google defaults to one.
+    pub max_alternatives: Option<usize>,
+}
+
 /// A google transcribe client. Capable of streaming audio data in and transcribe results out.
 #[derive(Debug)]
 pub struct TranscribeClient {
@@ -166,7 +198,7 @@ impl TranscribeClient {
         &mut self,
         model: &str,
         language_codes: &[String],
-        diarization: bool,
+        features: Features,
         interim_results: bool,
         audio_format: AudioFormat,
         mut audio_receiver: UnboundedReceiver<Vec<i16>>,
@@ -182,11 +214,12 @@ impl TranscribeClient {
             // TODO: configure
             model: model.into(),
             language_codes: language_codes.to_vec(),
-            features: diarization.then_some(RecognitionFeatures {
-                diarization_config: Some(SpeakerDiarizationConfig {
+            features: Some(RecognitionFeatures {
+                diarization_config: features.diarization.then_some(SpeakerDiarizationConfig {
                     min_speaker_count: 0,
                     max_speaker_count: 0,
                 }),
+                max_alternatives: features.max_alternatives.unwrap_or_default() as i32,
                 ..Default::default()
             }),
             adaptation: None,
@@ -214,7 +247,7 @@ impl TranscribeClient {
             recognizer = %recognizer,
             model = %model,
             language_codes = ?language_codes,
-            diarization,
+            ?features,
             interim_results,
             "Starting Google streaming_recognize"
         );
@@ -258,3 +291,48 @@ impl TranscribeClient {
         Ok(stream)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tonic::{Request, metadata::AsciiMetadataValue, service::Interceptor};
+
+    use super::*;
+
+    #[test]
+    fn trace_id_is_sent_as_request_metadata() {
+        let mut interceptor = AuthInterceptor {
+            metadata_value: AsciiMetadataValue::from_static("Bearer token"),
+            trace_id: Some(AsciiMetadataValue::from_static("conversation-1")),
+        };
+
+        let request = interceptor.call(Request::new(())).unwrap();
+
+        let metadata = request.metadata();
+        assert_eq!(metadata.get("authorization").unwrap(), "Bearer token");
+        assert_eq!(metadata.get(TRACE_ID_HEADER).unwrap(), "conversation-1");
+    }
+
+    #[test]
+    fn global_location_is_rejected_at_a_regional_endpoint() {
+        let error = Config::new(Region::Eu, Some("global")).err().unwrap();
+        assert!(error.to_string().contains("`global` location"));
+    }
+
+    #[test]
+    fn location_defaults_to_the_region() {
+        let config = Config::new(Region::Eu, None).unwrap();
+        assert_eq!(config.endpoint, "https://eu-speech.googleapis.com");
+        assert_eq!(config.location, "eu");
+
+        let config = Config::new(Region::Global, None).unwrap();
+        assert_eq!(config.endpoint, "https://speech.googleapis.com");
+        assert_eq!(config.location, "global");
+    }
+
+    #[test]
+    fn location_can_be_overridden() {
+        let config = Config::new(Region::Global, Some("europe-west3")).unwrap();
+        assert_eq!(config.endpoint, "https://speech.googleapis.com");
+        assert_eq!(config.location, "europe-west3");
+    }
+}
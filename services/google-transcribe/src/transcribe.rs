@@ -11,21 +11,47 @@ use tonic::Code;
 
 use context_switch_core::{
     AudioFormat, AudioFrame, AudioProducer, BillingRecord, BillingSchedule, Conversation,
-    ConversationOutput, Input, OutputModality, Service, language::Languages,
+    ConversationOutput, Input, InputModality, OutputModalities, Service,
+    language::Languages,
+    stable_interim::InterimStabilizer,
+    transcript::{Alternative, output_alternatives},
 };
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
-use crate::{Host, client::TranscribeClient};
+use crate::{
+    Host,
+    client::{Config, Features, TranscribeClient},
+};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Params {
+    #[serde(default = "default_model")]
     pub model: String,
+    /// One or more comma separated language codes, for example `en-US,de-DE`.
+    #[serde(alias = "languageCode")]
     pub language: String,
     #[serde(default)]
     pub diarization: bool,
     #[serde(default)]
     pub region: Region,
+    /// The recognizer location, for example `eu`. Defaults to the location of the region.
+    pub location: Option<String>,
+    /// Final results with a lower confidence (0.0 to 1.0) are suppressed. Results without a
+    /// confidence are always output.
+    pub min_confidence: Option<f32>,
+    /// If more than one, up to this many alternatives of each final result are output as a
+    /// `transcriptAlternatives` control event instead of text. Interim results are still output
+    /// as text.
+    pub max_alternatives: Option<usize>,
+    /// Output interim text only up to the words successive interim results agree on, so that
+    /// captions flicker less.
+    #[serde(default)]
+    pub stable_interim_text: bool,
+}
+
+fn default_model() -> String {
+    "long".into()
 }
 
 #[derive(Debug, Clone, Copy, Default, Deserialize)]
@@ -51,20 +77,27 @@ enum SessionExit {
 impl Service for GoogleTranscribe {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_text_output(true)
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
         conversation.require_text_output(true)?;
-        let interim_results = conversation
-            .output_modalities
-            .iter()
-            .any(|modality| matches!(modality, OutputModality::InterimText));
+        let interim_results = conversation.output_modalities().interim_text();
         let languages = Languages::from_csv(&params.language)
             .context("language must contain at least one locale code")?;
 
-        let host = Host::new(params.region.into()).await?;
+        let host = Host::new(Config::new(params.region, params.location.as_deref())?).await?;
 
-        let mut client = host.client().await?;
+        let mut client = host.client(conversation.trace_id()).await?;
         let (mut input, output) = conversation.start()?;
+        output.service_ready()?;
 
         loop {
             let (audio_producer, audio_consumer) = input_format.new_channel();
@@ -96,6 +129,7 @@ impl Service for GoogleTranscribe {
                                 forward_audio_and_emit_billing(
                                     &mut audio_producer,
                                     &output,
+                                    &params.model,
                                     frame,
                                 )?;
                             }
@@ -117,9 +151,11 @@ impl Service for GoogleTranscribe {
     }
 }
 
+/// The audio is billed per model, because Google prices the models differently.
 fn forward_audio_and_emit_billing(
     audio_producer: &mut Option<AudioProducer>,
     output: &ConversationOutput,
+    model: &str,
     frame: AudioFrame,
 ) -> Result<()> {
     let duration = frame.duration();
@@ -134,8 +170,8 @@ fn forward_audio_and_emit_billing(
     output
         .billing_records(
             None,
-            None,
-            [BillingRecord::duration("input:audio", duration)],
+            model.to_string(),
+            [BillingRecord::duration("transcribe:audio", duration)],
             BillingSchedule::Now,
         )
         .context("Failed to output billing records")
@@ -151,12 +187,16 @@ async fn transcribe_and_process_stream_session(
     output: &ConversationOutput,
 ) -> Result<SessionExit> {
     let include_detected_language = languages.len() > 1;
+    let max_alternatives = params.max_alternatives.filter(|max| *max > 1);
 
     let response_stream = client
         .transcribe(
             &params.model,
             languages,
-            params.diarization,
+            Features {
+                diarization: params.diarization,
+                max_alternatives,
+            },
             interim_results,
             audio_format,
             audio_receiver,
@@ -166,6 +206,9 @@ async fn transcribe_and_process_stream_session(
     process_stream_session(
         &params.model,
         include_detected_language,
+        params.min_confidence,
+        max_alternatives,
+        params.stable_interim_text,
         output,
         response_stream,
     )
@@ -175,6 +218,9 @@ async fn transcribe_and_process_stream_session(
 async fn process_stream_session<S>(
     model: &str,
     include_detected_language: bool,
+    min_confidence: Option<f32>,
+    max_alternatives: Option<usize>,
+    stable_interim_text: bool,
     output: &ConversationOutput,
     response_stream: S,
 ) -> Result<SessionExit>
@@ -183,7 +229,7 @@ where
 {
     let mut saw_end_of_single_utterance = false;
     futures::pin_mut!(response_stream);
-    let mut text_output = StreamTextOutput::new(output);
+    let mut text_output = StreamTextOutput::new(output, stable_interim_text);
     while let Some(response) = response_stream.next().await {
         let response = match response {
             Ok(response) => response,
@@ -206,7 +252,8 @@ where
         // - For each result, alternatives are ordered by confidence, most confident first.
         //
         // Implementation detail:
-        // - We always take the first alternative from each result.
+        // - We always take the first alternative from each result, unless more alternatives
+        //   of final results are requested.
         // - For non-final responses, we concatenate transcripts from all results in the
         //   current response as-is.
 
@@ -224,6 +271,28 @@ where
                 let language = include_detected_language
                     .then(|| one.language_code.trim().to_owned())
                     .filter(|x| !x.is_empty());
+                // A confidence of 0.0 means that none was provided.
+                if let Some(min_confidence) = min_confidence
+                    && alternative.confidence > 0.0
+                    && alternative.confidence < min_confidence
+                {
+                    debug!(
+                        confidence = alternative.confidence,
+                        min_confidence, "Suppressed final text with low confidence"
+                    );
+                    text_output.suppress_final_text();
+                    continue;
+                }
+                if let Some(max_alternatives) = max_alternatives {
+                    let alternatives = one
+                        .alternatives
+                        .iter()
+                        .take(max_alternatives)
+                        .map(|a| Alternative::new(a.transcript.trim(), a.confidence))
+                        .collect();
+                    text_output.final_alternatives(alternatives, language)?;
+                    continue;
+                }
                 let speaker = speaker_with_max_assigned_characters(&alternative.words);
                 text_output.final_text(
                     alternative.transcript.trim().to_owned(),
@@ -355,13 +424,15 @@ fn speaker_with_max_assigned_characters(words: &[WordInfo]) -> Option<String> {
 struct StreamTextOutput<'a> {
     output: &'a ConversationOutput,
     pending_interim_text: Option<(String, Option<String>)>,
+    stabilizer: Option<InterimStabilizer>,
 }
 
 impl<'a> StreamTextOutput<'a> {
-    fn new(output: &'a ConversationOutput) -> Self {
+    fn new(output: &'a ConversationOutput, stable_interim_text: bool) -> Self {
         Self {
             output,
             pending_interim_text: None,
+            stabilizer: stable_interim_text.then(InterimStabilizer::default),
         }
     }
 
@@ -372,12 +443,41 @@ impl<'a> StreamTextOutput<'a> {
         speaker: Option<String>,
     ) -> Result<()> {
         self.output.text(true, text, language, speaker)?;
-        self.pending_interim_text = None;
+        self.utterance_ended();
         Ok(())
     }
 
+    fn final_alternatives(
+        &mut self,
+        alternatives: Vec<Alternative>,
+        language: Option<String>,
+    ) -> Result<()> {
+        output_alternatives(self.output, true, alternatives, language)?;
+        self.utterance_ended();
+        Ok(())
+    }
+
+    /// Drops the pending interim text, so that it's not output as final text.
+    fn suppress_final_text(&mut self) {
+        self.utterance_ended();
+    }
+
+    fn utterance_ended(&mut self) {
+        self.pending_interim_text = None;
+        if let Some(stabilizer) = &mut self.stabilizer {
+            stabilizer.reset();
+        }
+    }
+
     fn interim_text(&mut self, text: String, language: Option<String>) -> Result<()> {
         self.pending_interim_text = Some((text.clone(), language.clone()));
+        let text = match &mut self.stabilizer {
+            Some(stabilizer) => match stabilizer.stabilize(&text) {
+                Some(stable) => stable,
+                None => return Ok(()),
+            },
+            None => text,
+        };
         self.output.text(false, text, language, None)
     }
 }
@@ -392,3 +492,132 @@ impl Drop for StreamTextOutput<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use context_switch_core::{
+        BillingContext, BillingId, Output, OutputModality, billing_collector::BillingCollector,
+    };
+    use googleapis_tonic_google_cloud_speech_v2::google::cloud::speech::v2::{
+        SpeechRecognitionAlternative, StreamingRecognitionResult,
+    };
+    use serde_json::json;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    use super::*;
+
+    const FORMAT: AudioFormat = AudioFormat {
+        channels: 1,
+        sample_rate: 16000,
+    };
+
+    fn response(is_final: bool, transcript: &str) -> Result<StreamingRecognizeResponse> {
+        Ok(StreamingRecognizeResponse {
+            results: vec![StreamingRecognitionResult {
+                alternatives: vec![SpeechRecognitionAlternative {
+                    transcript: transcript.into(),
+                    ..Default::default()
+                }],
+                is_final,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn model_defaults_to_long() {
+        let params: Params = serde_json::from_value(json!({ "languageCode": "en-US" })).unwrap();
+        assert_eq!(params.model, "long");
+        assert_eq!(params.language, "en-US");
+    }
+
+    #[test]
+    fn explicit_model_is_kept() {
+        let params: Params =
+            serde_json::from_value(json!({ "languageCode": "en-US", "model": "telephony" }))
+                .unwrap();
+        assert_eq!(params.model, "telephony");
+    }
+
+    #[tokio::test]
+    async fn results_are_output_as_text() {
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, mut output_receiver) = unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format: FORMAT },
+            [OutputModality::Text, OutputModality::InterimText],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        let responses =
+            futures::stream::iter([response(false, " Hello"), response(true, " Hello world")]);
+        let session_exit =
+            process_stream_session("long", false, None, None, false, &output, responses)
+                .await
+                .unwrap();
+        assert_eq!(session_exit, SessionExit::AudioInputEnded);
+
+        let mut texts = Vec::new();
+        while let Ok(event) = output_receiver.try_recv() {
+            if let Output::Text { is_final, text, .. } = event {
+                texts.push((is_final, text));
+            }
+        }
+        assert_eq!(
+            texts,
+            [
+                (false, "Hello".to_owned()),
+                (true, "Hello world".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn forwarded_audio_is_billed_per_model() {
+        let billing_id = BillingId::from("call".to_string());
+        let collector = Arc::new(Mutex::new(BillingCollector::default()));
+        let (_input_sender, input_receiver) = channel(1);
+        let (output_sender, _output_receiver) = unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format: FORMAT },
+            [OutputModality::Text],
+            input_receiver,
+            output_sender,
+        )
+        .with_billing_context(BillingContext::new(
+            billing_id.clone(),
+            "google-transcribe",
+            collector.clone(),
+        ))
+        .start()
+        .unwrap();
+
+        let (audio_producer, _audio_consumer) = FORMAT.new_channel();
+        let mut audio_producer = Some(audio_producer);
+        let frame = AudioFrame {
+            format: FORMAT,
+            samples: vec![0; 1600],
+        };
+        forward_audio_and_emit_billing(&mut audio_producer, &output, "telephony", frame).unwrap();
+
+        let records = collector.lock().unwrap().collect(&billing_id);
+        let [records] = &records[..] else {
+            panic!("Expected the records of one scope, got {records:?}");
+        };
+        assert_eq!(records.scope(), Some("telephony"));
+        assert_eq!(
+            records.records(),
+            [BillingRecord::duration(
+                "transcribe:audio",
+                Duration::from_millis(100)
+            )]
+        );
+    }
+}
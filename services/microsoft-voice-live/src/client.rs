@@ -66,6 +66,7 @@ impl Client {
 
         self.send_session_update(&params).await?;
         debug!("Session updated");
+        output.service_ready()?;
 
         let language = params.language.clone();
 
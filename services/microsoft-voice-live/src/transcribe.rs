@@ -2,7 +2,7 @@ use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
-use context_switch_core::{Conversation, Service, TurnDetection};
+use context_switch_core::{Conversation, InputModality, OutputModalities, Service, TurnDetection};
 
 use crate::host::Host;
 
@@ -62,6 +62,15 @@ pub struct MicrosoftVoiceLiveTranscribe;
 impl Service for MicrosoftVoiceLiveTranscribe {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_text_output(true)
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         let input_format = conversation.require_audio_input()?;
         conversation.require_text_output(true)?;
@@ -0,0 +1,295 @@
+//! Records the messages the realtime API sends and replays them later, so that dialogs can be
+//! tested deterministically without connecting to OpenAI.
+//!
+//! A [`Recorder`] wraps a connector and captures every message received on each of its
+//! connections. A [`Player`] serves the captured connections back in the same order and discards
+//! everything the client sends.
+use std::{
+    collections::VecDeque,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt, sink, stream};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::{self, protocol::Message};
+
+use crate::{Connect, Transport};
+
+/// The messages received on all connections of a recorded dialog.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    connections: Vec<Vec<Recorded>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+enum Recorded {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Close,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette `{}`", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write cassette `{}`", path.display()))
+    }
+}
+
+impl Recorded {
+    fn new(message: &Message) -> Option<Self> {
+        match message {
+            Message::Text(text) => Some(Self::Text(text.to_string())),
+            Message::Binary(data) => Some(Self::Binary(data.to_vec())),
+            Message::Ping(data) => Some(Self::Ping(data.to_vec())),
+            Message::Close(_) => Some(Self::Close),
+            Message::Pong(_) | Message::Frame(_) => None,
+        }
+    }
+
+    fn into_message(self) -> Message {
+        match self {
+            Self::Text(text) => Message::Text(text.into()),
+            Self::Binary(data) => Message::Binary(data.into()),
+            Self::Ping(data) => Message::Ping(data.into()),
+            Self::Close => Message::Close(None),
+        }
+    }
+}
+
+/// Connects through another connector and records all messages received.
+#[derive(Debug)]
+pub struct Recorder {
+    connector: Box<dyn Connect>,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl Recorder {
+    pub fn new(connector: Box<dyn Connect>) -> Self {
+        Self {
+            connector,
+            cassette: Default::default(),
+        }
+    }
+
+    /// A handle to the cassette that keeps recording after the recorder was moved into a
+    /// [`crate::Client`].
+    pub fn cassette(&self) -> Arc<Mutex<Cassette>> {
+        self.cassette.clone()
+    }
+}
+
+#[async_trait]
+impl Connect for Recorder {
+    async fn connect(&self) -> Result<Transport> {
+        let Transport { read, write } = self.connector.connect().await?;
+        let index = {
+            let mut cassette = self.cassette.lock().unwrap();
+            cassette.connections.push(Vec::new());
+            cassette.connections.len() - 1
+        };
+        let cassette = self.cassette.clone();
+        let read = read.inspect(move |message| {
+            if let Ok(message) = message
+                && let Some(recorded) = Recorded::new(message)
+            {
+                cassette.lock().unwrap().connections[index].push(recorded);
+            }
+        });
+        Ok(Transport {
+            read: Box::pin(read),
+            write,
+        })
+    }
+}
+
+/// Replays the connections of a cassette, one per connect.
+#[derive(Debug)]
+pub struct Player {
+    connections: Mutex<VecDeque<Vec<Recorded>>>,
+}
+
+impl Player {
+    pub fn new(cassette: Cassette) -> Self {
+        Self {
+            connections: Mutex::new(cassette.connections.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Connect for Player {
+    async fn connect(&self) -> Result<Transport> {
+        let Some(messages) = self.connections.lock().unwrap().pop_front() else {
+            bail!("No more recorded connections to replay");
+        };
+        let read = stream::iter(
+            messages
+                .into_iter()
+                .map(|recorded| Ok(recorded.into_message())),
+        );
+        let write = sink::drain().sink_map_err(|never| -> tungstenite::Error { match never {} });
+        Ok(Transport {
+            read: Box::pin(read),
+            write: Box::pin(write),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use context_switch_core::{AudioFormat, Conversation, InputModality, OutputModality, audio};
+
+    use super::*;
+    use crate::{Client, Params, transcription_state::TranscriptionSettings};
+
+    /// A provider that sends a fixed script of messages and then closes the connection.
+    #[derive(Debug)]
+    struct ScriptedConnector;
+
+    #[async_trait]
+    impl Connect for ScriptedConnector {
+        async fn connect(&self) -> Result<Transport> {
+            let session_created = json!({
+                "type": "session.created",
+                "event_id": "event-1",
+                "session": {
+                    "type": "realtime",
+                    "object": "realtime.session",
+                    "id": "sess-1",
+                    "model": "gpt-realtime",
+                    "output_modalities": ["audio"],
+                    "instructions": "",
+                    "tools": [],
+                    "tool_choice": "auto",
+                    "max_output_tokens": "inf",
+                },
+            });
+            let samples = audio::to_le_bytes([1i16, -2, 3, -4]);
+            let audio_delta = json!({
+                "type": "response.output_audio.delta",
+                "event_id": "event-2",
+                "response_id": "resp-1",
+                "item_id": "item-1",
+                "output_index": 0,
+                "content_index": 0,
+                "delta": BASE64_STANDARD.encode(samples),
+            });
+            let speech_started = json!({
+                "type": "input_audio_buffer.speech_started",
+                "event_id": "event-3",
+                "audio_start_ms": 0,
+                "item_id": "item-2",
+            });
+            let script = [session_created, audio_delta, speech_started]
+                .into_iter()
+                .map(|event| Message::Text(event.to_string().into()))
+                .chain([Message::Close(None)])
+                .map(Ok)
+                .collect::<Vec<_>>();
+            Ok(Transport {
+                read: Box::pin(stream::iter(script)),
+                write: Box::pin(
+                    sink::drain().sink_map_err(|never| -> tungstenite::Error { match never {} }),
+                ),
+            })
+        }
+    }
+
+    /// Runs a dialog until the provider closes the connection and returns the outputs.
+    async fn run_dialog(connector: Box<dyn Connect>) -> Vec<String> {
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = mpsc::channel(1);
+        let (output_sender, mut output_receiver) = mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        let mut client = Client::connect(connector).await.unwrap();
+        client
+            .dialog(
+                format,
+                format,
+                Params::new("key", "gpt-realtime"),
+                TranscriptionSettings {
+                    input: false,
+                    output: false,
+                    output_encoding: Default::default(),
+                },
+                input,
+                output,
+            )
+            .await
+            .unwrap();
+
+        let mut outputs = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            outputs.push(format!("{output:?}"));
+        }
+        outputs
+    }
+
+    #[tokio::test]
+    async fn replayed_dialog_produces_the_recorded_outputs() {
+        let recorder = Recorder::new(Box::new(ScriptedConnector));
+        let cassette = recorder.cassette();
+        let recorded_outputs = run_dialog(Box::new(recorder)).await;
+        assert!(
+            recorded_outputs
+                .iter()
+                .any(|output| output.contains("AudioFrame"))
+        );
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        cassette.lock().unwrap().save(file.path()).unwrap();
+        let cassette = Cassette::load(file.path()).unwrap();
+        assert_eq!(cassette.connections.len(), 1);
+        assert_eq!(cassette.connections[0].len(), 4);
+
+        let replayed_outputs = run_dialog(Box::new(Player::new(cassette))).await;
+        assert_eq!(replayed_outputs, recorded_outputs);
+    }
+
+    #[tokio::test]
+    async fn player_fails_when_the_recorded_connections_are_exhausted() {
+        let player = Player::new(Cassette::default());
+        assert!(player.connect().await.is_err());
+    }
+
+    #[test]
+    fn recorded_messages_round_trip() {
+        let messages = [
+            Message::Text("{}".into()),
+            Message::Binary(vec![0, 1].into()),
+            Message::Ping(vec![1, 2].into()),
+            Message::Close(None),
+        ];
+        for message in messages {
+            let recorded = Recorded::new(&message).unwrap();
+            assert_eq!(recorded.into_message(), message);
+        }
+        assert!(Recorded::new(&Message::Pong(Default::default())).is_none());
+    }
+}
@@ -1,31 +1,76 @@
 #[cfg(feature = "prompt-delay")]
 use std::collections::VecDeque;
+use std::pin::Pin;
+use std::{mem, time::Duration};
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, anyhow, bail};
 use base64::prelude::*;
-use futures::stream::{SplitSink, SplitStream};
-use futures::{SinkExt, StreamExt};
+use futures::{Sink, SinkExt, Stream, StreamExt, future};
 use openai_api_rs::realtime::client_event::{self, ClientEvent};
 use openai_api_rs::realtime::server_event::{self, ServerEvent};
 use openai_api_rs::realtime::types::{self, ItemStatus, ItemType, OutputModality, ResponseStatus};
-use tokio::{net::TcpStream, select};
-use tokio_tungstenite::tungstenite::{Bytes, protocol::Message};
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use serde_json::{Value, json};
+use tokio::select;
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::{self, Bytes, error::ProtocolError, protocol::Message};
 use tracing::{debug, info, trace, warn};
 #[cfg(feature = "prompt-delay")]
 use uuid::Uuid;
 
+use crate::host::Connect;
+use crate::tool_set::{ToolSet, tool_name};
 use crate::transcription_state::{TranscriptionSettings, TranscriptionState};
-use crate::{Params, ServiceInputEvent, ServiceOutputEvent};
+use crate::{Params, ResponseOverrides, ServiceInputEvent, ServiceOutputEvent};
 use context_switch_core::{
     AI_ASSISTANT_SPEAKER, AudioFormat, AudioFrame, BillingRecord, BillingSchedule,
-    ConversationInput, ConversationOutput, Input, OutputPath, audio,
+    ConversationInput, ConversationOutput, Input, OutputPath, TurnDetector,
+    audio::{self, StreamResampler},
+    barge_in::{self, BargeInState},
 };
 
+/// The sample rate of the realtime API's audio. Audio of other rates is resampled.
+const API_SAMPLE_RATE: u32 = 24000;
+/// The event id of the session update sent after the session was created, so that a rejection can
+/// be told apart from other errors.
+const INITIAL_SESSION_UPDATE_ID: &str = "initial-session-update";
+
+/// The delay before the first reconnect if none is configured.
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+/// How long to wait for `session.created` after connecting if not configured.
+const DEFAULT_SESSION_CREATED_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The RMS level above which input audio is considered speech by the idle disconnect.
+const SPEECH_THRESHOLD: f32 = 0.01;
+/// Pauses shorter than this are considered part of the speech.
+const SPEECH_HANGOVER: Duration = Duration::from_millis(500);
+
+/// Both directions of a websocket connection to the realtime API.
+pub struct Transport {
+    pub read: Pin<Box<dyn Stream<Item = Result<Message, tungstenite::Error>> + Send>>,
+    pub write: Pin<Box<dyn Sink<Message, Error = tungstenite::Error> + Send>>,
+}
+
 pub struct Client {
-    read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    connector: Box<dyn Connect>,
+    read: Pin<Box<dyn Stream<Item = Result<Message, tungstenite::Error>> + Send>>,
+    write: Pin<Box<dyn Sink<Message, Error = tungstenite::Error> + Send>>,
+    /// `false` after the upstream connection was closed because of inactivity.
+    connected: bool,
+    /// All session updates sent, so that they can be re-applied after a reconnect.
+    session_updates: Vec<Message>,
+    /// The session as configured by all updates sent, so that partial updates keep the fields they
+    /// don't specify.
+    session: types::RealtimeSession,
+    idle_disconnect: Option<IdleDisconnect>,
+    barge_in: Option<BargeInState>,
+    reconnects: Reconnects,
+    session_created_timeout: Duration,
+    input_resampler: Option<StreamResampler>,
+    output_resampler: Option<StreamResampler>,
     transcription_state: TranscriptionState,
+    commit_timer: Option<CommitTimer>,
+    assistant_playback: AssistantPlayback,
+    tools: ToolSet,
 
     #[cfg(feature = "prompt-delay")]
     prompt_coordinator: PromptCoordinator,
@@ -43,21 +88,34 @@ enum ResponseState {
 struct PromptCoordinator {
     response_state: ResponseState,
     inflight_prompt: Option<(String, PromptRequest)>,
+    /// The response to the inflight prompt must be cancelled as soon as it is created.
+    cancel_inflight: bool,
     pending_prompts: VecDeque<PromptRequest>,
 }
 
 impl Client {
-    pub(crate) fn new(
-        read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-        write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    ) -> Self {
-        Self {
+    pub async fn connect(connector: Box<dyn Connect>) -> Result<Self> {
+        let Transport { read, write } = connector.connect().await?;
+        Ok(Self {
+            connector,
             read,
             write,
+            connected: true,
+            session_updates: Vec::new(),
+            session: types::RealtimeSession::default(),
+            idle_disconnect: None,
+            barge_in: None,
+            reconnects: Reconnects::new(0, DEFAULT_INITIAL_BACKOFF),
+            session_created_timeout: DEFAULT_SESSION_CREATED_TIMEOUT,
+            input_resampler: None,
+            output_resampler: None,
             transcription_state: TranscriptionState::default(),
+            commit_timer: None,
+            assistant_playback: AssistantPlayback::default(),
+            tools: ToolSet::default(),
             #[cfg(feature = "prompt-delay")]
             prompt_coordinator: PromptCoordinator::new(),
-        }
+        })
     }
 
     /// Run an audio dialog.
@@ -70,30 +128,26 @@ impl Client {
         mut input: ConversationInput,
         output: ConversationOutput,
     ) -> Result<()> {
-        let expected_format = AudioFormat::new(1, 24000);
-        if input_format != expected_format {
-            bail!(
-                "Audio input has the wrong format {:?}, expected: {:?}",
-                input_format,
-                expected_format
-            );
+        // Input is mixed down to mono before it is sent.
+        if output_format.channels != 1 {
+            bail!("Audio output has the wrong format {output_format:?}, expected mono");
         }
+        self.input_resampler = (input_format.sample_rate != API_SAMPLE_RATE)
+            .then(|| StreamResampler::new(input_format.sample_rate, API_SAMPLE_RATE));
+        self.output_resampler = (output_format.sample_rate != API_SAMPLE_RATE)
+            .then(|| StreamResampler::new(API_SAMPLE_RATE, output_format.sample_rate));
 
-        if output_format != expected_format {
-            bail!(
-                "Audio output has the wrong format {:?}, expected: {:?}",
-                output_format,
-                expected_format
-            );
-        }
-
-        // Wait for the created event.
-        // TODO: Add a timeout here?
-        let message = self.read.next().await;
-        Self::verify_session_created_event(message)?;
+        self.session_created_timeout = params
+            .session_created_timeout_ms
+            .map_or(DEFAULT_SESSION_CREATED_TIMEOUT, Duration::from_millis);
+        let session = self.receive_session_created().await?;
 
         debug!("Session created");
 
+        if params.report_session_created {
+            output.service_event(OutputPath::Control, session_created_event(session))?;
+        }
+
         {
             let mut send_update = false;
             let mut session = types::RealtimeSession::default();
@@ -135,6 +189,7 @@ impl Client {
                 });
             }
 
+            self.tools = ToolSet::new(params.tools.clone());
             if !params.tools.is_empty() {
                 session.tools = Some(params.tools);
                 send_update = true;
@@ -145,32 +200,87 @@ impl Client {
                 send_update = true;
             }
 
+            self.session = session.clone();
             if send_update {
-                self.send_client_event(ClientEvent::SessionUpdate(client_event::SessionUpdate {
-                    event_id: None,
-                    session: client_event::SessionUpdatePayload::Tagged(types::Session::Realtime(
-                        session,
-                    )),
-                }))
+                self.update_session(session_update(
+                    session,
+                    Some(INITIAL_SESSION_UPDATE_ID.into()),
+                ))
                 .await?;
+                self.confirm_session_update(&output).await?;
                 debug!("Session updated");
             }
+
+            if let Some(commit_interval_ms) = params.commit_interval_ms {
+                // Server VAD is disabled by an explicit `null`, which the typed session can't
+                // express.
+                let event = json!({
+                    "type": "session.update",
+                    "session": {
+                        "type": "realtime",
+                        "audio": { "input": { "turn_detection": null } },
+                    },
+                });
+                let message = Message::Text(serde_json::to_string(&event)?.into());
+                self.write.send(message.clone()).await?;
+                self.session_updates.push(message);
+                debug!("Server VAD disabled, committing every {commit_interval_ms}ms");
+                self.commit_timer =
+                    Some(CommitTimer::new(Duration::from_millis(commit_interval_ms)));
+            }
+
+            if let Some(idle_disconnect_ms) = params.idle_disconnect_ms {
+                self.idle_disconnect = Some(IdleDisconnect::new(Duration::from_millis(
+                    idle_disconnect_ms,
+                )));
+            }
+
+            self.barge_in = params.barge_in.as_ref().map(BargeInState::new);
+            self.reconnects = Reconnects::new(
+                params.max_reconnects,
+                params
+                    .initial_backoff_ms
+                    .map_or(DEFAULT_INITIAL_BACKOFF, Duration::from_millis),
+            );
         }
 
+        output.service_ready()?;
+
         loop {
             select! {
                 input = input.recv() => {
                     if let Some(input) = input {
-                        self.process_input(input).await?;
+                        self.process_input(input, &output).await?;
                     } else {
                         // No more audio, end the session.
                         break;
                     }
                 }
 
-                message = self.read.next() => {
+                () = commit_due(&mut self.commit_timer), if self.connected => {
+                    self.send_client_event(ClientEvent::InputAudioBufferCommit(Default::default()))
+                        .await?;
+                }
+
+                () = idle_due(&self.idle_disconnect), if self.connected => {
+                    info!("Caller is silent, closing the upstream connection");
+                    self.write.close().await?;
+                    self.connected = false;
+                    if let Some(idle_disconnect) = &mut self.idle_disconnect {
+                        idle_disconnect.reset();
+                    }
+                    if let Some(commit_timer) = &mut self.commit_timer {
+                        commit_timer.audio_appended = false;
+                    }
+                }
+
+                message = self.read.next(), if self.connected => {
                     match message {
                         Some(Ok(message)) => {
+                            self.reconnects.reset();
+                            if let Some(idle_disconnect) = &mut self.idle_disconnect {
+                                idle_disconnect.notify_activity();
+                            }
                             match self.process_message(message, output_format, &output, &params.model, transcription).await? {
                                 FlowControl::End => { break; }
                                 FlowControl::PongAndContinue(payload) => {
@@ -180,7 +290,7 @@ impl Client {
                             }
                         }
                         Some(Err(e)) => {
-                            bail!(e)
+                            self.recover(e).await?;
                         }
                         None => {
                             // End of stream.
@@ -194,9 +304,17 @@ impl Client {
         Ok(())
     }
 
+    /// Waits for the `session.created` event the server sends first on each connection.
+    async fn receive_session_created(&mut self) -> Result<types::RealtimeSession> {
+        let message = time::timeout(self.session_created_timeout, self.read.next())
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for session.created"))?;
+        Self::verify_session_created_event(message)
+    }
+
     fn verify_session_created_event(
-        message: Option<Result<Message, tokio_tungstenite::tungstenite::Error>>,
-    ) -> Result<()> {
+        message: Option<Result<Message, tungstenite::Error>>,
+    ) -> Result<types::RealtimeSession> {
         let Some(message) = message else {
             // TODO: should this be an error.
             bail!("Failed to receive the initial message received");
@@ -247,7 +365,7 @@ impl Client {
         }
 
         // OpenAI may omit output modalities here; treat missing as default behavior.
-        let modalities = session.output_modalities.unwrap_or_default();
+        let modalities = session.output_modalities.as_deref().unwrap_or_default();
         if !modalities.is_empty()
             && !modalities
                 .iter()
@@ -256,12 +374,90 @@ impl Client {
             bail!("Expect audio output modality: {:?}", modalities);
         }
 
+        Ok(session)
+    }
+
+    /// Waits until the server confirmed the initial session update.
+    ///
+    /// If the server rejects it, the conversation fails with the reason, instead of with the first
+    /// error the main loop receives.
+    async fn confirm_session_update(&mut self, output: &ConversationOutput) -> Result<()> {
+        loop {
+            let Some(message) = self.read.next().await else {
+                bail!("Connection closed before the session update was confirmed");
+            };
+            let Message::Text(raw) = message? else {
+                continue;
+            };
+            match serde_json::from_str::<ServerEvent>(&raw)? {
+                ServerEvent::SessionUpdated(server_event::SessionUpdated { session, .. }) => {
+                    output.service_event(OutputPath::Control, session_updated_event(session))?;
+                    return Ok(());
+                }
+                ServerEvent::Error(_) => {
+                    let error: Value = serde_json::from_str(&raw)?;
+                    let error = &error["error"];
+                    if error["event_id"] == INITIAL_SESSION_UPDATE_ID {
+                        bail!(session_update_rejection(error, self.tools.tools()));
+                    }
+                    bail!("Server error while updating the session: {raw}");
+                }
+                event => {
+                    trace!("Ignored event before the session update was confirmed: {event:?}")
+                }
+            }
+        }
+    }
+
+    /// Re-establishes the upstream connection and re-applies all session updates.
+    async fn reconnect(&mut self) -> Result<()> {
+        info!("Re-establishing the upstream connection");
+        let Transport { read, write } = self.connector.connect().await?;
+        self.read = read;
+        self.write = write;
+        self.receive_session_created().await?;
+        for message in &self.session_updates {
+            self.write.send(message.clone()).await?;
+        }
+        self.connected = true;
+        Ok(())
+    }
+
+    /// Re-establishes the upstream connection after a recoverable read error. Waits with
+    /// exponential backoff before each attempt.
+    ///
+    /// Pending prompts and the response state are kept, only the connection is replaced.
+    async fn recover(&mut self, error: tungstenite::Error) -> Result<()> {
+        if !is_recoverable(&error) {
+            bail!(error);
+        }
+        loop {
+            let Some(backoff) = self.reconnects.next_backoff() else {
+                bail!(error);
+            };
+            warn!("Upstream connection failed: {error}, reconnecting in {backoff:?}");
+            time::sleep(backoff).await;
+            match self.reconnect().await {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("Reconnect failed: {e:#}"),
+            }
+        }
+    }
+
+    /// Sends a session update and records it for reconnects.
+    async fn update_session(&mut self, event: ClientEvent) -> Result<()> {
+        let message = Message::Text(serde_json::to_string(&event)?.into());
+        self.write.send(message.clone()).await?;
+        self.session_updates.push(message);
         Ok(())
     }
 
     async fn send_frame(&mut self, frame: AudioFrame) -> Result<()> {
         let mono = frame.into_mono();
-        let samples = mono.samples;
+        let samples = match &mut self.input_resampler {
+            Some(resampler) => resampler.process(&mono.samples),
+            None => mono.samples,
+        };
         let samples_le = audio::to_le_bytes(samples);
 
         let event = client_event::InputAudioBufferAppend {
@@ -274,6 +470,9 @@ impl Client {
         );
 
         self.write.send(message).await?;
+        if let Some(commit_timer) = &mut self.commit_timer {
+            commit_timer.notify_audio_appended();
+        }
         Ok(())
     }
 
@@ -283,17 +482,51 @@ impl Client {
         Ok(())
     }
 
-    async fn process_input(&mut self, input: Input) -> Result<()> {
+    async fn process_input(&mut self, input: Input, output: &ConversationOutput) -> Result<()> {
         match input {
             Input::Text { .. } => {
                 warn!("Unexpected text input");
             }
             Input::Audio { frame } => {
+                if let Some(idle_disconnect) = &mut self.idle_disconnect {
+                    let speech = idle_disconnect.process(&frame);
+                    if !self.connected {
+                        if !speech {
+                            return Ok(());
+                        }
+                        self.reconnect().await?;
+                    }
+                }
+                let barged_in = match &mut self.barge_in {
+                    Some(state) => {
+                        state.set_output_active(self.assistant_playback.is_playing());
+                        barge_in::detect(&frame, state)
+                    }
+                    None => false,
+                };
+                if barged_in {
+                    info!("Caller barged in, cancelling the response");
+                    self.interrupt(output).await?;
+
+                    #[cfg(feature = "prompt-delay")]
+                    self.prompt_coordinator
+                        .cancel_prompts(&mut self.write)
+                        .await?;
+
+                    #[cfg(not(feature = "prompt-delay"))]
+                    send_response_cancel(&mut self.write).await?;
+                }
                 // spellcheck: ignore
                 // debug!("Sending frame: {:?}", audio_frame.duration());
                 self.send_frame(frame).await?;
             }
             Input::ServiceEvent { value } => {
+                if let Some(idle_disconnect) = &mut self.idle_disconnect {
+                    idle_disconnect.notify_activity();
+                }
+                if !self.connected {
+                    self.reconnect().await?;
+                }
                 match serde_json::from_value(value)? {
                     ServiceInputEvent::FunctionCallResult { call_id, output } => {
                         debug!("Sending function call output");
@@ -314,15 +547,54 @@ impl Client {
                         self.send_client_event(ClientEvent::ResponseCreate(Default::default()))
                             .await?;
                     }
-                    ServiceInputEvent::Prompt { text } => {
+                    ServiceInputEvent::Prompt { text, response } => {
                         info!("Received prompt");
+                        let request = PromptRequest {
+                            text,
+                            overrides: response.unwrap_or_default(),
+                        };
+
                         #[cfg(feature = "prompt-delay")]
                         self.prompt_coordinator
-                            .push_prompt(&mut self.write, PromptRequest(text))
+                            .push_prompt(&mut self.write, request)
                             .await?;
 
                         #[cfg(not(feature = "prompt-delay"))]
-                        self.send_prompt_immediately(PromptRequest(text)).await?;
+                        self.send_prompt_immediately(request).await?;
+                    }
+                    ServiceInputEvent::CancelPrompts => {
+                        info!("Cancelling prompts");
+
+                        #[cfg(feature = "prompt-delay")]
+                        self.prompt_coordinator
+                            .cancel_prompts(&mut self.write)
+                            .await?;
+
+                        #[cfg(not(feature = "prompt-delay"))]
+                        send_response_cancel(&mut self.write).await?;
+                    }
+                    ServiceInputEvent::ReplacePrompt { text, response } => {
+                        info!("Replacing prompts");
+                        let request = PromptRequest {
+                            text,
+                            overrides: response.unwrap_or_default(),
+                        };
+
+                        #[cfg(feature = "prompt-delay")]
+                        {
+                            self.prompt_coordinator
+                                .cancel_prompts(&mut self.write)
+                                .await?;
+                            self.prompt_coordinator
+                                .push_prompt(&mut self.write, request)
+                                .await?;
+                        }
+
+                        #[cfg(not(feature = "prompt-delay"))]
+                        {
+                            send_response_cancel(&mut self.write).await?;
+                            self.send_prompt_immediately(request).await?;
+                        }
                     }
                     ServiceInputEvent::SessionUpdate {
                         instructions,
@@ -330,30 +602,51 @@ impl Client {
                         tools,
                         tool_choice,
                     } => {
-                        let audio = voice.map(|voice| types::AudioConfig {
-                            input: None,
-                            output: Some(types::AudioOutput {
-                                format: None,
-                                speed: 1.0,
-                                voice: Some(voice),
-                            }),
-                        });
-
-                        let session = types::RealtimeSession {
-                            instructions,
-                            audio,
-                            tools,
-                            tool_choice,
-                            ..Default::default()
-                        };
+                        if let Some(instructions) = instructions {
+                            self.session.instructions = Some(instructions);
+                        }
+                        if let Some(voice) = voice {
+                            let audio =
+                                self.session
+                                    .audio
+                                    .get_or_insert_with(|| types::AudioConfig {
+                                        input: None,
+                                        output: None,
+                                    });
+                            audio
+                                .output
+                                .get_or_insert_with(|| types::AudioOutput {
+                                    format: None,
+                                    speed: 1.0,
+                                    voice: None,
+                                })
+                                .voice = Some(voice);
+                        }
+                        if let Some(tools) = tools {
+                            self.tools.replace(tools.clone());
+                            self.session.tools = Some(tools);
+                        }
+                        if let Some(tool_choice) = tool_choice {
+                            self.session.tool_choice = Some(tool_choice);
+                        }
 
-                        let event = ClientEvent::SessionUpdate(client_event::SessionUpdate {
-                            session: client_event::SessionUpdatePayload::Tagged(
-                                types::Session::Realtime(session),
-                            ),
-                            ..Default::default()
-                        });
-                        self.send_client_event(event).await?;
+                        self.update_session(session_update(self.session.clone(), None))
+                            .await?;
+                    }
+                    ServiceInputEvent::AddTool { tool } => {
+                        self.tools.add(tool);
+                        self.session.tools = Some(self.tools.tools().to_vec());
+                        self.update_session(tools_session_update(self.tools.tools()))
+                            .await?;
+                    }
+                    ServiceInputEvent::RemoveTool { name } => {
+                        if !self.tools.remove(&name) {
+                            warn!("Tool `{name}` can't be removed, it's not part of the session");
+                            return Ok(());
+                        }
+                        self.session.tools = Some(self.tools.tools().to_vec());
+                        self.update_session(tools_session_update(self.tools.tools()))
+                            .await?;
                     }
                 }
             }
@@ -361,6 +654,21 @@ impl Client {
         Ok(())
     }
 
+    /// Clears the audio output and truncates the assistant's audio the caller did not hear.
+    async fn interrupt(&mut self, output: &ConversationOutput) -> Result<()> {
+        output.clear_audio()?;
+        if let Some(truncate) = self.assistant_playback.interrupt() {
+            debug!(
+                item_id = %truncate.item_id,
+                audio_end_ms = truncate.audio_end_ms,
+                "Truncating the interrupted assistant audio"
+            );
+            self.send_client_event(ClientEvent::ConversationItemTruncate(truncate))
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn process_message(
         &mut self,
         message: Message,
@@ -428,15 +736,25 @@ impl Client {
             }
             ServerEvent::ResponseOutputAudioDelta(audio_delta) => {
                 let decoded = BASE64_STANDARD.decode(audio_delta.delta)?;
-                let samples = audio::from_le_bytes(&decoded);
+                let mut samples = audio::from_le_bytes(&decoded);
+                if let Some(resampler) = &mut self.output_resampler {
+                    samples = resampler.process(&samples);
+                }
                 trace!("Sending {} samples", samples.len());
                 let frame = AudioFrame {
                     format: output_format,
                     samples,
                 };
+                self.assistant_playback.received(
+                    audio_delta.item_id,
+                    audio_delta.content_index,
+                    frame.duration(),
+                );
                 output.audio_frame(frame)?;
             }
-            ServerEvent::InputAudioBufferSpeechStarted(_) => output.clear_audio()?,
+            ServerEvent::InputAudioBufferSpeechStarted(_) => {
+                self.interrupt(output).await?;
+            }
             ServerEvent::ConversationItemInputAudioTranscriptionDelta(
                 server_event::ConversationItemInputAudioTranscriptionDelta {
                     item_id,
@@ -487,6 +805,7 @@ impl Client {
                         content_index,
                         delta,
                     );
+                    let text = transcription.output_encoding.encode(text);
                     output.text(false, text, None, Some(AI_ASSISTANT_SPEAKER.into()))?;
                 }
             }
@@ -508,6 +827,7 @@ impl Client {
                         transcript,
                     )
                 {
+                    let text = transcription.output_encoding.encode(text);
                     output.text(true, text, None, Some(AI_ASSISTANT_SPEAKER.into()))?;
                 }
             }
@@ -537,7 +857,7 @@ impl Client {
             ServerEvent::ResponseDone(server_event::ResponseDone {
                 response:
                     types::Response {
-                        id: _,
+                        id: response_id,
                         object,
                         status,
                         output: items,
@@ -551,6 +871,10 @@ impl Client {
                 //     .transcription_state
                 //     .has_output_transcript_events_for_response(&response_id);
 
+                if !matches!(status, ResponseStatus::Completed) {
+                    output.service_event(OutputPath::Control, response_status_event(raw)?)?;
+                }
+
                 #[cfg(feature = "prompt-delay")]
                 let mut any_function_call_request = false;
                 for item in items {
@@ -668,6 +992,7 @@ impl Client {
                 }
 
                 output.service_event(OutputPath::Media, ServiceOutputEvent::TurnComplete)?;
+                output.turn_completed(Some(response_id))?;
 
                 #[cfg(feature = "prompt-delay")]
                 {
@@ -684,14 +1009,7 @@ impl Client {
                 }
             }
             ServerEvent::SessionUpdated(server_event::SessionUpdated { session, .. }) => {
-                let tools = match session {
-                    types::UntaggedSession::Realtime(session) => session.tools,
-                    types::UntaggedSession::Transcription(_) => None,
-                };
-                output.service_event(
-                    OutputPath::Control,
-                    ServiceOutputEvent::SessionUpdated { tools },
-                )?
+                output.service_event(OutputPath::Control, session_updated_event(session))?
             }
 
             response => {
@@ -703,6 +1021,88 @@ impl Client {
     }
 }
 
+fn session_update(session: types::RealtimeSession, event_id: Option<String>) -> ClientEvent {
+    ClientEvent::SessionUpdate(client_event::SessionUpdate {
+        event_id,
+        session: client_event::SessionUpdatePayload::Tagged(types::Session::Realtime(session)),
+    })
+}
+
+/// A session update that replaces the session's tools.
+fn tools_session_update(tools: &[types::ToolDefinition]) -> ClientEvent {
+    session_update(
+        types::RealtimeSession {
+            tools: Some(tools.to_vec()),
+            ..Default::default()
+        },
+        None,
+    )
+}
+
+fn session_updated_event(session: types::UntaggedSession) -> ServiceOutputEvent {
+    let tools = match session {
+        types::UntaggedSession::Realtime(session) => session.tools,
+        types::UntaggedSession::Transcription(_) => None,
+    };
+    ServiceOutputEvent::SessionUpdated { tools }
+}
+
+/// Describes why the server rejected a session update. The error is taken from the raw event,
+/// because the parameter it reports, for example `session.tools[0].parameters`, is used to name
+/// the tool that was rejected.
+fn session_update_rejection(error: &Value, tools: &[types::ToolDefinition]) -> String {
+    let message = error["message"].as_str().unwrap_or("Unknown error");
+    let Some(param) = error["param"].as_str() else {
+        return format!("Session update rejected: {message}");
+    };
+    let tool = param
+        .strip_prefix("session.tools[")
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(index, _)| index.parse::<usize>().ok())
+        .and_then(|index| tools.get(index));
+    match tool {
+        Some(tool) => format!(
+            "Invalid tool definition for `{}`: {message}",
+            tool_name(tool)
+        ),
+        None => format!("Session update rejected: {message} (parameter `{param}`)"),
+    }
+}
+
+/// The status and reason of a response that did not complete. They are taken from the raw event to
+/// pass them on as reported by the server.
+fn response_status_event(raw: &str) -> Result<ServiceOutputEvent> {
+    let event: Value = serde_json::from_str(raw).context("Parsing response done event")?;
+    let response = &event["response"];
+    let details = &response["status_details"];
+    let reason = details["reason"]
+        .as_str()
+        .or_else(|| details["error"]["message"].as_str());
+    Ok(ServiceOutputEvent::ResponseStatus {
+        status: response["status"].as_str().unwrap_or_default().to_string(),
+        reason: reason.map(str::to_string),
+    })
+}
+
+/// The session configuration the server actually applied.
+fn session_created_event(session: types::RealtimeSession) -> ServiceOutputEvent {
+    let (input, output) = match session.audio {
+        Some(audio) => (audio.input, audio.output),
+        None => (None, None),
+    };
+    let input_format = input.and_then(|input| input.format);
+    let (output_format, voice) = match output {
+        Some(output) => (output.format, output.voice),
+        None => (None, None),
+    };
+    ServiceOutputEvent::SessionCreated {
+        output_modalities: session.output_modalities,
+        voice,
+        input_format,
+        output_format,
+    }
+}
+
 /// State management.
 impl Client {
     #[cfg(not(feature = "prompt-delay"))]
@@ -721,6 +1121,12 @@ impl Client {
             // conversation loop.
             return Ok(());
         }
+        let is_cancel_not_active_error =
+            api_error.code.as_deref() == Some("response_cancel_not_active");
+        if is_cancel_not_active_error {
+            // Prompts are cancelled without knowing if there is an active response.
+            return Ok(());
+        }
 
         bail!(format!("{error:?}, raw: {raw}"));
     }
@@ -732,6 +1138,7 @@ impl PromptCoordinator {
         Self {
             response_state: ResponseState::Idle,
             inflight_prompt: None,
+            cancel_inflight: false,
             pending_prompts: Default::default(),
         }
     }
@@ -739,23 +1146,51 @@ impl PromptCoordinator {
     /// Either send the prompt immediately if possible, or schedule it until it's safe to do.
     async fn push_prompt(
         &mut self,
-        write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+        write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
         request: PromptRequest,
     ) -> Result<()> {
         self.pending_prompts.push_back(request);
         self.flush_prompt(write).await
     }
 
+    /// Drop all pending prompts and cancel the active response.
+    ///
+    /// If the response to the inflight prompt was not created yet, it is cancelled as soon as it
+    /// is. The inflight prompt stays tracked until its response is done, so that no other prompt
+    /// is sent in the meantime.
+    async fn cancel_prompts(
+        &mut self,
+        write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
+    ) -> Result<()> {
+        self.pending_prompts.clear();
+        match self.response_state {
+            ResponseState::Idle => {
+                self.cancel_inflight = self.inflight_prompt.is_some();
+                Ok(())
+            }
+            ResponseState::Responding => send_response_cancel(write).await,
+            // The response is already done.
+            ResponseState::ExpectingFunctionResult => Ok(()),
+        }
+    }
+
     async fn update_response_state(
         &mut self,
-        write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+        write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
         state: ResponseState,
     ) -> Result<()> {
         info!("{:?} -> {state:?}", self.response_state);
 
         if self.inflight_prompt.is_some() && state == ResponseState::Idle {
             debug!("{state:?}: Clearing inflight prompt");
-            self.inflight_prompt = None
+            self.inflight_prompt = None;
+            self.cancel_inflight = false;
+        }
+
+        if self.cancel_inflight && state == ResponseState::Responding {
+            debug!("{state:?}: Cancelling the response to the inflight prompt");
+            self.cancel_inflight = false;
+            send_response_cancel(write).await?;
         }
 
         let previous = self.response_state;
@@ -770,7 +1205,7 @@ impl PromptCoordinator {
 
     async fn flush_prompt(
         &mut self,
-        write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+        write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
     ) -> Result<()> {
         if self.inflight_prompt.is_some() || self.response_state != ResponseState::Idle {
             return Ok(());
@@ -785,7 +1220,7 @@ impl PromptCoordinator {
 
     async fn send_prompt_with_tracking(
         &mut self,
-        write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+        write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
         prompt_request: PromptRequest,
     ) -> Result<()> {
         let event_id = Uuid::new_v4().to_string();
@@ -803,38 +1238,277 @@ impl PromptCoordinator {
             && let Some((inflight_prompt_event_id, prompt_request)) = &self.inflight_prompt
             && api_error.event_id == Some(inflight_prompt_event_id.into())
         {
-            debug!("Rescheduling inflight prompt");
-            self.pending_prompts.push_front(prompt_request.clone());
+            if self.cancel_inflight {
+                debug!("Dropping cancelled inflight prompt");
+                self.cancel_inflight = false;
+            } else {
+                debug!("Rescheduling inflight prompt");
+                self.pending_prompts.push_front(prompt_request.clone());
+            }
             self.inflight_prompt = None;
             return Ok(());
         }
 
+        let is_cancel_not_active_error =
+            api_error.code.as_deref() == Some("response_cancel_not_active");
+        if is_cancel_not_active_error {
+            // The response may be done before its cancellation arrives.
+            return Ok(());
+        }
+
         bail!(format!("{error:?}, raw: {raw}"));
     }
 }
 
 async fn send_prompt_event(
-    write: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
     prompt_request: &PromptRequest,
     event_id: Option<String>,
 ) -> Result<()> {
     info!("Sending prompt: {prompt_request:?}");
 
-    let mut event = serde_json::json!({
+    let event = prompt_event(prompt_request, event_id)?;
+    let message = Message::Text(serde_json::to_string(&event)?.into());
+    write.send(message).await?;
+    Ok(())
+}
+
+async fn send_response_cancel(
+    write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
+) -> Result<()> {
+    let event = ClientEvent::ResponseCancel(Default::default());
+    let message = Message::Text(serde_json::to_string(&event)?.into());
+    write.send(message).await?;
+    Ok(())
+}
+
+/// Build the `response.create` event for a prompt, including its response overrides.
+fn prompt_event(prompt_request: &PromptRequest, event_id: Option<String>) -> Result<Value> {
+    let mut response = json!({
+        "input": [],
+        "instructions": prompt_request.text,
+    });
+
+    let overrides = &prompt_request.overrides;
+    if let Some(max_output_tokens) = overrides.max_output_tokens {
+        response["max_output_tokens"] = max_output_tokens.into();
+    }
+    if let Some(output_modalities) = &overrides.output_modalities {
+        response["output_modalities"] = serde_json::to_value(output_modalities)?;
+    }
+    if let Some(voice) = &overrides.voice {
+        response["audio"] = json!({ "output": { "voice": serde_json::to_value(voice)? } });
+    }
+
+    let mut event = json!({
         "type": "response.create",
-        "response": {
-            "input": [],
-            "instructions": prompt_request.0,
-        }
+        "response": response,
     });
 
     if let Some(event_id) = event_id {
-        event["event_id"] = serde_json::Value::String(event_id);
+        event["event_id"] = Value::String(event_id);
     }
 
-    let message = Message::Text(serde_json::to_string(&event)?.into());
-    write.send(message).await?;
-    Ok(())
+    Ok(event)
+}
+
+/// Commits the input audio buffer in a fixed interval when server VAD is disabled.
+struct CommitTimer {
+    interval: Interval,
+    audio_appended: bool,
+}
+
+impl CommitTimer {
+    fn new(period: Duration) -> Self {
+        let mut interval = time::interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            interval,
+            audio_appended: false,
+        }
+    }
+
+    fn notify_audio_appended(&mut self) {
+        self.audio_appended = true;
+    }
+
+    /// Resolves at the next tick at which audio was appended since the previous commit, so that
+    /// empty buffers are never committed.
+    async fn commit_due(&mut self) {
+        loop {
+            self.interval.tick().await;
+            if mem::take(&mut self.audio_appended) {
+                return;
+            }
+        }
+    }
+}
+
+async fn commit_due(commit_timer: &mut Option<CommitTimer>) {
+    match commit_timer {
+        Some(commit_timer) => commit_timer.commit_due().await,
+        None => future::pending().await,
+    }
+}
+
+/// Tracks the caller's silence to close the upstream connection when the dialog is idle.
+struct IdleDisconnect {
+    detector: TurnDetector,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl IdleDisconnect {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            detector: TurnDetector::new(SPEECH_THRESHOLD, SPEECH_HANGOVER),
+            timeout,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Returns `true` if the caller is speaking.
+    fn process(&mut self, frame: &AudioFrame) -> bool {
+        self.detector.process(frame);
+        let speech = self.detector.in_turn();
+        if speech {
+            self.notify_activity();
+        }
+        speech
+    }
+
+    fn notify_activity(&mut self) {
+        self.deadline = Instant::now() + self.timeout;
+    }
+
+    /// Forgets a turn in progress, so that only new speech counts as activity. Called after
+    /// disconnecting, because the input may have stopped in the middle of a turn.
+    fn reset(&mut self) {
+        self.detector = TurnDetector::new(SPEECH_THRESHOLD, SPEECH_HANGOVER);
+    }
+}
+
+/// Counts the reconnects after network errors in a row.
+#[derive(Debug)]
+struct Reconnects {
+    max: u32,
+    initial_backoff: Duration,
+    attempts: u32,
+}
+
+impl Reconnects {
+    fn new(max: u32, initial_backoff: Duration) -> Self {
+        Self {
+            max,
+            initial_backoff,
+            attempts: 0,
+        }
+    }
+
+    /// The delay before the next attempt, `None` if all attempts are used up.
+    fn next_backoff(&mut self) -> Option<Duration> {
+        if self.attempts >= self.max {
+            return None;
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << self.attempts.min(16));
+        self.attempts += 1;
+        Some(backoff)
+    }
+
+    /// Called when the connection works again.
+    fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+/// Network errors a new connection may resolve.
+fn is_recoverable(error: &tungstenite::Error) -> bool {
+    matches!(
+        error,
+        tungstenite::Error::Io(_)
+            | tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)
+    )
+}
+
+async fn idle_due(idle_disconnect: &Option<IdleDisconnect>) {
+    match idle_disconnect {
+        Some(idle_disconnect) => time::sleep_until(idle_disconnect.deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// Estimates how much of the assistant's audio the caller has heard, so that the unheard rest can
+/// be truncated from the conversation when the caller interrupts.
+///
+/// The service does not see the actual playback position, so the audio is assumed to be played
+/// back in real time as soon as it is received, one item after the other.
+#[derive(Debug, Default)]
+struct AssistantPlayback {
+    current: Option<PlayingItem>,
+}
+
+#[derive(Debug)]
+struct PlayingItem {
+    item_id: String,
+    content_index: u32,
+    started: Instant,
+    /// The duration of the audio received for this item.
+    received: Duration,
+}
+
+impl PlayingItem {
+    fn end(&self) -> Instant {
+        self.started + self.received
+    }
+}
+
+impl AssistantPlayback {
+    fn received(&mut self, item_id: String, content_index: u32, duration: Duration) {
+        let now = Instant::now();
+        match &mut self.current {
+            Some(item) if item.item_id == item_id && item.content_index == content_index => {
+                // Playback stalls when the audio arrives slower than real time.
+                if item.end() < now {
+                    item.started = now - item.received;
+                }
+                item.received += duration;
+            }
+            current => {
+                let started = current.as_ref().map_or(now, |item| item.end().max(now));
+                *current = Some(PlayingItem {
+                    item_id,
+                    content_index,
+                    started,
+                    received: duration,
+                });
+            }
+        }
+    }
+
+    /// Returns `true` while the audio received is estimated to be played back.
+    fn is_playing(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|item| item.end() > Instant::now())
+    }
+
+    /// Forgets the current item and returns the event that truncates it at the estimated
+    /// playback position, if not all of its audio was played yet.
+    fn interrupt(&mut self) -> Option<client_event::ConversationItemTruncate> {
+        let item = self.current.take()?;
+        let played = Instant::now().saturating_duration_since(item.started);
+        if played >= item.received {
+            return None;
+        }
+        Some(client_event::ConversationItemTruncate {
+            event_id: None,
+            item_id: item.item_id,
+            content_index: item.content_index,
+            audio_end_ms: played.as_millis() as u32,
+        })
+    }
 }
 
 enum FlowControl {
@@ -844,4 +1518,1271 @@ enum FlowControl {
 }
 
 #[derive(Debug, Clone)]
-struct PromptRequest(String);
+struct PromptRequest {
+    text: String,
+    overrides: ResponseOverrides,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+
+    use async_trait::async_trait;
+    use futures::FutureExt;
+    use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+    use tokio::sync::mpsc as tokio_mpsc;
+
+    use context_switch_core::barge_in::BargeInParams;
+    use context_switch_core::{Conversation, InputModality, Output, OutputModality};
+
+    use super::*;
+
+    #[test]
+    fn tool_changes_send_the_resulting_tool_set() {
+        let mut tools = ToolSet::default();
+        let session_tools = |tools: &ToolSet| {
+            let event = serde_json::to_value(tools_session_update(tools.tools())).unwrap();
+            event["session"]["tools"].clone()
+        };
+
+        for name in ["get_time", "get_weather"] {
+            let ServiceInputEvent::AddTool { tool } = serde_json::from_value(json!({
+                "type": "addTool",
+                "tool": {
+                    "type": "function",
+                    "name": name,
+                    "description": name,
+                    "parameters": { "type": "object", "properties": {} },
+                },
+            }))
+            .unwrap() else {
+                panic!("Expected an add tool event");
+            };
+            tools.add(tool);
+        }
+        let names = |value: Value| -> Vec<Value> {
+            value
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|tool| tool["name"].clone())
+                .collect()
+        };
+        assert_eq!(
+            names(session_tools(&tools)),
+            [json!("get_time"), json!("get_weather")]
+        );
+
+        let ServiceInputEvent::RemoveTool { name } =
+            serde_json::from_value(json!({ "type": "removeTool", "name": "get_time" })).unwrap()
+        else {
+            panic!("Expected a remove tool event");
+        };
+        assert!(tools.remove(&name));
+        assert_eq!(names(session_tools(&tools)), [json!("get_weather")]);
+    }
+
+    fn session_created() -> Message {
+        let created = json!({
+            "type": "session.created",
+            "event_id": "event-1",
+            "session": {
+                "type": "realtime",
+                "object": "realtime.session",
+                "id": "sess-1",
+                "model": "gpt-realtime",
+                "output_modalities": ["audio"],
+                "instructions": "",
+                "tools": [],
+                "tool_choice": "auto",
+                "max_output_tokens": "inf",
+                "audio": {
+                    "input": {
+                        "format": { "type": "audio/pcm", "rate": 24000 },
+                        "transcription": null,
+                        "noise_reduction": null,
+                        "turn_detection": null,
+                    },
+                    "output": {
+                        "format": { "type": "audio/pcm", "rate": 24000 },
+                        "voice": "alloy",
+                        "speed": 1.0,
+                    },
+                },
+            },
+        });
+        Message::Text(created.to_string().into())
+    }
+
+    fn sent_events(receiver: &mut UnboundedReceiver<Message>) -> Vec<Value> {
+        let mut events = Vec::new();
+        while let Some(Some(message)) = receiver.next().now_or_never() {
+            let Message::Text(text) = message else {
+                panic!("Unexpected message: {message:?}");
+            };
+            events.push(serde_json::from_str(&text).unwrap());
+        }
+        events
+    }
+
+    fn sent_event_types(receiver: &mut UnboundedReceiver<Message>) -> Vec<String> {
+        sent_events(receiver)
+            .iter()
+            .map(|event| event["type"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    /// A `response.created` or `response.done` event of a response without output items.
+    fn response_event(event_type: &str, status: &str) -> Value {
+        json!({
+            "type": event_type,
+            "event_id": "event-1",
+            "response": {
+                "object": "realtime.response",
+                "id": "resp-1",
+                "status": status,
+                "status_details": null,
+                "output": [],
+                "conversation_id": "conv-1",
+                "output_modalities": ["audio"],
+                "max_output_tokens": "inf",
+                "metadata": null,
+                "usage": null,
+            },
+        })
+    }
+
+    #[test]
+    fn session_created_event_reports_the_applied_session() {
+        let message = session_created();
+
+        let session = Client::verify_session_created_event(Some(Ok(message))).unwrap();
+        let event = serde_json::to_value(session_created_event(session)).unwrap();
+
+        assert_eq!(
+            event,
+            json!({
+                "type": "sessionCreated",
+                "outputModalities": ["audio"],
+                "voice": "alloy",
+                "inputFormat": { "type": "audio/pcm", "rate": 24000 },
+                "outputFormat": { "type": "audio/pcm", "rate": 24000 },
+            })
+        );
+    }
+
+    #[test]
+    fn prompt_response_overrides_are_serialized_into_response_create() {
+        let event: ServiceInputEvent = serde_json::from_value(json!({
+            "type": "prompt",
+            "text": "Answer briefly",
+            "response": {
+                "maxOutputTokens": 64,
+                "outputModalities": ["text"],
+                "voice": "alloy",
+            }
+        }))
+        .unwrap();
+        let ServiceInputEvent::Prompt { text, response } = event else {
+            panic!("Expected a prompt event");
+        };
+        let request = PromptRequest {
+            text,
+            overrides: response.unwrap(),
+        };
+
+        let event = prompt_event(&request, Some("event-1".into())).unwrap();
+        assert_eq!(
+            event,
+            json!({
+                "type": "response.create",
+                "event_id": "event-1",
+                "response": {
+                    "input": [],
+                    "instructions": "Answer briefly",
+                    "max_output_tokens": 64,
+                    "output_modalities": ["text"],
+                    "audio": { "output": { "voice": "alloy" } },
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn plain_prompt_has_no_response_overrides() {
+        let event: ServiceInputEvent =
+            serde_json::from_value(json!({ "type": "prompt", "text": "Hello" })).unwrap();
+        let ServiceInputEvent::Prompt { text, response } = event else {
+            panic!("Expected a prompt event");
+        };
+        assert!(response.is_none());
+
+        let request = PromptRequest {
+            text,
+            overrides: Default::default(),
+        };
+        let event = prompt_event(&request, None).unwrap();
+        assert_eq!(
+            event,
+            json!({
+                "type": "response.create",
+                "response": { "input": [], "instructions": "Hello" }
+            })
+        );
+    }
+
+    /// The server side of a mock connection.
+    struct MockConnection {
+        written: UnboundedReceiver<Message>,
+        read: UnboundedSender<Result<Message, tungstenite::Error>>,
+    }
+
+    #[derive(Debug)]
+    struct MockConnector {
+        connections: tokio_mpsc::UnboundedSender<MockConnection>,
+        /// Send `session.created` right after connecting.
+        session_created: bool,
+        /// Send `session.updated` after `session.created`, to confirm the initial session update.
+        session_updated: bool,
+    }
+
+    #[async_trait]
+    impl Connect for MockConnector {
+        async fn connect(&self) -> Result<Transport> {
+            let (read_sender, read) = mpsc::unbounded();
+            let (write, written) = mpsc::unbounded();
+            if self.session_created {
+                read_sender.unbounded_send(Ok(session_created())).unwrap();
+            }
+            if self.session_updated {
+                let updated = json!({
+                    "type": "session.updated",
+                    "event_id": "event-2",
+                    "session": {
+                        "type": "realtime",
+                        "object": "realtime.session",
+                        "id": "sess-1",
+                        "model": "gpt-realtime",
+                        "output_modalities": ["audio"],
+                        "instructions": "Be brief",
+                        "tools": [],
+                        "tool_choice": "auto",
+                        "max_output_tokens": "inf",
+                    },
+                });
+                read_sender
+                    .unbounded_send(Ok(Message::Text(updated.to_string().into())))
+                    .unwrap();
+            }
+            let connection = MockConnection {
+                written,
+                read: read_sender,
+            };
+            assert!(self.connections.send(connection).is_ok());
+            Ok(Transport {
+                read: Box::pin(read),
+                write: Box::pin(write.sink_map_err(|_| tungstenite::Error::ConnectionClosed)),
+            })
+        }
+    }
+
+    #[test]
+    fn error_instead_of_session_created_fails_with_the_reason() {
+        let error = json!({
+            "type": "error",
+            "event_id": "event-1",
+            "error": {
+                "type": "invalid_request_error",
+                "code": "invalid_api_key",
+                "message": "Incorrect API key provided.",
+                "param": null,
+                "event_id": null,
+            },
+        });
+        let message = Message::Text(error.to_string().into());
+
+        let error = Client::verify_session_created_event(Some(Ok(message))).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Failed to create the session: Incorrect API key provided."
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dialog_fails_when_the_session_is_not_created_in_time() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: false,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        // Keep the connection open without sending anything.
+        let _connection = connections_receiver.recv().await.unwrap();
+
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, _output_receiver) = tokio_mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        let mut params = Params::new("key", "model");
+        params.session_created_timeout_ms = Some(1000);
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+        let started = Instant::now();
+        let error = client
+            .dialog(format, format, params, transcription, input, output)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "Timed out waiting for session.created");
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn session_updates_keep_the_instructions_of_the_session() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: true,
+        }))
+        .await
+        .unwrap();
+        let mut connection = connections_receiver.recv().await.unwrap();
+
+        let format = AudioFormat::new(1, 24000);
+        let (input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, _output_receiver) = tokio_mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        let mut params = Params::new("key", "model");
+        params.instructions = Some("Be brief".into());
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+        let dialog = client.dialog(format, format, params, transcription, input, output);
+
+        let server = async move {
+            time::sleep(Duration::from_secs(1)).await;
+            assert_eq!(
+                sent_event_types(&mut connection.written),
+                ["session.update"]
+            );
+
+            let update = json!({
+                "type": "sessionUpdate",
+                "tools": [{
+                    "type": "function",
+                    "name": "get_time",
+                    "description": "Returns the time",
+                    "parameters": { "type": "object", "properties": {} },
+                }],
+            });
+            input_sender
+                .send(Input::ServiceEvent { value: update })
+                .await
+                .unwrap();
+            time::sleep(Duration::from_secs(1)).await;
+
+            let [event] = &sent_events(&mut connection.written)[..] else {
+                panic!("Expected one session update");
+            };
+            assert_eq!(event["type"], "session.update");
+            assert_eq!(event["session"]["instructions"], "Be brief");
+            assert_eq!(event["session"]["tools"][0]["name"], "get_time");
+            drop(input_sender);
+        };
+
+        let (result, ()) = tokio::join!(dialog, server);
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_session_update_fails_with_the_reason() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        let connection = connections_receiver.recv().await.unwrap();
+        let rejection = json!({
+            "type": "error",
+            "event_id": "event-2",
+            "error": {
+                "type": "invalid_request_error",
+                "code": "invalid_value",
+                "message": "Invalid schema for function 'get_time'.",
+                "param": "session.tools[0].parameters",
+                "event_id": INITIAL_SESSION_UPDATE_ID,
+            },
+        });
+        connection
+            .read
+            .unbounded_send(Ok(Message::Text(rejection.to_string().into())))
+            .unwrap();
+
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, _output_receiver) = tokio_mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+
+        let mut params = Params::new("key", "model");
+        params.tools = serde_json::from_value(json!([{
+            "type": "function",
+            "name": "get_time",
+            "description": "Returns the time",
+            "parameters": { "type": "invalid" },
+        }]))
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+        let error = client
+            .dialog(format, format, params, transcription, input, output)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid tool definition for `get_time`: Invalid schema for function 'get_time'."
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn service_is_ready_only_after_the_session_was_created() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: false,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        let connection = connections_receiver.recv().await.unwrap();
+
+        let format = AudioFormat::new(1, 24000);
+        let (input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, mut output_receiver) = tokio_mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+        let dialog = client.dialog(
+            format,
+            format,
+            Params::new("key", "model"),
+            transcription,
+            input,
+            output,
+        );
+
+        let server = async move {
+            time::sleep(Duration::from_secs(1)).await;
+            assert!(matches!(
+                output_receiver.try_recv(),
+                Ok(Output::ServiceStarted { .. })
+            ));
+            assert!(output_receiver.try_recv().is_err());
+
+            connection
+                .read
+                .unbounded_send(Ok(session_created()))
+                .unwrap();
+            time::sleep(Duration::from_secs(1)).await;
+            assert!(matches!(
+                output_receiver.try_recv(),
+                Ok(Output::ServiceReady)
+            ));
+            drop(input_sender);
+        };
+
+        let (result, ()) = tokio::join!(dialog, server);
+        result.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn telephony_audio_is_resampled_from_and_to_the_api_rate() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        let mut connection = connections_receiver.recv().await.unwrap();
+
+        let format = AudioFormat::new(1, 8000);
+        let (input_sender, input_receiver) = tokio_mpsc::channel(4);
+        let (output_sender, mut output_receiver) = tokio_mpsc::unbounded_channel();
+        let (input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+        let dialog = client.dialog(
+            format,
+            format,
+            Params::new("key", "model"),
+            transcription,
+            input,
+            output,
+        );
+
+        let server = async move {
+            // 200ms of caller audio in frames of 20ms.
+            for _ in 0..10 {
+                let frame = AudioFrame {
+                    format,
+                    samples: vec![1000; 160],
+                };
+                input_sender.send(Input::Audio { frame }).await.unwrap();
+            }
+            time::sleep(Duration::from_secs(1)).await;
+            let appended: usize = sent_events(&mut connection.written)
+                .iter()
+                .map(|event| BASE64_STANDARD.decode(event["audio"].as_str().unwrap()))
+                .map(|audio| audio.unwrap().len() / 2)
+                .sum();
+            // All but the last 2ms of audio are sent upsampled.
+            assert!((4700..4800).contains(&appended), "{appended}");
+
+            // 200ms of assistant audio.
+            let samples = audio::to_le_bytes(vec![1000i16; 4800]);
+            let audio_delta = json!({
+                "type": "response.output_audio.delta",
+                "event_id": "event-2",
+                "response_id": "resp-1",
+                "item_id": "item-1",
+                "output_index": 0,
+                "content_index": 0,
+                "delta": BASE64_STANDARD.encode(samples),
+            });
+            connection
+                .read
+                .unbounded_send(Ok(Message::Text(audio_delta.to_string().into())))
+                .unwrap();
+            time::sleep(Duration::from_secs(1)).await;
+            let frames: Vec<_> = iter::from_fn(|| output_receiver.try_recv().ok())
+                .filter_map(|output| match output {
+                    Output::Audio { frame } => Some(frame),
+                    _ => None,
+                })
+                .collect();
+            let [frame] = &frames[..] else {
+                panic!("Expected one audio frame, got {frames:?}");
+            };
+            assert_eq!(frame.format, format);
+            assert!((1550..1600).contains(&frame.samples.len()));
+            // Away from the start, the level is kept.
+            assert!(
+                frame.samples[100..]
+                    .iter()
+                    .all(|&s| (995..=1005).contains(&s))
+            );
+            drop(input_sender);
+        };
+
+        let (result, ()) = tokio::join!(dialog, server);
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn incomplete_response_reports_its_status_and_skips_function_calls() {
+        let (connections, _connections) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, mut output_receiver) = tokio_mpsc::unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+
+        let raw = json!({
+            "type": "response.done",
+            "event_id": "event-2",
+            "response": {
+                "object": "realtime.response",
+                "id": "resp-1",
+                "status": "incomplete",
+                "status_details": { "type": "incomplete", "reason": "max_output_tokens" },
+                "output": [{
+                    "id": "item-1",
+                    "object": "realtime.item",
+                    "type": "function_call",
+                    "status": "completed",
+                    "name": "get_time",
+                    "call_id": "call-1",
+                    "arguments": "{}",
+                }],
+                "conversation_id": "conv-1",
+                "output_modalities": ["audio"],
+                "max_output_tokens": "inf",
+                "metadata": null,
+                "usage": null,
+            },
+        })
+        .to_string();
+        let event = serde_json::from_str(&raw).unwrap();
+        client
+            .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+            .await
+            .unwrap();
+
+        let mut events = Vec::new();
+        let mut completed_turns = Vec::new();
+        while let Ok(output) = output_receiver.try_recv() {
+            match output {
+                Output::ServiceEvent { path, value } => events.push((path, value)),
+                Output::TurnCompleted { response_id } => completed_turns.push(response_id),
+                Output::ServiceStarted { .. } => {}
+                output => panic!("Unexpected output: {output:?}"),
+            }
+        }
+        assert_eq!(completed_turns, [Some("resp-1".to_string())]);
+        assert_eq!(
+            events,
+            [
+                (
+                    OutputPath::Control,
+                    json!({
+                        "type": "responseStatus",
+                        "status": "incomplete",
+                        "reason": "max_output_tokens",
+                    })
+                ),
+                (OutputPath::Media, json!({ "type": "turnComplete" })),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interruption_truncates_the_unheard_assistant_audio() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        let mut connection = connections_receiver.recv().await.unwrap();
+
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, _output_receiver) = tokio_mpsc::unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+
+        // One second of assistant audio.
+        let samples = audio::to_le_bytes(vec![0i16; 24000]);
+        let audio_delta = json!({
+            "type": "response.output_audio.delta",
+            "event_id": "event-2",
+            "response_id": "resp-1",
+            "item_id": "item-1",
+            "output_index": 0,
+            "content_index": 0,
+            "delta": BASE64_STANDARD.encode(samples),
+        });
+        let speech_started = json!({
+            "type": "input_audio_buffer.speech_started",
+            "event_id": "event-3",
+            "audio_start_ms": 300,
+            "item_id": "item-2",
+        });
+
+        let raw = audio_delta.to_string();
+        let event = serde_json::from_str(&raw).unwrap();
+        client
+            .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+            .await
+            .unwrap();
+
+        time::advance(Duration::from_millis(300)).await;
+
+        let raw = speech_started.to_string();
+        let event = serde_json::from_str(&raw).unwrap();
+        client
+            .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sent_events(&mut connection.written),
+            [json!({
+                "type": "conversation.item.truncate",
+                "item_id": "item-1",
+                "content_index": 0,
+                "audio_end_ms": 300,
+            })]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caller_speech_barges_in_while_the_assistant_speaks() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        let mut connection = connections_receiver.recv().await.unwrap();
+        client.barge_in = Some(BargeInState::new(&BargeInParams {
+            threshold: 0.05,
+            min_duration_ms: 100,
+        }));
+
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, mut output_receiver) = tokio_mpsc::unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+
+        let raw = response_event("response.created", "in_progress").to_string();
+        let event = serde_json::from_str(&raw).unwrap();
+        client
+            .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+            .await
+            .unwrap();
+
+        // One second of assistant audio.
+        let samples = audio::to_le_bytes(vec![0i16; 24000]);
+        let raw = json!({
+            "type": "response.output_audio.delta",
+            "event_id": "event-2",
+            "response_id": "resp-1",
+            "item_id": "item-1",
+            "output_index": 0,
+            "content_index": 0,
+            "delta": BASE64_STANDARD.encode(samples),
+        })
+        .to_string();
+        let event = serde_json::from_str(&raw).unwrap();
+        client
+            .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+            .await
+            .unwrap();
+        while output_receiver.try_recv().is_ok() {}
+
+        // 100ms of speech in frames of 20ms.
+        for _ in 0..5 {
+            let frame = AudioFrame {
+                format,
+                samples: vec![8000; 480],
+            };
+            client
+                .process_input(Input::Audio { frame }, &output)
+                .await
+                .unwrap();
+            time::advance(Duration::from_millis(20)).await;
+        }
+
+        assert!(matches!(output_receiver.try_recv(), Ok(Output::ClearAudio)));
+        let events: Vec<_> = sent_event_types(&mut connection.written)
+            .into_iter()
+            .filter(|event| event != "input_audio_buffer.append")
+            .collect();
+        assert_eq!(events, ["conversation.item.truncate", "response.cancel"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caller_speech_after_the_response_is_done_barges_in_without_failing() {
+        let (connections, mut connections_receiver) = tokio_mpsc::unbounded_channel();
+        let mut client = Client::connect(Box::new(MockConnector {
+            connections,
+            session_created: true,
+            session_updated: false,
+        }))
+        .await
+        .unwrap();
+        let mut connection = connections_receiver.recv().await.unwrap();
+        client.barge_in = Some(BargeInState::new(&BargeInParams {
+            threshold: 0.05,
+            min_duration_ms: 100,
+        }));
+
+        let format = AudioFormat::new(1, 24000);
+        let (_input_sender, input_receiver) = tokio_mpsc::channel(1);
+        let (output_sender, mut output_receiver) = tokio_mpsc::unbounded_channel();
+        let (_input, output) = Conversation::new(
+            InputModality::Audio { format },
+            [OutputModality::Audio { format }],
+            input_receiver,
+            output_sender,
+        )
+        .start()
+        .unwrap();
+        let transcription = TranscriptionSettings {
+            input: false,
+            output: false,
+            output_encoding: Default::default(),
+        };
+
+        // The response is done while its second of audio is still played back.
+        let samples = audio::to_le_bytes(vec![0i16; 24000]);
+        let audio_delta = json!({
+            "type": "response.output_audio.delta",
+            "event_id": "event-2",
+            "response_id": "resp-1",
+            "item_id": "item-1",
+            "output_index": 0,
+            "content_index": 0,
+            "delta": BASE64_STANDARD.encode(samples),
+        });
+        for event in [
+            response_event("response.created", "in_progress"),
+            audio_delta,
+            response_event("response.done", "completed"),
+        ] {
+            let raw = event.to_string();
+            let event = serde_json::from_str(&raw).unwrap();
+            client
+                .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+                .await
+                .unwrap();
+        }
+        while output_receiver.try_recv().is_ok() {}
+
+        // 100ms of speech in frames of 20ms.
+        for _ in 0..5 {
+            let frame = AudioFrame {
+                format,
+                samples: vec![8000; 480],
+            };
+            client
+                .process_input(Input::Audio { frame }, &output)
+                .await
+                .unwrap();
+            time::advance(Duration::from_millis(20)).await;
+        }
+
+        assert!(matches!(output_receiver.try_recv(), Ok(Output::ClearAudio)));
+        let events: Vec<_> = sent_event_types(&mut connection.written)
+            .into_iter()
+            .filter(|event| event != "input_audio_buffer.append")
+            .collect();
+        // There is no active response left to cancel.
+        #[cfg(feature = "prompt-delay")]
+        assert_eq!(events, ["conversation.item.truncate"]);
+        #[cfg(not(feature = "prompt-delay"))]
+        assert_eq!(events, ["conversation.item.truncate", "response.cancel"]);
+
+        // A cancellation that crossed the end of the response is rejected by the server.
+        let raw = json!({
+            "type": "error",
+            "event_id": "event-4",
+            "error": {
+                "type": "invalid_request_error",
+                "code": "response_cancel_not_active",
+                "message": "Cancellation failed: no active response found.",
+                "param": null,
+                "event_id": null,
+            },
+        })
+        .to_string();
+        let event = serde_json::from_str(&raw).unwrap();
+        client
+            .handle_realtime_server_event(&raw, event, &output, format, "scope", transcription)
+            .await
+            .unwrap();
+    }
+
+    mod reconnect {
+        use std::io;
+
+        use tokio::sync::mpsc::{channel, unbounded_channel};
+
+        use super::*;
+
+        fn connection_reset() -> tungstenite::Error {
+            tungstenite::Error::Io(io::ErrorKind::ConnectionReset.into())
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn network_errors_reconnect_and_reapply_the_session() {
+            let (connections_sender, mut connections) = unbounded_channel();
+            let mut client = Client::connect(Box::new(MockConnector {
+                connections: connections_sender,
+                session_created: true,
+                session_updated: true,
+            }))
+            .await
+            .unwrap();
+            let mut first = connections.recv().await.unwrap();
+
+            let format = AudioFormat::new(1, 24000);
+            let (input_sender, input_receiver) = channel(1);
+            let (output_sender, _output_receiver) = unbounded_channel();
+            let (input, output) = Conversation::new(
+                InputModality::Audio { format },
+                [OutputModality::Audio { format }],
+                input_receiver,
+                output_sender,
+            )
+            .start()
+            .unwrap();
+
+            let mut params = Params::new("key", "model");
+            params.instructions = Some("Be brief".into());
+            params.max_reconnects = 2;
+            params.initial_backoff_ms = Some(100);
+            let transcription = TranscriptionSettings {
+                input: false,
+                output: false,
+                output_encoding: Default::default(),
+            };
+            let dialog = client.dialog(format, format, params, transcription, input, output);
+
+            let server = async move {
+                time::sleep(Duration::from_secs(1)).await;
+                assert_eq!(sent_event_types(&mut first.written), ["session.update"]);
+
+                let failed = Instant::now();
+                first.read.unbounded_send(Err(connection_reset())).unwrap();
+                let mut second = connections.recv().await.unwrap();
+                assert_eq!(failed.elapsed(), Duration::from_millis(100));
+                time::sleep(Duration::from_secs(1)).await;
+                assert_eq!(sent_event_types(&mut second.written), ["session.update"]);
+                drop(input_sender);
+            };
+
+            let (result, ()) = tokio::join!(dialog, server);
+            result.unwrap();
+        }
+
+        #[test]
+        fn backoff_doubles_until_the_attempts_are_used_up() {
+            let mut reconnects = Reconnects::new(3, Duration::from_millis(100));
+            let backoffs: Vec<_> = iter::from_fn(|| reconnects.next_backoff()).collect();
+            assert_eq!(
+                backoffs,
+                [100, 200, 400].map(Duration::from_millis).to_vec()
+            );
+
+            reconnects.reset();
+            assert_eq!(reconnects.next_backoff(), Some(Duration::from_millis(100)));
+            assert!(!is_recoverable(&tungstenite::Error::ConnectionClosed));
+            assert!(is_recoverable(&connection_reset()));
+        }
+    }
+
+    #[test]
+    fn completely_played_audio_is_not_truncated() {
+        let mut playback = AssistantPlayback::default();
+        playback.received("item-1".into(), 0, Duration::ZERO);
+        assert!(playback.interrupt().is_none());
+        assert!(playback.interrupt().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn commits_are_due_in_the_interval_only_when_audio_was_appended() {
+        let interval = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut commit_timer = CommitTimer::new(interval);
+
+        commit_timer.notify_audio_appended();
+        commit_timer.commit_due().await;
+        assert_eq!(start.elapsed(), interval);
+
+        // Without appended audio, no commit is due.
+        let no_commit = time::timeout(3 * interval, commit_timer.commit_due()).await;
+        assert!(no_commit.is_err());
+
+        commit_timer.notify_audio_appended();
+        commit_timer.commit_due().await;
+        assert_eq!(start.elapsed(), 5 * interval);
+    }
+
+    mod idle_disconnect {
+        use tokio::sync::mpsc::{channel, unbounded_channel};
+
+        use super::*;
+
+        fn frame(amplitude: i16) -> AudioFrame {
+            AudioFrame {
+                format: AudioFormat::new(1, 24000),
+                samples: vec![amplitude; 480],
+            }
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn upstream_is_closed_after_silence_and_reconnected_on_speech() {
+            let (connections_sender, mut connections) = unbounded_channel();
+            let mut client = Client::connect(Box::new(MockConnector {
+                connections: connections_sender,
+                session_created: true,
+                session_updated: true,
+            }))
+            .await
+            .unwrap();
+            let mut first = connections.recv().await.unwrap();
+
+            let format = AudioFormat::new(1, 24000);
+            let (input_sender, input_receiver) = channel(16);
+            let (output_sender, _output_receiver) = unbounded_channel();
+            let (input, output) = Conversation::new(
+                InputModality::Audio { format },
+                [OutputModality::Audio { format }],
+                input_receiver,
+                output_sender,
+            )
+            .start()
+            .unwrap();
+
+            let mut params = Params::new("key", "model");
+            params.instructions = Some("Be brief".into());
+            params.idle_disconnect_ms = Some(10_000);
+            let transcription = TranscriptionSettings {
+                input: false,
+                output: false,
+                output_encoding: Default::default(),
+            };
+            let dialog = client.dialog(format, format, params, transcription, input, output);
+
+            let caller = async move {
+                let speech = || Input::Audio { frame: frame(8000) };
+                input_sender.send(speech()).await.unwrap();
+                time::sleep(Duration::from_secs(1)).await;
+                assert_eq!(
+                    sent_event_types(&mut first.written),
+                    ["session.update", "input_audio_buffer.append"]
+                );
+
+                // The upstream connection is closed after the silence period.
+                assert_eq!(first.written.next().await, None);
+
+                // Silence does not reconnect, but speech does.
+                let silence = Input::Audio { frame: frame(0) };
+                input_sender.send(silence).await.unwrap();
+                time::sleep(Duration::from_secs(1)).await;
+                assert!(connections.try_recv().is_err());
+
+                input_sender.send(speech()).await.unwrap();
+                let mut second = connections.recv().await.unwrap();
+                time::sleep(Duration::from_secs(1)).await;
+                assert_eq!(
+                    sent_event_types(&mut second.written),
+                    ["session.update", "input_audio_buffer.append"]
+                );
+            };
+
+            let (result, ()) = tokio::join!(dialog, caller);
+            result.unwrap();
+        }
+    }
+
+    #[cfg(feature = "prompt-delay")]
+    mod prompt_coordinator {
+        use super::*;
+
+        fn prompt(text: &str) -> PromptRequest {
+            PromptRequest {
+                text: text.into(),
+                overrides: Default::default(),
+            }
+        }
+
+        #[tokio::test]
+        async fn cancelled_prompts_create_no_further_responses() {
+            let (sender, mut receiver) = mpsc::unbounded();
+            let mut write = sender.sink_map_err(|_| tungstenite::Error::ConnectionClosed);
+            let mut coordinator = PromptCoordinator::new();
+
+            for text in ["first", "second", "third"] {
+                coordinator
+                    .push_prompt(&mut write, prompt(text))
+                    .await
+                    .unwrap();
+            }
+            coordinator
+                .update_response_state(&mut write, ResponseState::Responding)
+                .await
+                .unwrap();
+            assert_eq!(coordinator.pending_prompts.len(), 2);
+
+            coordinator.cancel_prompts(&mut write).await.unwrap();
+            assert!(coordinator.pending_prompts.is_empty());
+
+            coordinator
+                .update_response_state(&mut write, ResponseState::Idle)
+                .await
+                .unwrap();
+            assert!(coordinator.inflight_prompt.is_none());
+            assert_eq!(
+                sent_event_types(&mut receiver),
+                ["response.create", "response.cancel"]
+            );
+        }
+
+        #[tokio::test]
+        async fn replaced_prompt_is_sent_after_the_cancelled_response() {
+            let (sender, mut receiver) = mpsc::unbounded();
+            let mut write = sender.sink_map_err(|_| tungstenite::Error::ConnectionClosed);
+            let mut coordinator = PromptCoordinator::new();
+
+            coordinator
+                .push_prompt(&mut write, prompt("first"))
+                .await
+                .unwrap();
+            coordinator
+                .push_prompt(&mut write, prompt("second"))
+                .await
+                .unwrap();
+
+            // The response to the first prompt was not created yet.
+            coordinator.cancel_prompts(&mut write).await.unwrap();
+            coordinator
+                .push_prompt(&mut write, prompt("replacement"))
+                .await
+                .unwrap();
+            assert_eq!(sent_event_types(&mut receiver), ["response.create"]);
+
+            coordinator
+                .update_response_state(&mut write, ResponseState::Responding)
+                .await
+                .unwrap();
+            assert_eq!(sent_event_types(&mut receiver), ["response.cancel"]);
+
+            coordinator
+                .update_response_state(&mut write, ResponseState::Idle)
+                .await
+                .unwrap();
+            let (_, request) = coordinator.inflight_prompt.as_ref().unwrap();
+            assert_eq!(request.text, "replacement");
+            assert_eq!(sent_event_types(&mut receiver), ["response.create"]);
+        }
+
+        #[tokio::test]
+        async fn queued_prompts_keep_their_voice_override() {
+            let (sender, mut receiver) = mpsc::unbounded();
+            let mut write = sender.sink_map_err(|_| tungstenite::Error::ConnectionClosed);
+            let mut coordinator = PromptCoordinator::new();
+
+            let with_voice = |text: &str, voice: &str| PromptRequest {
+                text: text.into(),
+                overrides: ResponseOverrides {
+                    voice: Some(serde_json::from_value(json!(voice)).unwrap()),
+                    ..Default::default()
+                },
+            };
+
+            for request in [
+                with_voice("first", "alloy"),
+                with_voice("second", "echo"),
+                prompt("third"),
+            ] {
+                coordinator.push_prompt(&mut write, request).await.unwrap();
+            }
+
+            let mut voices = Vec::new();
+            for _ in 0..3 {
+                let [event] = sent_events(&mut receiver).try_into().unwrap();
+                assert_eq!(event["type"], "response.create");
+                voices.push(event["response"]["audio"]["output"]["voice"].clone());
+
+                coordinator
+                    .update_response_state(&mut write, ResponseState::Responding)
+                    .await
+                    .unwrap();
+                coordinator
+                    .update_response_state(&mut write, ResponseState::Idle)
+                    .await
+                    .unwrap();
+            }
+
+            // The voice is part of each prompt's response, so it does not leak into the next one.
+            assert_eq!(voices, [json!("alloy"), json!("echo"), Value::Null]);
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use std::fmt;
+use std::time::Duration;
 
 use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
 use openai_api_rs::realtime::api::{RealtimeClient, RealtimeProtocol};
+use tokio::time::timeout;
 use url::Url;
 
-use crate::client::Client;
+use crate::client::Transport;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -22,8 +25,12 @@ impl Protocol {
     }
 }
 
+/// How long establishing the websocket connection may take before the conversation fails.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Host {
     client: RealtimeClient,
+    connect_timeout: Duration,
 }
 
 impl fmt::Debug for Host {
@@ -31,6 +38,7 @@ impl fmt::Debug for Host {
         f.debug_struct("Host")
             .field("wss_url", &self.client.wss_url)
             .field("model", &self.client.model)
+            .field("connect_timeout", &self.connect_timeout)
             .finish()
     }
 }
@@ -44,6 +52,7 @@ impl Host {
                 model.into(),
                 protocol.to_realtime_protocol(),
             ),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
         }
     }
 
@@ -55,17 +64,37 @@ impl Host {
                 model.into(),
                 protocol.to_realtime_protocol(),
             ),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        Self {
+            connect_timeout,
+            ..self
         }
     }
+}
+
+/// Establishes websocket connections to the realtime API. Used to reconnect after the upstream
+/// connection was closed because of inactivity.
+#[async_trait]
+pub trait Connect: fmt::Debug + Send + Sync {
+    async fn connect(&self) -> Result<Transport>;
+}
 
-    pub async fn connect(&self) -> Result<Client> {
-        let (write, read) = self
-            .client
-            .connect()
+#[async_trait]
+impl Connect for Host {
+    async fn connect(&self) -> Result<Transport> {
+        let (write, read) = timeout(self.connect_timeout, self.client.connect())
             .await
+            .map_err(|_| anyhow!("Connection to {} timed out", self.client.wss_url))?
             .map_err(|e| anyhow!(e.to_string()))?;
 
-        Ok(Client::new(read, write))
+        Ok(Transport {
+            read: Box::pin(read),
+            write: Box::pin(write),
+        })
     }
 }
 
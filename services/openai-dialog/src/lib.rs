@@ -6,17 +6,22 @@ use anyhow::{Result, bail};
 use async_trait::async_trait;
 use tracing::{info, warn};
 
-use context_switch_core::{Conversation, Service};
+use context_switch_core::{Conversation, InputModality, OutputModalities, Service};
 
+#[cfg(feature = "cassette")]
+mod cassette;
 mod client;
 mod host;
+mod tool_set;
 mod transcription_state;
 mod types;
 
-pub use client::Client;
-pub use host::{Host, Protocol};
+#[cfg(feature = "cassette")]
+pub use cassette::{Cassette, Player, Recorder};
+pub use client::{Client, Transport};
+pub use host::{Connect, Host, Protocol};
 use transcription_state::TranscriptionSettings;
-pub use types::{Params, ServiceInputEvent, ServiceOutputEvent};
+pub use types::{Params, ResponseOverrides, ServiceInputEvent, ServiceOutputEvent};
 
 use host::resolve_protocol;
 
@@ -27,6 +32,17 @@ pub struct OpenAIDialog;
 impl Service for OpenAIDialog {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_audio_input()?;
+        output_modalities.require_one_audio_output()?;
+        output_modalities.has_one_text_output()?;
+        Ok(())
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         // Only support audio input and output for now
         let input_format = conversation.require_audio_input()?;
@@ -35,6 +51,7 @@ impl Service for OpenAIDialog {
         let has_text_output = conversation.has_one_text_output()?;
         let output_transcription = params.output_audio_transcription && has_text_output;
         let input_transcription = params.input_audio_transcription && has_text_output;
+        let output_encoding = params.text_encoding;
         if !has_text_output
             && (params.input_audio_transcription || params.output_audio_transcription)
         {
@@ -56,7 +73,7 @@ impl Service for OpenAIDialog {
             Host::new(&params.api_key, &params.model, protocol)
         };
         info!("Connecting to {host:?}");
-        let mut client = host.connect().await?;
+        let mut client = Client::connect(Box::new(host)).await?;
 
         info!("Client connected");
 
@@ -70,6 +87,7 @@ impl Service for OpenAIDialog {
                 TranscriptionSettings {
                     input: input_transcription,
                     output: output_transcription,
+                    output_encoding,
                 },
                 input,
                 output,
@@ -0,0 +1,90 @@
+use openai_api_rs::realtime::types::ToolDefinition;
+
+/// The tools of the session.
+///
+/// The API replaces the tools of a session wholesale, so changes to single tools are applied here
+/// and the resulting set is sent.
+#[derive(Debug, Default)]
+pub struct ToolSet {
+    tools: Vec<ToolDefinition>,
+}
+
+impl ToolSet {
+    pub fn new(tools: Vec<ToolDefinition>) -> Self {
+        Self { tools }
+    }
+
+    pub fn tools(&self) -> &[ToolDefinition] {
+        &self.tools
+    }
+
+    pub fn replace(&mut self, tools: Vec<ToolDefinition>) {
+        self.tools = tools;
+    }
+
+    /// Adds the tool or replaces the tool with the same name.
+    pub fn add(&mut self, tool: ToolDefinition) {
+        match self
+            .tools
+            .iter_mut()
+            .find(|existing| tool_name(existing) == tool_name(&tool))
+        {
+            Some(existing) => *existing = tool,
+            None => self.tools.push(tool),
+        }
+    }
+
+    /// Returns `false` if there is no tool with this name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.tools.len();
+        self.tools.retain(|tool| tool_name(tool) != name);
+        self.tools.len() != len
+    }
+}
+
+pub fn tool_name(tool: &ToolDefinition) -> &str {
+    match tool {
+        ToolDefinition::Function { name, .. } => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn function(name: &str, description: &str) -> ToolDefinition {
+        ToolDefinition::Function {
+            name: name.into(),
+            description: description.into(),
+            parameters: json!({ "type": "object", "properties": {} }),
+        }
+    }
+
+    fn descriptions(tool_set: &ToolSet) -> Vec<String> {
+        tool_set
+            .tools()
+            .iter()
+            .map(|tool| match tool {
+                ToolDefinition::Function { description, .. } => description.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adding_a_tool_with_an_existing_name_replaces_it() {
+        let mut tool_set = ToolSet::new(vec![function("a", "a1"), function("b", "b1")]);
+        tool_set.add(function("a", "a2"));
+        tool_set.add(function("c", "c1"));
+        assert_eq!(descriptions(&tool_set), ["a2", "b1", "c1"]);
+    }
+
+    #[test]
+    fn removing_an_unknown_tool_is_reported() {
+        let mut tool_set = ToolSet::new(vec![function("a", "a1")]);
+        assert!(!tool_set.remove("b"));
+        assert!(tool_set.remove("a"));
+        assert!(tool_set.tools().is_empty());
+    }
+}
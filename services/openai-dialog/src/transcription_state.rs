@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 use tracing::warn;
 
+use context_switch_core::TextEncoding;
+
 #[derive(Debug, Clone, Copy)]
 pub struct TranscriptionSettings {
     pub input: bool,
     pub output: bool,
+    pub output_encoding: TextEncoding,
 }
 
 #[derive(Debug, Default)]
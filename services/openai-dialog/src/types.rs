@@ -1,6 +1,8 @@
-use openai_api_rs::realtime::types::{self, RealtimeVoice, ToolChoice};
+use openai_api_rs::realtime::types::{self, OutputModality, RealtimeVoice, ToolChoice};
 use serde::{Deserialize, Serialize};
 
+use context_switch_core::{TextEncoding, barge_in::BargeInParams};
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Params {
@@ -18,6 +20,30 @@ pub struct Params {
     #[serde(default)]
     pub tools: Vec<types::ToolDefinition>,
     pub(crate) tool_choice: Option<ToolChoice>,
+    /// If set, server VAD is disabled and the input audio buffer is committed in this interval
+    /// instead, as long as audio was appended since the last commit.
+    pub commit_interval_ms: Option<u64>,
+    /// If set, the upstream connection is closed after the caller was silent and no server events
+    /// were received for this duration. It is re-established with the same session configuration
+    /// as soon as the caller speaks again or a service event is sent.
+    pub idle_disconnect_ms: Option<u64>,
+    /// If set, the assistant's audio is interrupted when the energy of the caller's audio rises
+    /// above the threshold. Useful when server VAD is disabled with `commit_interval_ms`.
+    pub barge_in: Option<BargeInParams>,
+    /// How often in a row the upstream connection is re-established after a network error before
+    /// the conversation fails.
+    #[serde(default)]
+    pub max_reconnects: u32,
+    /// The delay before the first reconnect, doubled for each following one. Defaults to 250ms.
+    pub initial_backoff_ms: Option<u64>,
+    /// How long to wait for the server to create the session after connecting. Defaults to 15s.
+    pub session_created_timeout_ms: Option<u64>,
+    /// Send a `sessionCreated` event with the session configuration the server applied.
+    #[serde(default)]
+    pub report_session_created: bool,
+    /// How the transcript of the assistant's audio is output.
+    #[serde(default)]
+    pub text_encoding: TextEncoding,
 }
 
 impl Params {
@@ -33,6 +59,14 @@ impl Params {
             output_audio_transcription: false,
             tools: vec![],
             tool_choice: None,
+            commit_interval_ms: None,
+            idle_disconnect_ms: None,
+            barge_in: None,
+            max_reconnects: 0,
+            initial_backoff_ms: None,
+            session_created_timeout_ms: None,
+            report_session_created: false,
+            text_encoding: TextEncoding::Raw,
         }
     }
 }
@@ -50,6 +84,17 @@ pub enum ServiceInputEvent {
     },
     Prompt {
         text: String,
+        /// Optional overrides for the response that is created for this prompt.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        response: Option<ResponseOverrides>,
+    },
+    /// Drop all pending prompts and cancel the response to the prompt in flight.
+    CancelPrompts,
+    /// Cancel all prompts like `CancelPrompts` and queue a new one.
+    ReplacePrompt {
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        response: Option<ResponseOverrides>,
     },
     SessionUpdate {
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -61,6 +106,30 @@ pub enum ServiceInputEvent {
         #[serde(skip_serializing_if = "Option::is_none")]
         tool_choice: Option<ToolChoice>,
     },
+    /// Add a tool to the session, or replace the session's tool with the same name.
+    AddTool { tool: types::ToolDefinition },
+    /// Remove the tool with this name from the session.
+    RemoveTool { name: String },
+}
+
+/// Response-level overrides that are placed into the `response` of the `response.create` event
+/// sent for a prompt.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseOverrides {
+    /// Maximum number of output tokens for this response, including tool calls.
+    #[serde(
+        alias = "maxResponseOutputTokens",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_output_tokens: Option<u32>,
+    /// Output modalities of this response, for example `["text"]` to suppress audio output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_modalities: Option<Vec<OutputModality>>,
+    /// The voice of this response's audio output. It applies to this response only, the session's
+    /// voice is left unchanged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voice: Option<RealtimeVoice>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -78,9 +147,27 @@ pub enum ServiceOutputEvent {
         /// optional according to the JSON schema.
         arguments: Option<serde_json::Value>,
     },
+    /// The session as created by the server, sent if `reportSessionCreated` is set.
+    SessionCreated {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output_modalities: Option<Vec<OutputModality>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        voice: Option<RealtimeVoice>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        input_format: Option<types::AudioFormat>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output_format: Option<types::AudioFormat>,
+    },
     SessionUpdated {
         #[serde(skip_serializing_if = "Option::is_none")]
         tools: Option<Vec<types::ToolDefinition>>,
     },
+    /// A response ended without completing, for example because it was cancelled or exceeded the
+    /// maximum output tokens.
+    ResponseStatus {
+        status: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
     TurnComplete,
 }
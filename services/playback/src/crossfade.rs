@@ -0,0 +1,141 @@
+//! Joins the audio of consecutive playback items, optionally by crossfading them.
+//!
+//! The end of each item is held back until the next item starts, so that it can be overlapped with
+//! the next item's beginning. Without a crossfade duration, frames are passed through unchanged.
+use std::{mem, time::Duration};
+
+use context_switch_core::{AudioFormat, AudioFrame};
+
+#[derive(Debug)]
+pub struct Crossfader {
+    format: AudioFormat,
+    /// Number of samples that are overlapped between two items.
+    overlap: usize,
+    /// The end of the previous item that is faded out into the current item's beginning.
+    fade_out: Vec<i16>,
+    /// The number of `fade_out` samples already mixed into the current item.
+    faded: usize,
+    /// The end of the current item, held back for the next item.
+    held: Vec<i16>,
+}
+
+impl Crossfader {
+    pub fn new(format: AudioFormat, crossfade: Duration) -> Self {
+        let overlap = (format.sample_rate as f64 * crossfade.as_secs_f64()).round() as usize
+            * format.channels as usize;
+        Self {
+            format,
+            overlap,
+            fade_out: Vec::new(),
+            faded: 0,
+            held: Vec::new(),
+        }
+    }
+
+    /// Starts the next item. The held back end of the current item is faded out into the next
+    /// one's beginning.
+    ///
+    /// If the current item was too short to take the complete fade out of its predecessor, the
+    /// rest is returned unmixed and the next item starts without a crossfade.
+    pub fn start_item(&mut self) -> Option<AudioFrame> {
+        if self.faded < self.fade_out.len() {
+            let mut samples = mem::take(&mut self.held);
+            samples.extend_from_slice(&self.fade_out[self.faded..]);
+            self.fade_out.clear();
+            self.faded = 0;
+            return self.frame(samples);
+        }
+        self.fade_out = mem::take(&mut self.held);
+        self.faded = 0;
+        None
+    }
+
+    /// Pushes a frame of the current item and returns the audio that is ready to be played.
+    pub fn push(&mut self, frame: AudioFrame) -> Option<AudioFrame> {
+        let mut samples = frame.samples;
+        let fade_len = self.fade_out.len();
+        for sample in samples.iter_mut().take(fade_len - self.faded) {
+            // Linear gains that sum up to one.
+            let gain = (self.faded + 1) as f32 / (fade_len + 1) as f32;
+            let mixed = self.fade_out[self.faded] as f32 * (1.0 - gain) + *sample as f32 * gain;
+            *sample = mixed as i16;
+            self.faded += 1;
+        }
+
+        self.held.extend(samples);
+        let ready = self.held.len().saturating_sub(self.overlap);
+        let samples = self.held.drain(..ready).collect();
+        self.frame(samples)
+    }
+
+    /// Returns all the audio that is held back after the last item.
+    pub fn finish(mut self) -> Option<AudioFrame> {
+        let mut samples = mem::take(&mut self.held);
+        samples.extend_from_slice(&self.fade_out[self.faded..]);
+        self.frame(samples)
+    }
+
+    fn frame(&self, samples: Vec<i16>) -> Option<AudioFrame> {
+        if samples.is_empty() {
+            return None;
+        }
+        Some(AudioFrame {
+            format: self.format,
+            samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: AudioFormat = AudioFormat {
+        channels: 1,
+        sample_rate: 1000,
+    };
+
+    fn frame(samples: &[i16]) -> AudioFrame {
+        AudioFrame {
+            format: FORMAT,
+            samples: samples.to_vec(),
+        }
+    }
+
+    /// Plays the items in sequence and returns all samples.
+    fn play(mut crossfader: Crossfader, items: &[&[i16]]) -> Vec<i16> {
+        let mut output = Vec::new();
+        for item in items {
+            output.extend(crossfader.start_item().into_iter().flat_map(|f| f.samples));
+            output.extend(
+                crossfader
+                    .push(frame(item))
+                    .into_iter()
+                    .flat_map(|f| f.samples),
+            );
+        }
+        output.extend(crossfader.finish().into_iter().flat_map(|f| f.samples));
+        output
+    }
+
+    #[test]
+    fn items_are_concatenated_without_crossfade() {
+        let crossfader = Crossfader::new(FORMAT, Duration::ZERO);
+        assert_eq!(play(crossfader, &[&[1, 2], &[3, 4, 5]]), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn items_are_overlapped_by_the_crossfade_duration() {
+        // 3 samples at 1kHz.
+        let crossfader = Crossfader::new(FORMAT, Duration::from_millis(3));
+        let output = play(crossfader, &[&[400; 5], &[0; 5]]);
+        assert_eq!(output, [400, 400, 300, 200, 100, 0, 0]);
+    }
+
+    #[test]
+    fn item_shorter_than_the_crossfade_is_not_overlapped_further() {
+        let crossfader = Crossfader::new(FORMAT, Duration::from_millis(3));
+        let output = play(crossfader, &[&[400; 4], &[0], &[7; 2]]);
+        assert_eq!(output, [400, 300, 400, 400, 7, 7]);
+    }
+}
@@ -1,46 +1,153 @@
 use std::fs::{self, File};
 use std::io::{self, BufReader};
-use std::num::{NonZeroU16, NonZeroU32};
+use std::iter;
+use std::num::NonZeroU16;
 use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::Duration;
+use std::{error, fmt};
 
 use anyhow::{Context, Result, anyhow, bail};
 use async_trait::async_trait;
-use rodio::conversions::{ChannelCountConverter, SampleRateConverter};
 use rodio::{Decoder, Source};
 use serde::{Deserialize, Serialize};
 use tokio::task;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use url::Url;
 
 use context_switch_core::{
-    AudioFormat, AudioFrame, BillingRecord, BillingSchedule, Conversation, Input, Service, audio,
+    AudioFormat, AudioFrame, BillingRecord, BillingSchedule, Conversation, ConversationOutput,
+    Input, InputModality, OutputModalities, OutputPath, RequestFailed, RequestId, RetryParams,
+    Service, audio,
+    text::{DEFAULT_MAX_SYNTHESIZE_TEXT_LEN, require_max_len},
 };
 
+mod crossfade;
 mod stream_reader;
+mod verification;
+use crossfade::Crossfader;
 use stream_reader::StreamReader;
+use verification::ServiceOutputEvent;
+pub use verification::VerifyParams;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Params {
     pub synthesizer_service: String,
     pub synthesizer_params: serde_json::Value,
+    /// QA only: Transcribe the synthesized audio and send a service event that compares the
+    /// requested to the recognized text.
+    #[serde(default)]
+    pub verify: Option<VerifyParams>,
+    /// If set, consecutive audio files of one request overlap by this duration and are
+    /// crossfaded.
+    #[serde(default)]
+    pub crossfade_ms: Option<u64>,
+    /// The maximum number of characters of a text to synthesize. Longer texts are rejected.
+    /// Defaults to [`DEFAULT_MAX_SYNTHESIZE_TEXT_LEN`].
+    #[serde(default)]
+    pub max_text_len: Option<usize>,
+    /// If set, failing synthesize requests are retried as long as they did not produce audio
+    /// yet. Not applied in verify mode.
+    #[serde(default)]
+    pub synthesizer_retry: Option<RetryParams>,
+    /// Dither audio files when they are converted to 16 bits. Recommended for files with a higher
+    /// resolution, like 24 bit or 32 bit float.
+    #[serde(default)]
+    pub dither: bool,
 }
 
 #[derive(Debug)]
 pub struct Playback {
-    /// The local path root for local audio playback. If it's not set, local playback leads to an
-    /// error.
-    pub local_files: Option<PathBuf>,
+    /// The canonicalized local path root for local audio playback. If it's not set, local playback
+    /// leads to an error.
+    local_files: Option<PathBuf>,
+    /// The lowercase file extensions local audio files are allowed to have. If it's not set, all
+    /// supported audio types are allowed.
+    local_file_extensions: Option<Vec<String>>,
 }
 
+impl Playback {
+    /// Creates the playback service. The local root path is canonicalized here once, so that
+    /// resolved file paths can be compared against it.
+    pub fn new(local_files: Option<PathBuf>) -> Result<Self> {
+        let local_files = local_files
+            .map(|path| {
+                fs::canonicalize(&path)
+                    .with_context(|| format!("Failed to resolve local files path `{path:?}`"))
+            })
+            .transpose()?;
+        Ok(Self {
+            local_files,
+            local_file_extensions: None,
+        })
+    }
+
+    /// Restricts local playback to files with the given extensions. Extensions are matched
+    /// case-insensitively and may be specified with or without a leading dot.
+    pub fn with_local_file_extensions(
+        mut self,
+        extensions: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Self {
+        self.local_file_extensions = Some(
+            extensions
+                .into_iter()
+                .map(|extension| {
+                    let extension = extension.as_ref().trim();
+                    extension
+                        .strip_prefix('.')
+                        .unwrap_or(extension)
+                        .to_lowercase()
+                })
+                .filter(|extension| !extension.is_empty())
+                .collect(),
+        );
+        self
+    }
+}
+
+/// The error when a local audio file is requested, but no local root path is configured.
+///
+/// The message starts with a stable code, so that clients can recognize this server
+/// misconfiguration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalFilesNotConfigured;
+
+impl fmt::Display for LocalFilesNotConfigured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "local_files_not_configured: Can't play back a local audio file, local file playback is not configured on the server (audio-knife: set `AUDIO_KNIFE_LOCAL_FILES`)"
+        )
+    }
+}
+
+impl error::Error for LocalFilesNotConfigured {}
+
 #[async_trait]
 impl Service for Playback {
     type Params = Params;
 
+    fn check_modalities(
+        &self,
+        input_modality: InputModality,
+        output_modalities: &OutputModalities,
+    ) -> Result<()> {
+        input_modality.require_text_input_only()?;
+        output_modalities.require_single_audio_output()?;
+        Ok(())
+    }
+
     async fn conversation(&self, params: Params, conversation: Conversation) -> Result<()> {
         conversation.require_text_input_only()?;
         let output_format = conversation.require_single_audio_output()?;
 
+        let crossfade = Duration::from_millis(params.crossfade_ms.unwrap_or_default());
+        let dither = params.dither;
+        let max_text_len = params
+            .max_text_len
+            .unwrap_or(DEFAULT_MAX_SYNTHESIZE_TEXT_LEN);
+
         let (mut input, output) = conversation.start()?;
 
         loop {
@@ -55,92 +162,176 @@ impl Service for Playback {
                     text_type,
                     billing_scope,
                 } => {
-                    let text_type = text_type.as_deref().unwrap_or("text/plain");
-                    let method = PlaybackMethod::from_text_and_mime_type(
-                        text,
-                        text_type,
-                        self.local_files.as_deref(),
-                    )?;
-                    match method {
-                        PlaybackMethod::Synthesize { text, text_type } => {
-                            input
-                                .converse(
-                                    &output,
-                                    &params.synthesizer_service,
-                                    params.synthesizer_params.clone(),
-                                    Input::Text {
+                    let failed = RequestFailed(request_id.clone());
+                    async {
+                        let text_type = text_type.as_deref().unwrap_or("text/plain");
+                        let method = PlaybackMethod::from_text_and_mime_type(
+                            text,
+                            text_type,
+                            self.local_files.as_deref(),
+                            self.local_file_extensions.as_deref(),
+                        )?;
+                        match method {
+                            PlaybackMethod::Synthesize { text, text_type } => {
+                                // Providers may not complete requests without any text.
+                                if text.trim().is_empty() {
+                                    debug!("Text is empty, nothing to synthesize");
+                                    output.request_completed(request_id)?;
+                                    return Ok(());
+                                }
+                                require_max_len(&text, max_text_len)?;
+                                let request = Input::Text {
+                                    request_id: request_id.clone(),
+                                    text: text.clone(),
+                                    text_type: Some(text_type),
+                                    billing_scope: None,
+                                };
+                                let Some(verify) = &params.verify else {
+                                    match &params.synthesizer_retry {
+                                        Some(retry) => {
+                                            input
+                                                .converse_with_retry(
+                                                    &output,
+                                                    &params.synthesizer_service,
+                                                    params.synthesizer_params.clone(),
+                                                    request,
+                                                    retry,
+                                                )
+                                                .await?
+                                        }
+                                        None => {
+                                            input
+                                                .converse(
+                                                    &output,
+                                                    &params.synthesizer_service,
+                                                    params.synthesizer_params.clone(),
+                                                    request,
+                                                )
+                                                .await?
+                                        }
+                                    }
+                                    return Ok(());
+                                };
+
+                                let frames = input
+                                    .converse_capturing_audio(
+                                        &output,
+                                        &params.synthesizer_service,
+                                        params.synthesizer_params.clone(),
+                                        request,
+                                    )
+                                    .await?;
+                                let recognized_text = input
+                                    .transcribe(
+                                        &output,
+                                        &verify.transcriber_service,
+                                        verify.transcriber_params.clone(),
+                                        frames,
+                                    )
+                                    .await?
+                                    .join(" ");
+                                output.service_event(
+                                    OutputPath::Media,
+                                    ServiceOutputEvent::verification(
                                         request_id,
                                         text,
-                                        text_type: Some(text_type),
-                                        billing_scope: None,
-                                    },
-                                )
-                                .await?;
-                        }
-                        PlaybackMethod::File(path) => {
-                            let frames = task::spawn_blocking(move || {
-                                audio_file_to_frames(&path, output_format)
-                            })
-                            .await??;
-
-                            for frame in frames {
-                                let duration = frame.duration();
-                                output.audio_frame(frame)?;
-                                output.billing_records(
-                                    request_id.clone(),
-                                    None,
-                                    [BillingRecord::duration("playback:file", duration)],
-                                    BillingSchedule::Media,
+                                        recognized_text,
+                                    ),
                                 )?;
                             }
-                            output.request_completed(request_id)?;
-                        }
-                        PlaybackMethod::Remote(url) => {
-                            let response = reqwest::get(url.clone()).await?;
-                            let status = response.status();
-                            if !status.is_success() {
-                                bail!("Download of `{url}` failed with status {status}");
+                            PlaybackMethod::Files(files) => {
+                                let mut crossfader = Crossfader::new(output_format, crossfade);
+                                for file in files {
+                                    let frames = task::spawn_blocking(move || {
+                                        read_to_frames(BufReader::new(file), output_format, dither)
+                                    })
+                                    .await??;
+
+                                    let flushed = crossfader.start_item();
+                                    for frame in flushed.into_iter().chain(
+                                        frames
+                                            .into_iter()
+                                            .filter_map(|frame| crossfader.push(frame)),
+                                    ) {
+                                        output_frame(
+                                            &output,
+                                            &request_id,
+                                            None,
+                                            "playback:file",
+                                            frame,
+                                        )?;
+                                    }
+                                }
+                                if let Some(frame) = crossfader.finish() {
+                                    output_frame(
+                                        &output,
+                                        &request_id,
+                                        None,
+                                        "playback:file",
+                                        frame,
+                                    )?;
+                                }
+                                output.request_completed(request_id)?;
                             }
+                            PlaybackMethod::Remote(urls) => {
+                                let mut crossfader = Crossfader::new(output_format, crossfade);
+                                for url in urls {
+                                    let stream_reader = download(&url).await?;
 
-                            let mime_type = response
-                                .headers()
-                                .get(reqwest::header::CONTENT_TYPE)
-                                .and_then(|v| v.to_str().ok());
-
-                            check_supported_audio_type(url.path(), mime_type)?;
-
-                            // Create a streaming reader that implements Read + Seek
-                            let byte_stream = response.bytes_stream();
-                            let stream_reader = StreamReader::new(byte_stream);
-
-                            // Create a clone of output for use in the closure
-                            let output = output.clone();
-
-                            // Process frames directly as they're read
-                            task::spawn_blocking(move || -> Result<()> {
-                                read_with_frame_callback(
-                                    stream_reader,
-                                    output_format,
-                                    |frame| -> Result<()> {
-                                        let duration = frame.duration();
-                                        // Send the frame directly to output
-                                        output.audio_frame(frame)?;
-
-                                        // Write billing records and complete the request inside the spawn_blocking
-                                        output.billing_records(
-                                            request_id.clone(),
-                                            billing_scope.clone(),
-                                            [BillingRecord::duration("playback:remote", duration)],
-                                            BillingSchedule::Media,
-                                        )
-                                    },
-                                )?;
+                                    // Create clones for use in the closure
+                                    let output = output.clone();
+                                    let request_id = request_id.clone();
+                                    let billing_scope = billing_scope.clone();
 
-                                output.request_completed(request_id)
-                            })
-                            .await??;
+                                    // Process frames directly as they're read
+                                    crossfader =
+                                        task::spawn_blocking(move || -> Result<Crossfader> {
+                                            if let Some(frame) = crossfader.start_item() {
+                                                output_frame(
+                                                    &output,
+                                                    &request_id,
+                                                    billing_scope.clone(),
+                                                    "playback:remote",
+                                                    frame,
+                                                )?;
+                                            }
+                                            read_with_frame_callback(
+                                                stream_reader,
+                                                output_format,
+                                                dither,
+                                                |frame| -> Result<()> {
+                                                    let Some(frame) = crossfader.push(frame) else {
+                                                        return Ok(());
+                                                    };
+                                                    output_frame(
+                                                        &output,
+                                                        &request_id,
+                                                        billing_scope.clone(),
+                                                        "playback:remote",
+                                                        frame,
+                                                    )
+                                                },
+                                            )?;
+                                            Ok(crossfader)
+                                        })
+                                        .await??;
+                                }
+                                if let Some(frame) = crossfader.finish() {
+                                    output_frame(
+                                        &output,
+                                        &request_id,
+                                        billing_scope,
+                                        "playback:remote",
+                                        frame,
+                                    )?;
+                                }
+                                output.request_completed(request_id)?;
+                            }
                         }
+                        Result::<()>::Ok(())
                     }
+                    .await
+                    .context(failed)?;
                 }
                 Input::Audio { .. } => {
                     bail!("Audio input is not supported");
@@ -153,6 +344,43 @@ impl Service for Playback {
     }
 }
 
+/// Sends a frame of played back audio and bills its duration.
+fn output_frame(
+    output: &ConversationOutput,
+    request_id: &Option<RequestId>,
+    billing_scope: Option<String>,
+    record_name: &str,
+    frame: AudioFrame,
+) -> Result<()> {
+    let duration = frame.duration();
+    output.audio_frame(frame)?;
+    output.billing_records(
+        request_id.clone(),
+        billing_scope,
+        [BillingRecord::duration(record_name, duration)],
+        BillingSchedule::Media,
+    )
+}
+
+/// Starts the download of a remote audio file and returns a reader that streams its content.
+async fn download(url: &Url) -> Result<StreamReader> {
+    let response = reqwest::get(url.clone()).await?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Download of `{url}` failed with status {status}");
+    }
+
+    let mime_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+
+    check_supported_audio_type(url.path(), mime_type)?;
+
+    // Create a streaming reader that implements Read + Seek
+    Ok(StreamReader::new(response.bytes_stream()))
+}
+
 /// Render the file into 100ms audio frames mono.
 pub fn audio_file_to_frames(path: &Path, format: AudioFormat) -> Result<Vec<AudioFrame>> {
     check_supported_audio_type(&path.to_string_lossy(), None)?;
@@ -166,16 +394,22 @@ pub fn audio_file_to_frames(path: &Path, format: AudioFormat) -> Result<Vec<Audi
         error!("Failed to open audio file: `{path:?}`: {e:?}");
     })?;
     let buf_reader = BufReader::new(file);
-    read_to_frames(buf_reader, format)
+    read_to_frames(buf_reader, format, false)
 }
 
+/// Decodes audio into 100ms frames. Integer sources of up to 32 bits and float sources are
+/// supported.
+///
+/// If `dither` is set, the decoded samples are dithered when they are converted to 16 bits, see
+/// [`audio::to_i16_dithered`].
 pub fn read_to_frames(
     reader: impl io::Read + io::Seek + Send + Sync + 'static,
     format: AudioFormat,
+    dither: bool,
 ) -> Result<Vec<AudioFrame>> {
     let mut output_frames = Vec::new();
 
-    read_with_frame_callback(reader, format, |frame| {
+    read_with_frame_callback(reader, format, dither, |frame| {
         output_frames.push(frame);
         Ok(())
     })?;
@@ -186,6 +420,7 @@ pub fn read_to_frames(
 pub fn read_with_frame_callback<F>(
     reader: impl io::Read + io::Seek + Send + Sync + 'static,
     format: AudioFormat,
+    dither: bool,
     mut callback: F,
 ) -> Result<()>
 where
@@ -197,39 +432,45 @@ where
     if target_channels.get() != 1 {
         bail!("Only mono output is supported");
     }
-    let Some(target_sample_rate) = NonZeroU32::new(format.sample_rate) else {
+    if format.sample_rate == 0 {
         bail!("Output sample rate must be greater than zero");
-    };
+    }
 
     let source = Decoder::new(reader)?;
     let source_sample_rate = source.sample_rate();
     let source_channels = source.channels();
 
-    // Correctness: This does not seem to actually mix the channels it just extracts one channel.
-    let converter = ChannelCountConverter::new(source, source_channels, target_channels);
-
-    // Create the appropriate source based on whether we need resampling
-    let mut source_iterator: Box<dyn Iterator<Item = f32> + Send> =
-        if source_sample_rate.get() != format.sample_rate {
-            // Quality: This resampler is a simple linear resampler.
-            Box::new(SampleRateConverter::new(
-                converter,
-                source_sample_rate,
-                target_sample_rate,
-                target_channels,
-            ))
+    let mut converter = downmix(source, source_channels.get().into());
+    let to_i16 = |samples: &[f32]| {
+        if dither {
+            audio::to_i16_dithered(samples)
         } else {
-            Box::new(converter)
-        };
+            audio::into_i16(samples)
+        }
+    };
 
     // Calculate samples for 100ms frame (10 frames per second)
     let samples_per_frame = format.sample_rate / 10;
 
+    if source_sample_rate.get() != format.sample_rate {
+        // The whole source is resampled at once, so that the filter is not interrupted at frame
+        // boundaries.
+        let samples = to_i16(&converter.collect::<Vec<_>>());
+        let samples = audio::resample(&samples, source_sample_rate.get(), format.sample_rate);
+        for samples in samples.chunks(samples_per_frame as usize) {
+            callback(AudioFrame {
+                format,
+                samples: samples.to_vec(),
+            })?;
+        }
+        return Ok(());
+    }
+
     loop {
         // Collect 100ms of samples at a time
         let mut frame_samples = Vec::with_capacity(samples_per_frame as usize);
         for _ in 0..samples_per_frame {
-            match source_iterator.next() {
+            match converter.next() {
                 Some(sample) => frame_samples.push(sample),
                 None => break,
             }
@@ -241,7 +482,7 @@ where
         }
 
         // Convert to i16 samples
-        let i16_samples = audio::into_i16(&frame_samples);
+        let i16_samples = to_i16(&frame_samples);
 
         // Create the frame and pass it to the callback
         let frame = AudioFrame {
@@ -260,10 +501,26 @@ where
     Ok(())
 }
 
+/// Averages the interleaved channels of `source` into mono.
+fn downmix(mut source: impl Iterator<Item = f32>, channels: usize) -> impl Iterator<Item = f32> {
+    iter::from_fn(move || {
+        let (sum, count) = source
+            .by_ref()
+            .take(channels)
+            .fold((0.0, 0), |(sum, count), sample| (sum + sample, count + 1));
+        (count > 0).then(|| sum / count as f32)
+    })
+}
+
 enum PlaybackMethod {
-    Synthesize { text: String, text_type: String },
-    File(PathBuf),
-    Remote(Url),
+    Synthesize {
+        text: String,
+        text_type: String,
+    },
+    /// The local files, opened via their resolved paths, played back in sequence.
+    Files(Vec<File>),
+    /// The remote files, played back in sequence.
+    Remote(Vec<Url>),
 }
 
 impl PlaybackMethod {
@@ -271,6 +528,7 @@ impl PlaybackMethod {
         text: String,
         mime: &str,
         local_root: Option<&Path>,
+        allowed_extensions: Option<&[String]>,
     ) -> Result<PlaybackMethod> {
         Ok(match mime {
             "text/plain" => PlaybackMethod::Synthesize {
@@ -282,46 +540,45 @@ impl PlaybackMethod {
                 text_type: mime.into(),
             },
             "text/uri-list" => {
-                let lines: Vec<&str> = text.lines().collect();
-                if lines.len() != 1 {
-                    bail!("Invalid input: Expected a single line in text/uri-list");
-                }
-                let uri = lines[0];
-                let url = Url::parse(uri).context("Failed to parse URI in text/uri-list")?;
-                match url.scheme() {
-                    "http" | "https" => {
-                        // Security: prevent access of internal networks.
-                        PlaybackMethod::Remote(url)
-                    }
-                    _ => bail!(
-                        "Unsupported URI scheme in text/uri-list, expecting either `http://` or `https://`"
-                    ),
+                // Lines starting with `#` are comments (RFC 2483).
+                let urls = list_lines(&text)
+                    .filter(|line| !line.starts_with('#'))
+                    .map(|uri| {
+                        let url = Url::parse(uri).context("Failed to parse URI in text/uri-list")?;
+                        match url.scheme() {
+                            "http" | "https" => {
+                                // Security: prevent access of internal networks.
+                                Ok(url)
+                            }
+                            _ => bail!(
+                                "Unsupported URI scheme in text/uri-list, expecting either `http://` or `https://`"
+                            ),
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if urls.is_empty() {
+                    bail!("Invalid input: Expected at least one URI in text/uri-list");
                 }
+                PlaybackMethod::Remote(urls)
             }
             "application/x-file-path" => {
                 let Some(local_root) = local_root else {
-                    bail!("Can't play back a local audio file: No local root path configured")
+                    static WARN_ONCE: Once = Once::new();
+                    WARN_ONCE.call_once(|| {
+                        warn!(
+                            "A local audio file was requested, but no local root path is configured"
+                        )
+                    });
+                    bail!(LocalFilesNotConfigured)
                 };
 
-                let path = PathBuf::from(text.trim());
-
-                if path.is_absolute() {
-                    bail!("Absolute paths are not supported in local audio file playback");
-                }
-
-                let path = local_root.join(path);
-
-                // Resolve the path to ensure it doesn't escape a trusted directory
-                let path = fs::canonicalize(&path)
-                    .inspect_err(|e| error!("Failed to resolve file path: `{path:?}`: {e:?}"))?;
-                if !path.starts_with(local_root) {
-                    error!(
-                        "Resolved file path `{path:?}` does not match local root path `{local_root:?}`"
-                    );
-                    bail!("Access to the specified path is not allowed");
+                let files = list_lines(&text)
+                    .map(|path| open_local_file(Path::new(path), local_root, allowed_extensions))
+                    .collect::<Result<Vec<_>>>()?;
+                if files.is_empty() {
+                    bail!("Invalid input: Expected at least one file path");
                 }
-
-                PlaybackMethod::File(path)
+                PlaybackMethod::Files(files)
             }
             _ => {
                 bail!(
@@ -332,10 +589,59 @@ impl PlaybackMethod {
     }
 }
 
+/// The trimmed, non-empty lines of a list of files.
+fn list_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty())
+}
+
+fn open_local_file(
+    path: &Path,
+    local_root: &Path,
+    allowed_extensions: Option<&[String]>,
+) -> Result<File> {
+    if path.is_absolute() {
+        bail!("Absolute paths are not supported in local audio file playback");
+    }
+
+    let path = local_root.join(path);
+
+    // Resolve the path to ensure it doesn't escape a trusted directory. This also resolves
+    // symlinks inside the local root that point outside of it.
+    let path = fs::canonicalize(&path)
+        .inspect_err(|e| error!("Failed to resolve file path: `{path:?}`: {e:?}"))?;
+    if !path.starts_with(local_root) {
+        error!("Resolved file path `{path:?}` does not match local root path `{local_root:?}`");
+        bail!("Access to the specified path is not allowed");
+    }
+
+    if let Some(allowed_extensions) = allowed_extensions {
+        check_allowed_extension(&path, allowed_extensions)?;
+    }
+    check_supported_audio_type(&path.to_string_lossy(), None)?;
+    // Open the resolved path right away, so that later changes to the file system can't redirect
+    // playback.
+    File::open(&path).inspect_err(|e| error!("Failed to open audio file: `{path:?}`: {e:?}"))
+}
+
+fn check_allowed_extension(path: &Path, allowed_extensions: &[String]) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension {
+        Some(extension) if allowed_extensions.contains(&extension) => Ok(()),
+        Some(extension) => {
+            bail!("Local audio files with the extension `.{extension}` are not allowed")
+        }
+        None => bail!("Local audio files without an extension are not allowed"),
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum AudioType {
     Wav,
     MP3,
+    /// Ogg Vorbis.
+    Ogg,
 }
 
 pub fn check_supported_audio_type(
@@ -345,28 +651,43 @@ pub fn check_supported_audio_type(
     let mime_type = if let Some(mime) = mime_type_override {
         mime.to_string()
     } else {
-        let guessed_mime = mime_guess2::from_path(path)
-            .first()
-            .ok_or_else(|| anyhow!("Invalid audio url (should end in `.mp3` or `.wav`)"))?;
+        let guessed_mime = mime_guess2::from_path(path).first().ok_or_else(|| {
+            anyhow!("Invalid audio url (should end in `.mp3`, `.wav`, or `.ogg`)")
+        })?;
         guessed_mime.essence_str().to_string()
     };
 
     match mime_type.as_str() {
         "audio/wav" => Ok(AudioType::Wav),
         "audio/mpeg" => Ok(AudioType::MP3),
+        "audio/ogg" | "application/ogg" => Ok(AudioType::Ogg),
         mime => bail!("Invalid audio url, guessed or provided mime type: {mime}"),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::f64::consts::TAU;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Cursor, Write};
+    use std::net::TcpListener;
+    use std::thread;
 
-    use context_switch_core::AudioFormat;
+    use anyhow::{Result, bail};
+    use async_trait::async_trait;
+    use context_switch_core::{
+        AudioFormat, AudioFrame, Conversation, Input, InputModality, Output, OutputModality,
+        Registry, Service, text::TextTooLong,
+    };
     use rstest::rstest;
+    use serde_json::json;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
     use url::Url;
 
-    use crate::{AudioType, check_supported_audio_type, read_to_frames};
+    use crate::{
+        AudioType, LocalFilesNotConfigured, Params, Playback, PlaybackMethod, VerifyParams,
+        check_supported_audio_type, downmix, read_to_frames,
+    };
 
     #[rstest]
     #[case("http://test.wav", false)]
@@ -374,7 +695,8 @@ mod tests {
     #[case("http://test.com/test.mp3", true)]
     #[case("http://test.com/test.mp3?query=10", true)]
     #[case("http://test.com/test.MP3", true)]
-    #[case("http://test.com/test.ogg", false)]
+    #[case("http://test.com/test.ogg", true)]
+    #[case("http://test.com/test.flac", false)]
     fn supported_file_formats(#[case] url: &str, #[case] acceptable: bool) {
         let url = Url::parse(url).unwrap();
         match check_supported_audio_type(url.path(), None) {
@@ -392,6 +714,8 @@ mod tests {
     #[case("http://test.com/audio-file", "audio/mpeg", AudioType::MP3)]
     #[case("http://test.com/audio.unknown", "audio/wav", AudioType::Wav)]
     #[case("http://test.com/audio.ogg", "audio/mpeg", AudioType::MP3)]
+    #[case("http://test.com/audio-file", "audio/ogg", AudioType::Ogg)]
+    #[case("http://test.com/audio-file", "application/ogg", AudioType::Ogg)]
     fn mime_type_override(#[case] url: &str, #[case] mime: &str, #[case] expected: AudioType) {
         let url = Url::parse(url).unwrap();
         let result = check_supported_audio_type(url.path(), Some(mime));
@@ -406,18 +730,568 @@ mod tests {
         let samples = vec![0; sample_rate as usize / 10];
         let wav = pcm_wav(sample_rate, &samples);
 
-        let frames = read_to_frames(Cursor::new(wav), format).expect("valid PCM WAV should decode");
+        let frames =
+            read_to_frames(Cursor::new(wav), format, false).expect("valid PCM WAV should decode");
 
         assert_eq!(frames.len(), 1);
         assert_eq!(frames[0].format, format);
         assert_eq!(frames[0].samples.len(), samples.len());
     }
 
+    /// A 500 Hz sine at half of full scale.
+    fn half_scale_sine(sample_rate: u32, len: usize) -> impl Iterator<Item = f64> {
+        (0..len).map(move |i| 0.5 * (TAU * 500.0 * i as f64 / sample_rate as f64).sin())
+    }
+
+    #[rstest]
+    #[case::pcm_24_bit(WAVE_FORMAT_PCM, 24)]
+    #[case::pcm_32_bit(WAVE_FORMAT_PCM, 32)]
+    #[case::float_32_bit(WAVE_FORMAT_IEEE_FLOAT, 32)]
+    fn high_resolution_wav_decodes_to_audio_frames(
+        #[case] format_tag: u16,
+        #[case] bits_per_sample: u16,
+        #[values(false, true)] dither: bool,
+    ) {
+        let format = AudioFormat::new(1, 16_000);
+        let data: Vec<u8> = half_scale_sine(16_000, 1600)
+            .flat_map(|sample| match (format_tag, bits_per_sample) {
+                (WAVE_FORMAT_IEEE_FLOAT, _) => (sample as f32).to_le_bytes().to_vec(),
+                (_, 24) => ((sample * 8_388_607.0) as i32).to_le_bytes()[..3].to_vec(),
+                _ => ((sample * i32::MAX as f64) as i32).to_le_bytes().to_vec(),
+            })
+            .collect();
+        let wav = wav(format_tag, bits_per_sample, 16_000, 1, &data);
+
+        let frames = read_to_frames(Cursor::new(wav), format, dither).expect("WAV should decode");
+
+        assert_eq!(frames.len(), 1);
+        for (sample, expected) in frames[0].samples.iter().zip(half_scale_sine(16_000, 1600)) {
+            let expected = expected * i16::MAX as f64;
+            assert!(
+                (*sample as f64 - expected).abs() <= 2.0,
+                "{sample} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn ogg_vorbis_decodes_to_audio_frames() {
+        // A 265 Hz tone of 160ms.
+        let ogg = include_bytes!("../testdata/tone.ogg");
+        let format = AudioFormat::new(1, 8000);
+
+        let frames = read_to_frames(Cursor::new(ogg), format, false)
+            .expect("valid Ogg Vorbis should decode");
+
+        let lengths: Vec<_> = frames.iter().map(|frame| frame.samples.len()).collect();
+        assert_eq!(lengths, [800, 480]);
+        assert!(frames.iter().all(|frame| frame.format == format));
+        let peak = frames
+            .iter()
+            .flat_map(|frame| &frame.samples)
+            .map(|sample| sample.unsigned_abs())
+            .max();
+        assert!(peak > Some(10000), "{peak:?}");
+    }
+
+    #[test]
+    fn pcm_wav_is_resampled_to_the_output_format() {
+        let format = AudioFormat::new(1, 8000);
+        let samples = vec![1000; 48_000 / 4];
+        let wav = pcm_wav(48_000, &samples);
+
+        let frames =
+            read_to_frames(Cursor::new(wav), format, false).expect("valid PCM WAV should decode");
+
+        let lengths: Vec<_> = frames.iter().map(|frame| frame.samples.len()).collect();
+        assert_eq!(lengths, [800, 800, 400]);
+        assert!(frames.iter().all(|frame| frame.format == format));
+        // Away from the edges, the level is kept.
+        assert!((frames[1].samples[400] - 1000).abs() <= 1);
+    }
+
+    #[test]
+    fn stereo_channels_are_averaged_into_mono() {
+        let format = AudioFormat::new(1, 16_000);
+        // Left and right in opposite phase.
+        let samples: Vec<i16> = (0..1600)
+            .flat_map(|i| {
+                let sample = if i % 20 < 10 { 8000 } else { -8000 };
+                [sample, -sample]
+            })
+            .collect();
+        let wav = interleaved_pcm_wav(16_000, 2, &samples);
+
+        let frames =
+            read_to_frames(Cursor::new(wav), format, false).expect("valid PCM WAV should decode");
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].samples.len(), 1600);
+        assert!(frames[0].samples.iter().all(|sample| sample.abs() <= 1));
+    }
+
+    #[test]
+    fn downmix_averages_all_channels() {
+        let interleaved = [0.5, -0.5, 0.25, 0.25, 0.75, 0.25, 1.0];
+        let mono: Vec<f32> = downmix(interleaved.into_iter(), 2).collect();
+        assert_eq!(mono, [0.0, 0.25, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn local_file_without_local_root_is_a_configuration_error() {
+        let Err(e) = PlaybackMethod::from_text_and_mime_type(
+            "audio.wav".into(),
+            "application/x-file-path",
+            None,
+            None,
+        ) else {
+            panic!("Expected an error");
+        };
+
+        assert_eq!(
+            e.downcast_ref::<LocalFilesNotConfigured>(),
+            Some(&LocalFilesNotConfigured)
+        );
+        assert!(e.to_string().starts_with("local_files_not_configured:"));
+    }
+
+    fn play_file(playback: &Playback, path: &str) -> anyhow::Result<PlaybackMethod> {
+        PlaybackMethod::from_text_and_mime_type(
+            path.into(),
+            "application/x-file-path",
+            playback.local_files.as_deref(),
+            playback.local_file_extensions.as_deref(),
+        )
+    }
+
+    #[test]
+    fn local_file_outside_of_the_extension_allowlist_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        for file in ["notes.txt", "audio.wav", "audio.mp3"] {
+            fs::write(root.path().join(file), []).unwrap();
+        }
+
+        let playback = Playback::new(Some(root.path().into()))
+            .unwrap()
+            .with_local_file_extensions([".WAV"]);
+
+        assert!(matches!(
+            play_file(&playback, "audio.wav"),
+            Ok(PlaybackMethod::Files(_))
+        ));
+        for (path, extension) in [("notes.txt", ".txt"), ("audio.mp3", ".mp3")] {
+            let Err(e) = play_file(&playback, path) else {
+                panic!("`{path}` must be rejected");
+            };
+            assert_eq!(
+                e.to_string(),
+                format!("Local audio files with the extension `{extension}` are not allowed")
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_pointing_outside_of_the_local_root_is_denied() {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("inside.wav"), []).unwrap();
+        fs::write(outside.path().join("secret.wav"), []).unwrap();
+        symlink(
+            outside.path().join("secret.wav"),
+            root.path().join("escape.wav"),
+        )
+        .unwrap();
+        symlink(outside.path(), root.path().join("escape")).unwrap();
+
+        let playback = Playback::new(Some(root.path().into())).unwrap();
+
+        assert!(matches!(
+            play_file(&playback, "inside.wav"),
+            Ok(PlaybackMethod::Files(_))
+        ));
+        for path in ["escape.wav", "escape/secret.wav", "../secret.wav"] {
+            let Err(e) = play_file(&playback, path) else {
+                panic!("Access to `{path}` must be denied");
+            };
+            assert!(!e.to_string().contains("secret"), "{e}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_local_root_is_canonicalized() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("inside.wav"), []).unwrap();
+        symlink(&root, dir.path().join("root-link")).unwrap();
+
+        let playback = Playback::new(Some(dir.path().join("root-link"))).unwrap();
+
+        assert!(matches!(
+            play_file(&playback, "inside.wav"),
+            Ok(PlaybackMethod::Files(_))
+        ));
+    }
+
+    const FORMAT: AudioFormat = AudioFormat {
+        channels: 1,
+        sample_rate: 16000,
+    };
+
+    /// Synthesizes one audio frame per word.
+    #[derive(Debug)]
+    struct MockSynthesizer;
+
+    #[async_trait]
+    impl Service for MockSynthesizer {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), conversation: Conversation) -> Result<()> {
+            let format = conversation.require_single_audio_output()?;
+            let (mut input, output) = conversation.start()?;
+            while let Some(Input::Text {
+                request_id, text, ..
+            }) = input.recv().await
+            {
+                for _ in text.split_whitespace() {
+                    output.audio_frame(AudioFrame {
+                        format,
+                        samples: vec![0; 160],
+                    })?;
+                }
+                output.request_completed(request_id)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Recognizes `hello` for each audio frame.
+    #[derive(Debug)]
+    struct MockTranscriber;
+
+    #[async_trait]
+    impl Service for MockTranscriber {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), conversation: Conversation) -> Result<()> {
+            conversation.require_audio_input()?;
+            conversation.require_text_output(false)?;
+            let (mut input, output) = conversation.start()?;
+            let mut words = Vec::new();
+            while let Some(Input::Audio { .. }) = input.recv().await {
+                words.push("hello");
+            }
+            output.text(true, words.join(" "), None, None)?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_mode_compares_requested_and_recognized_text() {
+        let registry = Registry::empty()
+            .add_service("synthesize", MockSynthesizer)
+            .add_service("transcribe", MockTranscriber);
+        let (input_tx, input_rx) = channel(1);
+        let (output_tx, mut output_rx) = unbounded_channel();
+        let conversation = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format: FORMAT }],
+            input_rx,
+            output_tx,
+        )
+        .with_registry(registry.into());
+        let params = Params {
+            synthesizer_service: "synthesize".into(),
+            synthesizer_params: json!(null),
+            verify: Some(VerifyParams {
+                transcriber_service: "transcribe".into(),
+                transcriber_params: json!(null),
+            }),
+            crossfade_ms: None,
+            max_text_len: None,
+            synthesizer_retry: None,
+            dither: false,
+        };
+
+        input_tx
+            .send(Input::Text {
+                request_id: Some("r1".to_string().into()),
+                text: "Hello, world!".into(),
+                text_type: None,
+                billing_scope: None,
+            })
+            .await
+            .unwrap();
+        drop(input_tx);
+        Playback::new(None)
+            .unwrap()
+            .conversation(params, conversation)
+            .await
+            .unwrap();
+
+        let mut audio_frames = 0;
+        let mut events = Vec::new();
+        while let Ok(output) = output_rx.try_recv() {
+            match output {
+                Output::Audio { .. } => audio_frames += 1,
+                Output::ServiceEvent { value, .. } => events.push(value),
+                _ => {}
+            }
+        }
+
+        // The synthesized audio is still played back.
+        assert_eq!(audio_frames, 2);
+        assert_eq!(
+            events,
+            [json!({
+                "type": "verification",
+                "requestId": "r1",
+                "requestedText": "Hello, world!",
+                "recognizedText": "hello hello",
+                "similarity": 0.5,
+            })]
+        );
+    }
+
+    /// Fails every request.
+    #[derive(Debug)]
+    struct FailingSynthesizer;
+
+    #[async_trait]
+    impl Service for FailingSynthesizer {
+        type Params = ();
+
+        async fn conversation(&self, _params: (), _conversation: Conversation) -> Result<()> {
+            bail!("The synthesizer must not be called");
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_text_completes_without_synthesizing() {
+        let registry = Registry::empty().add_service("synthesize", FailingSynthesizer);
+        let (input_tx, input_rx) = channel(1);
+        let (output_tx, mut output_rx) = unbounded_channel();
+        let conversation = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format: FORMAT }],
+            input_rx,
+            output_tx,
+        )
+        .with_registry(registry.into());
+        let params = Params {
+            synthesizer_service: "synthesize".into(),
+            synthesizer_params: json!(null),
+            verify: None,
+            crossfade_ms: None,
+            max_text_len: None,
+            synthesizer_retry: None,
+            dither: false,
+        };
+
+        input_tx
+            .send(Input::Text {
+                request_id: Some("r1".to_string().into()),
+                text: " \n\t".into(),
+                text_type: None,
+                billing_scope: None,
+            })
+            .await
+            .unwrap();
+        drop(input_tx);
+        Playback::new(None)
+            .unwrap()
+            .conversation(params, conversation)
+            .await
+            .unwrap();
+
+        let mut outputs = Vec::new();
+        while let Ok(output) = output_rx.try_recv() {
+            outputs.push(output);
+        }
+        assert!(matches!(
+            &outputs[..],
+            [Output::RequestCompleted { request_id: Some(id) }] if id.to_string() == "r1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn over_long_text_is_rejected_without_synthesizing() {
+        let registry = Registry::empty().add_service("synthesize", FailingSynthesizer);
+        let (input_tx, input_rx) = channel(1);
+        let (output_tx, _output_rx) = unbounded_channel();
+        let conversation = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format: FORMAT }],
+            input_rx,
+            output_tx,
+        )
+        .with_registry(registry.into());
+        let params = Params {
+            synthesizer_service: "synthesize".into(),
+            synthesizer_params: json!(null),
+            verify: None,
+            crossfade_ms: None,
+            max_text_len: Some(5),
+            synthesizer_retry: None,
+            dither: false,
+        };
+
+        input_tx
+            .send(Input::Text {
+                request_id: Some("r1".to_string().into()),
+                text: "Hello World".into(),
+                text_type: None,
+                billing_scope: None,
+            })
+            .await
+            .unwrap();
+        drop(input_tx);
+        let error = Playback::new(None)
+            .unwrap()
+            .conversation(params, conversation)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<TextTooLong>(),
+            Some(&TextTooLong {
+                len: 11,
+                max_len: 5
+            })
+        );
+    }
+
+    #[test]
+    fn uri_list_skips_comments_and_blank_lines() {
+        let text = "# greeting\nhttp://test.com/hello.wav\n\n  http://test.com/menu.mp3  \n";
+        let Ok(PlaybackMethod::Remote(urls)) =
+            PlaybackMethod::from_text_and_mime_type(text.into(), "text/uri-list", None, None)
+        else {
+            panic!("Expected remote playback");
+        };
+        let urls: Vec<_> = urls.iter().map(Url::as_str).collect();
+        assert_eq!(
+            urls,
+            ["http://test.com/hello.wav", "http://test.com/menu.mp3"]
+        );
+
+        assert!(
+            PlaybackMethod::from_text_and_mime_type("# none".into(), "text/uri-list", None, None)
+                .is_err()
+        );
+    }
+
+    /// Serves each of the WAV files at its path once and returns the base URL.
+    fn serve_wav_files(files: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = files.len();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                let request_line = lines.next().unwrap().unwrap();
+                // Skip the headers.
+                for line in lines {
+                    if line.unwrap().is_empty() {
+                        break;
+                    }
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap();
+                let (_, wav) = files.iter().find(|(p, _)| *p == path).unwrap();
+                let mut writer = &stream;
+                write!(
+                    writer,
+                    "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    wav.len()
+                )
+                .unwrap();
+                writer.write_all(wav).unwrap();
+            }
+        });
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn uri_list_is_played_back_in_order_before_completion() {
+        let base = serve_wav_files(vec![
+            ("/first.wav", pcm_wav(16000, &[8000; 1600])),
+            ("/second.wav", pcm_wav(16000, &[-8000; 1600])),
+        ]);
+
+        let (input_tx, input_rx) = channel(1);
+        let (output_tx, mut output_rx) = unbounded_channel();
+        let conversation = Conversation::new(
+            InputModality::Text,
+            [OutputModality::Audio { format: FORMAT }],
+            input_rx,
+            output_tx,
+        );
+        let params = Params {
+            synthesizer_service: "synthesize".into(),
+            synthesizer_params: json!(null),
+            verify: None,
+            crossfade_ms: None,
+            max_text_len: None,
+            synthesizer_retry: None,
+            dither: false,
+        };
+
+        input_tx
+            .send(Input::Text {
+                request_id: Some("r1".to_string().into()),
+                text: format!("{base}/first.wav\n{base}/second.wav"),
+                text_type: Some("text/uri-list".into()),
+                billing_scope: None,
+            })
+            .await
+            .unwrap();
+        drop(input_tx);
+        Playback::new(None)
+            .unwrap()
+            .conversation(params, conversation)
+            .await
+            .unwrap();
+
+        let mut played = Vec::new();
+        while let Ok(output) = output_rx.try_recv() {
+            match output {
+                Output::Audio { frame } if frame.samples[0] > 0 => played.push("first"),
+                Output::Audio { .. } => played.push("second"),
+                Output::RequestCompleted { .. } => played.push("completed"),
+                _ => {}
+            }
+        }
+        assert_eq!(played, ["first", "second", "completed"]);
+    }
+
     fn pcm_wav(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
-        let channel_count = 1u16;
-        let bits_per_sample = 16u16;
+        interleaved_pcm_wav(sample_rate, 1, samples)
+    }
+
+    fn interleaved_pcm_wav(sample_rate: u32, channel_count: u16, samples: &[i16]) -> Vec<u8> {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        wav(WAVE_FORMAT_PCM, 16, sample_rate, channel_count, &data)
+    }
+
+    const WAVE_FORMAT_PCM: u16 = 1;
+    const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+    fn wav(
+        format_tag: u16,
+        bits_per_sample: u16,
+        sample_rate: u32,
+        channel_count: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
         let bytes_per_sample = bits_per_sample / 8;
-        let data_len = samples.len() as u32 * bytes_per_sample as u32;
+        let data_len = data.len() as u32;
         let chunk_len = 36 + data_len;
         let byte_rate = sample_rate * channel_count as u32 * bytes_per_sample as u32;
         let block_align = channel_count * bytes_per_sample;
@@ -428,7 +1302,7 @@ mod tests {
         wav.extend_from_slice(b"WAVE");
         wav.extend_from_slice(b"fmt ");
         wav.extend_from_slice(&16u32.to_le_bytes());
-        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&format_tag.to_le_bytes());
         wav.extend_from_slice(&channel_count.to_le_bytes());
         wav.extend_from_slice(&sample_rate.to_le_bytes());
         wav.extend_from_slice(&byte_rate.to_le_bytes());
@@ -436,9 +1310,7 @@ mod tests {
         wav.extend_from_slice(&bits_per_sample.to_le_bytes());
         wav.extend_from_slice(b"data");
         wav.extend_from_slice(&data_len.to_le_bytes());
-        for sample in samples {
-            wav.extend_from_slice(&sample.to_le_bytes());
-        }
+        wav.extend_from_slice(data);
         wav
     }
 }
@@ -0,0 +1,91 @@
+//! QA verification of synthesized audio: The audio is transcribed again and the recognized text is
+//! compared to the requested text.
+use serde::{Deserialize, Serialize};
+
+use context_switch_core::RequestId;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyParams {
+    pub transcriber_service: String,
+    pub transcriber_params: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum ServiceOutputEvent {
+    Verification {
+        request_id: Option<RequestId>,
+        requested_text: String,
+        recognized_text: String,
+        /// `1.0` if the words match, `0.0` if none do.
+        similarity: f64,
+    },
+}
+
+impl ServiceOutputEvent {
+    pub fn verification(
+        request_id: Option<RequestId>,
+        requested_text: String,
+        recognized_text: String,
+    ) -> Self {
+        let similarity = similarity(&requested_text, &recognized_text);
+        Self::Verification {
+            request_id,
+            requested_text,
+            recognized_text,
+            similarity,
+        }
+    }
+}
+
+/// Word based similarity of two texts, ignoring case and punctuation.
+///
+/// This is one minus the word level edit distance relative to the word count of the longer text.
+fn similarity(a: &str, b: &str) -> f64 {
+    let a = words(a);
+    let b = words(b);
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(&a, &b) as f64 / longest as f64
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+fn edit_distance(a: &[String], b: &[String]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_word) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_word) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_word != b_word);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::similarity;
+
+    #[test]
+    fn similarity_compares_words() {
+        assert_eq!(similarity("Hello, world!", "hello world"), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("hello world", "hello there"), 0.5);
+        assert_eq!(similarity("one two three four", "one three four"), 0.75);
+        assert_eq!(similarity("hello", ""), 0.0);
+    }
+}
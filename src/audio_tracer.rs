@@ -1,24 +1,63 @@
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use anyhow::{Context, Result};
-use hound::{SampleFormat, WavSpec, WavWriter};
+use anyhow::{Context, Result, bail};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 use tracing::error;
 
-use context_switch_core::AudioFrame;
+use context_switch_core::{AudioFormat, AudioFrame};
 
 #[derive(Debug)]
 pub struct AudioTracer {
     filename: PathBuf,
+    compression: TraceCompression,
     frames: Vec<AudioFrame>,
 }
 
+/// How audio traces are written to disk.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TraceCompression {
+    /// Plain WAV files.
+    #[default]
+    None,
+    /// Gzip compressed WAV files with an additional `.gz` extension.
+    Gzip,
+}
+
+impl FromStr for TraceCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            _ => bail!("Unsupported trace compression: `{s}`, expected `none` or `gzip`"),
+        }
+    }
+}
+
 impl AudioTracer {
     pub fn new(filename: impl Into<PathBuf>) -> Self {
         Self {
             filename: filename.into(),
+            compression: TraceCompression::None,
             frames: Vec::new(),
         }
     }
+
+    /// Compresses the trace when it's written. For gzip, `.gz` is appended to the filename.
+    pub fn with_compression(mut self, compression: TraceCompression) -> Self {
+        if compression == TraceCompression::Gzip {
+            self.filename.as_mut_os_string().push(".gz");
+        }
+        self.compression = compression;
+        self
+    }
 }
 
 impl Drop for AudioTracer {
@@ -42,19 +81,45 @@ impl AudioTracer {
             return Ok(());
         }
 
+        match self.compression {
+            TraceCompression::None => {
+                let writer = WavWriter::create(&self.filename, self.spec()).with_context(|| {
+                    format!("Creating file {}", self.filename.to_string_lossy())
+                })?;
+                self.write_samples(writer)
+            }
+            TraceCompression::Gzip => {
+                // The WAV writer needs to seek back to the header, so the WAV is rendered in
+                // memory first.
+                let mut wav = Cursor::new(Vec::new());
+                self.write_samples(
+                    WavWriter::new(&mut wav, self.spec()).context("Creating WAV writer")?,
+                )?;
+
+                let file = File::create(&self.filename).with_context(|| {
+                    format!("Creating file {}", self.filename.to_string_lossy())
+                })?;
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                encoder.write_all(wav.get_ref()).context("Compressing")?;
+                encoder.finish().context("Finalizing")?;
+                Ok(())
+            }
+        }
+    }
+
+    fn spec(&self) -> WavSpec {
         // We don't care about format changes for now.
         let format = self.frames[0].format;
 
-        let spec = WavSpec {
+        WavSpec {
             channels: format.channels,
             sample_rate: format.sample_rate,
             bits_per_sample: 16,
             sample_format: SampleFormat::Int,
-        };
-
-        let mut writer = WavWriter::create(&self.filename, spec)
-            .with_context(|| format!("Creating file {}", self.filename.to_string_lossy()))?;
+        }
+    }
 
+    fn write_samples<W: Write + Seek>(&self, mut writer: WavWriter<W>) -> Result<()> {
         for frame in &self.frames {
             for sample in &frame.samples {
                 writer.write_sample(*sample).context("Writing sample")?;
@@ -64,3 +129,50 @@ impl AudioTracer {
         writer.finalize().context("Finalizing")
     }
 }
+
+/// Reads an audio trace written by [`AudioTracer`]. Traces ending in `.gz` are decompressed.
+pub fn read_trace(path: impl AsRef<Path>) -> Result<AudioFrame> {
+    let path = path.as_ref();
+    let file =
+        File::open(path).with_context(|| format!("Opening file {}", path.to_string_lossy()))?;
+    let reader: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let reader = WavReader::new(reader).context("Reading WAV header")?;
+    let spec = reader.spec();
+    let samples = reader
+        .into_samples::<i16>()
+        .collect::<Result<_, _>>()
+        .context("Reading samples")?;
+
+    Ok(AudioFrame {
+        format: AudioFormat::new(spec.channels, spec.sample_rate),
+        samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_trace_round_trips_to_identical_samples() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = AudioFrame {
+            format: AudioFormat::new(1, 16000),
+            samples: (0..1600).map(|i| (i * 20) as i16).collect(),
+        };
+
+        let mut tracer =
+            AudioTracer::new(dir.path().join("trace.wav")).with_compression(TraceCompression::Gzip);
+        tracer.capture_frame(frame.clone());
+        drop(tracer);
+
+        let read = read_trace(dir.path().join("trace.wav.gz")).unwrap();
+        assert_eq!(read.format, frame.format);
+        assert_eq!(read.samples, frame.samples);
+    }
+}
@@ -1,5 +1,5 @@
-use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -7,23 +7,41 @@ use std::time::Duration;
 use anyhow::{Context, Result, bail};
 use chrono::Local;
 use static_assertions::assert_impl_all;
-use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender, channel, unbounded_channel};
-use tokio::{select, time};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{Permit, UnboundedReceiver, UnboundedSender, channel, unbounded_channel};
+use tokio::time::Instant;
+use tokio::{pin, select, time};
 use tracing::{Span, error, info, warn};
 use tracing_futures::Instrument;
 
-use crate::{AudioTracer, ClientEvent, ConversationId, InputModality, ServerEvent};
+use crate::input_queue::{
+    InputQueueReceiver, InputQueueSender, input_queue, recommended_channel_capacity,
+};
+use crate::{
+    AudioTracer, ClientEvent, ConversationId, InputModality, ServerEvent, TraceCompression,
+};
 use context_switch_core::billing_collector::BillingCollector;
-use context_switch_core::{AudioFrame, BillingContext, Conversation, Input, Output, Registry};
+use context_switch_core::{
+    AudioFormat, AudioFrame, BillingContext, BillingRecord, Conversation, Input, Output,
+    OutputPath, Preprocessing, Preprocessor, Registry, RequestFailed,
+};
 
 #[derive(Debug)]
 pub struct ContextSwitch {
     registry: Arc<Registry>,
     conversations: HashMap<ConversationId, ActiveConversation>,
+    /// Receives the ids of conversations that ended, so that they can be removed.
+    ended_receiver: UnboundedReceiver<ConversationId>,
+    ended_sender: UnboundedSender<ConversationId>,
     output: UnboundedSender<ServerEvent>,
     shutdown_timeout: Duration,
+    /// Conversations that receive no input for this duration are stopped.
+    idle_timeout: Option<Duration>,
+    max_queued_input_events: usize,
+    max_nested_conversations: usize,
     /// The directory defining where to store audio files for input data.
     audio_traces: Option<PathBuf>,
+    trace_compression: TraceCompression,
     billing_collector: Arc<Mutex<BillingCollector>>,
 }
 assert_impl_all!(ContextSwitch: Send);
@@ -31,7 +49,8 @@ assert_impl_all!(ContextSwitch: Send);
 #[derive(Debug)]
 struct ActiveConversation {
     pub input_modality: InputModality,
-    pub client_sender: Sender<ClientEvent>,
+    pub client_sender: InputQueueSender,
+    pub preprocessor: Option<Preprocessor>,
 }
 
 /// All the services we currently support in CS
@@ -56,18 +75,29 @@ pub fn registry() -> Registry {
 impl ContextSwitch {
     /// This should be enough to terminate all connections gracefully to all servers world-wide.
     pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+    /// The maximum shutdown timeout a start event may request.
+    pub const MAX_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+    pub const DEFAULT_MAX_QUEUED_INPUT_EVENTS: usize = 256;
+    pub const DEFAULT_MAX_NESTED_CONVERSATIONS: usize = 4;
 
     pub fn new(
         registry: Arc<Registry>,
         sender: UnboundedSender<ServerEvent>,
         audio_traces: Option<PathBuf>,
     ) -> Self {
+        let (ended_sender, ended_receiver) = unbounded_channel();
         Self {
             registry,
             conversations: Default::default(),
+            ended_receiver,
+            ended_sender,
             output: sender,
             shutdown_timeout: Self::DEFAULT_SHUTDOWN_TIMEOUT,
+            idle_timeout: None,
+            max_queued_input_events: Self::DEFAULT_MAX_QUEUED_INPUT_EVENTS,
+            max_nested_conversations: Self::DEFAULT_MAX_NESTED_CONVERSATIONS,
             audio_traces,
+            trace_compression: TraceCompression::None,
             billing_collector: Mutex::new(BillingCollector::default()).into(),
         }
     }
@@ -78,14 +108,71 @@ impl ContextSwitch {
         self
     }
 
-    pub fn with_billing_collector(self, billing_collector: Arc<Mutex<BillingCollector>>) -> Self {
-        Self {
-            billing_collector,
-            ..self
-        }
+    /// Stops conversations that receive no audio, text, or service events for `timeout` with an
+    /// error. This prevents conversations of clients that vanished without stopping them from
+    /// living forever.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum number of audio frames queued per conversation. If more are queued, audio
+    /// frames are shed until the conversation catches up. Other events are never shed and are
+    /// passed to the conversation ahead of the queued audio.
+    ///
+    /// The limit is meant for frames of 20ms. It is scaled for conversations that are started with
+    /// a different frame duration.
+    pub fn with_max_queued_input_events(mut self, max_queued_input_events: usize) -> Self {
+        self.max_queued_input_events = max_queued_input_events;
+        self
+    }
+
+    /// Sets the maximum number of nested conversations a conversation runs at the same time, for
+    /// example the synthesize requests of playback. Further requests wait, which bounds the load
+    /// each conversation puts on providers.
+    pub fn with_max_nested_conversations(mut self, max_nested_conversations: usize) -> Self {
+        self.max_nested_conversations = max_nested_conversations;
+        self
+    }
+
+    /// Sets the compression of the audio traces, trading CPU for disk space.
+    pub fn with_trace_compression(mut self, compression: TraceCompression) -> Self {
+        self.trace_compression = compression;
+        self
+    }
+
+    pub fn with_billing_collector(
+        mut self,
+        billing_collector: Arc<Mutex<BillingCollector>>,
+    ) -> Self {
+        self.billing_collector = billing_collector;
+        self
+    }
+
+    /// Validates a start event without starting a conversation: looks up the service,
+    /// deserializes its params, and checks if the service supports the modalities.
+    pub fn validate_start(&self, event: &ClientEvent) -> Result<()> {
+        let ClientEvent::Start {
+            service,
+            params,
+            input_modality,
+            output_modalities,
+            ..
+        } = event
+        else {
+            bail!("Expected a start event");
+        };
+
+        self.registry.service(service)?.validate(
+            params,
+            *input_modality,
+            &output_modalities.clone().into(),
+        )
     }
 
     pub fn process(&mut self, event: ClientEvent) -> Result<()> {
+        self.remove_ended_conversations();
+
         match self.conversations.entry(event.conversation_id().clone()) {
             Entry::Vacant(vacant_entry) => {
                 // A new conversation must be initiated with a Start event. Store the input modality
@@ -96,12 +183,24 @@ impl ContextSwitch {
                     input_modality,
                     ref billing_id,
                     ref output_modalities,
+                    shutdown_timeout_ms,
+                    ref preprocessing,
+                    frame_duration_ms,
                     ..
                 } = event
                 else {
+                    if let ClientEvent::Stop { .. } | ClientEvent::Hangup { .. } = event {
+                        // The conversation ended on its own, for example after an idle timeout,
+                        // and already sent its final event.
+                        return Ok(());
+                    }
                     bail!("Expected start event for a new conversation id");
                 };
 
+                let shutdown_timeout = shutdown_timeout_ms.map_or(self.shutdown_timeout, |ms| {
+                    Duration::from_millis(ms).min(Self::MAX_SHUTDOWN_TIMEOUT)
+                });
+
                 let billing_context = billing_id.as_ref().map(|billing_id| {
                     BillingContext::new(billing_id.clone(), service, self.billing_collector.clone())
                 });
@@ -110,63 +209,146 @@ impl ContextSwitch {
                     "Conversation starting: {id}, {service}, input: {input_modality:?}, output: {output_modalities:?}"
                 );
 
-                // Robustness: Clearly define this number somewhere else.
-                let (sender, receiver) = channel(256);
+                let max_queued_input_events = match frame_duration_ms {
+                    Some(0) => bail!("The frame duration must be positive"),
+                    Some(ms) => recommended_channel_capacity(
+                        self.max_queued_input_events,
+                        Duration::from_millis(ms),
+                    ),
+                    None => self.max_queued_input_events,
+                };
+                let (sender, receiver) = input_queue(max_queued_input_events);
+                let preprocessor = preprocessing.as_ref().map(Preprocessing::make_preprocessor);
 
                 // The task is expected to handle all circumstances and so its never required to abort it or
                 // inspect its return value.
                 tokio::spawn(
                     process_conversation(
                         self.registry.clone(),
-                        self.shutdown_timeout,
+                        Limits {
+                            shutdown_timeout,
+                            idle_timeout: self.idle_timeout,
+                            max_nested_conversations: self.max_nested_conversations,
+                        },
                         event,
                         billing_context,
                         receiver,
-                        self.output.clone(),
-                        self.audio_traces.clone(),
+                        Reports {
+                            output: self.output.clone(),
+                            ended: self.ended_sender.clone(),
+                        },
+                        self.audio_traces.clone().map(|dir| AudioTraces {
+                            dir,
+                            compression: self.trace_compression,
+                        }),
                     )
                     .instrument(Span::current()),
                 );
                 vacant_entry.insert(ActiveConversation {
                     input_modality,
                     client_sender: sender,
+                    preprocessor,
                 });
             }
-            Entry::Occupied(occupied_entry) => {
-                if let ClientEvent::Stop { .. } = event {
-                    // This drops the ActiveConversation, which drops the input channel, which in turn
-                    // causes the conversation to shut down gracefully.
-                    occupied_entry.remove();
-                } else {
+            Entry::Occupied(occupied_entry) => match event {
+                ClientEvent::Stop { .. } => {
+                    // This drops the ActiveConversation, which drops the input channel after the
+                    // Stop event, which in turn causes the conversation to shut down gracefully.
+                    let conversation = occupied_entry.remove();
+                    // A conversation that just ended on its own does not receive events anymore.
+                    if !conversation.client_sender.is_closed() {
+                        conversation
+                            .client_sender
+                            .send(event)
+                            .context("Sending stop event to active conversation")?;
+                    }
+                }
+                ClientEvent::Hangup { reason, .. } => {
+                    // Cancels the conversation without passing the input that is still queued.
+                    occupied_entry.remove().client_sender.hang_up(reason);
+                }
+                event => {
                     occupied_entry
                         .get()
                         .client_sender
-                        .try_send(event)
+                        .send(event)
                         .context("Sending client event to active conversation")?;
-                };
-            }
+                }
+            },
         }
 
         Ok(())
     }
+
+    /// Removes the conversations that ended on their own, for example after an idle timeout.
+    fn remove_ended_conversations(&mut self) {
+        while let Ok(id) = self.ended_receiver.try_recv() {
+            // The id may already belong to a new conversation.
+            if let Entry::Occupied(entry) = self.conversations.entry(id)
+                && entry.get().client_sender.is_closed()
+            {
+                entry.remove();
+            }
+        }
+    }
+}
+
+impl Drop for ContextSwitch {
+    fn drop(&mut self) {
+        // Dropping the active conversations closes their inputs, which starts their graceful
+        // shutdown.
+        self.conversations.clear();
+
+        // Service shutdown is asynchronous, so it has to run detached from the drop.
+        let Ok(runtime) = Handle::try_current() else {
+            warn!("No runtime available, services are not shut down");
+            return;
+        };
+        let registry = self.registry.clone();
+        runtime.spawn(async move { registry.shutdown().await }.instrument(Span::current()));
+    }
+}
+
+/// Where and how the audio traces of a conversation are written.
+#[derive(Debug)]
+struct AudioTraces {
+    dir: PathBuf,
+    compression: TraceCompression,
+}
+
+/// Where a conversation reports back to.
+#[derive(Debug)]
+struct Reports {
+    output: UnboundedSender<ServerEvent>,
+    /// Receives the id of the conversation after it ended.
+    ended: UnboundedSender<ConversationId>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// How long a stopped conversation may take to shut down gracefully.
+    shutdown_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    max_nested_conversations: usize,
 }
 
 /// This further wraps the conversation processor to guarantee that there is a final stopped or
 /// error event is sent.
 async fn process_conversation(
     registry: Arc<Registry>,
-    shutdown_timeout: Duration,
+    limits: Limits,
     initial_event: ClientEvent,
     billing_context: Option<BillingContext>,
-    input: Receiver<ClientEvent>,
-    output: UnboundedSender<ServerEvent>,
-    audio_traces: Option<PathBuf>,
+    input: InputQueueReceiver,
+    reports: Reports,
+    audio_traces: Option<AudioTraces>,
 ) {
+    let Reports { output, ended } = reports;
     let id = initial_event.conversation_id().clone();
 
     let final_event = match process_conversation_protected(
         registry,
-        shutdown_timeout,
+        limits,
         initial_event,
         billing_context,
         input,
@@ -184,13 +366,20 @@ async fn process_conversation(
                 .map(|e| e.to_string())
                 .collect::<Vec<String>>()
                 .join(": ");
+            let request_id = e
+                .downcast_ref::<RequestFailed>()
+                .and_then(|failed| failed.0.clone());
             ServerEvent::Error {
                 id: id.clone(),
                 message: error,
+                request_id,
             }
         }
     };
     info!("Conversation ended: {:?}", final_event);
+    // Before the final event, so that the conversation is gone when the client reacts to it. If
+    // the ContextSwitch is gone, there is nothing to remove.
+    let _ = ended.send(id.clone());
     if let Result::Err(e) = output.send(final_event) {
         warn!(
             "Failed to deliver the final event of the conversation, output receiver may be gone: `{id}`: {e:?}"
@@ -202,12 +391,12 @@ async fn process_conversation(
 /// the final server event is generator and sent.
 async fn process_conversation_protected(
     registry: Arc<Registry>,
-    shutdown_timeout: Duration,
+    limits: Limits,
     initial_event: ClientEvent,
     billing_context: Option<BillingContext>,
-    mut input: Receiver<ClientEvent>,
+    mut input: InputQueueReceiver,
     server_output: &UnboundedSender<ServerEvent>,
-    audio_traces: Option<PathBuf>,
+    audio_traces: Option<AudioTraces>,
 ) -> Result<ServerEvent> {
     let ClientEvent::Start {
         id: conversation_id,
@@ -215,11 +404,17 @@ async fn process_conversation_protected(
         params,
         input_modality,
         output_modalities,
+        metadata,
+        billing_path,
         ..
     } = initial_event
     else {
         bail!("Initial client event must be a Start event")
     };
+    let billing_path = billing_path.unwrap_or(OutputPath::Media);
+
+    // The conversation takes the billing context, but a hangup is recorded after it's gone.
+    let hangup_billing_context = billing_context.clone();
 
     // Idea: Move input / output dispatching into the Conversation type?
 
@@ -233,7 +428,7 @@ async fn process_conversation_protected(
     // large audio files (local playback for example) in one go and are not able to block sends.
     let (output_sender, mut output_receiver) = unbounded_channel();
     // We might receive a large number of audio frames before the service can process them.
-    let (input_sender, input_receiver) = channel(256);
+    let (input_sender, input_receiver) = channel(input.max_queued());
 
     let conversation = {
         let conversation = Conversation::new(
@@ -242,7 +437,15 @@ async fn process_conversation_protected(
             input_receiver,
             output_sender,
         )
-        .with_registry(conversation_registry);
+        .with_registry(conversation_registry)
+        .with_max_nested_conversations(limits.max_nested_conversations)
+        .with_trace_id(conversation_id.to_string());
+
+        let conversation = if let Some(metadata) = metadata {
+            conversation.with_metadata(metadata)
+        } else {
+            conversation
+        };
 
         if let Some(billing_context) = billing_context {
             conversation.with_billing_context(billing_context)
@@ -256,57 +459,78 @@ async fn process_conversation_protected(
     let mut audio_tracer = audio_traces.map(|traces| {
         let timestamp = Local::now().format("%Y%m%dT%H%M%S");
         let filename = format!("{timestamp}-{conversation_id}.wav");
-        AudioTracer::new(traces.join(filename))
+        AudioTracer::new(traces.dir.join(filename)).with_compression(traces.compression)
     });
 
+    let mut drained = false;
+    let mut hangup = None;
+    // After the client stopped, the input that is still queued is passed to the service before
+    // the conversation ends.
+    let mut stopped = false;
+    let mut audio_ended = false;
+    // Events that wait for room in the service's input channel. They are passed on before the
+    // queued audio.
+    let mut pending = VecDeque::new();
+    // Room in the service's input channel. Audio frames are only received from the input queue
+    // when there is room, so that they pile up in the input queue, where they are shed.
+    let mut slot: Option<Permit<Input>> = None;
+
+    let idle = time::sleep(limits.idle_timeout.unwrap_or(Duration::MAX));
+    pin!(idle);
+
     loop {
+        if !pending.is_empty()
+            && let Some(permit) = slot.take()
+            && let Some(input) = pending.pop_front()
+        {
+            permit.send(input);
+        }
+
+        // Biased, so that a hangup takes effect first, events are received before audio, and output
+        // is not held up by a flood of audio.
         select! {
+            biased;
+
+            reason = input.hangup.recv() => {
+                hangup = Some(reason);
+                break;
+            }
+
             // Drive the conversation.
             result = &mut conversation => {
                 () = result?;
                 bail!("Conversation ended prematurely");
             }
 
-            // Process input events.
-            input = input.recv() => {
-                let Some(input) = input else {
-                    break;
-                };
-                match input {
-                    ClientEvent::Start { .. } => {
+            // Events other than audio are received as soon as they arrive, so that they don't
+            // wait behind queued audio.
+            event = input.events.recv(), if !stopped => {
+                match event {
+                    // The client is gone.
+                    None => stopped = true,
+                    Some(ClientEvent::Start { .. }) => {
                         bail!("Received unexpected Start event")
                     },
-                    ClientEvent::Stop { .. } => {
-                        // Stop isn't handled through this event, we stop by disconnecting
-                        // the input.
-                        bail!("Received unexpected Stop event")
+                    Some(ClientEvent::Stop { drain, .. }) => {
+                        // The input is disconnected right after the Stop event.
+                        drained = drain;
+                        stopped = true;
                     },
-                    ClientEvent::Audio { samples, .. } => {
-                        let InputModality::Audio { format } = input_modality else {
-                            bail!("Received unexpected Audio");
-                        };
-
-                        let frame = AudioFrame { format, samples: samples.into() };
-
-                        if let Some(tracer) = audio_tracer.as_mut() {
-                            tracer.capture_frame(frame.clone())
-                        }
-
-                        input_sender
-                            .try_send(Input::Audio { frame })
-                            .context("Sending input audio frame to conversation")?;
+                    Some(ClientEvent::Hangup { .. }) => {
+                        bail!("Received unexpected Hangup event")
                     },
-                    ClientEvent::Text { content, content_type, billing_scope,.. } => {
+                    Some(ClientEvent::Audio { .. }) => {
+                        bail!("Received Audio apart from the audio queue");
+                    },
+                    Some(ClientEvent::Text { request_id, content, content_type, billing_scope,.. }) => {
                         if let InputModality::Text = input_modality {
-                            input_sender
-                                .try_send(Input::Text { request_id: None, text: content, text_type: content_type, billing_scope })
-                                .context("Sending input text to conversation")?;
+                            pending.push_back(Input::Text { request_id, text: content, text_type: content_type, billing_scope });
                         } else {
                             bail!("Received unexpected Text");
                         }
                     },
-                    ClientEvent::Service { value, ..} => {
-                        input_sender.try_send(Input::ServiceEvent { value }).context("Sending service event")?;
+                    Some(ClientEvent::Service { value, ..}) => {
+                        pending.push_back(Input::ServiceEvent { value });
                     }
                 }
             }
@@ -314,13 +538,69 @@ async fn process_conversation_protected(
             // Forward output events
             output = output_receiver.recv() => {
                 if let Some(output) = output {
-                    let event = output_to_server_event(&conversation_id, output);
+                    let event = output_to_server_event(&conversation_id, billing_path, output);
                     server_output.send(event).context("Forwarding output server event")?;
                 } else {
                     bail!("Service output channel closed.")
                 }
             }
+
+            permit = input_sender.reserve(), if slot.is_none() && (!pending.is_empty() || !audio_ended) => {
+                slot = Some(permit.context("Sending input to conversation")?);
+            }
+
+            samples = input.audio.recv(), if slot.is_some() && pending.is_empty() && !audio_ended => {
+                if let Some(samples) = samples
+                    && let Some(permit) = slot.take()
+                {
+                    let InputModality::Audio { format } = input_modality else {
+                        bail!("Received unexpected Audio");
+                    };
+
+                    let frame = AudioFrame { format, samples: samples.into() };
+
+                    if let Some(tracer) = audio_tracer.as_mut() {
+                        tracer.capture_frame(frame.clone())
+                    }
+
+                    permit.send(Input::Audio { frame });
+                } else {
+                    audio_ended = true;
+                }
+            }
+
+            () = &mut idle, if limits.idle_timeout.is_some() => {
+                // Input the client sent counts, even if it is still queued.
+                let deadline = input.last_input() + limits.idle_timeout.unwrap_or_default();
+                if deadline > Instant::now() {
+                    idle.as_mut().reset(deadline);
+                } else {
+                    // Dropping the conversation cancels the service and its upstream connections.
+                    bail!("idle timeout");
+                }
+            }
+        }
+
+        if stopped && audio_ended && pending.is_empty() {
+            break;
+        }
+    }
+    drop(slot);
+
+    if let Some(reason) = hangup {
+        // Dropping the conversation cancels the service and its upstream connections.
+        drop(conversation);
+        info!("Caller hung up: {reason}");
+        if let Some(billing_context) = hangup_billing_context {
+            billing_context.record(
+                None,
+                vec![BillingRecord::count(format!("hangup:{reason}"), 1)],
+            )?;
         }
+        return Ok(ServerEvent::Stopped {
+            id: conversation_id,
+            drained: false,
+        });
     }
 
     // Drop the sender. If the conversation is running, it will receive a None input
@@ -333,35 +613,43 @@ async fn process_conversation_protected(
         r = conversation => {
             () = r?;
         }
-        () = time::sleep(shutdown_timeout) => {
+        () = time::sleep(limits.shutdown_timeout) => {
             // We don't bail here and confuse clients with an error. After all, dropping the
             // conversation must always be reliable. The graceful shutdown is just for closing
             // internet connections and keeping services from panicking too much.
-            error!("Graceful shutdown period expired after waiting for {}ms", shutdown_timeout.as_millis());
+            error!("Graceful shutdown period expired after waiting for {}ms", limits.shutdown_timeout.as_millis());
         }
     }
 
     Ok(ServerEvent::Stopped {
         id: conversation_id,
+        drained,
     })
 }
 
 impl ContextSwitch {
     /// Post audio to a conversation.
     ///
+    /// If the conversation was started with preprocessing, it is applied to the frame first.
+    ///
     /// Returns an error if the conversation does not exist or its input modality does not match the
     /// format of the audio frame.
     pub fn post_audio_frame(
-        &self,
+        &mut self,
         conversation_id: &ConversationId,
         frame: AudioFrame,
     ) -> Result<()> {
-        match self.conversations.get(conversation_id) {
+        self.remove_ended_conversations();
+        match self.conversations.get_mut(conversation_id) {
             Some(conversation) => {
                 if conversation.input_modality.can_receive_audio(frame.format) {
+                    let frame = match &mut conversation.preprocessor {
+                        Some(preprocessor) => preprocessor.process(frame),
+                        None => frame,
+                    };
                     Ok(conversation
                         .client_sender
-                        .try_send(ClientEvent::Audio {
+                        .send(ClientEvent::Audio {
                             id: conversation_id.clone(),
                             samples: frame.samples.into(),
                         })
@@ -373,14 +661,57 @@ impl ContextSwitch {
             None => bail!("Conversation does not exist"),
         }
     }
+
+    /// Post the audio of a shared source to multiple conversations, for example to transcribe it
+    /// in multiple languages at once.
+    ///
+    /// The frame is converted to the input format of each conversation, once per distinct format.
+    /// All conversations are validated before the audio is posted to any of them.
+    pub fn post_audio_to_many(
+        &mut self,
+        conversation_ids: &[ConversationId],
+        frame: AudioFrame,
+    ) -> Result<()> {
+        self.remove_ended_conversations();
+        let mut converted: HashMap<AudioFormat, AudioFrame> = HashMap::new();
+        let mut targets = Vec::with_capacity(conversation_ids.len());
+        for id in conversation_ids {
+            let Some(conversation) = self.conversations.get(id) else {
+                bail!("Conversation does not exist: `{id}`");
+            };
+            let InputModality::Audio { format } = conversation.input_modality else {
+                bail!("Conversation does not receive audio: `{id}`");
+            };
+            let frame = match converted.entry(format) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(
+                    frame
+                        .clone()
+                        .convert_to(format)
+                        .with_context(|| format!("Converting audio for conversation `{id}`"))?,
+                ),
+            };
+            targets.push((id, frame.clone()));
+        }
+
+        for (id, frame) in targets {
+            self.post_audio_frame(id, frame)?;
+        }
+        Ok(())
+    }
 }
 
-fn output_to_server_event(id: &ConversationId, output: Output) -> ServerEvent {
+fn output_to_server_event(
+    id: &ConversationId,
+    billing_path: OutputPath,
+    output: Output,
+) -> ServerEvent {
     match output {
         Output::ServiceStarted { modalities } => ServerEvent::Started {
             id: id.clone(),
             modalities,
         },
+        Output::ServiceReady => ServerEvent::Ready { id: id.clone() },
         Output::Audio { frame } => ServerEvent::Audio {
             id: id.clone(),
             samples: frame.samples.into(),
@@ -401,6 +732,10 @@ fn output_to_server_event(id: &ConversationId, output: Output) -> ServerEvent {
             id: id.clone(),
             request_id,
         },
+        Output::TurnCompleted { response_id } => ServerEvent::TurnCompleted {
+            id: id.clone(),
+            response_id,
+        },
         Output::ClearAudio => ServerEvent::ClearAudio { id: id.clone() },
         Output::ServiceEvent { path, value } => ServerEvent::Service {
             id: id.clone(),
@@ -414,6 +749,7 @@ fn output_to_server_event(id: &ConversationId, output: Output) -> ServerEvent {
             records,
         } => ServerEvent::BillingRecords {
             id: id.clone(),
+            path: billing_path,
             request_id,
             service,
             scope,
@@ -0,0 +1,178 @@
+//! The queue of client events that are sent to a conversation.
+//!
+//! Audio frames are queued apart from all other events. The number of queued audio frames is
+//! limited. When the limit is reached, audio frames are shed. All other events are queued without a
+//! limit and can be received ahead of the queued audio, because losing or delaying them would break
+//! the conversation. A hangup is signaled apart from both, so that it takes effect immediately.
+use std::future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{ClientEvent, Samples};
+
+/// Only every n-th shed audio frame is logged.
+const SHED_WARNING_INTERVAL: usize = 100;
+/// The frame duration the limit of queued audio frames is meant for.
+const REFERENCE_FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// Scales the limit of queued audio frames, which is meant for frames of 20ms, so that the same
+/// duration of audio is queued for frames of `frame_duration`.
+///
+/// This sizes the input queue of a conversation and the service's input channel, which takes the
+/// capacity of the queue. Output and audio channels are unbounded and not affected.
+pub fn recommended_channel_capacity(max_queued: usize, frame_duration: Duration) -> usize {
+    let scaled = max_queued as u128 * REFERENCE_FRAME_DURATION.as_micros()
+        / frame_duration.as_micros().max(1);
+    (scaled as usize).max(1)
+}
+
+pub fn input_queue(max_queued: usize) -> (InputQueueSender, InputQueueReceiver) {
+    let (event_sender, event_receiver) = unbounded_channel();
+    let (audio_sender, audio_receiver) = unbounded_channel();
+    let (hangup_sender, hangup_receiver) = oneshot::channel();
+    let queued = Arc::new(AtomicUsize::new(0));
+    let last_input = Arc::new(Mutex::new(Instant::now()));
+    (
+        InputQueueSender {
+            events: event_sender,
+            audio: audio_sender,
+            hangup: hangup_sender,
+            queued: queued.clone(),
+            last_input: last_input.clone(),
+            max_queued,
+            shed_audio_frames: AtomicUsize::new(0),
+        },
+        InputQueueReceiver {
+            events: event_receiver,
+            audio: AudioQueueReceiver {
+                receiver: audio_receiver,
+                queued,
+                max_queued,
+            },
+            hangup: HangupReceiver(Some(hangup_receiver)),
+            last_input,
+        },
+    )
+}
+
+#[derive(Debug)]
+pub struct InputQueueSender {
+    events: UnboundedSender<ClientEvent>,
+    audio: UnboundedSender<Samples>,
+    hangup: oneshot::Sender<String>,
+    /// The number of queued audio frames.
+    queued: Arc<AtomicUsize>,
+    /// When the last audio, text, or service event was sent, including shed audio frames.
+    last_input: Arc<Mutex<Instant>>,
+    max_queued: usize,
+    shed_audio_frames: AtomicUsize,
+}
+
+impl InputQueueSender {
+    /// Queues the event, or sheds it if it's an audio frame and the audio queue is full.
+    ///
+    /// Hangups are signaled with [`Self::hang_up`].
+    pub fn send(&self, event: ClientEvent) -> Result<()> {
+        match event {
+            ClientEvent::Audio { samples, .. } => {
+                self.received_input();
+                if self.queued.load(Ordering::Relaxed) >= self.max_queued {
+                    let shed = self.shed_audio_frames.fetch_add(1, Ordering::Relaxed) + 1;
+                    if shed == 1 || shed.is_multiple_of(SHED_WARNING_INTERVAL) {
+                        warn!(
+                            "Input queue is full (max {} audio frames), {shed} audio frame(s) shed so far",
+                            self.max_queued
+                        );
+                    }
+                    return Ok(());
+                }
+                self.queued.fetch_add(1, Ordering::Relaxed);
+                Ok(self.audio.send(samples)?)
+            }
+            ClientEvent::Text { .. } | ClientEvent::Service { .. } => {
+                self.received_input();
+                Ok(self.events.send(event)?)
+            }
+            ClientEvent::Start { .. } | ClientEvent::Stop { .. } => Ok(self.events.send(event)?),
+            ClientEvent::Hangup { .. } => bail!("Hangups must not be queued"),
+        }
+    }
+
+    /// Signals that the client hung up. The events and audio that are still queued are dropped.
+    pub fn hang_up(self, reason: String) {
+        // The conversation may have ended already.
+        let _ = self.hangup.send(reason);
+    }
+
+    /// Returns `true` if the conversation stopped receiving events.
+    pub fn is_closed(&self) -> bool {
+        self.events.is_closed()
+    }
+
+    fn received_input(&self) {
+        *self.last_input.lock().expect("Lock poisoned") = Instant::now();
+    }
+}
+
+#[derive(Debug)]
+pub struct InputQueueReceiver {
+    /// All events but audio, in the order they were sent. `None` is received after the sender is
+    /// dropped.
+    pub events: UnboundedReceiver<ClientEvent>,
+    pub audio: AudioQueueReceiver,
+    pub hangup: HangupReceiver,
+    last_input: Arc<Mutex<Instant>>,
+}
+
+impl InputQueueReceiver {
+    /// When the client sent its last audio, text, or service event, which may still be queued.
+    pub fn last_input(&self) -> Instant {
+        *self.last_input.lock().expect("Lock poisoned")
+    }
+
+    pub fn max_queued(&self) -> usize {
+        self.audio.max_queued
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioQueueReceiver {
+    receiver: UnboundedReceiver<Samples>,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+}
+
+impl AudioQueueReceiver {
+    /// Receives the samples of the next audio frame. Returns `None` if the sender is dropped and
+    /// all frames are received.
+    pub async fn recv(&mut self) -> Option<Samples> {
+        let samples = self.receiver.recv().await?;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(samples)
+    }
+}
+
+#[derive(Debug)]
+pub struct HangupReceiver(Option<oneshot::Receiver<String>>);
+
+impl HangupReceiver {
+    /// Resolves to the reason when the client hung up. Never resolves if the client stopped the
+    /// conversation instead.
+    pub async fn recv(&mut self) -> String {
+        if let Some(receiver) = &mut self.0 {
+            let result = receiver.await;
+            self.0 = None;
+            if let Ok(reason) = result {
+                return reason;
+            }
+        }
+        future::pending().await
+    }
+}
@@ -1,13 +1,16 @@
 mod audio_tracer;
 mod context_switch;
+mod input_queue;
 mod protocol;
 
 #[cfg(test)]
 mod tests;
 
-pub use audio_tracer::AudioTracer;
+pub use audio_tracer::{AudioTracer, TraceCompression, read_trace};
+pub use conditioning::{make_agc_processor, make_dc_removal_processor};
 pub use context_switch::*;
 pub use context_switch_core::*;
+pub use loudness::make_loudness_normalizer;
 pub use protocol::*;
 pub use speech_gate::make_speech_gate_processor;
 
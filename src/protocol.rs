@@ -3,7 +3,8 @@ use derive_more::derive::{Deref, Display, From, Into};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use context_switch_core::{
-    BillingId, BillingRecord, InputModality, OutputModality, OutputPath, RequestId, audio,
+    BillingId, BillingRecord, InputModality, OutputModality, OutputPath, Preprocessing, RequestId,
+    audio,
 };
 
 /// Conversation identifier.
@@ -17,7 +18,7 @@ impl ConversationId {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum ClientEvent {
     #[serde(rename_all = "camelCase")]
@@ -33,10 +34,46 @@ pub enum ClientEvent {
         output_modalities: Vec<OutputModality>,
         /// Optional billing id. If set billing records are sent to the billing collector and can be
         /// collected from there.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
         billing_id: Option<BillingId>,
+        /// Optional information about the caller that is passed to the service, like the calling
+        /// and called numbers or the preferred language.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<serde_json::Value>,
+        /// Optional time the service is given to shut down gracefully after the conversation is
+        /// stopped. Clamped to [`ContextSwitch::MAX_SHUTDOWN_TIMEOUT`].
+        ///
+        /// [`ContextSwitch::MAX_SHUTDOWN_TIMEOUT`]: crate::ContextSwitch::MAX_SHUTDOWN_TIMEOUT
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        shutdown_timeout_ms: Option<u64>,
+        /// Optional preprocessing of the input audio, applied before it is passed to the
+        /// service.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preprocessing: Option<Preprocessing>,
+        /// Optional duration of the audio frames the client sends. If set, the input queue is
+        /// sized to buffer the same duration of audio for every frame duration, so that bursts of
+        /// short frames are not shed early.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        frame_duration_ms: Option<u64>,
+        /// Optional output path of the inband billing records. Defaults to the media path, see
+        /// [`ServerEvent::BillingRecords`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        billing_path: Option<OutputPath>,
     },
     Stop {
         id: ConversationId,
+        /// If set, the final `Stopped` event is ordered after all the media output that was sent
+        /// before it, instead of overtaking it.
+        #[serde(default)]
+        drain: bool,
+    },
+    /// The caller hung up. Unlike `Stop`, the conversation and its upstream connections are
+    /// cancelled immediately, without a graceful shutdown.
+    Hangup {
+        id: ConversationId,
+        /// Why the call ended, for example `NORMAL_CLEARING`. Recorded as a `hangup:{reason}`
+        /// billing record.
+        reason: String,
     },
     Audio {
         id: ConversationId,
@@ -45,6 +82,9 @@ pub enum ClientEvent {
     #[serde(rename_all = "camelCase")]
     Text {
         id: ConversationId,
+        /// Optional id of the request, used in the `RequestCompleted` and `Error` events that
+        /// relate to it.
+        request_id: Option<RequestId>,
         content: String,
         content_type: Option<String>,
         billing_scope: Option<String>,
@@ -60,6 +100,7 @@ impl ClientEvent {
         match self {
             ClientEvent::Start { id, .. }
             | ClientEvent::Stop { id, .. }
+            | ClientEvent::Hangup { id, .. }
             | ClientEvent::Audio { id, .. }
             | ClientEvent::Text { id, .. }
             | ClientEvent::Service { id, .. } => id,
@@ -74,12 +115,22 @@ pub enum ServerEvent {
         id: ConversationId,
         modalities: Vec<OutputModality>,
     },
+    /// Sent after `Started` when the service is connected to its upstream provider and configured.
+    /// Only services that connect to a provider send this event.
+    Ready { id: ConversationId },
     Stopped {
         id: ConversationId,
+        /// Set if the conversation was stopped with `drain`.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        drained: bool,
     },
+    #[serde(rename_all = "camelCase")]
     Error {
         id: ConversationId,
         message: String,
+        /// The request the service failed on, if known.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
     },
     Audio {
         id: ConversationId,
@@ -88,9 +139,7 @@ pub enum ServerEvent {
     /// Clear all buffered audio data on the client. This typically occurs when a dialog is
     /// interrupted by the client. Upon receiving this event, the client must discard all buffered
     /// audio and immediately play any subsequent audio samples.
-    ClearAudio {
-        id: ConversationId,
-    },
+    ClearAudio { id: ConversationId },
     #[serde(rename_all = "camelCase")]
     Text {
         id: ConversationId,
@@ -109,6 +158,15 @@ pub enum ServerEvent {
         #[serde(skip_serializing_if = "Option::is_none")]
         request_id: Option<RequestId>,
     },
+    /// Sent by dialog services when the response of a turn is complete. On the media path, it
+    /// arrives after the turn's audio, so it tells the client that the assistant finished
+    /// speaking.
+    #[serde(rename_all = "camelCase")]
+    TurnCompleted {
+        id: ConversationId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        response_id: Option<String>,
+    },
     /// A service event
     Service {
         id: ConversationId,
@@ -117,11 +175,15 @@ pub enum ServerEvent {
     },
     /// Billing
     ///
-    /// Inband Billing records are _only_ sent through the media path. All other billing
-    /// records are sent to the billing collector directly from within the service.
+    /// Inband Billing records are sent through the media path by default, so that they are
+    /// recorded when the media they bill is played back, and not at all if the media is discarded.
+    /// If the conversation was started with the control path as its `billing_path`, they overtake
+    /// queued media instead. All other billing records are sent to the billing collector directly
+    /// from within the service.
     #[serde(rename_all = "camelCase")]
     BillingRecords {
         id: ConversationId,
+        path: OutputPath,
         #[serde(skip_serializing_if = "Option::is_none")]
         request_id: Option<RequestId>,
         service: String,
@@ -135,11 +197,13 @@ impl ServerEvent {
     pub fn conversation_id(&self) -> &ConversationId {
         match self {
             ServerEvent::Started { id, .. }
-            | ServerEvent::Stopped { id }
+            | ServerEvent::Ready { id }
+            | ServerEvent::Stopped { id, .. }
             | ServerEvent::Error { id, .. }
             | ServerEvent::Audio { id, .. }
             | ServerEvent::Text { id, .. }
             | ServerEvent::RequestCompleted { id, .. }
+            | ServerEvent::TurnCompleted { id, .. }
             | ServerEvent::ClearAudio { id }
             | ServerEvent::Service { id, .. } => id,
             ServerEvent::BillingRecords { id, .. } => id,
@@ -149,12 +213,14 @@ impl ServerEvent {
     pub fn set_conversation_id(&mut self, id: ConversationId) {
         let id_ref = match self {
             ServerEvent::Started { id, .. } => id,
-            ServerEvent::Stopped { id } => id,
+            ServerEvent::Ready { id } => id,
+            ServerEvent::Stopped { id, .. } => id,
             ServerEvent::Error { id, .. } => id,
             ServerEvent::Audio { id, .. } => id,
             ServerEvent::ClearAudio { id } => id,
             ServerEvent::Text { id, .. } => id,
             ServerEvent::RequestCompleted { id, .. } => id,
+            ServerEvent::TurnCompleted { id, .. } => id,
             ServerEvent::Service { id, .. } => id,
             ServerEvent::BillingRecords { id, .. } => id,
         };
@@ -163,20 +229,21 @@ impl ServerEvent {
 
     pub fn output_path(&self) -> OutputPath {
         match self {
+            // Errors are expedited and overtake all pending media, and so does a `Stopped` event
+            // that was not requested to drain it.
             ServerEvent::Started { .. }
-            // TODO: The Stopped and Error events might need special consideration as they do
-            // overtake all pending media which they probably should not.
-            | ServerEvent::Stopped { .. }
+            | ServerEvent::Ready { .. }
+            | ServerEvent::Stopped { drained: false, .. }
             | ServerEvent::Error { .. } => OutputPath::Control,
 
-            ServerEvent::Audio { .. }
+            ServerEvent::Stopped { drained: true, .. }
+            | ServerEvent::Audio { .. }
             | ServerEvent::ClearAudio { .. }
             | ServerEvent::Text { .. }
-            | ServerEvent::RequestCompleted { .. } => OutputPath::Media,
-
-            | ServerEvent::Service { path, .. } => *path,
+            | ServerEvent::RequestCompleted { .. }
+            | ServerEvent::TurnCompleted { .. } => OutputPath::Media,
 
-            ServerEvent::BillingRecords { .. } => OutputPath::Media,
+            ServerEvent::Service { path, .. } | ServerEvent::BillingRecords { path, .. } => *path,
         }
     }
 }
@@ -219,7 +286,11 @@ impl<'de> Deserialize<'de> for Samples {
 
 #[cfg(test)]
 mod tests {
-    use serde::{Deserialize, Serialize};
+    use serde::{Deserialize, Serialize, de::DeserializeOwned};
+    use serde_json::{Value, json};
+
+    use super::{ClientEvent, ServerEvent};
+    use context_switch_core::OutputPath;
 
     #[derive(Deserialize, Serialize)]
     struct Test {
@@ -239,4 +310,206 @@ mod tests {
         let str = serde_json::to_string(&test).unwrap();
         assert_eq!(str, "{}")
     }
+
+    /// Deserializes a golden JSON representation of an event and asserts that it serializes back to
+    /// exactly the same JSON, so that renamed fields or changed encodings break these tests instead
+    /// of the clients.
+    fn round_trip<T: Serialize + DeserializeOwned>(golden: &Value) -> T {
+        let event: T = serde_json::from_value(golden.clone())
+            .unwrap_or_else(|e| panic!("Failed to deserialize {golden}: {e}"));
+        assert_eq!(&serde_json::to_value(&event).unwrap(), golden);
+        event
+    }
+
+    fn client_variant(event: &ClientEvent) -> &'static str {
+        match event {
+            ClientEvent::Start { .. } => "start",
+            ClientEvent::Stop { .. } => "stop",
+            ClientEvent::Hangup { .. } => "hangup",
+            ClientEvent::Audio { .. } => "audio",
+            ClientEvent::Text { .. } => "text",
+            ClientEvent::Service { .. } => "service",
+        }
+    }
+
+    fn server_variant(event: &ServerEvent) -> &'static str {
+        match event {
+            ServerEvent::Started { .. } => "started",
+            ServerEvent::Ready { .. } => "ready",
+            ServerEvent::Stopped { .. } => "stopped",
+            ServerEvent::Error { .. } => "error",
+            ServerEvent::Audio { .. } => "audio",
+            ServerEvent::ClearAudio { .. } => "clearAudio",
+            ServerEvent::Text { .. } => "text",
+            ServerEvent::RequestCompleted { .. } => "requestCompleted",
+            ServerEvent::TurnCompleted { .. } => "turnCompleted",
+            ServerEvent::Service { .. } => "service",
+            ServerEvent::BillingRecords { .. } => "billingRecords",
+        }
+    }
+
+    /// The samples `[1, -1, 256]` as little endian bytes in base64.
+    const SAMPLES: &str = "AQD//wAB";
+
+    #[test]
+    fn client_events() {
+        let goldens = [
+            json!({
+                "type": "start",
+                "id": "c",
+                "service": "azure-transcribe",
+                "params": { "language": "en-US" },
+                "inputModality": { "type": "audio", "format": { "channels": 1, "sampleRate": 16000 } },
+                "outputModalities": [
+                    { "type": "text" },
+                    { "type": "interimText" },
+                    { "type": "audio", "format": { "channels": 1, "sampleRate": 8000 } }
+                ],
+                "billingId": "b",
+                "metadata": { "caller": "+4930123" },
+                "shutdownTimeoutMs": 10000,
+                "preprocessing": {
+                    "agc": { "targetRms": 0.25, "maxGain": 10.0 },
+                    "speechGate": { "threshold": 0.5, "attackMs": 10.0, "releaseMs": 300.0 }
+                },
+                "frameDurationMs": 10,
+                "billingPath": "control"
+            }),
+            json!({
+                "type": "start",
+                "id": "c",
+                "service": "playback",
+                "params": null,
+                "inputModality": { "type": "text" },
+                "outputModalities": []
+            }),
+            json!({ "type": "stop", "id": "c", "drain": true }),
+            json!({ "type": "hangup", "id": "c", "reason": "NORMAL_CLEARING" }),
+            json!({ "type": "audio", "id": "c", "samples": SAMPLES }),
+            json!({
+                "type": "text",
+                "id": "c",
+                "requestId": "r",
+                "content": "Hello",
+                "contentType": "text/plain",
+                "billingScope": "scope"
+            }),
+            json!({ "type": "service", "id": "c", "value": { "any": ["thing"] } }),
+        ];
+
+        let variants: Vec<_> = goldens
+            .iter()
+            .map(|golden| {
+                let event: ClientEvent = round_trip(golden);
+                assert_eq!(event.conversation_id().as_str(), "c");
+                if let ClientEvent::Audio { samples, .. } = &event {
+                    assert_eq!(**samples, [1, -1, 256]);
+                }
+                client_variant(&event)
+            })
+            .collect();
+
+        assert_eq!(
+            variants,
+            [
+                "start", "start", "stop", "hangup", "audio", "text", "service"
+            ]
+        );
+    }
+
+    #[test]
+    fn server_events() {
+        let goldens = [
+            json!({
+                "type": "started",
+                "id": "c",
+                "modalities": [{ "type": "audio", "format": { "channels": 1, "sampleRate": 16000 } }]
+            }),
+            json!({ "type": "ready", "id": "c" }),
+            json!({ "type": "stopped", "id": "c" }),
+            json!({ "type": "stopped", "id": "c", "drained": true }),
+            json!({ "type": "error", "id": "c", "message": "failed" }),
+            json!({ "type": "error", "id": "c", "message": "failed", "requestId": "r" }),
+            json!({ "type": "audio", "id": "c", "samples": SAMPLES }),
+            json!({ "type": "clearAudio", "id": "c" }),
+            json!({
+                "type": "text",
+                "id": "c",
+                "isFinal": true,
+                "content": "Hello",
+                "language": "en-US",
+                "speaker": "1"
+            }),
+            json!({ "type": "text", "id": "c", "isFinal": false, "content": "Hel" }),
+            json!({ "type": "requestCompleted", "id": "c", "requestId": "r" }),
+            json!({ "type": "requestCompleted", "id": "c" }),
+            json!({ "type": "turnCompleted", "id": "c", "responseId": "resp" }),
+            json!({ "type": "turnCompleted", "id": "c" }),
+            json!({ "type": "service", "id": "c", "path": "control", "value": 1 }),
+            json!({ "type": "service", "id": "c", "path": "media", "value": null }),
+            json!({
+                "type": "billingRecords",
+                "id": "c",
+                "path": "media",
+                "requestId": "r",
+                "service": "azure-synthesize",
+                "scope": "Neural",
+                "records": [
+                    { "name": "output:audio", "duration": 1.5 },
+                    { "name": "tokens", "count": 42 }
+                ]
+            }),
+        ];
+
+        let variants: Vec<_> = goldens
+            .iter()
+            .map(|golden| {
+                let event: ServerEvent = round_trip(golden);
+                assert_eq!(event.conversation_id().as_str(), "c");
+                if let ServerEvent::Audio { samples, .. } = &event {
+                    assert_eq!(**samples, [1, -1, 256]);
+                }
+                server_variant(&event)
+            })
+            .collect();
+
+        assert_eq!(
+            variants,
+            [
+                "started",
+                "ready",
+                "stopped",
+                "stopped",
+                "error",
+                "error",
+                "audio",
+                "clearAudio",
+                "text",
+                "text",
+                "requestCompleted",
+                "requestCompleted",
+                "turnCompleted",
+                "turnCompleted",
+                "service",
+                "service",
+                "billingRecords",
+            ]
+        );
+    }
+
+    #[test]
+    fn output_paths_are_lowercase() {
+        assert_eq!(json!(OutputPath::Control), json!("control"));
+        assert_eq!(json!(OutputPath::Media), json!("media"));
+    }
+
+    #[test]
+    fn unknown_event_types_are_rejected() {
+        assert!(
+            serde_json::from_value::<ClientEvent>(json!({ "type": "pause", "id": "c" })).is_err()
+        );
+        assert!(
+            serde_json::from_value::<ServerEvent>(json!({ "type": "paused", "id": "c" })).is_err()
+        );
+    }
 }
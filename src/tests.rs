@@ -1,11 +1,17 @@
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use helper::*;
-use serde_json::Value;
+use serde_json::{Value, json};
 use tokio::sync::mpsc::{channel, unbounded_channel};
+use tokio::time;
 
 use crate::{ClientEvent, ContextSwitch, ConversationId, Registry, ServerEvent};
-use context_switch_core::InputModality;
+use context_switch_core::billing_collector::BillingCollector;
+use context_switch_core::{
+    AudioFormat, AudioFrame, BillingId, BillingRecord, Input, InputModality, OutputPath,
+    Preprocessing, SpeechGateParams,
+};
 
 #[tokio::test]
 async fn never_ending_service_shut_downs_gracefully_in_response_to_stop() {
@@ -26,28 +32,302 @@ async fn never_ending_service_shut_downs_gracefully_in_response_to_stop() {
 
     let conv: ConversationId = "conv".to_string().into();
 
-    cs.process(ClientEvent::Start {
-        id: conv.clone(),
-        service: "test-service".into(),
-        params: Value::Null,
-        input_modality: InputModality::Text,
-        output_modalities: Vec::new(),
-        billing_id: None,
+    cs.process(start_event(&conv, InputModality::Text)).unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+    assert_eq!(n_recv.recv().await, Some(Notification::Started));
+
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: false,
     })
     .unwrap();
 
+    assert_eq!(n_recv.recv().await, Some(Notification::Lingering));
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { drained: false, .. }));
+
+    assert_eq!(n_recv.recv().await, Some(Notification::Stopped));
+}
+
+#[tokio::test]
+async fn start_event_shutdown_timeout_overrides_the_default() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let (n_send, mut n_recv) = channel(10);
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        TestService {
+            notification: n_send,
+            scenario: Scenario::NeverEnd,
+        },
+    );
+
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_shutdown_timeout(Duration::from_micros(1));
+
+    let conv: ConversationId = "conv".to_string().into();
+
+    let mut start = start_event(&conv, InputModality::Text);
+    if let ClientEvent::Start {
+        shutdown_timeout_ms,
+        ..
+    } = &mut start
+    {
+        *shutdown_timeout_ms = Some(200);
+    }
+    cs.process(start).unwrap();
+
     let ev = server_receiver.recv().await.unwrap();
     assert!(matches!(ev, ServerEvent::Started { .. }));
     assert_eq!(n_recv.recv().await, Some(Notification::Started));
 
-    cs.process(ClientEvent::Stop { id: conv }).unwrap();
-
+    let stopping = Instant::now();
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: false,
+    })
+    .unwrap();
     assert_eq!(n_recv.recv().await, Some(Notification::Lingering));
 
     let ev = server_receiver.recv().await.unwrap();
     assert!(matches!(ev, ServerEvent::Stopped { .. }));
+    assert!(stopping.elapsed() >= Duration::from_millis(200));
+}
 
-    assert_eq!(n_recv.recv().await, Some(Notification::Stopped));
+#[tokio::test]
+async fn stop_with_drain_is_reported_in_stopped_event() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let (n_send, _n_recv) = channel(10);
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        TestService {
+            notification: n_send,
+            scenario: Scenario::NeverEnd,
+        },
+    );
+
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_shutdown_timeout(Duration::from_micros(1));
+
+    let conv: ConversationId = "conv".to_string().into();
+
+    cs.process(start_event(&conv, InputModality::Text)).unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: true,
+    })
+    .unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { drained: true, .. }));
+}
+
+#[tokio::test]
+async fn hangup_cancels_the_conversation_and_records_the_reason() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let (n_send, mut n_recv) = channel(10);
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        TestService {
+            notification: n_send,
+            scenario: Scenario::NeverEnd,
+        },
+    );
+
+    let billing_collector = Arc::new(Mutex::new(BillingCollector::default()));
+    // A graceful shutdown would not end before the test times out.
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_shutdown_timeout(Duration::from_secs(3600))
+        .with_billing_collector(billing_collector.clone());
+
+    let conv: ConversationId = "conv".to_string().into();
+    let billing_id: BillingId = "billing".to_string().into();
+
+    let mut start = start_event(&conv, InputModality::Text);
+    if let ClientEvent::Start {
+        billing_id: start_billing_id,
+        ..
+    } = &mut start
+    {
+        *start_billing_id = Some(billing_id.clone());
+    }
+    cs.process(start).unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+    assert_eq!(n_recv.recv().await, Some(Notification::Started));
+
+    cs.process(ClientEvent::Hangup {
+        id: conv,
+        reason: "NORMAL_CLEARING".into(),
+    })
+    .unwrap();
+
+    let ev = time::timeout(Duration::from_secs(5), server_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { drained: false, .. }));
+    // The service was cancelled before it saw the end of its input.
+    assert!(n_recv.try_recv().is_err());
+
+    let records = billing_collector.lock().unwrap().collect(&billing_id);
+    let [records] = &records[..] else {
+        panic!("Expected the records of one service, got {records:?}");
+    };
+    assert_eq!(
+        records.records(),
+        [BillingRecord::count("hangup:NORMAL_CLEARING", 1)]
+    );
+}
+
+#[tokio::test]
+async fn hangup_does_not_wait_for_queued_input() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service("test-service", StallingService);
+
+    // A graceful shutdown would not end before the test times out.
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_shutdown_timeout(Duration::from_secs(3600))
+        .with_max_queued_input_events(2);
+
+    let conv: ConversationId = "conv".to_string().into();
+    let format = AudioFormat::new(1, 16000);
+    cs.process(start_event(&conv, InputModality::Audio { format }))
+        .unwrap();
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    // The service does not take any input, so the audio stays queued.
+    for _ in 0..10 {
+        cs.process(ClientEvent::Audio {
+            id: conv.clone(),
+            samples: vec![0i16; 320].into(),
+        })
+        .unwrap();
+    }
+    cs.process(ClientEvent::Hangup {
+        id: conv,
+        reason: "NORMAL_CLEARING".into(),
+    })
+    .unwrap();
+
+    let ev = time::timeout(Duration::from_secs(5), server_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { drained: false, .. }));
+}
+
+#[tokio::test]
+async fn idle_conversation_is_stopped_with_an_error() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+    let (input_sender, mut input_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        RecordingService {
+            inputs: input_sender,
+        },
+    );
+
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_idle_timeout(Duration::from_millis(200));
+
+    let conv: ConversationId = "conv".to_string().into();
+    let start = || start_event(&conv, InputModality::Text);
+
+    cs.process(start()).unwrap();
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    // Each input resets the timer.
+    for _ in 0..4 {
+        time::sleep(Duration::from_millis(100)).await;
+        cs.process(ClientEvent::Text {
+            id: conv.clone(),
+            request_id: None,
+            content: "text".into(),
+            content_type: None,
+            billing_scope: None,
+        })
+        .unwrap();
+        assert!(input_receiver.recv().await.is_some());
+    }
+    assert!(server_receiver.try_recv().is_err());
+
+    let ev = time::timeout(Duration::from_secs(5), server_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let ServerEvent::Error { message, .. } = ev else {
+        panic!("Expected an error, got {ev:?}");
+    };
+    assert!(message.ends_with("idle timeout"), "{message}");
+
+    // Clients may still stop the ended conversation.
+    cs.process(ClientEvent::Stop {
+        id: conv.clone(),
+        drain: false,
+    })
+    .unwrap();
+
+    // The ended conversation does not block its id.
+    cs.process(start()).unwrap();
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+}
+
+#[tokio::test]
+async fn queued_input_keeps_a_conversation_from_idling() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service("test-service", StallingService);
+
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_idle_timeout(Duration::from_millis(200))
+        .with_max_queued_input_events(1);
+
+    let conv: ConversationId = "conv".to_string().into();
+    cs.process(start_event(&conv, InputModality::Text)).unwrap();
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    // The service does not take the input, so it stays queued.
+    for _ in 0..4 {
+        time::sleep(Duration::from_millis(100)).await;
+        cs.process(ClientEvent::Text {
+            id: conv.clone(),
+            request_id: None,
+            content: "text".into(),
+            content_type: None,
+            billing_scope: None,
+        })
+        .unwrap();
+    }
+    assert!(server_receiver.try_recv().is_err());
+
+    let ev = time::timeout(Duration::from_secs(5), server_receiver.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let ServerEvent::Error { message, .. } = ev else {
+        panic!("Expected an error, got {ev:?}");
+    };
+    assert!(message.ends_with("idle timeout"), "{message}");
 }
 
 #[tokio::test]
@@ -60,18 +340,10 @@ async fn params_deserialization_failure_is_emitted_as_conversation_error() {
 
     let conv: ConversationId = "conv-deser-fail".to_string().into();
 
-    cs.process(ClientEvent::Start {
-        id: conv.clone(),
-        service: "test-service".into(),
-        params: Value::Null,
-        input_modality: InputModality::Text,
-        output_modalities: Vec::new(),
-        billing_id: None,
-    })
-    .unwrap();
+    cs.process(start_event(&conv, InputModality::Text)).unwrap();
 
     let event = server_receiver.recv().await.unwrap();
-    let ServerEvent::Error { id, message } = event else {
+    let ServerEvent::Error { id, message, .. } = event else {
         panic!("Expected ServerEvent::Error");
     };
 
@@ -80,6 +352,440 @@ async fn params_deserialization_failure_is_emitted_as_conversation_error() {
     assert!(message.contains("Failed to deserialize service params"));
 }
 
+#[tokio::test]
+async fn start_events_are_validated_without_starting_a_conversation() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service("test-service", TextOnlyService);
+    let cs = ContextSwitch::new(registry.into(), server_sender, None);
+
+    let start = |name: &str, value: Value, input_modality| {
+        let mut start = start_event(&"conv".to_string().into(), input_modality);
+        if let ClientEvent::Start {
+            service, params, ..
+        } = &mut start
+        {
+            *service = name.into();
+            *params = value;
+        }
+        start
+    };
+    let valid_params = json!({ "_required": "value" });
+    let error = |event| format!("{:#}", cs.validate_start(&event).unwrap_err());
+
+    cs.validate_start(&start(
+        "test-service",
+        valid_params.clone(),
+        InputModality::Text,
+    ))
+    .unwrap();
+
+    assert!(
+        error(start(
+            "unknown-service",
+            valid_params.clone(),
+            InputModality::Text
+        ))
+        .contains("Unregistered service")
+    );
+    assert!(
+        error(start("test-service", Value::Null, InputModality::Text))
+            .contains("Failed to deserialize service params")
+    );
+    let audio = InputModality::Audio {
+        format: AudioFormat::new(1, 16000),
+    };
+    assert!(
+        error(start("test-service", valid_params, audio)).contains("Audio input is not supported")
+    );
+
+    assert!(server_receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn start_metadata_is_accessible_to_the_service() {
+    let (server_sender, _server_receiver) = unbounded_channel();
+    let (metadata_sender, mut metadata_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        MetadataService {
+            metadata: metadata_sender,
+        },
+    );
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None);
+
+    let metadata = json!({ "ani": "+4930123456", "dnis": "+4940654321", "language": "de-DE" });
+    let mut start = start_event(&"conv".to_string().into(), InputModality::Text);
+    if let ClientEvent::Start {
+        metadata: start_metadata,
+        ..
+    } = &mut start
+    {
+        *start_metadata = Some(metadata.clone());
+    }
+    cs.process(start).unwrap();
+
+    assert_eq!(metadata_receiver.recv().await, Some(Some(metadata)));
+}
+
+#[tokio::test]
+async fn inband_billing_records_take_the_billing_path_of_the_start_event() {
+    assert_eq!(inband_billing_path(None).await, OutputPath::Media);
+    assert_eq!(
+        inband_billing_path(Some(OutputPath::Control)).await,
+        OutputPath::Control
+    );
+}
+
+async fn inband_billing_path(billing_path: Option<OutputPath>) -> OutputPath {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service("test-service", MediaBillingService);
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None);
+
+    let mut start = start_event(&"conv".to_string().into(), InputModality::Text);
+    if let ClientEvent::Start {
+        billing_id: start_billing_id,
+        billing_path: start_billing_path,
+        ..
+    } = &mut start
+    {
+        *start_billing_id = Some("billing".to_string().into());
+        *start_billing_path = billing_path;
+    }
+    cs.process(start).unwrap();
+
+    assert!(matches!(
+        server_receiver.recv().await,
+        Some(ServerEvent::Started { .. })
+    ));
+    let Some(ServerEvent::BillingRecords { path, .. }) = server_receiver.recv().await else {
+        panic!("Expected inband billing records");
+    };
+    path
+}
+
+#[tokio::test]
+async fn services_are_shut_down_when_context_switch_drops() {
+    let (server_sender, _server_receiver) = unbounded_channel();
+
+    let (n_send, mut n_recv) = channel(10);
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        ShutdownService {
+            notification: n_send,
+        },
+    );
+
+    let cs = ContextSwitch::new(registry.into(), server_sender, None);
+    drop(cs);
+
+    assert_eq!(n_recv.recv().await, Some(Notification::ShutDown));
+}
+
+#[tokio::test]
+async fn input_capacity_scales_with_the_frame_duration() {
+    assert_eq!(queued_audio_frames(None).await, 4);
+    assert_eq!(queued_audio_frames(Some(20)).await, 4);
+    assert_eq!(queued_audio_frames(Some(10)).await, 8);
+    assert_eq!(queued_audio_frames(Some(40)).await, 2);
+}
+
+/// Sends a burst of audio frames to a conversation that queues 4 input events for frames of 20ms
+/// and returns how many of them were not shed.
+async fn queued_audio_frames(frame_duration_ms: Option<u64>) -> usize {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+    let (input_sender, mut input_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        RecordingService {
+            inputs: input_sender,
+        },
+    );
+
+    let mut cs =
+        ContextSwitch::new(registry.into(), server_sender, None).with_max_queued_input_events(4);
+
+    let conv: ConversationId = "conv".to_string().into();
+
+    let format = AudioFormat::new(1, 16000);
+    let mut start = start_event(&conv, InputModality::Audio { format });
+    if let ClientEvent::Start {
+        frame_duration_ms: duration,
+        ..
+    } = &mut start
+    {
+        *duration = frame_duration_ms;
+    }
+    cs.process(start).unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    for _ in 0..20 {
+        cs.process(ClientEvent::Audio {
+            id: conv.clone(),
+            samples: vec![0i16; 160].into(),
+        })
+        .unwrap();
+    }
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: true,
+    })
+    .unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { drained: true, .. }));
+
+    let mut audio_frames = 0;
+    while let Ok(input) = input_receiver.try_recv() {
+        assert!(matches!(input, Input::Audio { .. }), "{input:?}");
+        audio_frames += 1;
+    }
+    audio_frames
+}
+
+#[tokio::test]
+async fn input_burst_beyond_capacity_sheds_audio_but_passes_control_events_first() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+    let (input_sender, mut input_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        RecordingService {
+            inputs: input_sender,
+        },
+    );
+
+    let mut cs =
+        ContextSwitch::new(registry.into(), server_sender, None).with_max_queued_input_events(4);
+
+    let conv: ConversationId = "conv".to_string().into();
+
+    cs.process(start_event(
+        &conv,
+        InputModality::Audio {
+            format: AudioFormat::new(1, 16000),
+        },
+    ))
+    .unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    // The conversation does not run before the test yields, so all of these events are queued at
+    // once.
+    let audio = || ClientEvent::Audio {
+        id: conv.clone(),
+        samples: vec![0i16; 320].into(),
+    };
+    for _ in 0..10 {
+        cs.process(audio()).unwrap();
+    }
+    cs.process(ClientEvent::Service {
+        id: conv.clone(),
+        value: Value::Bool(true),
+    })
+    .unwrap();
+    for _ in 0..10 {
+        cs.process(audio()).unwrap();
+    }
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: true,
+    })
+    .unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { drained: true, .. }));
+
+    // The service event overtakes the queued audio.
+    assert!(matches!(
+        input_receiver.try_recv(),
+        Ok(Input::ServiceEvent { .. })
+    ));
+    let mut audio_frames = 0;
+    while let Ok(input) = input_receiver.try_recv() {
+        match input {
+            Input::Audio { .. } => audio_frames += 1,
+            input => panic!("Unexpected input: {input:?}"),
+        }
+    }
+    assert_eq!(audio_frames, 4);
+}
+
+#[tokio::test]
+async fn requested_preprocessing_is_applied_to_posted_audio() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+    let (input_sender, mut input_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        RecordingService {
+            inputs: input_sender,
+        },
+    );
+
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_shutdown_timeout(Duration::from_secs(1));
+
+    let conv: ConversationId = "conv".to_string().into();
+    let format = AudioFormat::new(1, 16000);
+
+    let mut start = start_event(&conv, InputModality::Audio { format });
+    if let ClientEvent::Start { preprocessing, .. } = &mut start {
+        *preprocessing = Some(Preprocessing {
+            agc: None,
+            speech_gate: Some(SpeechGateParams {
+                threshold: 0.5,
+                attack_ms: 10.0,
+                release_ms: 300.0,
+            }),
+        });
+    }
+    cs.process(start).unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    // Audio far below the gate's threshold.
+    for _ in 0..3 {
+        cs.post_audio_frame(
+            &conv,
+            AudioFrame {
+                format,
+                samples: vec![1000; 320],
+            },
+        )
+        .unwrap();
+    }
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: true,
+    })
+    .unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Stopped { .. }));
+
+    let mut frames = Vec::new();
+    while let Ok(input) = input_receiver.try_recv() {
+        match input {
+            Input::Audio { frame } => frames.push(frame),
+            input => panic!("Unexpected input: {input:?}"),
+        }
+    }
+    assert_eq!(frames.len(), 3);
+    assert!(
+        frames
+            .iter()
+            .all(|frame| frame.samples.iter().all(|&s| s == 0))
+    );
+}
+
+#[tokio::test]
+async fn audio_posted_to_many_is_converted_to_each_conversations_format() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+    let (input_sender, mut input_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service(
+        "test-service",
+        RecordingService {
+            inputs: input_sender,
+        },
+    );
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None)
+        .with_shutdown_timeout(Duration::from_secs(1));
+
+    let source_format = AudioFormat::new(1, 16000);
+    let conversations = [
+        ("same", source_format),
+        ("resampled", AudioFormat::new(1, 8000)),
+    ];
+    let ids: Vec<ConversationId> = conversations
+        .iter()
+        .map(|(id, _)| id.to_string().into())
+        .collect();
+    for (id, (_, format)) in ids.iter().zip(conversations) {
+        cs.process(start_event(id, InputModality::Audio { format }))
+            .unwrap();
+        let ev = server_receiver.recv().await.unwrap();
+        assert!(matches!(ev, ServerEvent::Started { .. }));
+    }
+
+    cs.post_audio_to_many(
+        &ids,
+        AudioFrame {
+            format: source_format,
+            samples: vec![1000; 320],
+        },
+    )
+    .unwrap();
+    for id in ids {
+        cs.process(ClientEvent::Stop { id, drain: true }).unwrap();
+        let ev = server_receiver.recv().await.unwrap();
+        assert!(matches!(ev, ServerEvent::Stopped { .. }));
+    }
+
+    let mut frames = Vec::new();
+    while let Ok(input) = input_receiver.try_recv() {
+        match input {
+            Input::Audio { frame } => frames.push((frame.format, frame.samples)),
+            input => panic!("Unexpected input: {input:?}"),
+        }
+    }
+    frames.sort_by_key(|(format, _)| format.sample_rate);
+    assert_eq!(
+        frames,
+        [
+            (AudioFormat::new(1, 8000), vec![1000; 160]),
+            (source_format, vec![1000; 320]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn request_errors_carry_the_id_of_the_failed_request() {
+    let (server_sender, mut server_receiver) = unbounded_channel();
+
+    let registry = Registry::empty().add_service("test-service", FailingRequestService);
+    let mut cs = ContextSwitch::new(registry.into(), server_sender, None);
+
+    let conv: ConversationId = "conv".to_string().into();
+
+    cs.process(start_event(&conv, InputModality::Text)).unwrap();
+
+    let ev = server_receiver.recv().await.unwrap();
+    assert!(matches!(ev, ServerEvent::Started { .. }));
+
+    for request_id in ["r1", "r2"] {
+        cs.process(ClientEvent::Text {
+            id: conv.clone(),
+            request_id: Some(request_id.to_string().into()),
+            content: "fail".into(),
+            content_type: None,
+            billing_scope: None,
+        })
+        .unwrap();
+    }
+
+    let ev = server_receiver.recv().await.unwrap();
+    let ServerEvent::Error {
+        message,
+        request_id,
+        ..
+    } = ev
+    else {
+        panic!("Expected ServerEvent::Error, got {ev:?}");
+    };
+    assert_eq!(request_id, Some("r1".to_string().into()));
+    assert!(message.contains("Request `r1` failed"), "{message}");
+}
+
 // This is currently a limitation. No output events can be sent while a graceful shutdown has
 // started.
 // #[tokio::test]
@@ -102,21 +808,17 @@ async fn output_events_can_be_sent_after_shutdown() {
 
     let conv: ConversationId = "conv".to_string().into();
 
-    cs.process(ClientEvent::Start {
-        id: conv.clone(),
-        service: "test-service".into(),
-        params: Value::Null,
-        input_modality: InputModality::Text,
-        output_modalities: Vec::new(),
-        billing_id: None,
-    })
-    .unwrap();
+    cs.process(start_event(&conv, InputModality::Text)).unwrap();
 
     let ev = server_receiver.recv().await.unwrap();
     assert!(matches!(ev, ServerEvent::Started { .. }));
     assert_eq!(n_recv.recv().await, Some(Notification::Started));
 
-    cs.process(ClientEvent::Stop { id: conv }).unwrap();
+    cs.process(ClientEvent::Stop {
+        id: conv,
+        drain: false,
+    })
+    .unwrap();
 
     let ev = server_receiver.recv().await.unwrap();
     assert!(matches!(ev, ServerEvent::ClearAudio { .. }));
@@ -129,21 +831,46 @@ async fn output_events_can_be_sent_after_shutdown() {
 
 mod helper {
 
+    use std::future;
     use std::time::Duration;
 
-    use anyhow::Result;
+    use anyhow::{Context, Result, anyhow};
     use async_trait::async_trait;
     use serde::Deserialize;
-    use tokio::sync::mpsc::Sender;
+    use serde_json::Value;
+    use tokio::sync::mpsc::{Sender, UnboundedSender};
     use tokio::time;
 
-    use context_switch_core::{Conversation, Service};
+    use context_switch_core::{
+        BillingRecord, BillingSchedule, Conversation, Input, InputModality, OutputModalities,
+        RequestFailed, Service,
+    };
+
+    use crate::{ClientEvent, ConversationId};
+
+    /// A start event for the test service without params and optional settings.
+    pub fn start_event(id: &ConversationId, input_modality: InputModality) -> ClientEvent {
+        ClientEvent::Start {
+            id: id.clone(),
+            service: "test-service".into(),
+            params: Value::Null,
+            input_modality,
+            output_modalities: Vec::new(),
+            billing_id: None,
+            metadata: None,
+            shutdown_timeout_ms: None,
+            preprocessing: None,
+            frame_duration_ms: None,
+            billing_path: None,
+        }
+    }
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum Notification {
         Started,
         Lingering,
         Stopped,
+        ShutDown,
     }
 
     #[derive(Debug)]
@@ -158,9 +885,42 @@ mod helper {
         pub scenario: Scenario,
     }
 
+    #[derive(Debug)]
+    pub struct ShutdownService {
+        pub notification: Sender<Notification>,
+    }
+
+    /// Forwards all inputs of the conversation.
+    #[derive(Debug)]
+    pub struct RecordingService {
+        pub inputs: UnboundedSender<Input>,
+    }
+
+    /// Reports the metadata of the conversation.
+    #[derive(Debug)]
+    pub struct MetadataService {
+        pub metadata: UnboundedSender<Option<Value>>,
+    }
+
+    /// Posts inband billing records right after it started.
+    #[derive(Debug)]
+    pub struct MediaBillingService;
+
+    /// Never takes any input.
+    #[derive(Debug)]
+    pub struct StallingService;
+
     #[derive(Debug)]
     pub struct InvalidParamsService;
 
+    /// Accepts text input only.
+    #[derive(Debug)]
+    pub struct TextOnlyService;
+
+    /// Fails on every request with the text `fail` and completes all others.
+    #[derive(Debug)]
+    pub struct FailingRequestService;
+
     #[derive(Debug, Deserialize)]
     pub struct RequiredParams {
         pub _required: String,
@@ -179,6 +939,131 @@ mod helper {
         }
     }
 
+    #[async_trait]
+    impl Service for TextOnlyService {
+        type Params = RequiredParams;
+
+        fn check_modalities(
+            &self,
+            input_modality: InputModality,
+            _output_modalities: &OutputModalities,
+        ) -> Result<()> {
+            input_modality.require_text_input_only()
+        }
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            conversation: Conversation,
+        ) -> Result<()> {
+            conversation.require_text_input_only()
+        }
+    }
+
+    #[async_trait]
+    impl Service for FailingRequestService {
+        type Params = ();
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            conversation: Conversation,
+        ) -> Result<()> {
+            let (mut input, output) = conversation.start()?;
+            while let Some(Input::Text {
+                request_id, text, ..
+            }) = input.recv().await
+            {
+                if text == "fail" {
+                    return Err(anyhow!("Synthesis failed")).context(RequestFailed(request_id));
+                }
+                output.request_completed(request_id)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Service for StallingService {
+        type Params = ();
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            conversation: Conversation,
+        ) -> Result<()> {
+            let (_input, _output) = conversation.start()?;
+            future::pending().await
+        }
+    }
+
+    #[async_trait]
+    impl Service for MetadataService {
+        type Params = ();
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            conversation: Conversation,
+        ) -> Result<()> {
+            self.metadata.send(conversation.metadata().cloned())?;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Service for MediaBillingService {
+        type Params = ();
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            conversation: Conversation,
+        ) -> Result<()> {
+            let (_input, output) = conversation.start()?;
+            let billing = BillingRecord::count("output:characters", 5);
+            output.billing_records(None, None, [billing], BillingSchedule::Media)?;
+            future::pending().await
+        }
+    }
+
+    #[async_trait]
+    impl Service for RecordingService {
+        type Params = ();
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            conversation: Conversation,
+        ) -> Result<()> {
+            let (mut input, _output) = conversation.start()?;
+            while let Some(input) = input.recv().await {
+                self.inputs.send(input)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Service for ShutdownService {
+        type Params = ();
+
+        async fn conversation(
+            &self,
+            _params: Self::Params,
+            _conversation: Conversation,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn shutdown(&self) {
+            self.notification
+                .send(Notification::ShutDown)
+                .await
+                .unwrap();
+        }
+    }
+
     #[async_trait]
     impl Service for TestService {
         type Params = ();